@@ -2,11 +2,45 @@ import bpy
 import bpy.mathutils as mathutils
 from math import radians
 import socket
+import ssl
 import json
 import threading
 import time
 
 OUTPUT_DATA_RATE = 50  # Hz
+# Version of the bridge's message format this script understands
+BRIDGE_PROTOCOL = 1
+# Certificate and key to accept a bridge running with --tls; None for plain TCP
+TLS_CERT = None
+TLS_KEY = None
+# Only accept bridges presenting a client certificate signed by this CA
+TLS_CLIENT_CA = None
+
+# The receiver of the running rotation operator, used to send commands back
+active_receiver = None
+
+def answer_hello(conn, hello):
+    """Acknowledges the hello of a compatible bridge, or rejects it and returns False."""
+    protocol = hello.get('protocol')
+    if protocol != BRIDGE_PROTOCOL:
+        reason = f"protocol {protocol} is not supported, expected {BRIDGE_PROTOCOL}"
+        print(f"Rejecting bridge {hello.get('bridge')}: {reason}")
+        conn.sendall((json.dumps({'hello_reject': reason}) + '\n').encode())
+        return False
+    if hello.get('units') == 'rad':
+        print("Warning: the bridge sends radians, but degrees are expected")
+    print(f"Bridge {hello.get('bridge')} connected ({hello.get('source')})")
+    conn.sendall((json.dumps({'hello_ack': {'protocol': BRIDGE_PROTOCOL}}) + '\n').encode())
+    return True
+
+def tls_context():
+    """The server side of the bridge's --tls."""
+    context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    context.load_cert_chain(TLS_CERT, TLS_KEY)
+    if TLS_CLIENT_CA:
+        context.verify_mode = ssl.CERT_REQUIRED
+        context.load_verify_locations(TLS_CLIENT_CA)
+    return context
 
 class RotationReceiver:
     def __init__(self, host='127.0.0.1', port=65432):
@@ -14,6 +48,7 @@ class RotationReceiver:
         self.port = port
         self.latest_rotation = {'x': 0.0, 'y': 0.0, 'z': 0.0}
         self.running = True
+        self.conn = None
 
     def start(self):
         self.thread = threading.Thread(target=self.receive_data)
@@ -30,23 +65,65 @@ class RotationReceiver:
             while self.running:
                 try:
                     conn, addr = s.accept()
+                    if TLS_CERT:
+                        conn = tls_context().wrap_socket(conn, server_side=True)
                     with conn:
                         print(f"Connected by {addr}")
-                        while self.running:
+                        self.conn = conn
+                        buffer = b''
+                        compatible = True
+                        while self.running and compatible:
                             data = conn.recv(1024)
                             if not data:
                                 break
-                            try:
-                                rotation_data = json.loads(data.decode())
-                                self.latest_rotation = rotation_data
-                            except json.JSONDecodeError:
-                                print("Received invalid JSON data")
+                            *lines, buffer = (buffer + data).split(b'\n')
+                            for line in lines:
+                                if not self.handle_line(conn, line):
+                                    compatible = False
+                                    break
+                    self.conn = None
                 except socket.timeout:
                     continue
                 except Exception as e:
                     print(f"Error: {e}")
                     time.sleep(1)
 
+    def handle_line(self, conn, line):
+        """Handles one JSON line from the bridge, returns False for a bridge this script can't work with."""
+        try:
+            message = json.loads(line.decode())
+        except json.JSONDecodeError:
+            print("Received invalid JSON data")
+            return True
+        if isinstance(message, list):
+            # A batch from --batch-format array, only the latest one matters
+            if not message:
+                return True
+            message = message[-1]
+        if 'hello' in message:
+            return answer_hello(conn, message['hello'])
+        if 'heartbeat' in message or 'temp' in message:
+            return True
+        if 'event' in message:
+            print(f"Event: {message['event']}")
+            return True
+        if 'gesture' in message:
+            print(f"Gesture: {message['gesture']} ({message.get('confidence', 0):.0%})")
+            return True
+        if 'ping' in message:
+            # Sent by the bridge's latency command
+            conn.sendall((json.dumps({'pong': message['ping']}) + '\n').encode())
+            return True
+        self.latest_rotation = message
+        return True
+
+    def send_command(self, cmd):
+        """Sends a command to the board, relayed by a bridge running with --relay-commands."""
+        if self.conn is None:
+            return False
+        self.conn.sendall((json.dumps({'cmd': cmd}) + '\n').encode())
+        return True
+
 class CubeRotationOperator(bpy.types.Operator):
     bl_idname = "object.rotate_cube_from_socket"
     bl_label = "Rotate Cube From Socket"
@@ -69,19 +146,27 @@ class CubeRotationOperator(bpy.types.Operator):
                 self.report({'ERROR'}, "Default cube not found. Please add a cube to the scene.")
                 self.cancel(context)
                 return {'CANCELLED'}
-            # Convert Euler angles to quaternion to avoid gimbal lock
-            # this will be useful for the time when magnetometer data is added.
-            rotation = mathutils.Euler((
-                radians(self.receiver.latest_rotation['x']),
-                radians(self.receiver.latest_rotation['y']),
-                radians(self.receiver.latest_rotation['z'])
-            ), 'XYZ')
+            latest = self.receiver.latest_rotation
             cube.rotation_mode = 'QUATERNION'
-            cube.rotation_quaternion = rotation.to_quaternion()
+            if 'qw' in latest:
+                # Firmware built with the quaternion feature
+                cube.rotation_quaternion = mathutils.Quaternion((
+                    latest['qw'], latest['qx'], latest['qy'], latest['qz']
+                ))
+            else:
+                # Convert Euler angles to quaternion to avoid gimbal lock
+                rotation = mathutils.Euler((
+                    radians(latest['x']),
+                    radians(latest['y']),
+                    radians(latest['z'])
+                ), 'XYZ')
+                cube.rotation_quaternion = rotation.to_quaternion()
 
         return {'PASS_THROUGH'}
 
     def execute(self, context):
+        global active_receiver
+        active_receiver = self.receiver
         self.receiver.start()
         wm = context.window_manager
         self._timer = wm.event_timer_add(1/OUTPUT_DATA_RATE, window=context.window)
@@ -93,10 +178,34 @@ class CubeRotationOperator(bpy.types.Operator):
         wm = context.window_manager
         wm.event_timer_remove(self._timer)
 
+class TareOperator(bpy.types.Operator):
+    bl_idname = "object.tare_microbit"
+    bl_label = "Tare Microbit"
+    bl_description = "Make the Microbit's current orientation the new zero"
+
+    def execute(self, context):
+        if active_receiver is None or not active_receiver.send_command('tare'):
+            self.report({'ERROR'}, "Bridge not connected. Start the rotation operator first.")
+            return {'CANCELLED'}
+        return {'FINISHED'}
+
+class MicrobitPanel(bpy.types.Panel):
+    bl_label = "Microbit"
+    bl_space_type = 'VIEW_3D'
+    bl_region_type = 'UI'
+    bl_category = "Microbit"
+
+    def draw(self, context):
+        self.layout.operator(TareOperator.bl_idname)
+
 def register():
     bpy.utils.register_class(CubeRotationOperator)
+    bpy.utils.register_class(TareOperator)
+    bpy.utils.register_class(MicrobitPanel)
 
 def unregister():
+    bpy.utils.unregister_class(MicrobitPanel)
+    bpy.utils.unregister_class(TareOperator)
     bpy.utils.unregister_class(CubeRotationOperator)
 
 if __name__ == "__main__":
@@ -0,0 +1,40 @@
+//! The buttons A and B, whose presses go into the stream as events.
+
+use embedded_hal::digital::v2::InputPin;
+
+/// A button polled once per sample. Polling that seldom also debounces
+/// it, the contacts settle long before the next poll.
+pub struct Button<P> {
+    pin: P,
+    event: &'static str,
+    down: bool,
+}
+
+impl<P: InputPin> Button<P> {
+    /// A button whose presses are reported as `{"event":EVENT}`.
+    pub fn new(pin: P, event: &'static str) -> Self {
+        Button {
+            pin,
+            event,
+            down: false,
+        }
+    }
+
+    /// Whether the button is held right now. The buttons pull their pin
+    /// low while they're held.
+    pub fn is_held(&self) -> bool {
+        self.pin.is_low().unwrap_or(false)
+    }
+
+    /// The event if the button was pressed since the last poll.
+    pub fn poll(&mut self) -> Option<&'static str> {
+        let down = self.is_held();
+        let pressed = down && !self.down;
+        self.down = down;
+        if pressed {
+            Some(self.event)
+        } else {
+            None
+        }
+    }
+}
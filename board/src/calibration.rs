@@ -0,0 +1,205 @@
+//! Six-position calibration of the accelerometer, run when button A is
+//! held at boot. The LEDs ask for each axis pointing up and down in turn,
+//! and a reading is taken once the board is held still that way. The
+//! offsets and scales found apply until the next reset.
+
+use microbit::display::nonblocking::GreyscaleImage;
+
+/// A reading of 1g. The driver reports the acceleration in mg, whatever
+/// the range and mode.
+pub const ONE_G: i32 = 1000;
+/// Share of 1g the axis asked for has to read before it counts as pointing
+/// up or down, so a board half way there isn't captured.
+const MIN_G: i32 = ONE_G * 8 / 10;
+/// Readings averaged per position, about 0.6s at 50Hz.
+const SAMPLES: i32 = 32;
+/// How far the readings may spread while the board counts as still.
+const MAX_SPREAD: i32 = ONE_G / 20;
+
+struct Position {
+    axis: usize,
+    sign: i32,
+    /// The prompt: an arrow towards the edge to stand the board on, or the
+    /// face to put up.
+    image: [[u8; 5]; 5],
+}
+
+const POSITIONS: [Position; 6] = [
+    Position {
+        axis: 2,
+        sign: 1,
+        // Face up
+        image: [
+            [0, 0, 0, 0, 0],
+            [0, 9, 9, 9, 0],
+            [0, 9, 9, 9, 0],
+            [0, 9, 9, 9, 0],
+            [0, 0, 0, 0, 0],
+        ],
+    },
+    Position {
+        axis: 0,
+        sign: 1,
+        // Right edge down
+        image: [
+            [0, 0, 9, 0, 0],
+            [0, 0, 0, 9, 0],
+            [9, 9, 9, 9, 9],
+            [0, 0, 0, 9, 0],
+            [0, 0, 9, 0, 0],
+        ],
+    },
+    Position {
+        axis: 0,
+        sign: -1,
+        // Left edge down
+        image: [
+            [0, 0, 9, 0, 0],
+            [0, 9, 0, 0, 0],
+            [9, 9, 9, 9, 9],
+            [0, 9, 0, 0, 0],
+            [0, 0, 9, 0, 0],
+        ],
+    },
+    Position {
+        axis: 1,
+        sign: 1,
+        // Top edge down
+        image: [
+            [0, 0, 9, 0, 0],
+            [0, 9, 9, 9, 0],
+            [9, 0, 9, 0, 9],
+            [0, 0, 9, 0, 0],
+            [0, 0, 9, 0, 0],
+        ],
+    },
+    Position {
+        axis: 1,
+        sign: -1,
+        // Bottom edge down
+        image: [
+            [0, 0, 9, 0, 0],
+            [0, 0, 9, 0, 0],
+            [9, 0, 9, 0, 9],
+            [0, 9, 9, 9, 0],
+            [0, 0, 9, 0, 0],
+        ],
+    },
+    Position {
+        axis: 2,
+        sign: -1,
+        // Face down, last as the LEDs can't be seen then
+        image: [
+            [9, 9, 9, 9, 9],
+            [9, 0, 0, 0, 9],
+            [9, 0, 0, 0, 9],
+            [9, 0, 0, 0, 9],
+            [9, 9, 9, 9, 9],
+        ],
+    },
+];
+
+/// Corrections for the accelerometer's readings.
+pub struct Calibration {
+    offset: [i32; 3],
+    scale: [f32; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            offset: [0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl Calibration {
+    /// `accel` corrected, so every axis reads ±1g when it points up or down.
+    pub fn apply(&self, accel: (i32, i32, i32)) -> (i32, i32, i32) {
+        let correct = |axis: usize, value: i32| {
+            ((value - self.offset[axis]) as f32 * self.scale[axis]) as i32
+        };
+        (
+            correct(0, accel.0),
+            correct(1, accel.1),
+            correct(2, accel.2),
+        )
+    }
+}
+
+/// Collects the readings of the six positions.
+#[derive(Default)]
+pub struct Calibrator {
+    /// The position asked for, one past the last once all are captured.
+    position: usize,
+    /// Average reading of the axis pointing up or down, per position.
+    captured: [i32; 6],
+    sum: i32,
+    count: i32,
+    min: i32,
+    max: i32,
+}
+
+impl Calibrator {
+    /// What the LEDs should show.
+    pub fn prompt(&self) -> GreyscaleImage {
+        GreyscaleImage::new(&POSITIONS[self.position].image)
+    }
+
+    /// Takes a reading, and returns the calibration once the last position
+    /// has been captured.
+    pub fn push(&mut self, accel: (i32, i32, i32)) -> Option<Calibration> {
+        let position = &POSITIONS[self.position];
+        let value = [accel.0, accel.1, accel.2][position.axis];
+
+        if value * position.sign < MIN_G {
+            // Not there yet
+            self.count = 0;
+            return None;
+        }
+        if self.count == 0 {
+            self.sum = 0;
+            self.min = value;
+            self.max = value;
+        }
+        self.sum += value;
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.max - self.min > MAX_SPREAD {
+            // Still moving, start over
+            self.count = 0;
+            return None;
+        }
+        if self.count < SAMPLES {
+            return None;
+        }
+
+        self.captured[self.position] = self.sum / SAMPLES;
+        self.count = 0;
+        self.position += 1;
+        if self.position < POSITIONS.len() {
+            return None;
+        }
+        self.position = 0;
+        Some(self.calibration())
+    }
+
+    fn calibration(&self) -> Calibration {
+        let mut calibration = Calibration::default();
+        for axis in 0..3 {
+            let reading = |sign: i32| {
+                let position = POSITIONS
+                    .iter()
+                    .position(|position| position.axis == axis && position.sign == sign)
+                    .unwrap();
+                self.captured[position]
+            };
+            let (up, down) = (reading(1), reading(-1));
+            calibration.offset[axis] = (up + down) / 2;
+            calibration.scale[axis] = (2 * ONE_G) as f32 / (up - down) as f32;
+        }
+        calibration
+    }
+}
@@ -0,0 +1,72 @@
+//! Parsing of the commands the bridge sends over the UART: a line of text
+//! followed by its CRC16 as `*XXXX`, see the bridge's `command` module.
+
+use crate::crc;
+use heapless::String;
+
+/// Longest command line including the checksum.
+const MAX_LINE_LEN: usize = 64;
+
+pub enum Command {
+    /// Take the current orientation as the new zero.
+    Tare,
+    /// Answer right away, for measuring the round trip.
+    Ping,
+    /// Sample and send at this many Hz, one of `RATES`.
+    Rate(u8),
+}
+
+/// Rates the `rate` command accepts.
+pub const RATES: [u8; 4] = [10, 25, 50, 100];
+
+#[derive(Default)]
+pub struct CommandReader {
+    line: String<MAX_LINE_LEN>,
+    /// Set when a line didn't fit, the rest of it is skipped.
+    overflow: bool,
+}
+
+impl CommandReader {
+    /// Feeds one received byte, returning the command once a line is
+    /// complete, or why it was rejected.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Command, &'static str>> {
+        match byte {
+            b'\n' => {
+                let result = if self.overflow {
+                    Err("command too long")
+                } else {
+                    parse(&self.line)
+                };
+                self.line.clear();
+                self.overflow = false;
+                Some(result)
+            }
+            b'\r' => None,
+            _ => {
+                if self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+fn parse(line: &str) -> Result<Command, &'static str> {
+    let (command, checksum) = line.rsplit_once('*').ok_or("missing checksum")?;
+    let checksum = u16::from_str_radix(checksum, 16).map_err(|_| "missing checksum")?;
+    if checksum != crc::crc16(command.as_bytes()) {
+        return Err("bad checksum");
+    }
+
+    let mut words = command.split(' ').filter(|word| !word.is_empty());
+    match (words.next(), words.next(), words.next()) {
+        (Some("tare"), None, None) => Ok(Command::Tare),
+        (Some("ping"), None, None) => Ok(Command::Ping),
+        (Some("rate"), Some(rate), None) => match rate.parse() {
+            Ok(rate) if RATES.contains(&rate) => Ok(Command::Rate(rate)),
+            _ => Err("rate must be 10, 25, 50 or 100"),
+        },
+        _ => Err("unknown command"),
+    }
+}
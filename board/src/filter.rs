@@ -0,0 +1,68 @@
+//! Smoothing of the orientation on the board, so the stream is steady
+//! before it ever reaches the bridge.
+
+/// Seconds the estimate takes to follow about two thirds of a change,
+/// whatever the sample rate.
+pub const TIME_CONSTANT: f32 = 0.08;
+
+/// Complementary filter over the pitch, roll and heading: every new
+/// reading moves the estimate by `1 - alpha` of the difference. The
+/// micro:bit has no gyroscope, so the previous estimate stands in for the
+/// short-term half and the accelerometer and magnetometer readings for
+/// the long-term half.
+pub struct ComplementaryFilter {
+    time_constant: f32,
+    /// Weight of the previous estimate against a new reading, 0.8 at 50Hz.
+    alpha: f32,
+    estimate: Option<(f32, f32, f32)>,
+}
+
+impl ComplementaryFilter {
+    /// A filter for readings taken every `period` seconds.
+    pub fn new(time_constant: f32, period: f32) -> Self {
+        let mut filter = ComplementaryFilter {
+            time_constant,
+            alpha: 0.0,
+            estimate: None,
+        };
+        filter.set_period(period);
+        filter
+    }
+
+    /// Keeps the time constant when the sample rate changes.
+    pub fn set_period(&mut self, period: f32) {
+        self.alpha = self.time_constant / (self.time_constant + period);
+    }
+
+    /// Takes in a reading in degrees and returns the new estimate. The
+    /// heading is blended the short way round, so it doesn't swing through
+    /// 0 when it crosses ±180.
+    pub fn update(&mut self, pitch: f32, roll: f32, heading: f32) -> (f32, f32, f32) {
+        let estimate = match self.estimate {
+            // The first reading has nothing to be blended with
+            None => (pitch, roll, heading),
+            Some((last_pitch, last_roll, last_heading)) => {
+                let gain = 1.0 - self.alpha;
+                (
+                    last_pitch + gain * (pitch - last_pitch),
+                    last_roll + gain * (roll - last_roll),
+                    wrap_degrees(last_heading + gain * wrap_degrees(heading - last_heading)),
+                )
+            }
+        };
+        self.estimate = Some(estimate);
+        estimate
+    }
+}
+
+/// `angle` in degrees moved back into (-180, 180] after adding or
+/// subtracting.
+pub fn wrap_degrees(angle: f32) -> f32 {
+    if angle > 180.0 {
+        angle - 360.0
+    } else if angle <= -180.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
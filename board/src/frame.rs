@@ -0,0 +1,71 @@
+//! Fixed-layout binary frames (feature `binary`), about a quarter of the
+//! size of a JSON line:
+//!
+//! `0xAA 0x55`, a type byte, the payload and the CRC16 of type and
+//! payload, every number little-endian. The payload of
+//!
+//! - `0x01` is the pitch, roll and heading as i16 hundredths of a degree,
+//!   the raw readings `ax`..`mz` as i16 and the u32 sequence number,
+//! - `0x02` the same with the quaternion `qw`..`qz` as i16
+//!   ten-thousandths in place of the angles,
+//! - `0x03` a length byte and a JSON object, for the seldom messages like
+//!   acks, events and the temperature.
+
+use crate::crc;
+use heapless::Vec;
+use micromath::F32Ext;
+
+pub const SYNC: [u8; 2] = [0xAA, 0x55];
+
+const ANGLES: u8 = 0x01;
+const QUATERNION: u8 = 0x02;
+const TEXT: u8 = 0x03;
+
+/// A text frame carrying the longest JSON message the firmware sends, 96
+/// bytes, is the longest frame.
+pub const MAX_FRAME_LEN: usize = SYNC.len() + 2 + 96 + 2;
+
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+
+/// Encodes the orientation fields (the angles or a quaternion), the raw
+/// readings and the sequence number.
+pub fn encode(orientation: &[(&str, f32)], raw: [i32; 6], seq: u32) -> Frame {
+    let (kind, scale) = if cfg!(feature = "quaternion") {
+        (QUATERNION, 10000.0)
+    } else {
+        (ANGLES, 100.0)
+    };
+    let mut frame = Frame::new();
+    frame.extend_from_slice(&SYNC).unwrap();
+    frame.push(kind).unwrap();
+    for (_, value) in orientation.iter() {
+        push_i16(&mut frame, (value * scale).round() as i32);
+    }
+    for value in raw.iter() {
+        push_i16(&mut frame, *value);
+    }
+    frame.extend_from_slice(&seq.to_le_bytes()).unwrap();
+    finish(frame)
+}
+
+/// Encodes a JSON object like `{"ack":"tare"}`.
+pub fn encode_text(json: &str) -> Frame {
+    let mut frame = Frame::new();
+    frame.extend_from_slice(&SYNC).unwrap();
+    frame.push(TEXT).unwrap();
+    frame.push(json.len() as u8).unwrap();
+    frame.extend_from_slice(json.as_bytes()).unwrap();
+    finish(frame)
+}
+
+/// Values beyond the range of an i16 are clamped to it.
+fn push_i16(frame: &mut Frame, value: i32) {
+    let value = value.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+    frame.extend_from_slice(&value.to_le_bytes()).unwrap();
+}
+
+fn finish(mut frame: Frame) -> Frame {
+    let checksum = crc::crc16(&frame[SYNC.len()..]);
+    frame.extend_from_slice(&checksum.to_le_bytes()).unwrap();
+    frame
+}
@@ -0,0 +1,66 @@
+//! The 5×5 LED matrix, showing a level bubble for the board's tilt so the
+//! sensor can be seen working before any host software is attached.
+//!
+//! The matrix is refreshed from the TIMER1 interrupt, so showing an image
+//! never holds up the sampling loop.
+
+use crate::calibration::ONE_G;
+use core::cell::RefCell;
+use cortex_m::interrupt::{free, Mutex};
+use microbit::{
+    display::nonblocking::{Display, GreyscaleImage},
+    gpio::DisplayPins,
+    pac::{self, interrupt},
+};
+use micromath::F32Ext;
+
+/// Tilt in g that moves the bubble by one LED, about 17°.
+const G_PER_LED: f32 = 0.3;
+/// Brightness of the bubble and of the dot marking the level position.
+const BUBBLE: u8 = 9;
+const CENTER: u8 = 2;
+
+static DISPLAY: Mutex<RefCell<Option<Display<pac::TIMER1>>>> = Mutex::new(RefCell::new(None));
+
+/// Takes over the matrix and starts refreshing it.
+pub fn init(timer: pac::TIMER1, pins: DisplayPins) {
+    let display = Display::new(timer, pins);
+    free(|cs| *DISPLAY.borrow(cs).borrow_mut() = Some(display));
+    // Safety: the handler only touches the display, through the mutex
+    unsafe { pac::NVIC::unmask(pac::Interrupt::TIMER1) };
+}
+
+/// Shows `image` until the next call.
+pub fn show(image: &GreyscaleImage) {
+    free(|cs| {
+        if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
+            display.show(image);
+        }
+    });
+}
+
+/// A spirit level: the bubble sits in the middle while the board lies
+/// flat and moves towards the raised edge as it tilts.
+pub fn bubble(accel: (i32, i32, i32)) -> GreyscaleImage {
+    // Offsets in LEDs from the middle
+    let offset = |value: i32| {
+        let leds = (value as f32 / ONE_G as f32 / G_PER_LED).round() as i32;
+        leds.max(-2).min(2)
+    };
+    let column = (2 - offset(accel.0)) as usize;
+    let row = (2 + offset(accel.1)) as usize;
+
+    let mut leds = [[0; 5]; 5];
+    leds[2][2] = CENTER;
+    leds[row][column] = BUBBLE;
+    GreyscaleImage::new(&leds)
+}
+
+#[interrupt]
+fn TIMER1() {
+    free(|cs| {
+        if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
+            display.handle_display_event();
+        }
+    });
+}
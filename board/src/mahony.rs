@@ -0,0 +1,110 @@
+//! On-board orientation as a quaternion (feature `quaternion`), from a
+//! Mahony filter over the accelerometer and magnetometer.
+//!
+//! The micro:bit has no gyroscope, so the filter only has the correction
+//! half: the estimate is turned towards where gravity and the magnetic
+//! field are measured, by an amount proportional to the error. (Madgwick's
+//! filter takes steps of a fixed size instead, which without a gyroscope
+//! keep it jittering around the right answer.)
+
+use micromath::F32Ext;
+
+/// A rotation as `[w, x, y, z]`.
+pub type Quaternion = [f32; 4];
+
+/// Proportional gain (Mahony's 2Kp) in 1/s: the estimate follows a tilt
+/// within about `1 / KP` seconds, and the heading somewhat slower, as only
+/// the horizontal part of the magnetic field turns it.
+const KP: f32 = 5.0;
+
+pub struct Mahony {
+    q: Quaternion,
+}
+
+impl Mahony {
+    pub fn new() -> Self {
+        Mahony {
+            q: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    /// Moves the estimate towards the readings taken `dt` seconds after the
+    /// previous ones and returns it. The units of the readings don't
+    /// matter, only their directions do.
+    pub fn update(&mut self, accel: (i32, i32, i32), mag: (i32, i32, i32), dt: f32) -> Quaternion {
+        let (ax, ay, az) = match normalized(accel) {
+            Some(accel) => accel,
+            // Free fall, nothing to correct with
+            None => return self.q,
+        };
+        let [q0, q1, q2, q3] = self.q;
+        let (q0q0, q0q1, q0q2, q0q3) = (q0 * q0, q0 * q1, q0 * q2, q0 * q3);
+        let (q1q1, q1q2, q1q3) = (q1 * q1, q1 * q2, q1 * q3);
+        let (q2q2, q2q3, q3q3) = (q2 * q2, q2 * q3, q3 * q3);
+
+        // Half the error between the measured and the estimated direction
+        // of gravity, as their cross product
+        let (vx, vy, vz) = (q1q3 - q0q2, q0q1 + q2q3, q0q0 - 0.5 + q3q3);
+        let mut error = (ay * vz - az * vy, az * vx - ax * vz, ax * vy - ay * vx);
+
+        // The same for the magnetic field, whose direction in the earth
+        // frame follows from the current estimate. Only the part of its
+        // error around the vertical is used, so the compass turns the
+        // heading but can't tilt the estimate away from gravity.
+        if let Some((mx, my, mz)) = normalized(mag) {
+            let hx = 2.0 * (mx * (0.5 - q2q2 - q3q3) + my * (q1q2 - q0q3) + mz * (q1q3 + q0q2));
+            let hy = 2.0 * (mx * (q1q2 + q0q3) + my * (0.5 - q1q1 - q3q3) + mz * (q2q3 - q0q1));
+            let bx = (hx * hx + hy * hy).sqrt();
+            let bz = 2.0 * (mx * (q1q3 - q0q2) + my * (q2q3 + q0q1) + mz * (0.5 - q1q1 - q2q2));
+            let wx = bx * (0.5 - q2q2 - q3q3) + bz * (q1q3 - q0q2);
+            let wy = bx * (q1q2 - q0q3) + bz * (q0q1 + q2q3);
+            let wz = bx * (q0q2 + q1q3) + bz * (0.5 - q1q1 - q2q2);
+            let (ex, ey, ez) = (my * wz - mz * wy, mz * wx - mx * wz, mx * wy - my * wx);
+            let vertical = ex * ax + ey * ay + ez * az;
+            error.0 += vertical * ax;
+            error.1 += vertical * ay;
+            error.2 += vertical * az;
+        }
+
+        // Turn by the correction for `dt`
+        let (gx, gy, gz) = (
+            KP * error.0 * 0.5 * dt,
+            KP * error.1 * 0.5 * dt,
+            KP * error.2 * 0.5 * dt,
+        );
+        let q = [
+            q0 - q1 * gx - q2 * gy - q3 * gz,
+            q1 + q0 * gx + q2 * gz - q3 * gy,
+            q2 + q0 * gy - q1 * gz + q3 * gx,
+            q3 + q0 * gz + q1 * gy - q2 * gx,
+        ];
+        let norm = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+        self.q = [q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm];
+        self.q
+    }
+}
+
+/// The inverse of the unit quaternion `q`.
+pub fn conjugate(q: Quaternion) -> Quaternion {
+    [q[0], -q[1], -q[2], -q[3]]
+}
+
+/// The rotation `b` followed by `a`.
+pub fn multiply(a: Quaternion, b: Quaternion) -> Quaternion {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+/// `v` scaled to unit length, or nothing for a zero vector.
+fn normalized(v: (i32, i32, i32)) -> Option<(f32, f32, f32)> {
+    let (x, y, z) = (v.0 as f32, v.1 as f32, v.2 as f32);
+    let norm = (x * x + y * y + z * z).sqrt();
+    if norm == 0.0 {
+        return None;
+    }
+    Some((x / norm, y / norm, z / norm))
+}
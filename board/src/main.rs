@@ -2,28 +2,78 @@
 #![no_std] // Don't link the Rust standard library
 
 use cortex_m_rt::entry;  // Provides our new entry point
+#[cfg(not(feature = "quaternion"))]
 use micromath::F32Ext;   // Math operations for f32 without std
 use panic_rtt_target as _;  // Handles program crashes
 use rtt_target::rtt_init_print;  // Allows debug printing
 
 use microbit::{
+    hal::temp::Temp,
     hal::twim,
     hal::uarte,
-    hal::uarte::{Baudrate, Parity},
+    hal::uarte::{Baudrate, Instance, Parity},
     pac::twim0::frequency::FREQUENCY_A,
 };
 
+mod buttons;
+mod calibration;
+mod command;
+mod crc;
+mod leds;
 mod serial_setup;
+use buttons::Button;
+use calibration::{Calibration, Calibrator};
+use command::{Command, CommandReader};
 use serial_setup::UartePort;
 
-use core::{f32::EPSILON, fmt::Write};
-use lsm303agr::{AccelOutputDataRate, Lsm303agr};
+#[cfg(not(feature = "quaternion"))]
+mod filter;
+#[cfg(not(feature = "quaternion"))]
+use filter::{wrap_degrees, ComplementaryFilter};
+#[cfg(feature = "quaternion")]
+mod mahony;
+#[cfg(feature = "quaternion")]
+use mahony::Mahony;
 
+#[cfg(feature = "cbor")]
+mod wire;
+#[cfg(feature = "binary")]
+mod frame;
+#[cfg(any(feature = "cbor", feature = "binary"))]
+use embedded_hal::blocking::serial::Write as _;
+
+#[cfg(all(feature = "cbor", feature = "binary"))]
+compile_error!("the `cbor` and `binary` wire formats can't be combined");
+
+#[cfg(not(feature = "quaternion"))]
+use core::f32::EPSILON;
+use core::fmt::Write;
+use embedded_hal::serial::Read as _;
+use heapless::String;
+use lsm303agr::{AccelOutputDataRate, Lsm303agr, MagOutputDataRate};
+
+/// Samples per second until a `rate` command changes it.
+const DEFAULT_RATE: u8 = 50;
+/// Seconds between two temperature readings.
+const TEMPERATURE_INTERVAL: f32 = 1.0;
+
+/// The sensor's output data rates for sampling at `rate` Hz. The
+/// magnetometer has no 25Hz mode and runs at 50Hz then.
+fn output_data_rates(rate: u8) -> (AccelOutputDataRate, MagOutputDataRate) {
+    match rate {
+        10 => (AccelOutputDataRate::Hz10, MagOutputDataRate::Hz10),
+        25 => (AccelOutputDataRate::Hz25, MagOutputDataRate::Hz50),
+        100 => (AccelOutputDataRate::Hz100, MagOutputDataRate::Hz100),
+        _ => (AccelOutputDataRate::Hz50, MagOutputDataRate::Hz50),
+    }
+}
+
+#[cfg(not(feature = "quaternion"))]
 fn calculate_rotation(x: i32, y: i32, z: i32) -> (f32, f32) {
-    // Convert raw accelerometer data to g force (assuming ±2g range)
-    let x_g = (x as f32) / 16384.0;
-    let y_g = (y as f32) / 16384.0;
-    let z_g = (z as f32) / 16384.0;
+    // Convert the accelerometer data from mg to g
+    let x_g = (x as f32) / calibration::ONE_G as f32;
+    let y_g = (y as f32) / calibration::ONE_G as f32;
+    let z_g = (z as f32) / calibration::ONE_G as f32;
 
     let pitch = (y_g / ((x_g * x_g + z_g * z_g).sqrt() + EPSILON)).atan();
     let roll = (x_g / ((y_g * y_g + z_g * z_g).sqrt() + EPSILON)).atan();
@@ -34,6 +84,64 @@ fn calculate_rotation(x: i32, y: i32, z: i32) -> (f32, f32) {
     (pitch_deg, roll_deg)
 }
 
+/// Tilt-compensated compass heading in degrees, in (-180, 180].
+#[cfg(not(feature = "quaternion"))]
+fn calculate_heading(accel: (i32, i32, i32), mag: (i32, i32, i32)) -> f32 {
+    let (ax, ay, az) = (accel.0 as f32, accel.1 as f32, accel.2 as f32);
+    let (mx, my, mz) = (mag.0 as f32, mag.1 as f32, mag.2 as f32);
+
+    // Rotate the magnetic field back into the horizontal plane, using the
+    // tilt around the x axis (phi) and y axis (theta)
+    let phi = ay.atan2(az);
+    let theta = (-ax).atan2(ay * phi.sin() + az * phi.cos());
+    let horizontal_x =
+        mx * theta.cos() + my * theta.sin() * phi.sin() + mz * theta.sin() * phi.cos();
+    let horizontal_y = mz * phi.sin() - my * phi.cos();
+
+    horizontal_y.atan2(horizontal_x) * 57.295779513 // 180/pi
+}
+
+/// Sends a JSON line followed by its CRC16 as `*XXXX`, or as a text frame
+/// with the `binary` feature.
+#[cfg(not(feature = "cbor"))]
+fn send_line<T: Instance>(serial: &mut UartePort<T>, line: &str) {
+    #[cfg(feature = "binary")]
+    serial.bwrite_all(&frame::encode_text(line)).unwrap();
+
+    #[cfg(not(feature = "binary"))]
+    {
+        let checksum = crc::crc16(line.as_bytes());
+        write!(serial, "{}*{:04X}\r\n", line, checksum).unwrap();
+    }
+}
+
+/// Sends `{"KEY":"TEXT"}`: the answer to a command, `{"ack":COMMAND}` or
+/// `{"nack":REASON}`, or an event like `{"event":"button_a"}`.
+fn send_text<T: Instance>(serial: &mut UartePort<T>, key: &str, text: &str) {
+    #[cfg(feature = "cbor")]
+    serial.bwrite_all(&wire::encode_text(key, text)).unwrap();
+
+    #[cfg(not(feature = "cbor"))]
+    {
+        let mut line: String<96> = String::new();
+        write!(line, "{{\"{}\":\"{}\"}}", key, text).unwrap();
+        send_line(serial, &line);
+    }
+}
+
+/// Sends `{"KEY":VALUE}`, an occasional reading like `{"temp":23.25}`.
+fn send_number<T: Instance>(serial: &mut UartePort<T>, key: &str, value: f32) {
+    #[cfg(feature = "cbor")]
+    serial.bwrite_all(&wire::encode_number(key, value)).unwrap();
+
+    #[cfg(not(feature = "cbor"))]
+    {
+        let mut line: String<32> = String::new();
+        write!(line, "{{\"{}\":{:.2}}}", key, value).unwrap();
+        send_line(serial, &line);
+    }
+}
+
 #[entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -49,26 +157,166 @@ fn main() -> ! {
         UartePort::new(serial)
     };
 
+    leds::init(board.TIMER1, board.display_pins);
+
     let i2c = { twim::Twim::new(board.TWIM0, board.i2c_internal.into(), FREQUENCY_A::K100) };
 
     let mut sensor = Lsm303agr::new_with_i2c(i2c);
     sensor.init().unwrap();
-    sensor.set_accel_odr(AccelOutputDataRate::Hz50).unwrap();
+    let (accel_odr, mag_odr) = output_data_rates(DEFAULT_RATE);
+    sensor.set_accel_odr(accel_odr).unwrap();
+    sensor.set_mag_odr(mag_odr).unwrap();
 
     let mut sensor = sensor.into_mag_continuous().ok().unwrap();
 
+    // Lets the bridge notice lost messages, wraps around after 2^32
+    let mut seq: u32 = 0;
+    // The latest magnetometer reading, for the heading and sent along for
+    // fusion in the bridge
+    let mut mag = (0, 0, 0);
+
+    // Seconds between two samples, paced by the accelerometer
+    let mut period = 1.0 / DEFAULT_RATE as f32;
+
+    // The die temperature of the nRF, in steps of 0.25°C, for telling
+    // drift in long sessions apart from thermal changes
+    let mut temp = Temp::new(board.TEMP);
+    let mut since_temperature = 0.0;
+
+    #[cfg(not(feature = "quaternion"))]
+    let mut smoothing = ComplementaryFilter::new(filter::TIME_CONSTANT, period);
+    #[cfg(feature = "quaternion")]
+    let mut fusion = Mahony::new();
+
+    let mut buttons = [
+        Button::new(board.buttons.button_a.degrade(), "button_a"),
+        Button::new(board.buttons.button_b.degrade(), "button_b"),
+    ];
+
+    // Button A held at boot calibrates the accelerometer, guided by the LEDs
+    let calibration = if buttons[0].is_held() {
+        let mut calibrator = Calibrator::default();
+        let calibration = loop {
+            leds::show(&calibrator.prompt());
+            while !sensor.accel_status().unwrap().xyz_new_data {}
+            let data = sensor.accel_data().unwrap();
+            if let Some(calibration) = calibrator.push((data.x, data.y, data.z)) {
+                break calibration;
+            }
+        };
+        send_text(&mut serial, "event", "calibrated");
+        calibration
+    } else {
+        Calibration::default()
+    };
+
+    let mut commands = CommandReader::default();
+    // Orientation taken as zero by the last `tare` command, as angles or
+    // as the inverse of the quaternion
+    #[cfg(not(feature = "quaternion"))]
+    let mut offset = (0.0, 0.0, 0.0);
+    #[cfg(feature = "quaternion")]
+    let mut offset = [1.0, 0.0, 0.0, 0.0];
+    let mut tare = false;
+
     loop {
-        // Wait until accelerometer data is ready
-        while !sensor.accel_status().unwrap().xyz_new_data {}
+        // Wait until accelerometer data is ready, handling commands meanwhile
+        while !sensor.accel_status().unwrap().xyz_new_data {
+            if let Ok(byte) = serial.read() {
+                match commands.push(byte) {
+                    Some(Ok(Command::Tare)) => tare = true,
+                    Some(Ok(Command::Ping)) => send_text(&mut serial, "ack", "ping"),
+                    Some(Ok(Command::Rate(rate))) => {
+                        // The loop waits for the accelerometer, so it follows
+                        let (accel_odr, mag_odr) = output_data_rates(rate);
+                        sensor.set_accel_odr(accel_odr).unwrap();
+                        sensor.set_mag_odr(mag_odr).unwrap();
+                        period = 1.0 / rate as f32;
+                        #[cfg(not(feature = "quaternion"))]
+                        smoothing.set_period(period);
+                        send_text(&mut serial, "ack", "rate");
+                    }
+                    Some(Err(reason)) => send_text(&mut serial, "nack", reason),
+                    None => (),
+                }
+            }
+        }
 
         let accel_data = sensor.accel_data().unwrap();
-        let (pitch, roll) = calculate_rotation(accel_data.x, accel_data.y, accel_data.z);
-
-        write!(
-            serial,
-            "{{\"x\":{:.1},\"y\":{:.1},\"z\":0.0}}\r\n",
-            pitch, roll
-        )
-        .unwrap();
+        let accel = calibration.apply((accel_data.x, accel_data.y, accel_data.z));
+        if let Ok(mag_data) = sensor.mag_data() {
+            mag = (mag_data.x, mag_data.y, mag_data.z);
+        }
+
+        #[cfg(not(feature = "quaternion"))]
+        let orientation = {
+            let (pitch, roll) = calculate_rotation(accel.0, accel.1, accel.2);
+            let heading = calculate_heading(accel, mag);
+            let (pitch, roll, heading) = smoothing.update(pitch, roll, heading);
+            if tare {
+                offset = (pitch, roll, heading);
+            }
+            [
+                ("x", pitch - offset.0),
+                ("y", roll - offset.1),
+                ("z", wrap_degrees(heading - offset.2)),
+            ]
+        };
+
+        #[cfg(feature = "quaternion")]
+        let orientation = {
+            let q = fusion.update(accel, mag, period);
+            if tare {
+                offset = mahony::conjugate(q);
+            }
+            let q = mahony::multiply(offset, q);
+            [("qw", q[0]), ("qx", q[1]), ("qy", q[2]), ("qz", q[3])]
+        };
+
+        if tare {
+            tare = false;
+            send_text(&mut serial, "ack", "tare");
+        }
+        let raw = [accel.0, accel.1, accel.2, mag.0, mag.1, mag.2];
+
+        #[cfg(feature = "cbor")]
+        serial.bwrite_all(&wire::encode(&orientation, raw, seq)).unwrap();
+
+        #[cfg(feature = "binary")]
+        serial.bwrite_all(&frame::encode(&orientation, raw, seq)).unwrap();
+
+        #[cfg(not(any(feature = "cbor", feature = "binary")))]
+        {
+            let mut line: String<192> = String::new();
+            // Tenths of a degree, or enough for a quaternion to stay a unit one
+            let decimals = if cfg!(feature = "quaternion") { 4 } else { 1 };
+            line.push('{').unwrap();
+            for (key, value) in orientation.iter() {
+                write!(line, "\"{}\":{:.*},", key, decimals, value).unwrap();
+            }
+            write!(
+                line,
+                "\"ax\":{},\"ay\":{},\"az\":{},\"mx\":{},\"my\":{},\"mz\":{},\"seq\":{}}}",
+                raw[0], raw[1], raw[2], raw[3], raw[4], raw[5], seq
+            )
+            .unwrap();
+            send_line(&mut serial, &line);
+        }
+
+        seq = seq.wrapping_add(1);
+
+        since_temperature += period;
+        if since_temperature >= TEMPERATURE_INTERVAL {
+            since_temperature = 0.0;
+            send_number(&mut serial, "temp", temp.measure().to_num::<f32>());
+        }
+
+        leds::show(&leds::bubble(accel));
+
+        for button in buttons.iter_mut() {
+            if let Some(event) = button.poll() {
+                send_text(&mut serial, "event", event);
+            }
+        }
     }
 }
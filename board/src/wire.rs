@@ -0,0 +1,97 @@
+//! Binary wire format: the orientation as a CBOR map, framed with COBS so
+//! every message ends in the only `0x00` byte on the link.
+
+use heapless::Vec;
+
+/// Four two-letter keys with f32 values (a quaternion), six raw readings
+/// and the u32 sequence number are 90 bytes of CBOR, COBS adds one byte of
+/// overhead plus the delimiter.
+pub const MAX_FRAME_LEN: usize = 96;
+
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+
+/// Keys of the raw accelerometer and magnetometer readings.
+const RAW_KEYS: [&[u8; 2]; 6] = [b"ax", b"ay", b"az", b"mx", b"my", b"mz"];
+
+/// Encodes the orientation fields (the angles or a quaternion), the raw
+/// readings and the sequence number as one map.
+pub fn encode(orientation: &[(&str, f32)], raw: [i32; 6], seq: u32) -> Frame {
+    let mut message: Vec<u8, MAX_FRAME_LEN> = Vec::new();
+    message.push(0xA0 | (orientation.len() + 7) as u8).unwrap(); // map
+
+    for (key, value) in orientation.iter() {
+        message.push(0x60 | key.len() as u8).unwrap(); // short text string
+        message.extend_from_slice(key.as_bytes()).unwrap();
+        message.push(0xFA).unwrap(); // single precision float
+        message.extend_from_slice(&value.to_be_bytes()).unwrap();
+    }
+
+    for (key, value) in RAW_KEYS.iter().zip(raw.iter()) {
+        message.push(0x62).unwrap(); // text string of length 2
+        message.extend_from_slice(&key[..]).unwrap();
+        if *value >= 0 {
+            message.push(0x1A).unwrap(); // 32-bit unsigned integer
+            message.extend_from_slice(&(*value as u32).to_be_bytes()).unwrap();
+        } else {
+            message.push(0x3A).unwrap(); // 32-bit negative integer, -1 - n
+            message.extend_from_slice(&((-1 - *value) as u32).to_be_bytes()).unwrap();
+        }
+    }
+
+    message.extend_from_slice(b"\x63seq").unwrap(); // text string of length 3
+    message.push(0x1A).unwrap(); // 32-bit unsigned integer
+    message.extend_from_slice(&seq.to_be_bytes()).unwrap();
+
+    cobs_encode(&message)
+}
+
+/// Encodes a map with a single text entry, like `{"ack":"tare"}`.
+pub fn encode_text(key: &str, text: &str) -> Frame {
+    let mut message: Vec<u8, MAX_FRAME_LEN> = Vec::new();
+    message.push(0xA1).unwrap(); // map with 1 pair
+    for part in [key, text].iter() {
+        // Text strings shorter than 24 bytes carry their length in the
+        // initial byte, longer ones in the byte after it
+        if part.len() < 24 {
+            message.push(0x60 | part.len() as u8).unwrap();
+        } else {
+            message.push(0x78).unwrap();
+            message.push(part.len() as u8).unwrap();
+        }
+        message.extend_from_slice(part.as_bytes()).unwrap();
+    }
+
+    cobs_encode(&message)
+}
+
+/// Encodes a map with a single number entry, like `{"temp":23.25}`.
+pub fn encode_number(key: &str, value: f32) -> Frame {
+    let mut message: Vec<u8, MAX_FRAME_LEN> = Vec::new();
+    message.push(0xA1).unwrap(); // map with 1 pair
+    message.push(0x60 | key.len() as u8).unwrap(); // short text string
+    message.extend_from_slice(key.as_bytes()).unwrap();
+    message.push(0xFA).unwrap(); // single precision float
+    message.extend_from_slice(&value.to_be_bytes()).unwrap();
+
+    cobs_encode(&message)
+}
+
+fn cobs_encode(data: &[u8]) -> Frame {
+    let mut frame = Frame::new();
+    let mut code_index = 0;
+    frame.push(0).unwrap();
+
+    for &byte in data {
+        if byte == 0 {
+            frame[code_index] = (frame.len() - code_index) as u8;
+            code_index = frame.len();
+            frame.push(0).unwrap();
+        } else {
+            frame.push(byte).unwrap();
+        }
+    }
+
+    frame[code_index] = (frame.len() - code_index) as u8;
+    frame.push(0).unwrap(); // delimiter
+    frame
+}
@@ -0,0 +1,504 @@
+//! Recording to Apache Arrow IPC files (Feather v2), for long captures
+//! analysed with polars (`pl.read_ipc`) or pandas (`pd.read_feather`).
+//!
+//! Every message or marker becomes a row of typed columns:
+//!
+//! | column      | type               |                                       |
+//! |-------------|--------------------|---------------------------------------|
+//! | `timestamp` | timestamp[us, UTC] | when the bridge received it           |
+//! | `pitch`     | float64            | `x`, null for events and markers      |
+//! | `roll`      | float64            | `y`                                   |
+//! | `yaw`       | float64            | `z`                                   |
+//! | `event`     | utf8               | the `event` or `gesture` name, if any |
+//! | `marker`    | utf8               | the label of a marker, if any         |
+//! | `device`    | utf8               | the board, with several of them       |
+//!
+//! Rows are written in record batches as they come, so memory stays bounded
+//! however long the capture, and the file's footer is written when the
+//! bridge shuts down, or when it stops on an error. The metadata is
+//! flatbuffers, encoded by hand like the other formats of the bridge.
+
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Extensions of the files recorded as Arrow instead of JSON.
+const EXTENSIONS: [&str; 3] = ["arrow", "feather", "ipc"];
+
+/// Rows per record batch.
+const BATCH_ROWS: usize = 4096;
+
+const MAGIC: &[u8] = b"ARROW1";
+const CONTINUATION: [u8; 4] = [0xFF; 4];
+
+/// Metadata version V5
+const VERSION: i16 = 4;
+
+/// Message header types
+const SCHEMA: u8 = 1;
+const RECORD_BATCH: u8 = 3;
+
+/// Column types
+const FLOATING_POINT: u8 = 3;
+const UTF8: u8 = 5;
+const TIMESTAMP: u8 = 10;
+
+const DOUBLE: i16 = 2;
+const MICROSECOND: i16 = 2;
+
+/// Whether `path` names a file to record as Arrow.
+pub fn is_arrow(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|arrow| arrow.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// The columns of the rows not written yet.
+#[derive(Default)]
+struct Rows {
+    timestamps: Vec<i64>,
+    angles: [Vec<Option<f64>>; 3],
+    events: Vec<Option<String>>,
+    markers: Vec<Option<String>>,
+    devices: Vec<Option<String>>,
+}
+
+impl Rows {
+    fn push(
+        &mut self,
+        angles: [Option<f64>; 3],
+        event: Option<String>,
+        marker: Option<String>,
+        device: Option<String>,
+    ) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.timestamps.push(now.as_micros() as i64);
+        for (column, angle) in self.angles.iter_mut().zip(angles) {
+            column.push(angle);
+        }
+        self.events.push(event);
+        self.markers.push(marker);
+        self.devices.push(device);
+    }
+
+    fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+}
+
+/// One Arrow block in the file, as listed in the footer.
+struct Block {
+    offset: u64,
+    metadata_length: u32,
+    body_length: u64,
+}
+
+pub struct ArrowRecorder {
+    file: BufWriter<File>,
+    rows: Rows,
+    batches: Vec<Block>,
+    finished: bool,
+}
+
+impl ArrowRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        info!("Recording to {} as Arrow", path.display());
+
+        file.write_all(MAGIC)?;
+        file.write_all(&[0, 0])?;
+        let schema = message(SCHEMA, schema(), 0);
+        write_message(&mut file, &schema, &[])?;
+
+        Ok(Self {
+            file,
+            rows: Rows::default(),
+            batches: Vec::new(),
+            finished: false,
+        })
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.rows.timestamps.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let mut body = Body::default();
+        body.fixed(&rows.timestamps, |timestamp| Some(timestamp.to_le_bytes()));
+        for column in &rows.angles {
+            body.fixed(column, |angle| angle.map(f64::to_le_bytes));
+        }
+        for column in [&rows.events, &rows.markers, &rows.devices] {
+            body.strings(column);
+        }
+
+        let batch = Fb::Table(vec![
+            (0, Fb::I64(rows.len() as i64)),
+            (1, Fb::Structs(body.nodes.len() / 16, body.nodes)),
+            (2, Fb::Structs(body.buffers.len() / 16, body.buffers)),
+        ]);
+        let metadata = message(RECORD_BATCH, batch, body.data.len() as u64);
+
+        let offset = self.file.stream_position()?;
+        let metadata_length = write_message(&mut self.file, &metadata, &body.data)?;
+        self.batches.push(Block {
+            offset,
+            metadata_length,
+            body_length: body.data.len() as u64,
+        });
+        Ok(())
+    }
+
+    /// Writes the last rows, the end of the stream and the footer.
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        self.file.write_all(&CONTINUATION)?;
+        self.file.write_all(&0u32.to_le_bytes())?;
+
+        let mut blocks = Vec::new();
+        for block in &self.batches {
+            blocks.extend_from_slice(&block.offset.to_le_bytes());
+            blocks.extend_from_slice(&block.metadata_length.to_le_bytes());
+            blocks.extend_from_slice(&[0; 4]);
+            blocks.extend_from_slice(&block.body_length.to_le_bytes());
+        }
+        let footer = Fb::Table(vec![
+            (0, Fb::I16(VERSION)),
+            (1, schema()),
+            (3, Fb::Structs(self.batches.len(), blocks)),
+        ]);
+        let footer = footer.finish();
+        self.file.write_all(&footer)?;
+        self.file.write_all(&(footer.len() as u32).to_le_bytes())?;
+        self.file.write_all(MAGIC)?;
+        self.file.flush()?;
+
+        info!(
+            "Recorded {} batch(es) of up to {} rows",
+            self.batches.len(),
+            BATCH_ROWS
+        );
+        Ok(())
+    }
+}
+
+impl Sink for ArrowRecorder {
+    fn name(&self) -> &str {
+        "Recorder"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let angles = ["x", "y", "z"].map(|field| message.get(field).and_then(Value::as_f64));
+        let text = |field| {
+            message
+                .get(field)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let event = text("event").or_else(|| text("gesture"));
+        self.rows.push(angles, event, None, text("device"));
+        if self.rows.len() >= BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.rows
+            .push([None; 3], None, Some(label.to_string()), None);
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.finish()?;
+        self.file.get_ref().sync_all()
+    }
+}
+
+impl Drop for ArrowRecorder {
+    /// Finishes a recording that wasn't closed, e.g. when the bridge stops
+    /// on another sink's error, so the file is still readable.
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            if let Err(e) = self.finish() {
+                warn!("Can't finish the Arrow recording: {}", e);
+            }
+        }
+    }
+}
+
+/// The body of a record batch: the buffers of every column, and the field
+/// nodes and buffer locations describing them.
+#[derive(Default)]
+struct Body {
+    data: Vec<u8>,
+    nodes: Vec<u8>,
+    buffers: Vec<u8>,
+}
+
+impl Body {
+    /// Appends a buffer, padded to 8 bytes as Arrow wants them aligned.
+    fn buffer(&mut self, bytes: &[u8]) {
+        self.buffers
+            .extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        self.buffers
+            .extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        self.data.extend_from_slice(bytes);
+        self.data.resize(self.data.len().next_multiple_of(8), 0);
+    }
+
+    /// Appends the node of a column and its validity bitmap, which is left
+    /// empty when nothing is null.
+    fn validity<T>(&mut self, values: &[Option<T>]) {
+        let nulls = values.iter().filter(|value| value.is_none()).count();
+        self.nodes
+            .extend_from_slice(&(values.len() as u64).to_le_bytes());
+        self.nodes.extend_from_slice(&(nulls as u64).to_le_bytes());
+
+        let mut bitmap = Vec::new();
+        if nulls > 0 {
+            bitmap = vec![0u8; values.len().div_ceil(8)];
+            for (index, value) in values.iter().enumerate() {
+                if value.is_some() {
+                    bitmap[index / 8] |= 1 << (index % 8);
+                }
+            }
+        }
+        self.buffer(&bitmap);
+    }
+
+    /// A column of 8-byte values, zeros where they are null.
+    fn fixed<T>(&mut self, values: &[T], bytes: impl Fn(&T) -> Option<[u8; 8]>) {
+        let values: Vec<Option<[u8; 8]>> = values.iter().map(bytes).collect();
+        self.validity(&values);
+        let data: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.unwrap_or_default())
+            .collect();
+        self.buffer(&data);
+    }
+
+    fn strings(&mut self, values: &[Option<String>]) {
+        self.validity(values);
+        let mut offsets = vec![0u8; 4];
+        let mut data = Vec::new();
+        for value in values {
+            data.extend_from_slice(value.as_deref().unwrap_or_default().as_bytes());
+            offsets.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        }
+        self.buffer(&offsets);
+        self.buffer(&data);
+    }
+}
+
+fn schema() -> Fb {
+    let float = || (FLOATING_POINT, Fb::Table(vec![(0, Fb::I16(DOUBLE))]));
+    let utf8 = || (UTF8, Fb::Table(Vec::new()));
+    let timestamp = (
+        TIMESTAMP,
+        Fb::Table(vec![
+            (0, Fb::I16(MICROSECOND)),
+            (1, Fb::Str("UTC".to_string())),
+        ]),
+    );
+    let columns = [
+        ("timestamp", timestamp),
+        ("pitch", float()),
+        ("roll", float()),
+        ("yaw", float()),
+        ("event", utf8()),
+        ("marker", utf8()),
+        ("device", utf8()),
+    ];
+
+    let fields = columns
+        .into_iter()
+        .map(|(name, (type_type, kind))| {
+            Fb::Table(vec![
+                (0, Fb::Str(name.to_string())),
+                (1, Fb::Bool(name != "timestamp")),
+                (2, Fb::U8(type_type)),
+                (3, kind),
+                (5, Fb::Offsets(Vec::new())),
+            ])
+        })
+        .collect();
+    // Little endian
+    Fb::Table(vec![(0, Fb::I16(0)), (1, Fb::Offsets(fields))])
+}
+
+fn message(header_type: u8, header: Fb, body_length: u64) -> Fb {
+    Fb::Table(vec![
+        (0, Fb::I16(VERSION)),
+        (1, Fb::U8(header_type)),
+        (2, header),
+        (3, Fb::I64(body_length as i64)),
+    ])
+}
+
+/// Writes an encapsulated message and its body, returning the length of
+/// its metadata including the prefix.
+fn write_message(file: &mut impl Write, metadata: &Fb, body: &[u8]) -> io::Result<u32> {
+    let mut metadata = metadata.finish();
+    // The body has to start 8-byte aligned, after the 8-byte prefix
+    metadata.resize(metadata.len().next_multiple_of(8), 0);
+    file.write_all(&CONTINUATION)?;
+    file.write_all(&(metadata.len() as u32).to_le_bytes())?;
+    file.write_all(&metadata)?;
+    file.write_all(body)?;
+    Ok(metadata.len() as u32 + 8)
+}
+
+/// A flatbuffers value, just what the Arrow metadata needs.
+enum Fb {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I64(i64),
+    Str(String),
+    /// Fields of a table by their id, in increasing order.
+    Table(Vec<(u16, Fb)>),
+    /// A vector of tables.
+    Offsets(Vec<Fb>),
+    /// A vector of `count` structs of 8-byte alignment, already encoded.
+    Structs(usize, Vec<u8>),
+}
+
+impl Fb {
+    /// Size of the value inline in a table, offsets for the others.
+    fn inline_size(&self) -> usize {
+        match self {
+            Fb::Bool(_) | Fb::U8(_) => 1,
+            Fb::I16(_) => 2,
+            Fb::I64(_) => 8,
+            _ => 4,
+        }
+    }
+
+    fn inline_bytes(&self) -> Vec<u8> {
+        match self {
+            Fb::Bool(value) => vec![u8::from(*value)],
+            Fb::U8(value) => vec![*value],
+            Fb::I16(value) => value.to_le_bytes().to_vec(),
+            Fb::I64(value) => value.to_le_bytes().to_vec(),
+            // Patched once the value is written
+            _ => vec![0; 4],
+        }
+    }
+
+    fn is_inline(&self) -> bool {
+        matches!(self, Fb::Bool(_) | Fb::U8(_) | Fb::I16(_) | Fb::I64(_))
+    }
+
+    /// The value as the root of a buffer.
+    fn finish(&self) -> Vec<u8> {
+        let mut buffer = vec![0; 4];
+        let root = self.write(&mut buffer);
+        patch(&mut buffer, 0, root);
+        buffer
+    }
+
+    /// Appends the value, with everything it refers to after it as
+    /// flatbuffers offsets only point forward, and returns its position.
+    fn write(&self, buffer: &mut Vec<u8>) -> usize {
+        match self {
+            Fb::Table(fields) => {
+                // Lay the fields out behind the vtable offset, each aligned
+                // to its size (the table itself is 8-byte aligned)
+                let mut size: usize = 4;
+                let mut layout = Vec::new();
+                for (id, value) in fields {
+                    let field_size = value.inline_size();
+                    size = size.next_multiple_of(field_size);
+                    layout.push((*id, size));
+                    size += field_size;
+                }
+
+                let slots = fields.last().map_or(0, |(id, _)| usize::from(*id) + 1);
+                let mut vtable = vec![0u16; 2 + slots];
+                vtable[0] = (4 + 2 * slots) as u16;
+                vtable[1] = size as u16;
+                for (id, offset) in &layout {
+                    vtable[2 + usize::from(*id)] = *offset as u16;
+                }
+                // The table has to follow the vtable 8-byte aligned
+                let vtable_length = vtable.len() * 2;
+                let padding = (8 - (buffer.len() + vtable_length) % 8) % 8;
+                buffer.resize(buffer.len() + padding, 0);
+                let vtable_position = buffer.len();
+                for entry in vtable {
+                    buffer.extend_from_slice(&entry.to_le_bytes());
+                }
+
+                let table = buffer.len();
+                buffer.extend_from_slice(&((table - vtable_position) as i32).to_le_bytes());
+                buffer.resize(table + size, 0);
+                for ((_, value), (_, offset)) in fields.iter().zip(&layout) {
+                    let bytes = value.inline_bytes();
+                    buffer[table + offset..table + offset + bytes.len()].copy_from_slice(&bytes);
+                }
+                for ((_, value), (_, offset)) in fields.iter().zip(&layout) {
+                    if !value.is_inline() {
+                        let position = value.write(buffer);
+                        patch(buffer, table + offset, position);
+                    }
+                }
+                table
+            }
+            Fb::Str(text) => {
+                align(buffer, 4);
+                let position = buffer.len();
+                buffer.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(text.as_bytes());
+                buffer.push(0);
+                position
+            }
+            Fb::Offsets(items) => {
+                align(buffer, 4);
+                let position = buffer.len();
+                buffer.extend_from_slice(&(items.len() as u32).to_le_bytes());
+                buffer.resize(position + 4 + 4 * items.len(), 0);
+                for (index, item) in items.iter().enumerate() {
+                    let item_position = item.write(buffer);
+                    patch(buffer, position + 4 + 4 * index, item_position);
+                }
+                position
+            }
+            Fb::Structs(count, bytes) => {
+                // The structs after the length have to be 8-byte aligned
+                while buffer.len() % 8 != 4 {
+                    buffer.push(0);
+                }
+                let position = buffer.len();
+                buffer.extend_from_slice(&(*count as u32).to_le_bytes());
+                buffer.extend_from_slice(bytes);
+                position
+            }
+            _ => unreachable!("scalars are written inline"),
+        }
+    }
+}
+
+fn align(buffer: &mut Vec<u8>, alignment: usize) {
+    buffer.resize(buffer.len().next_multiple_of(alignment), 0);
+}
+
+/// Points the offset at `at` to `target`.
+fn patch(buffer: &mut [u8], at: usize, target: usize) {
+    buffer[at..at + 4].copy_from_slice(&((target - at) as u32).to_le_bytes());
+}
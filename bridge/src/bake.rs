@@ -0,0 +1,164 @@
+//! Baking recordings into Blender keyframes, so a capture becomes part of
+//! the scene instead of only playing live.
+//!
+//! The result is either a Python script to run in Blender's Text Editor,
+//! which keyframes the object's rotation the same way the live script sets
+//! it, or the keyframes as JSON for importers of one's own:
+//!
+//! ```text
+//! {"fps":24,"object":"Cube","frame_start":1,"frame_end":240,
+//!  "keyframes":[{"frame":1,"x":1.5,"y":-3.0,"z":0.0},...]}
+//! ```
+
+use crate::info;
+use crate::recording;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Frame the keyframes start at, Blender's default start of the timeline.
+const FIRST_FRAME: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BakeFormat {
+    /// A Python script to run in Blender
+    Python,
+    /// The keyframes as JSON
+    Json,
+}
+
+impl BakeFormat {
+    /// The format the file name `output` suggests.
+    fn of(output: &Path) -> Option<Self> {
+        match output.extension()?.to_str()? {
+            "py" => Some(BakeFormat::Python),
+            "json" => Some(BakeFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct BakeOptions {
+    /// Frame rate of the Blender scene
+    #[arg(long, default_value_t = 24, value_parser = clap::value_parser!(u32).range(1..=240))]
+    pub fps: u32,
+
+    /// Object the keyframes are for
+    #[arg(long, default_value = "Cube")]
+    pub object: String,
+
+    /// python or json, by default from the extension of OUTPUT
+    #[arg(long, value_enum)]
+    pub format: Option<BakeFormat>,
+
+    /// Board to bake from a recording of several (its `device` name)
+    #[arg(long)]
+    pub device: Option<String>,
+}
+
+/// Bakes the recording at `path` into keyframes written to `output`.
+pub fn bake(path: &Path, output: &Path, options: &BakeOptions) -> io::Result<()> {
+    let format = options
+        .format
+        .or_else(|| BakeFormat::of(output))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "can't tell the format of {}, name it .py or .json or pass --format",
+                    output.display()
+                ),
+            )
+        })?;
+
+    let frames = recording::frames(
+        path,
+        1.0 / f64::from(options.fps),
+        options.device.as_deref(),
+    )?;
+    let keyframes: Vec<[f64; 3]> = frames
+        .iter()
+        .map(|message| {
+            ["x", "y", "z"].map(|field| {
+                let angle = message.get(field).and_then(Value::as_f64).unwrap_or(0.0);
+                (angle * 1000.0).round() / 1000.0
+            })
+        })
+        .collect();
+    let last_frame = FIRST_FRAME + keyframes.len() as u32 - 1;
+
+    let contents = match format {
+        BakeFormat::Python => python(&keyframes, options, last_frame),
+        BakeFormat::Json => {
+            let keyframes: Vec<Value> = keyframes
+                .iter()
+                .zip(FIRST_FRAME..)
+                .map(|([x, y, z], frame)| json!({ "frame": frame, "x": x, "y": y, "z": z }))
+                .collect();
+            let baked = json!({
+                "fps": options.fps,
+                "object": options.object,
+                "frame_start": FIRST_FRAME,
+                "frame_end": last_frame,
+                "keyframes": keyframes,
+            });
+            format!("{}\n", baked)
+        }
+    };
+    fs::write(output, contents)?;
+
+    info!(
+        "Baked {} keyframe(s) for {} at {} fps to {}",
+        keyframes.len(),
+        options.object,
+        options.fps,
+        output.display()
+    );
+    Ok(())
+}
+
+/// A script keyframing the object's rotation. Like the live script, it
+/// turns the angles into a quaternion to avoid gimbal lock.
+fn python(keyframes: &[[f64; 3]], options: &BakeOptions, last_frame: u32) -> String {
+    let rows: Vec<String> = keyframes
+        .iter()
+        .map(|[x, y, z]| format!("    ({}, {}, {}),", x, y, z))
+        .collect();
+    format!(
+        r#"# Baked by the microblender bridge. Run in Blender's Text Editor.
+import bpy
+import mathutils
+from math import radians
+
+OBJECT = {object}
+FPS = {fps}
+FIRST_FRAME = {first}
+
+# x, y and z in degrees, one row per frame
+ROTATIONS = [
+{rows}
+]
+
+scene = bpy.context.scene
+scene.render.fps = FPS
+scene.render.fps_base = 1
+scene.frame_start = FIRST_FRAME
+scene.frame_end = {last}
+
+obj = bpy.data.objects[OBJECT]
+obj.rotation_mode = 'QUATERNION'
+for frame, (x, y, z) in enumerate(ROTATIONS, start=FIRST_FRAME):
+    rotation = mathutils.Euler((radians(x), radians(y), radians(z)), 'XYZ')
+    obj.rotation_quaternion = rotation.to_quaternion()
+    obj.keyframe_insert(data_path="rotation_quaternion", frame=frame)
+"#,
+        object = Value::from(options.object.as_str()),
+        fps = options.fps,
+        first = FIRST_FRAME,
+        rows = rows.join("\n"),
+        last = last_frame,
+    )
+}
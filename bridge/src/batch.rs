@@ -0,0 +1,118 @@
+//! Coalescing of messages for the TCP sinks, so high-rate streams take one
+//! write per batch instead of one per message.
+
+use crate::sink::{Sink, SinkStats};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How a batch goes over the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BatchFormat {
+    /// The usual JSON lines, written together
+    Lines,
+    /// A single JSON array of the messages per line
+    Array,
+}
+
+/// Batching as configured on the command line: flushed after `size`
+/// messages or once the oldest one waited `interval`, whichever is first.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchConfig {
+    pub size: Option<usize>,
+    pub interval: Option<Duration>,
+    pub format: BatchFormat,
+}
+
+struct BatchedSink {
+    inner: Box<dyn Sink>,
+    config: BatchConfig,
+    pending: Vec<Value>,
+    oldest: Instant,
+}
+
+/// Wraps `sink` so it gets its messages in batches, or leaves it as it is
+/// when batching is off.
+pub fn wrap(sink: Box<dyn Sink>, config: BatchConfig) -> Box<dyn Sink> {
+    if config.size.is_none() && config.interval.is_none() {
+        return sink;
+    }
+    Box::new(BatchedSink {
+        inner: sink,
+        config,
+        pending: Vec::new(),
+        oldest: Instant::now(),
+    })
+}
+
+impl BatchedSink {
+    fn due(&self) -> bool {
+        let full = self
+            .config
+            .size
+            .is_some_and(|size| self.pending.len() >= size);
+        let waited = self
+            .config
+            .interval
+            .is_some_and(|interval| self.oldest.elapsed() >= interval);
+        !self.pending.is_empty() && (full || waited)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.pending);
+        match self.config.format {
+            BatchFormat::Lines => self.inner.send_batch(&batch),
+            BatchFormat::Array => self.inner.send(&Value::Array(batch)),
+        }
+    }
+}
+
+impl Sink for BatchedSink {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        if self.pending.is_empty() {
+            self.oldest = Instant::now();
+        }
+        self.pending.push(message.clone());
+        if self.due() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        self.inner.status()
+    }
+
+    fn stats(&self) -> SinkStats {
+        let stats = self.inner.stats();
+        SinkStats {
+            queued: stats.queued + self.pending.len(),
+            ..stats
+        }
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        // The interval also holds when the messages stop coming
+        if self.due() {
+            self.flush()?;
+        }
+        self.inner.tick()
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.inner.mark(label)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.inner.close()
+    }
+}
@@ -0,0 +1,120 @@
+//! Decoding of the fixed-layout binary frames sent by firmware built with
+//! the `binary` feature: `0xAA 0x55`, a type byte, the payload and the
+//! CRC16 of type and payload, every number little-endian.
+//!
+//! The frames carry no delimiter, so the stream is searched for the sync
+//! bytes; a frame that fails its CRC check is skipped by searching again
+//! from the byte after its sync bytes.
+
+use crate::crc::crc16;
+use serde_json::{json, Map, Value};
+
+pub const SYNC: [u8; 2] = [0xAA, 0x55];
+
+/// Pitch, roll and heading in hundredths of a degree, then the rest of a
+/// sample.
+const ANGLES: u8 = 0x01;
+/// A quaternion in ten-thousandths, then the rest of a sample.
+const QUATERNION: u8 = 0x02;
+/// A length byte and a JSON object.
+const TEXT: u8 = 0x03;
+
+/// The raw readings following the orientation, as i16.
+const RAW_FIELDS: [&str; 6] = ["ax", "ay", "az", "mx", "my", "mz"];
+/// Bytes of the raw readings and the u32 sequence number.
+const SAMPLE_TAIL_LEN: usize = RAW_FIELDS.len() * 2 + 4;
+/// Sync bytes, type byte and CRC16.
+const OVERHEAD: usize = SYNC.len() + 1 + 2;
+
+/// Appends received bytes to `buffer` and returns every complete frame,
+/// decoded, or why it couldn't be. Bytes before the sync bytes are
+/// dropped, the rest of an incomplete frame stays in `buffer`.
+pub fn split_frames(data: &[u8], buffer: &mut Vec<u8>) -> Vec<Result<Value, String>> {
+    buffer.extend_from_slice(data);
+    let mut messages = Vec::new();
+    let mut start = 0;
+    loop {
+        let Some(offset) = buffer[start..]
+            .windows(SYNC.len())
+            .position(|window| window == SYNC)
+        else {
+            // Keep a first sync byte whose second one is still to come
+            let pending = buffer.len() > start && buffer.last() == Some(&SYNC[0]);
+            start = buffer.len() - usize::from(pending);
+            break;
+        };
+        start += offset;
+
+        let frame = &buffer[start..];
+        let len = match frame_len(frame) {
+            Some(Ok(len)) => len,
+            Some(Err(e)) => {
+                messages.push(Err(e));
+                start += 1;
+                continue;
+            }
+            None => break,
+        };
+        if frame.len() < len {
+            break;
+        }
+
+        let body = &frame[SYNC.len()..len - 2];
+        let checksum = u16::from_le_bytes([frame[len - 2], frame[len - 1]]);
+        if crc16(body) == checksum {
+            messages.push(decode(body));
+            start += len;
+        } else {
+            messages.push(Err("binary frame failed its CRC check".to_string()));
+            start += 1;
+        }
+    }
+    buffer.drain(..start);
+    messages
+}
+
+/// The length of the frame starting at `frame`, nothing if too little of
+/// it arrived to tell, or an error for an unknown type.
+fn frame_len(frame: &[u8]) -> Option<Result<usize, String>> {
+    let kind = *frame.get(SYNC.len())?;
+    let payload = match kind {
+        ANGLES => 3 * 2 + SAMPLE_TAIL_LEN,
+        QUATERNION => 4 * 2 + SAMPLE_TAIL_LEN,
+        TEXT => 1 + usize::from(*frame.get(SYNC.len() + 1)?),
+        _ => return Some(Err(format!("unknown binary frame type 0x{:02X}", kind))),
+    };
+    Some(Ok(OVERHEAD + payload))
+}
+
+/// Decodes the type byte and payload of a frame into a message.
+fn decode(body: &[u8]) -> Result<Value, String> {
+    let (kind, payload) = (body[0], &body[1..]);
+    let (fields, scale): (&[&str], f64) = match kind {
+        ANGLES => (&["x", "y", "z"], 100.0),
+        QUATERNION => (&["qw", "qx", "qy", "qz"], 10000.0),
+        _ => {
+            let message: Value = serde_json::from_slice(&payload[1..])
+                .map_err(|e| format!("invalid JSON in binary frame: {}", e))?;
+            if !message.is_object() {
+                return Err("binary frame text is not a JSON object".to_string());
+            }
+            return Ok(message);
+        }
+    };
+
+    let mut values = payload
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]));
+    let mut message = Map::new();
+    for field in fields {
+        let value = f64::from(values.next().unwrap_or_default()) / scale;
+        message.insert(field.to_string(), json!(value));
+    }
+    for field in RAW_FIELDS {
+        message.insert(field.to_string(), json!(values.next().unwrap_or_default()));
+    }
+    let seq = &payload[payload.len() - 4..];
+    let seq = u32::from_le_bytes([seq[0], seq[1], seq[2], seq[3]]);
+    message.insert("seq".to_string(), json!(seq));
+    Ok(Value::Object(message))
+}
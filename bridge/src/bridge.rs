@@ -0,0 +1,880 @@
+//! The forwarding loop: reading the boards, processing each message and
+//! handing it to the sinks.
+
+use crate::bake;
+use crate::bvh;
+use crate::calibrate;
+use crate::cli::{self, Args, Command, ReplayOptions};
+use crate::command::{self, BoardLink};
+use crate::control::{ControlCommand, ControlServer};
+use crate::crc::CrcChecker;
+use crate::daemon;
+use crate::error::BridgeError;
+use crate::framing::Framer;
+#[cfg(feature = "gui")]
+use crate::gui;
+use crate::hotkeys::{Hotkey, Hotkeys};
+use crate::latency;
+use crate::log::{self, Level};
+use crate::manager::ConnectionManager;
+use crate::mdns;
+use crate::monitor::Monitor;
+use crate::parser::{FrameParser, JsonParser};
+use crate::recording::Recording;
+use crate::sanity::Sanity;
+use crate::selftest;
+use crate::sequence::SequenceTracker;
+use crate::serial::{self, LineSettings, WireFormat};
+use crate::service;
+use crate::shutdown;
+use crate::source::{DataSource, SerialSource, SimulatedSource, StreamSource};
+use crate::stats::Stats;
+use crate::systemd::{self, Watchdog};
+use crate::tare::Tare;
+use crate::timestamp::Timestamper;
+use crate::transform::Pipeline;
+use crate::validate::Validator;
+use crate::{debug, error, info, trace, warn};
+use serde_json::{json, Value};
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the monitor is refreshed while no messages arrive.
+const MONITOR_TICK: Duration = Duration::from_millis(100);
+/// How long `--command` waits for the boards to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A complete line read from one of the Microbits.
+pub struct SerialLine {
+    device: String,
+    line: String,
+}
+
+/// What a source hands to the forwarding loop.
+enum SourceEvent {
+    Line(SerialLine),
+    /// The source ended with a failure, e.g. its board was unplugged.
+    Failed {
+        device: String,
+        reason: String,
+    },
+}
+
+/// The stages a message passes through on its way to the sinks.
+pub struct Bridge {
+    crc: CrcChecker,
+    parser: Box<dyn FrameParser>,
+    validator: Option<Validator>,
+    sanity: Sanity,
+    warn_saturation: bool,
+    sequence: SequenceTracker,
+    timestamps: Option<Timestamper>,
+    transforms: Pipeline,
+    connections: ConnectionManager,
+    monitor: Option<Monitor>,
+    stats: Option<Stats>,
+    hotkeys: Option<Hotkeys>,
+    control: Option<ControlServer>,
+    tare: Tare,
+    /// The last orientation forwarded, for the control API.
+    orientation: Option<Value>,
+    paused: bool,
+    markers: u32,
+    started: Instant,
+    received: u64,
+    forwarded: u64,
+    /// `--duration` and `--count`.
+    duration: Option<Duration>,
+    count: Option<u64>,
+    watchdog: Option<Watchdog>,
+}
+
+impl Bridge {
+    /// The stages as `args` set them up. Those of a `recording` take the
+    /// messages as the JSON they were recorded as, which was checked when
+    /// they were received.
+    fn new(args: &Args, link: &BoardLink, recording: bool) -> Result<Self, BridgeError> {
+        let (crc, parser, validator, timestamps) = if recording {
+            let parser: Box<dyn FrameParser> = Box::new(JsonParser);
+            (CrcChecker::new(false), parser, None, None)
+        } else {
+            let validator = if args.strict {
+                Some(Validator::new(args.quarantine.as_deref())?)
+            } else {
+                None
+            };
+            (
+                CrcChecker::new(args.require_crc),
+                args.input_format.parser(&args.input_columns),
+                validator,
+                args.timestamp.map(Timestamper::new),
+            )
+        };
+        Ok(Self {
+            crc,
+            parser,
+            validator,
+            sanity: Sanity::new(args.angle_limit),
+            warn_saturation: args.warn_saturation,
+            sequence: SequenceTracker::default(),
+            timestamps,
+            transforms: Pipeline::from_args(args)?,
+            connections: ConnectionManager::new(args, link)?,
+            monitor: monitor_from_args(args),
+            stats: stats_from_args(args),
+            hotkeys: args.hotkeys.then(Hotkeys::start),
+            control: control_from_args(args)?,
+            tare: Tare::default(),
+            orientation: None,
+            paused: false,
+            markers: 0,
+            started: Instant::now(),
+            received: 0,
+            forwarded: 0,
+            duration: args.duration,
+            count: args.count,
+            watchdog: Watchdog::from_env(),
+        })
+    }
+
+    /// Reports an event on the console, or in the monitor's event list.
+    fn report(&mut self, level: Level, event: String) {
+        match &mut self.monitor {
+            Some(monitor) => monitor.record_event(event),
+            None => log::write(level, None, format_args!("{}", event)),
+        }
+    }
+
+    /// Keeps the monitor up to date while no messages arrive.
+    fn refresh(&mut self) -> Result<(), BridgeError> {
+        match &mut self.monitor {
+            Some(monitor) => {
+                monitor.update_sinks(self.connections.statuses());
+                Ok(monitor.draw()?)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Logs the statistics when they're due.
+    fn report_stats(&mut self) {
+        let sinks = self.connections.stats();
+        if let Some(report) = self.stats.as_mut().and_then(|stats| stats.report(&sinks)) {
+            self.report(Level::Info, report);
+        }
+    }
+
+    /// Pings the systemd watchdog, if the service has one.
+    fn keep_alive(&mut self) {
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.tick();
+        }
+    }
+
+    /// Stops the bridge once `--duration` is over or `--count` messages
+    /// were forwarded.
+    fn check_limits(&mut self) {
+        if shutdown::requested() {
+            return;
+        }
+        if self
+            .duration
+            .is_some_and(|duration| self.started.elapsed() >= duration)
+        {
+            info!("Stopping: the --duration is over");
+            shutdown::request();
+        } else if self.count.is_some_and(|count| self.forwarded >= count) {
+            info!("Stopping: {} message(s) forwarded", self.forwarded);
+            shutdown::request();
+        }
+    }
+
+    fn count_error(&mut self) {
+        if let Some(stats) = &mut self.stats {
+            stats.count_error();
+        }
+    }
+
+    /// Closes the sinks and prints what happened during the session.
+    fn finish(mut self) -> Result<(), BridgeError> {
+        // Give the terminal back before printing anything
+        self.monitor = None;
+        self.hotkeys = None;
+        systemd::notify("STOPPING=1");
+        info!("Shutting down...");
+        self.connections.close();
+
+        info!(
+            "Session summary: {:.1}s, {} message(s) received, {} forwarded",
+            self.started.elapsed().as_secs_f64(),
+            self.received,
+            self.forwarded
+        );
+        let problems = [
+            ("corrupted", self.crc.corrupted()),
+            ("lost", self.sequence.dropped()),
+            (
+                "rejected",
+                self.validator.as_ref().map_or(0, Validator::violations),
+            ),
+            ("rejected as not finite", self.sanity.rejected()),
+            ("value(s) clamped", self.sanity.clamped()),
+        ];
+        for (problem, count) in problems {
+            if count > 0 {
+                info!("  {} {}", count, problem);
+            }
+        }
+        if self.markers > 0 {
+            info!("  {} marker(s) dropped", self.markers);
+        }
+        Ok(())
+    }
+
+    /// Answers the control API's requests since the last call.
+    fn handle_control(&mut self) -> Result<(), BridgeError> {
+        while let Some(request) = self.control.as_ref().and_then(ControlServer::poll) {
+            match self.answer(&request.command) {
+                Ok(answer) => request.reply(answer),
+                Err((status, error)) => request.fail(status, error),
+            }
+        }
+        Ok(())
+    }
+
+    /// The answer to a control request, or the status and error to fail
+    /// it with.
+    fn answer(&mut self, command: &ControlCommand) -> Result<Value, (u16, String)> {
+        let answer = match command {
+            ControlCommand::Status => json!({
+                "paused": self.paused,
+                "received": self.received,
+                "forwarded": self.forwarded,
+                "uptime": self.started.elapsed().as_secs_f64(),
+                "orientation": self.orientation,
+                "recording": self.connections.recording(),
+            }),
+            ControlCommand::Pause | ControlCommand::Resume => {
+                self.paused = matches!(command, ControlCommand::Pause);
+                let state = if self.paused { "paused" } else { "resumed" };
+                self.report(
+                    Level::Info,
+                    format!("Forwarding {} via the control API", state),
+                );
+                json!({ "paused": self.paused })
+            }
+            ControlCommand::Tare => {
+                self.tare.zero();
+                self.report(
+                    Level::Info,
+                    "Orientation zeroed via the control API".to_string(),
+                );
+                json!({ "tared": true })
+            }
+            ControlCommand::Sinks => {
+                let stats = self.connections.stats();
+                let sinks: Vec<Value> = self
+                    .connections
+                    .statuses()
+                    .into_iter()
+                    .zip(stats)
+                    .map(|((name, status), (_, stats))| {
+                        json!({
+                            "name": name,
+                            "status": status,
+                            "queued": stats.queued,
+                            "capacity": stats.capacity,
+                            "dropped": stats.dropped,
+                            "reconnects": stats.reconnects,
+                        })
+                    })
+                    .collect();
+                json!({ "sinks": sinks })
+            }
+            ControlCommand::Filters => json!({ "filters": self.filter_specs() }),
+            ControlCommand::SetFilters(specs) => {
+                if let Some(filters) = self.transforms.filters() {
+                    filters.lock().unwrap().set_specs(specs);
+                }
+                let specs = self.filter_specs();
+                self.report(
+                    Level::Info,
+                    format!("Filters set to [{}] via the control API", specs.join(", ")),
+                );
+                json!({ "filters": specs })
+            }
+            ControlCommand::Record(file) => {
+                if let Err(e) = self.connections.start_recording(file) {
+                    let status = match e.kind() {
+                        io::ErrorKind::AlreadyExists => 409,
+                        _ => 500,
+                    };
+                    return Err((status, format!("can't record to {}: {}", file.display(), e)));
+                }
+                self.report(
+                    Level::Info,
+                    format!("Recording to {} via the control API", file.display()),
+                );
+                json!({ "recording": file })
+            }
+            ControlCommand::StopRecording => match self.connections.stop_recording() {
+                Ok(Some(file)) => {
+                    self.report(
+                        Level::Info,
+                        format!("Recorded to {} via the control API", file.display()),
+                    );
+                    json!({ "recording": null, "file": file })
+                }
+                Ok(None) => return Err((409, "not recording".to_string())),
+                Err(e) => return Err((500, format!("can't finish the recording: {}", e))),
+            },
+        };
+        Ok(answer)
+    }
+
+    fn filter_specs(&self) -> Vec<String> {
+        self.transforms.filters().map_or(Vec::new(), |filters| {
+            filters
+                .lock()
+                .unwrap()
+                .specs()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        })
+    }
+
+    /// Acts on the hotkeys pressed since the last call.
+    fn handle_hotkeys(&mut self) -> Result<(), BridgeError> {
+        while let Some(key) = self.hotkeys.as_ref().and_then(Hotkeys::poll) {
+            match key {
+                Hotkey::Tare => {
+                    self.tare.zero();
+                    self.report(Level::Info, "Orientation zeroed".to_string());
+                }
+                Hotkey::Pause => {
+                    self.paused = !self.paused;
+                    let state = if self.paused { "paused" } else { "resumed" };
+                    self.report(Level::Info, format!("Forwarding {}", state));
+                }
+                Hotkey::Marker => {
+                    self.markers += 1;
+                    let label = format!("marker {}", self.markers);
+                    self.connections.mark(&label)?;
+                    self.report(Level::Info, format!("Dropped {}", label));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn process_json_line(
+    line: &str,
+    device: Option<&str>,
+    bridge: &mut Bridge,
+) -> Result<(), BridgeError> {
+    bridge.received += 1;
+    if let Some(stats) = &mut bridge.stats {
+        stats.count(line.len());
+    }
+    let line = match bridge.crc.check(line) {
+        Ok(payload) => payload,
+        Err(e) => {
+            bridge.count_error();
+            let corrupted = bridge.crc.corrupted();
+            bridge.report(
+                Level::Warn,
+                format!(
+                    "Dropped corrupted frame ({}, {} so far): {}",
+                    e, corrupted, line
+                ),
+            );
+            return Ok(());
+        }
+    };
+    let parsed = bridge.parser.parse(line);
+
+    if let Some(reply) = parsed.as_ref().ok().and_then(command::reply) {
+        match device {
+            Some(device) => bridge.report(Level::Info, format!("Board on {}: {}", device, reply)),
+            None => bridge.report(Level::Info, format!("Board: {}", reply)),
+        }
+        return Ok(());
+    }
+
+    if let Some(validator) = &mut bridge.validator {
+        if let Err(e) = validator.check(line, parsed.as_ref().ok()) {
+            let violations = validator.violations();
+            bridge.count_error();
+            bridge.report(
+                Level::Warn,
+                format!(
+                    "Rejected message ({}, {} schema violations so far): {}",
+                    e, violations, line
+                ),
+            );
+            return Ok(());
+        }
+    }
+
+    match parsed {
+        Ok(mut parsed) => {
+            let lost = bridge.sequence.check(device.unwrap_or_default(), &parsed);
+            if lost > 0 {
+                let dropped = bridge.sequence.dropped();
+                if let Some(monitor) = &mut bridge.monitor {
+                    monitor.set_dropped(dropped);
+                }
+                bridge.report(
+                    Level::Warn,
+                    format!(
+                        "Lost {} message(s) before {} ({} dropped so far)",
+                        lost, line, dropped
+                    ),
+                );
+            }
+            // After the sequence check, so a rejected message isn't reported lost
+            match bridge.sanity.check(&mut parsed) {
+                Ok(saturated) => {
+                    if bridge.warn_saturation && !saturated.is_empty() {
+                        let clamped = bridge.sanity.clamped();
+                        bridge.report(
+                            Level::Warn,
+                            format!(
+                                "Clamped {} ({} value(s) so far): {}",
+                                saturated.join(", "),
+                                clamped,
+                                line
+                            ),
+                        );
+                    }
+                }
+                Err(e) => {
+                    let rejected = bridge.sanity.rejected();
+                    bridge.count_error();
+                    bridge.report(
+                        Level::Warn,
+                        format!("Rejected message ({}, {} so far): {}", e, rejected, line),
+                    );
+                    return Ok(());
+                }
+            }
+            if let (Some(device), Value::Object(fields)) = (device, &mut parsed) {
+                fields.insert("device".to_string(), device.into());
+            }
+            if let Some(timestamps) = &bridge.timestamps {
+                timestamps.apply(&mut parsed);
+            }
+            forward_message(parsed, bridge)?;
+        }
+        Err(e) => {
+            bridge.count_error();
+            bridge.report(
+                Level::Warn,
+                format!("Invalid message received ({}): {}", e, line),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn forward_message(mut message: Value, bridge: &mut Bridge) -> Result<(), BridgeError> {
+    bridge.tare.apply(&mut message);
+    if bridge.paused {
+        return Ok(());
+    }
+
+    for message in bridge.transforms.apply(message) {
+        if shutdown::requested() {
+            break;
+        }
+        bridge.connections.forward_data(&message)?;
+        bridge.forwarded += 1;
+        if bridge.control.is_some() && (message.get("x").is_some() || message.get("qw").is_some()) {
+            bridge.orientation = Some(message.clone());
+        }
+        bridge.check_limits();
+
+        match &mut bridge.monitor {
+            Some(monitor) => {
+                monitor.record_message(&message);
+                bridge.refresh()?;
+            }
+            None => {
+                trace!("Forwarded {}", message);
+                log::status(format_args!("Forwarded: {}", message))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hands the messages of a source to the forwarding loop until either
+/// ends.
+fn read_source(mut source: Box<dyn DataSource>, lines: Sender<SourceEvent>) {
+    while let Some(line) = source.next_line() {
+        let line = SerialLine {
+            device: source.name().to_string(),
+            line,
+        };
+        if lines.send(SourceEvent::Line(line)).is_err() {
+            return;
+        }
+    }
+    if let Some(reason) = source.failure() {
+        let _ = lines.send(SourceEvent::Failed {
+            device: source.name().to_string(),
+            reason,
+        });
+    }
+}
+
+fn run_data_processing(
+    sources: Vec<Box<dyn DataSource>>,
+    args: &Args,
+    tag_devices: bool,
+    link: BoardLink,
+    mut bridge: Bridge,
+) -> Result<(), BridgeError> {
+    let (sender, receiver) = mpsc::channel();
+    for source in sources {
+        let sender = sender.clone();
+        thread::spawn(move || read_source(source, sender));
+    }
+    drop(sender);
+
+    if args.repl {
+        command::spawn_repl(link);
+    }
+
+    shutdown::install();
+    systemd::notify("READY=1");
+    info!("Starting data forwarding...");
+    info!("Press Ctrl+C to exit");
+
+    // Closes the sinks however forwarding ended, so recordings are always
+    // complete, before reporting what stopped it
+    let forwarded = forward_lines(&receiver, tag_devices, &mut bridge);
+    let finished = bridge.finish();
+    forwarded.and(finished)
+}
+
+/// Forwards the sources' lines until shutdown or until they all ended.
+fn forward_lines(
+    receiver: &Receiver<SourceEvent>,
+    tag_devices: bool,
+    bridge: &mut Bridge,
+) -> Result<(), BridgeError> {
+    while !shutdown::requested() {
+        bridge.check_limits();
+        bridge.keep_alive();
+        bridge.handle_hotkeys()?;
+        bridge.handle_control()?;
+        bridge.connections.tick()?;
+        bridge.report_stats();
+        match receiver.recv_timeout(MONITOR_TICK) {
+            Ok(SourceEvent::Line(SerialLine { device, line })) => {
+                let device = tag_devices.then_some(device.as_str());
+                process_json_line(&line, device, bridge)?;
+            }
+            Ok(SourceEvent::Failed { device, reason }) => {
+                return Err(BridgeError::SerialLost {
+                    port: device,
+                    reason,
+                });
+            }
+            Err(RecvTimeoutError::Timeout) => bridge.refresh()?,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Sends `--command`s to the boards and prints their answers.
+fn run_commands(
+    ports: Vec<(String, Box<dyn serialport::SerialPort>)>,
+    commands: &[String],
+    format: WireFormat,
+) -> Result<(), BridgeError> {
+    let link = BoardLink::default();
+    for (path, port) in &ports {
+        link.attach(path, port.as_ref());
+    }
+    for command in commands {
+        match link.send(command) {
+            Ok(boards) => info!("Sent '{}' to {} board(s)", command, boards),
+            Err(e) => error!("Can't send '{}': {}", command, e),
+        }
+    }
+
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    let mut serial_buf: Vec<u8> = vec![0; 1000];
+    for (path, mut port) in ports {
+        let mut framer = Framer::new(format);
+        let mut crc = CrcChecker::new(false);
+        while Instant::now() < deadline {
+            let t = match port.read(serial_buf.as_mut_slice()) {
+                Ok(t) => t,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in framer.push(&serial_buf[..t]).into_iter().flatten() {
+                let reply = crc
+                    .check(&line)
+                    .ok()
+                    .and_then(|line| serde_json::from_str::<Value>(line).ok())
+                    .and_then(|message| command::reply(&message));
+                if let Some(reply) = reply {
+                    info!("{}: {}", path, reply);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends the messages of a recording with their original timing, scaled by
+/// the replay speed.
+fn run_replay(path: &Path, options: &ReplayOptions, mut bridge: Bridge) -> Result<(), BridgeError> {
+    shutdown::install();
+    systemd::notify("READY=1");
+    info!("Replaying {}", path.display());
+
+    // Like forwarding, closes the sinks whether or not the replay failed
+    let replayed = replay(path, options, &mut bridge);
+    let finished = bridge.finish();
+    replayed.and(finished)
+}
+
+/// Replays the recording once or, with --loop, until shutdown.
+fn replay(path: &Path, options: &ReplayOptions, bridge: &mut Bridge) -> Result<(), BridgeError> {
+    loop {
+        let replayed = replay_once(path, options, bridge)?;
+        if shutdown::requested() {
+            return Ok(());
+        }
+        if replayed == 0 {
+            warn!("The recording has no messages in the selected time range");
+            break;
+        }
+        if !options.looping {
+            break;
+        }
+        debug!("Starting over");
+    }
+
+    info!("Replay finished");
+    Ok(())
+}
+
+/// Replays the selected range of the recording once, returning how many
+/// messages were sent.
+fn replay_once(
+    path: &Path,
+    options: &ReplayOptions,
+    bridge: &mut Bridge,
+) -> Result<u64, BridgeError> {
+    let from = Duration::from_secs_f64(options.start);
+    let to = options.end.map(Duration::from_secs_f64);
+    let mut replayed = 0;
+
+    let start = Instant::now();
+    for entry in Recording::open(path)? {
+        let (offset, message) = entry?;
+        if offset < from {
+            continue;
+        }
+        if to.is_some_and(|to| offset > to) {
+            break;
+        }
+
+        let due = start + (offset - from).div_f64(options.speed);
+        while let Some(wait) = due.checked_duration_since(Instant::now()) {
+            bridge.check_limits();
+            bridge.keep_alive();
+            if shutdown::requested() {
+                return Ok(replayed);
+            }
+            thread::sleep(wait.min(MONITOR_TICK));
+            bridge.handle_hotkeys()?;
+            bridge.handle_control()?;
+            bridge.connections.tick()?;
+            bridge.report_stats();
+            bridge.refresh()?;
+        }
+        if shutdown::requested() {
+            return Ok(replayed);
+        }
+        bridge.received += 1;
+        if let Some(stats) = &mut bridge.stats {
+            stats.count(0);
+        }
+        replayed += 1;
+        bridge.keep_alive();
+        forward_message(message, bridge)?;
+    }
+    Ok(replayed)
+}
+
+fn control_from_args(args: &Args) -> io::Result<Option<ControlServer>> {
+    let port = match (args.control_port, args.gui) {
+        (Some(port), _) => port,
+        // The panel is opened on whichever port is free
+        (None, true) => 0,
+        (None, false) => return Ok(None),
+    };
+    let server = ControlServer::bind(&args.control_bind, port, args.control_token.clone())?;
+    #[cfg(feature = "gui")]
+    if args.gui {
+        gui::open(server.local_addr());
+    }
+    Ok(Some(server))
+}
+
+fn monitor_from_args(args: &Args) -> Option<Monitor> {
+    match (args.monitor, args.plot) {
+        (_, true) => Some(Monitor::new().with_plot()),
+        (true, false) => Some(Monitor::new()),
+        (false, false) => None,
+    }
+}
+
+fn stats_from_args(args: &Args) -> Option<Stats> {
+    (args.stats_interval > 0.0).then(|| Stats::new(Duration::from_secs_f64(args.stats_interval)))
+}
+
+/// Runs the bridge with the given options until the serial ports close.
+pub fn run(mut args: Args) -> Result<(), BridgeError> {
+    // The service runs the bridge with options of its own, logging as they say
+    if let Some(Command::Service { action }) = &args.command {
+        return Ok(service::run(action)?);
+    }
+    log::init(args.verbose, args.quiet, args.log_format);
+    cli::apply_command(&mut args);
+    match args.command {
+        Some(Command::ListPorts) => return Ok(serial::list_ports()?),
+        Some(Command::Selftest) => return selftest::run(),
+        Some(Command::Discover { timeout }) => {
+            return Ok(mdns::discover(Duration::from_secs_f64(timeout))?)
+        }
+        Some(Command::Export {
+            ref file,
+            ref output,
+            fps,
+            ref device,
+        }) => return Ok(bvh::export(file, output, fps, device.as_deref())?),
+        Some(Command::Bake {
+            ref file,
+            ref output,
+            ref options,
+        }) => return Ok(bake::bake(file, output, options)?),
+        _ => {}
+    }
+    cli::validate_configuration(&args).map_err(BridgeError::Config)?;
+    let _pid_file = daemon::start(&args)?;
+
+    let link = BoardLink::default();
+    if let Some(Command::Replay { file, options }) = &args.command {
+        let bridge = Bridge::new(&args, &link, true)?;
+        return run_replay(file, options, bridge);
+    }
+
+    let mut paths = if args.simulate.is_some() || args.input.is_some() {
+        Vec::new()
+    } else if args.all_devices {
+        serial::microbit_ports()
+    } else if !args.port.is_empty() {
+        args.port.clone()
+    } else {
+        serial::detect_port().into_iter().collect()
+    };
+    if paths.is_empty() && args.simulate.is_none() && args.input.is_none() {
+        if !args.wait_for_device {
+            return Err(BridgeError::NoDevice);
+        }
+        paths = serial::wait_for_microbits();
+        if !args.all_devices {
+            paths.truncate(1);
+        }
+    }
+
+    let settings = cli::line_settings(&args);
+    let mut ports = Vec::new();
+    for path in paths {
+        let mut port = match serial::open_port(&path, &settings) {
+            Ok(port) => port,
+            Err(e) if args.wait_for_device => {
+                debug!("Can't open {} yet ({})", path, e);
+                serial::wait_for_port(&path, &settings)
+            }
+            Err(e) => {
+                return Err(BridgeError::Serial {
+                    port: path,
+                    source: e.into(),
+                })
+            }
+        };
+        if args.detect_baud {
+            serial::detect_baud(port.as_mut(), &path, args.baud, args.wire_format);
+        }
+        ports.push((path, port));
+    }
+    // The hello names the ports opened, auto-detected ones included
+    args.port = ports.iter().map(|(path, _)| path.clone()).collect();
+    if !args.commands.is_empty() {
+        return run_commands(ports, &args.commands, args.wire_format);
+    }
+    if let Some(Command::Latency { options }) = &args.command {
+        return Ok(latency::run(ports, &args, options)?);
+    }
+    if let Some(Command::Calibrate { options }) = &args.command {
+        return Ok(calibrate::run(ports, &args, options)?);
+    }
+    // With a single board any Microbit plugged back in is the same one
+    let any_microbit = ports.len() == 1;
+    let mut sources: Vec<Box<dyn DataSource>> = Vec::new();
+    for (path, port) in ports {
+        // Reopened at the rate it was detected at, if it was
+        let settings = LineSettings {
+            baud: port.baud_rate().unwrap_or(settings.baud),
+            ..settings
+        };
+        let mut source = SerialSource::new(
+            path,
+            settings,
+            args.wire_format,
+            port,
+            any_microbit,
+            link.clone(),
+        );
+        if args.exit_on_disconnect {
+            source = source.with_exit_on_disconnect();
+        }
+        sources.push(Box::new(source));
+    }
+    if let Some(pattern) = args.simulate {
+        sources.push(Box::new(SimulatedSource::new(pattern)));
+    }
+    if let Some(path) = &args.input {
+        sources.push(Box::new(StreamSource::open(path, args.wire_format)?));
+    }
+    run_sources(&args, sources, link)
+}
+
+/// Forwards the messages of `sources` until they have all ended or the
+/// bridge is stopped, e.g. with sources of one's own.
+pub fn run_sources(
+    args: &Args,
+    sources: Vec<Box<dyn DataSource>>,
+    link: BoardLink,
+) -> Result<(), BridgeError> {
+    let tag_devices = args.all_devices || sources.len() > 1;
+
+    let bridge = Bridge::new(args, &link, false)?;
+
+    run_data_processing(sources, args, tag_devices, link, bridge)
+}
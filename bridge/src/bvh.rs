@@ -0,0 +1,66 @@
+//! Export of recordings as BVH motion capture, with a skeleton of a single
+//! joint turning like the board, for tools that import mocap files rather
+//! than take the live stream.
+//!
+//! BVH wants a constant frame time, so the recording is resampled at
+//! `--fps`, interpolating between the recorded messages. The axes are
+//! mapped to BVH's Y-up frame so importers that convert to Z-up, like
+//! Blender's, end up with the same rotation the live stream gives.
+
+use crate::info;
+use crate::recording;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Length of the bone hanging off the joint, so the motion is visible.
+const BONE_LENGTH: f64 = 10.0;
+
+/// Writes the recording at `path`, of the board `device` or of the first
+/// one recorded, to `output` as BVH with `fps` frames per second.
+pub fn export(path: &Path, output: &Path, fps: f64, device: Option<&str>) -> io::Result<()> {
+    let frame_time = 1.0 / fps;
+    let frames = recording::frames(path, frame_time, device)?;
+    let mut file = BufWriter::new(File::create(output)?);
+    write_header(&mut file, frames.len(), frame_time)?;
+
+    for message in &frames {
+        let angle = |field| message.get(field).and_then(Value::as_f64).unwrap_or(0.0);
+        // Z X Y rotation channels: roll (negated, as BVH's Z points back
+        // where the board's roll axis points forward), pitch and yaw
+        writeln!(
+            file,
+            "{:.4} {:.4} {:.4}",
+            -angle("y"),
+            angle("x"),
+            angle("z")
+        )?;
+    }
+    file.flush()?;
+
+    info!(
+        "Exported {} frame(s) ({:.1}s at {} fps) to {}",
+        frames.len(),
+        frames.len() as f64 * frame_time,
+        fps,
+        output.display()
+    );
+    Ok(())
+}
+
+fn write_header(file: &mut impl Write, frames: usize, frame_time: f64) -> io::Result<()> {
+    writeln!(file, "HIERARCHY")?;
+    writeln!(file, "ROOT microbit")?;
+    writeln!(file, "{{")?;
+    writeln!(file, "\tOFFSET 0.0 0.0 0.0")?;
+    writeln!(file, "\tCHANNELS 3 Zrotation Xrotation Yrotation")?;
+    writeln!(file, "\tEnd Site")?;
+    writeln!(file, "\t{{")?;
+    writeln!(file, "\t\tOFFSET 0.0 {:.1} 0.0", BONE_LENGTH)?;
+    writeln!(file, "\t}}")?;
+    writeln!(file, "}}")?;
+    writeln!(file, "MOTION")?;
+    writeln!(file, "Frames: {}", frames)?;
+    writeln!(file, "Frame Time: {:.6}", frame_time)
+}
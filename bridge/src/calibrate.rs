@@ -0,0 +1,263 @@
+//! The `calibrate` command: zeroes every board in the position it should
+//! read zero in, once it's held still there, and checks that it does.
+//!
+//! A board is sampled until its orientation stays within the tolerance for
+//! the number of samples asked for. Their mean is the offset the board had,
+//! and their spread the noise of its readings. The board is then sent
+//! `tare` and, once it acknowledged that, sampled again the same way.
+
+use crate::cli::{self, Args};
+use crate::command;
+use crate::crc::CrcChecker;
+use crate::framing::Framer;
+use crate::units;
+use crate::{info, warn};
+use serde_json::{json, Value};
+use serialport::{ClearBuffer, SerialPort};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How long the board has to acknowledge the `tare`.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(1);
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+
+#[derive(clap::Args, Clone, Copy, Debug)]
+pub struct CalibrateOptions {
+    /// Samples the board has to be held still for, about 50 a second
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(2..))]
+    pub samples: u32,
+
+    /// Degrees the orientation may wander while the board counts as still
+    #[arg(long, value_name = "DEGREES", default_value_t = 1.0, value_parser = parse_tolerance)]
+    pub tolerance: f64,
+
+    /// How long to wait for the board to be held still before giving up
+    #[arg(long, value_name = "TIME", default_value = "30s", value_parser = cli::parse_duration)]
+    pub give_up_after: Duration,
+}
+
+fn parse_tolerance(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(tolerance) if tolerance.is_finite() && tolerance > 0.0 => Ok(tolerance),
+        _ => Err(format!(
+            "expected a positive number of degrees, got '{}'",
+            value
+        )),
+    }
+}
+
+/// The orientation of a board held still.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// Mean of the samples, in degrees.
+    pub mean: [f64; 3],
+    /// Standard deviation of the samples, in degrees.
+    pub noise: [f64; 3],
+}
+
+/// Collects samples while the board is held still.
+pub struct Hold {
+    samples: usize,
+    tolerance: f64,
+    /// The first sample, which the others are measured from so the yaw
+    /// can wrap around between them.
+    reference: [f64; 3],
+    deltas: Vec<[f64; 3]>,
+}
+
+impl Hold {
+    pub fn new(samples: usize, tolerance: f64) -> Self {
+        Self {
+            samples,
+            tolerance,
+            reference: [0.0; 3],
+            deltas: Vec::with_capacity(samples),
+        }
+    }
+
+    /// Adds a sample, starting over from it when the board moved further
+    /// than the tolerance. Returns the reading once enough samples in a row
+    /// were still.
+    pub fn push(&mut self, angles: [f64; 3]) -> Option<Reading> {
+        if self.deltas.is_empty() {
+            self.reference = angles;
+        }
+        let delta: [f64; 3] =
+            std::array::from_fn(|axis| units::wrap_degrees(angles[axis] - self.reference[axis]));
+        let moved = self
+            .deltas
+            .iter()
+            .any(|other| (0..3).any(|axis| (delta[axis] - other[axis]).abs() > self.tolerance));
+        if moved {
+            self.deltas.clear();
+            self.reference = angles;
+            self.deltas.push([0.0; 3]);
+            return None;
+        }
+        self.deltas.push(delta);
+        if self.deltas.len() < self.samples {
+            return None;
+        }
+
+        let count = self.deltas.len() as f64;
+        let average: [f64; 3] =
+            std::array::from_fn(|axis| self.deltas.iter().map(|d| d[axis]).sum::<f64>() / count);
+        let noise = std::array::from_fn(|axis| {
+            let variance = self
+                .deltas
+                .iter()
+                .map(|d| (d[axis] - average[axis]).powi(2))
+                .sum::<f64>()
+                / count;
+            variance.sqrt()
+        });
+        let mean =
+            std::array::from_fn(|axis| units::wrap_degrees(self.reference[axis] + average[axis]));
+        self.deltas.clear();
+        Some(Reading { mean, noise })
+    }
+}
+
+/// Calibrates every board in turn.
+pub fn run(
+    ports: Vec<(String, Box<dyn SerialPort>)>,
+    args: &Args,
+    options: &CalibrateOptions,
+) -> io::Result<()> {
+    for (path, mut port) in ports {
+        let mut board = Board {
+            port: port.as_mut(),
+            framer: Framer::new(args.wire_format),
+            crc: CrcChecker::new(args.require_crc),
+            buffer: vec![0; 1000],
+        };
+        calibrate(&path, &mut board, options)?;
+    }
+    Ok(())
+}
+
+fn calibrate(path: &str, board: &mut Board, options: &CalibrateOptions) -> io::Result<()> {
+    board.port.clear(ClearBuffer::Input)?;
+    info!(
+        "Hold the board on {} still in the position that should read zero",
+        path
+    );
+    let before = board.hold_still(options)?;
+    println!("{}: read {}", path, describe(&before));
+
+    board.tare()?;
+    info!("Zeroed the board on {}, keep holding it still", path);
+    let after = board.hold_still(options)?;
+    println!("{}: now reads {}", path, describe(&after));
+    if after
+        .mean
+        .iter()
+        .any(|angle| angle.abs() > options.tolerance)
+    {
+        warn!(
+            "The board on {} still isn't within {}° of zero, try again",
+            path, options.tolerance
+        );
+    }
+    Ok(())
+}
+
+fn describe(reading: &Reading) -> String {
+    let [x, y, z] = reading.mean;
+    let noise = reading.noise.iter().copied().fold(0.0, f64::max);
+    format!(
+        "x {:.2}°, y {:.2}°, z {:.2}°, noise ±{:.2}°",
+        x, y, z, noise
+    )
+}
+
+/// A board's serial port, read message by message.
+struct Board<'a> {
+    port: &'a mut dyn SerialPort,
+    framer: Framer,
+    crc: CrcChecker,
+    buffer: Vec<u8>,
+}
+
+impl Board<'_> {
+    /// The messages of the next read, if any arrived.
+    fn read(&mut self) -> io::Result<Vec<Value>> {
+        let count = match self.port.read(&mut self.buffer) {
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let lines = self.framer.push(&self.buffer[..count]);
+        Ok(lines
+            .into_iter()
+            .flatten()
+            .filter_map(|line| {
+                let line = self.crc.check(&line).ok()?;
+                serde_json::from_str(line).ok()
+            })
+            .collect())
+    }
+
+    /// Waits for the board to be held still.
+    fn hold_still(&mut self, options: &CalibrateOptions) -> io::Result<Reading> {
+        let mut hold = Hold::new(options.samples as usize, options.tolerance);
+        let deadline = Instant::now() + options.give_up_after;
+        while Instant::now() < deadline {
+            for message in self.read()? {
+                if message.get("qw").is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the board sends quaternions, calibrating needs angles",
+                    ));
+                }
+                let Some(angles) = angles(&message) else {
+                    continue;
+                };
+                if let Some(reading) = hold.push(angles) {
+                    return Ok(reading);
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "the board wasn't held still for {:?}",
+                options.give_up_after
+            ),
+        ))
+    }
+
+    /// Sends `tare` and waits for the board to acknowledge it.
+    fn tare(&mut self) -> io::Result<()> {
+        let tare = command::frame("tare").map_err(io::Error::other)?;
+        self.port.write_all(&tare)?;
+        self.port.flush()?;
+        let sent = Instant::now();
+        while sent.elapsed() < ANSWER_TIMEOUT {
+            for message in self.read()? {
+                if message.get("ack") == Some(&json!("tare")) {
+                    return Ok(());
+                }
+                if let Some(reason) = message.get("nack") {
+                    return Err(io::Error::other(format!(
+                        "the board refused to zero: {}",
+                        reason
+                    )));
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the board didn't acknowledge the tare, is its firmware up to date?",
+        ))
+    }
+}
+
+fn angles(message: &Value) -> Option<[f64; 3]> {
+    let mut angles = [0.0; 3];
+    for (angle, axis) in angles.iter_mut().zip(AXES) {
+        *angle = message.get(axis)?.as_f64()?;
+    }
+    Some(angles)
+}
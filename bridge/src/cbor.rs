@@ -0,0 +1,218 @@
+//! Decoding of the CBOR (RFC 8949) messages sent by the board's binary
+//! wire format into JSON values for the sinks, and encoding of messages as
+//! CBOR for `--encoding cbor`.
+
+use serde_json::{Map, Number, Value};
+
+/// Nesting limit, a corrupted frame must not be able to overflow the stack.
+const MAX_DEPTH: usize = 16;
+
+pub fn decode(data: &[u8]) -> Result<Value, String> {
+    let mut decoder = Decoder { data, position: 0 };
+    let value = decoder.value(0)?;
+    if decoder.position != data.len() {
+        return Err("trailing bytes after CBOR value".to_string());
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| "truncated CBOR value".to_string())?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    /// Reads the argument following an initial byte's additional info.
+    fn argument(&mut self, info: u8) -> Result<u64, String> {
+        let bytes = match info {
+            0..=23 => return Ok(u64::from(info)),
+            24 => self.take(1)?,
+            25 => self.take(2)?,
+            26 => self.take(4)?,
+            27 => self.take(8)?,
+            _ => return Err(format!("unsupported CBOR additional info {}", info)),
+        };
+        Ok(bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+    }
+
+    fn length(&mut self, info: u8) -> Result<usize, String> {
+        let length = self.argument(info)?;
+        // Every item takes at least one byte, which bounds sane lengths
+        if length > (self.data.len() - self.position) as u64 {
+            return Err("CBOR length exceeds frame".to_string());
+        }
+        Ok(length as usize)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("CBOR value nested too deeply".to_string());
+        }
+
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1F);
+
+        match major {
+            0 => Ok(self.argument(info)?.into()),
+            1 => {
+                let value = -1 - i128::from(self.argument(info)?);
+                i64::try_from(value)
+                    .map(Value::from)
+                    .map_err(|_| "CBOR integer out of range".to_string())
+            }
+            2 => {
+                let length = self.length(info)?;
+                let bytes = self.take(length)?;
+                Ok(bytes.iter().map(|byte| Value::from(*byte)).collect())
+            }
+            3 => {
+                let length = self.length(info)?;
+                let text = self.take(length)?;
+                String::from_utf8(text.to_vec())
+                    .map(Value::String)
+                    .map_err(|_| "invalid UTF-8 in CBOR text".to_string())
+            }
+            4 => {
+                let length = self.length(info)?;
+                (0..length).map(|_| self.value(depth + 1)).collect()
+            }
+            5 => {
+                let length = self.length(info)?;
+                let mut map = Map::new();
+                for _ in 0..length {
+                    let key = match self.value(depth + 1)? {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    map.insert(key, self.value(depth + 1)?);
+                }
+                Ok(Value::Object(map))
+            }
+            // Tags carry no meaning for the sinks, use the tagged value
+            6 => {
+                self.argument(info)?;
+                self.value(depth + 1)
+            }
+            _ => self.simple(info),
+        }
+    }
+
+    fn simple(&mut self, info: u8) -> Result<Value, String> {
+        let float = match info {
+            20 => return Ok(Value::Bool(false)),
+            21 => return Ok(Value::Bool(true)),
+            22 | 23 => return Ok(Value::Null),
+            25 => {
+                let bytes = self.take(2)?;
+                half_to_f64(u16::from_be_bytes([bytes[0], bytes[1]]))
+            }
+            26 => {
+                let bytes = self.take(4)?;
+                let float = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                // Widen via the shortest representation, so 0.1f32 stays 0.1
+                // instead of becoming 0.10000000149011612
+                float.to_string().parse().unwrap_or(f64::NAN)
+            }
+            27 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                f64::from_be_bytes(bytes)
+            }
+            _ => return Err(format!("unsupported CBOR simple value {}", info)),
+        };
+        // JSON has no NaN or infinity
+        Ok(Number::from_f64(float).map_or(Value::Null, Value::Number))
+    }
+}
+
+/// Encodes a message with the shortest heads, and fractional numbers as
+/// 64-bit floats so none of their precision is lost.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    put_value(&mut buffer, value);
+    buffer
+}
+
+fn put_value(buffer: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buffer.push(0xF6),
+        Value::Bool(false) => buffer.push(0xF4),
+        Value::Bool(true) => buffer.push(0xF5),
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                put_head(buffer, 0, value);
+            } else if let Some(value) = number.as_i64() {
+                put_head(buffer, 1, (-1 - value) as u64);
+            } else {
+                buffer.push(0xFB);
+                buffer.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(text) => {
+            put_head(buffer, 3, text.len() as u64);
+            buffer.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            put_head(buffer, 4, items.len() as u64);
+            for item in items {
+                put_value(buffer, item);
+            }
+        }
+        Value::Object(fields) => {
+            put_head(buffer, 5, fields.len() as u64);
+            for (name, field) in fields {
+                put_head(buffer, 3, name.len() as u64);
+                buffer.extend_from_slice(name.as_bytes());
+                put_value(buffer, field);
+            }
+        }
+    }
+}
+
+/// The initial byte of a `major` type and its argument.
+fn put_head(buffer: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    if argument < 24 {
+        buffer.push(major | argument as u8);
+    } else if let Ok(argument) = u8::try_from(argument) {
+        buffer.extend_from_slice(&[major | 24, argument]);
+    } else if let Ok(argument) = u16::try_from(argument) {
+        buffer.push(major | 25);
+        buffer.extend_from_slice(&argument.to_be_bytes());
+    } else if let Ok(argument) = u32::try_from(argument) {
+        buffer.push(major | 26);
+        buffer.extend_from_slice(&argument.to_be_bytes());
+    } else {
+        buffer.push(major | 27);
+        buffer.extend_from_slice(&argument.to_be_bytes());
+    }
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = i32::from((half >> 10) & 0x1F);
+    let mantissa = f64::from(half & 0x3FF);
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    if half & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
@@ -0,0 +1,87 @@
+//! `--send-on-change`: drops samples that match the last one forwarded, so
+//! a board lying still costs next to no traffic. A sample still goes out
+//! every `--change-keepalive`, so consumers can tell a still board from a
+//! lost one. Messages without an orientation, like events, always pass.
+
+use crate::transform::Transform;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Fields of which a sample has at least one: the angles, or the
+/// quaternion of firmware built with the `quaternion` feature.
+const ORIENTATION_FIELDS: [&str; 4] = ["x", "y", "z", "qw"];
+/// Fields that differ from sample to sample whether or not the board moved.
+const IGNORED_FIELDS: [&str; 3] = ["seq", "t_mono", "t_wall"];
+
+struct Forwarded {
+    fields: Map<String, Value>,
+    at: Instant,
+}
+
+pub struct ChangeFilter {
+    /// How far a number may move and still count as unchanged.
+    epsilon: f64,
+    keepalive: Duration,
+    /// The last sample forwarded per device.
+    last: HashMap<String, Forwarded>,
+}
+
+impl ChangeFilter {
+    pub fn new(epsilon: f64, keepalive: Duration) -> Self {
+        Self {
+            epsilon,
+            keepalive,
+            last: HashMap::new(),
+        }
+    }
+
+    fn unchanged(&self, previous: &Map<String, Value>, fields: &Map<String, Value>) -> bool {
+        let compared = |(name, _): &(&String, &Value)| !IGNORED_FIELDS.contains(&name.as_str());
+        let count = |fields: &Map<String, Value>| fields.iter().filter(compared).count();
+        count(previous) == count(fields)
+            && fields.iter().filter(compared).all(|(name, value)| {
+                match (previous.get(name), value) {
+                    (Some(Value::Number(before)), Value::Number(now)) => {
+                        match (before.as_f64(), now.as_f64()) {
+                            (Some(before), Some(now)) => (now - before).abs() <= self.epsilon,
+                            _ => before == now,
+                        }
+                    }
+                    (before, now) => before == Some(now),
+                }
+            })
+    }
+}
+
+impl Transform for ChangeFilter {
+    fn transform(&mut self, message: Value) -> Vec<Value> {
+        let Value::Object(fields) = &message else {
+            return vec![message];
+        };
+        if !ORIENTATION_FIELDS
+            .iter()
+            .any(|field| fields.contains_key(*field))
+        {
+            return vec![message];
+        }
+        let device = fields
+            .get("device")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        if let Some(previous) = self.last.get(device) {
+            if previous.at.elapsed() < self.keepalive && self.unchanged(&previous.fields, fields) {
+                return Vec::new();
+            }
+        }
+        self.last.insert(
+            device.to_string(),
+            Forwarded {
+                fields: fields.clone(),
+                at: Instant::now(),
+            },
+        );
+        vec![message]
+    }
+}
@@ -0,0 +1,1057 @@
+//! Command line options of the bridge.
+
+use crate::bake::BakeOptions;
+use crate::batch::BatchFormat;
+use crate::calibrate::CalibrateOptions;
+use crate::command;
+use crate::connection::{BackoffSpec, ReconnectPolicy};
+use crate::encoding::EncodingSpec;
+use crate::events::EventRule;
+use crate::failover::FailoverSpec;
+use crate::filter::FilterSpec;
+use crate::format::Format;
+use crate::latency::LatencyOptions;
+use crate::log::LogFormat;
+use crate::parser::{self, InputFormat};
+use crate::queue::{OverflowPolicy, QueueSpec};
+use crate::rate::{RateMode, RateSpec};
+use crate::remap::AxisMap;
+use crate::sanity;
+use crate::serial::{FlowControl, LineSettings, Parity, Signal, WireFormat};
+use crate::simulator::Pattern;
+use crate::sink::SinkSpec;
+use crate::subscribe::Subscription;
+use crate::timestamp::TimestampMode;
+use crate::units::UnitSpec;
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(mut_args = shared)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Serial port of a Microbit (repeatable), detected by its USB ID when omitted
+    #[arg(
+        long,
+        env = "MICROBLENDER_PORT",
+        value_delimiter = ',',
+        help_heading = "Input"
+    )]
+    pub port: Vec<String>,
+
+    /// Read from every connected Microbit
+    #[arg(long, conflicts_with = "port", env = "MICROBLENDER_ALL_DEVICES", value_parser = BoolishValueParser::new(), help_heading = "Input")]
+    pub all_devices: bool,
+
+    /// Wait for the Microbit to be plugged in instead of exiting when it
+    /// isn't connected at startup
+    #[arg(long, env = "MICROBLENDER_WAIT_FOR_DEVICE", value_parser = BoolishValueParser::new(), help_heading = "Input")]
+    pub wait_for_device: bool,
+
+    /// Stop with exit status 4 when a board is unplugged, instead of
+    /// waiting for it to come back, so a service manager can react
+    #[arg(long, help_heading = "Input")]
+    pub exit_on_disconnect: bool,
+
+    /// Forward synthetic data instead of reading a Microbit
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        num_args = 0..=1,
+        default_missing_value = "sweep",
+        conflicts_with_all = ["port", "all_devices", "commands"],
+        help_heading = "Input"
+    )]
+    pub simulate: Option<Pattern>,
+
+    /// Read the board's output from a file, or from stdin with `-`, instead
+    /// of a serial port, e.g. a capture of the port or another program's
+    /// output. The bridge stops at its end
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["port", "all_devices", "commands", "simulate", "detect_baud"],
+        help_heading = "Input"
+    )]
+    pub input: Option<PathBuf>,
+
+    /// Drop lines without a `*XXXX` CRC16 suffix instead of accepting them
+    /// unchecked (lines with a checksum are always verified)
+    #[arg(long, env = "MICROBLENDER_REQUIRE_CRC", value_parser = BoolishValueParser::new(), help_heading = "Input")]
+    pub require_crc: bool,
+
+    /// Format of the board's lines, for firmware that doesn't send JSON
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = InputFormat::Json, help_heading = "Input")]
+    pub input_format: InputFormat,
+
+    /// Fields of the values on a line with --input-format csv, in order
+    #[arg(
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        default_value = parser::DEFAULT_COLUMNS,
+        help_heading = "Input"
+    )]
+    pub input_columns: Vec<String>,
+
+    /// Send a command (e.g. "tare") to the board and exit (repeatable)
+    #[arg(
+        long = "command",
+        value_name = "COMMAND",
+        help_heading = "Board commands"
+    )]
+    pub commands: Vec<String>,
+
+    /// Read commands for the board from stdin while forwarding
+    #[arg(long, conflicts_with = "monitor", help_heading = "Board commands")]
+    pub repl: bool,
+
+    /// Relay JSON commands like {"cmd":"tare"} that TCP services (e.g. the
+    /// Blender add-on) send back to the board
+    #[arg(long, help_heading = "Board commands")]
+    pub relay_commands: bool,
+
+    /// Log messages and bytes per second, the error rate and the state of
+    /// every sink's queue this often; 0 turns it off
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0, value_parser = parse_seconds, help_heading = "Session")]
+    pub stats_interval: f64,
+
+    /// Don't greet TCP services and WebSocket clients with a hello naming
+    /// the protocol version, for consumers that only expect data
+    #[arg(long, help_heading = "Session")]
+    pub no_hello: bool,
+
+    /// Only send data to TCP services that acknowledge the hello, to fail
+    /// early when a consumer rejects this bridge's version
+    #[arg(long, conflicts_with = "no_hello", help_heading = "Session")]
+    pub require_ack: bool,
+
+    /// Act on single keys typed while running: z zeroes the orientation,
+    /// p pauses forwarding, m drops a marker into recordings
+    #[arg(long, conflicts_with = "repl", help_heading = "Session")]
+    pub hotkeys: bool,
+
+    /// Serve the HTTP control API (pause, resume, tare, sinks, filters) on
+    /// this port
+    #[arg(long, value_name = "PORT", help_heading = "Session")]
+    pub control_port: Option<u16>,
+
+    /// Open a control panel in the browser, showing the orientation and the
+    /// sinks, with filter sliders and tare and record buttons, served by
+    /// the control API (needs a bridge built with --features gui)
+    #[arg(long, help_heading = "Session")]
+    pub gui: bool,
+
+    /// Address the control API binds to
+    #[arg(long, default_value = "127.0.0.1", help_heading = "Session")]
+    pub control_bind: String,
+
+    /// Only accept control requests with `Authorization: Bearer TOKEN`
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        requires = "control_port",
+        env = "MICROBLENDER_CONTROL_TOKEN",
+        hide_env_values = true,
+        help_heading = "Session"
+    )]
+    pub control_token: Option<String>,
+
+    /// Stop after this long, e.g. 30s, 5m or 1h
+    #[arg(long, value_name = "TIME", value_parser = parse_duration, help_heading = "Session")]
+    pub duration: Option<Duration>,
+
+    /// Stop after forwarding this many messages
+    #[arg(long, value_name = "MESSAGES", value_parser = clap::value_parser!(u64).range(1..), help_heading = "Session")]
+    pub count: Option<u64>,
+
+    /// Only forward messages with finite numeric x/y/z or qw/qx/qy/qz fields
+    #[arg(long, help_heading = "Validation")]
+    pub strict: bool,
+
+    /// Write messages rejected by --strict to this file
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "strict",
+        help_heading = "Validation"
+    )]
+    pub quarantine: Option<PathBuf>,
+
+    /// Clamp the x/y/z angles to this many degrees either way
+    #[arg(long, value_name = "DEGREES", default_value_t = sanity::DEFAULT_ANGLE_LIMIT, value_parser = parse_angle_limit, help_heading = "Validation")]
+    pub angle_limit: f64,
+
+    /// Warn whenever a value is clamped to its limit
+    #[arg(long, help_heading = "Validation")]
+    pub warn_saturation: bool,
+
+    /// Add the host's receive time to every message
+    #[arg(long, value_enum, value_name = "CLOCK", help_heading = "Processing")]
+    pub timestamp: Option<TimestampMode>,
+
+    /// Show a live dashboard instead of printing every message
+    #[arg(long, help_heading = "Console and logging")]
+    pub monitor: bool,
+
+    /// Show rolling charts of pitch, roll and yaw in the monitor instead of
+    /// gauges, e.g. to tune filters without Blender
+    #[arg(long, help_heading = "Console and logging")]
+    pub plot: bool,
+
+    /// Log more detail (-v for debug, -vv for every forwarded message)
+    #[arg(short, long, action = ArgAction::Count, help_heading = "Console and logging")]
+    pub verbose: u8,
+
+    /// Log less (-q for warnings and errors only, -qq for errors only)
+    #[arg(short, long, action = ArgAction::Count, help_heading = "Console and logging")]
+    pub quiet: u8,
+
+    /// How log lines are written
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, env = "MICROBLENDER_LOG_FORMAT", help_heading = "Console and logging")]
+    pub log_format: LogFormat,
+
+    /// Detach from the terminal and keep running in the background (Unix)
+    #[arg(long, conflicts_with_all = ["monitor", "repl", "hotkeys"], help_heading = "Console and logging")]
+    pub daemon: bool,
+
+    /// Append the log to this file instead of discarding it with --daemon
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "daemon",
+        help_heading = "Console and logging"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    /// Write the bridge's process ID to this file while it runs
+    #[arg(long, value_name = "FILE", help_heading = "Console and logging")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Compute pitch/roll/yaw from the raw accelerometer and magnetometer
+    /// readings, with a tilt-compensated compass heading as yaw
+    #[arg(long, help_heading = "Processing")]
+    pub fusion: bool,
+
+    /// Transform every message with a script, see the `script` module docs
+    #[arg(long, value_name = "FILE", help_heading = "Processing")]
+    pub script: Option<PathBuf>,
+
+    /// Smooth x/y/z before forwarding: none, ema[:alpha=0.2,x=..,y=..,z=..]
+    /// (also lowpass), kalman[:q=0.5,r=4], deadzone[:threshold=1,release=0.5]
+    /// or hampel[:window=7,k=3,min=5,mode=hold|clamp] (repeatable, applied
+    /// in order)
+    #[arg(long = "filter", value_name = "FILTER", help_heading = "Processing")]
+    pub filters: Vec<FilterSpec>,
+
+    /// Add {"event":"NAME"} to the stream once CONDITION held for DURATION,
+    /// e.g. "tilt_left=x < -60 for 200ms" (repeatable)
+    #[arg(
+        long = "event",
+        value_name = "NAME=CONDITION [for DURATION]",
+        allow_hyphen_values = true,
+        help_heading = "Processing"
+    )]
+    pub events: Vec<EventRule>,
+
+    /// Add {"gesture":"shake","confidence":0.8} to the stream when a board
+    /// is flipped, shaken, tapped twice or tilted around in a circle
+    #[arg(long, help_heading = "Processing")]
+    pub gestures: bool,
+
+    /// Drop samples that match the last one forwarded, with every value
+    /// within EPSILON of it (default: exactly equal)
+    #[arg(long, value_name = "EPSILON", num_args = 0..=1, default_missing_value = "0", value_parser = parse_epsilon, help_heading = "Processing")]
+    pub send_on_change: Option<f64>,
+
+    /// With --send-on-change, still forward a sample this often while
+    /// nothing changes
+    #[arg(long, value_name = "TIME", default_value = "1s", value_parser = parse_duration, help_heading = "Processing")]
+    pub change_keepalive: Duration,
+
+    /// Send a named sink only some kinds of messages: orientation, events
+    /// and/or gestures, e.g. Visualizer=orientation (repeatable)
+    #[arg(
+        long = "subscribe",
+        value_name = "NAME=KIND[+KIND]",
+        help_heading = "Processing"
+    )]
+    pub subscriptions: Vec<Subscription>,
+
+    /// Remap axes for all sinks (x=roll,y=-pitch,z=yaw) or for a named one
+    /// (NAME:x=roll,...). Sources are fields or pitch/roll/yaw, `-` negates
+    #[arg(long = "map", value_name = "[NAME:]AXES", help_heading = "Processing")]
+    pub maps: Vec<AxisMap>,
+
+    /// Reshape the messages for all sinks or for a named one (NAME:...):
+    /// NEW=FIELD renames, +FIELD keeps only the listed fields, -FIELD drops
+    /// one and decimals=N rounds, e.g. Visualizer:pitch=x,roll=y,+pitch,+roll.
+    /// Applied after --units and --map
+    #[arg(
+        long = "format",
+        value_name = "[NAME:]RULES",
+        allow_hyphen_values = true,
+        help_heading = "Processing"
+    )]
+    pub formats: Vec<Format>,
+
+    /// File of --format values, one per line, with `#` comments. Formats
+    /// given on the command line take precedence
+    #[arg(long, value_name = "FILE", help_heading = "Processing")]
+    pub format_file: Option<PathBuf>,
+
+    /// Angle unit for all sinks (deg or rad) or for a named one (NAME=UNIT),
+    /// converted before any --map
+    #[arg(long, value_name = "[NAME=]UNIT", help_heading = "Processing")]
+    pub units: Vec<UnitSpec>,
+
+    /// Encoding on the TCP outputs, the server and the local socket (json,
+    /// protobuf, msgpack or cbor), for all of them or a named one
+    /// (NAME=ENCODING)
+    #[arg(long, value_name = "[NAME=]ENCODING", help_heading = "Processing")]
+    pub encoding: Vec<EncodingSpec>,
+
+    /// Limit the message rate of all sinks (HZ) or of a named one (NAME=HZ)
+    #[arg(long, value_name = "[NAME=]HZ", help_heading = "Processing")]
+    pub max_rate: Vec<RateSpec>,
+
+    /// How messages are reduced to the limited rate
+    #[arg(long, value_enum, default_value_t = RateMode::Decimate, help_heading = "Processing")]
+    pub rate_mode: RateMode,
+
+    /// Send all sinks (HZ) or a named one (NAME=HZ) messages at a steady
+    /// rate, interpolated between the board's samples
+    #[arg(long, value_name = "[NAME=]HZ", help_heading = "Processing")]
+    pub resample: Vec<RateSpec>,
+
+    /// Messages queued per sink, each sink being fed by a thread of its own
+    /// so a slow one can't hold up the others; 0 sends from the main loop
+    #[arg(
+        long,
+        value_name = "MESSAGES",
+        default_value_t = 64,
+        help_heading = "Processing"
+    )]
+    pub queue_size: usize,
+
+    /// What happens to messages for a sink whose queue is full; files
+    /// always wait
+    #[arg(long, value_enum, default_value_t = OverflowPolicy::DropOldest, help_heading = "Processing")]
+    pub overflow: OverflowPolicy,
+
+    /// The queue of all sinks or a named one (NAME:...): size=MESSAGES and
+    /// overflow=POLICY, e.g. Visualizer:size=8,overflow=drop-newest
+    #[arg(
+        long = "queue",
+        value_name = "[NAME:]SETTINGS",
+        help_heading = "Processing"
+    )]
+    pub queues: Vec<QueueSpec>,
+
+    /// Send the messages to TCP services and server clients in batches of
+    /// this many, one write per batch
+    #[arg(long, value_name = "MESSAGES", value_parser = clap::value_parser!(u64).range(2..), help_heading = "Processing")]
+    pub batch: Option<u64>,
+
+    /// Send batches at least this often, e.g. 20ms or 0.1s, also for
+    /// batches that aren't full yet
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval, help_heading = "Processing")]
+    pub batch_interval: Option<Duration>,
+
+    /// How batches are written: the usual lines together, or one JSON
+    /// array per batch
+    #[arg(long, value_enum, default_value_t = BatchFormat::Lines, help_heading = "Processing")]
+    pub batch_format: BatchFormat,
+
+    #[arg(
+        long,
+        default_value_t = 115200,
+        env = "MICROBLENDER_BAUD",
+        help_heading = "Serial port"
+    )]
+    pub baud: u32,
+
+    /// Try the common baud rates, starting with --baud, until the board's
+    /// messages come through, and keep that rate
+    #[arg(long, conflicts_with = "simulate", help_heading = "Serial port")]
+    pub detect_baud: bool,
+
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(5..=8), help_heading = "Serial port")]
+    pub data_bits: u8,
+
+    #[arg(long, value_enum, default_value_t = Parity::None, help_heading = "Serial port")]
+    pub parity: Parity,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2), help_heading = "Serial port")]
+    pub stop_bits: u8,
+
+    #[arg(long, value_enum, default_value_t = FlowControl::None, help_heading = "Serial port")]
+    pub flow_control: FlowControl,
+
+    /// How long a read of the serial port waits for data, e.g. 10ms
+    #[arg(long, value_name = "INTERVAL", default_value = "10ms", value_parser = parse_interval, help_heading = "Serial port")]
+    pub read_timeout: Duration,
+
+    /// Set the DTR line once the port is open, for adapters that need it
+    #[arg(long, value_enum, value_name = "SIGNAL", help_heading = "Serial port")]
+    pub dtr: Option<Signal>,
+
+    /// Set the RTS line once the port is open
+    #[arg(long, value_enum, value_name = "SIGNAL", help_heading = "Serial port")]
+    pub rts: Option<Signal>,
+
+    /// Encoding used by the board on the serial link
+    #[arg(long, value_enum, default_value_t = WireFormat::Json, env = "MICROBLENDER_WIRE_FORMAT", help_heading = "Serial port")]
+    pub wire_format: WireFormat,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1",
+        env = "MICROBLENDER_HOST",
+        help_heading = "Outputs"
+    )]
+    pub host: String,
+
+    #[arg(long, env = "MICROBLENDER_BLENDER", value_parser = BoolishValueParser::new(), help_heading = "Outputs")]
+    pub blender: bool,
+
+    #[arg(long, env = "MICROBLENDER_VISUALIZER", value_parser = BoolishValueParser::new(), help_heading = "Outputs")]
+    pub visualizer: bool,
+
+    #[arg(long, default_value_t = 65432, help_heading = "Outputs")]
+    pub blender_port: u16,
+
+    #[arg(long, default_value_t = 65433, help_heading = "Outputs")]
+    pub viz_port: u16,
+
+    /// Forward to a named TCP service, e.g. unity=tcp://127.0.0.1:7000 (repeatable)
+    #[arg(
+        long = "sink",
+        value_name = "NAME=tcp://HOST:PORT",
+        env = "MICROBLENDER_SINKS",
+        value_delimiter = ',',
+        help_heading = "Outputs"
+    )]
+    pub sinks: Vec<SinkSpec>,
+
+    /// Treat TCP sinks as one consumer, sending to the first of them that
+    /// is connected, e.g. Blender=local,remote (repeatable)
+    #[arg(long, value_name = "NAME=PRIMARY,FALLBACK", help_heading = "Outputs")]
+    pub failover: Vec<FailoverSpec>,
+
+    /// Initial delay before reconnecting to a lost service
+    #[arg(long, default_value_t = 500, help_heading = "Outputs")]
+    pub reconnect_delay_ms: u64,
+
+    /// Upper bound for the exponential reconnect backoff
+    #[arg(long, default_value_t = 30000, help_heading = "Outputs")]
+    pub reconnect_max_delay_ms: u64,
+
+    /// How messages are handled while a service is reconnecting
+    #[arg(long, value_enum, default_value_t = ReconnectPolicy::Drop, help_heading = "Outputs")]
+    pub reconnect_policy: ReconnectPolicy,
+
+    /// Maximum number of messages kept per service with the buffer policy
+    #[arg(long, default_value_t = 100, help_heading = "Outputs")]
+    pub reconnect_buffer: usize,
+
+    /// How reconnecting backs off, for all services or a named one
+    /// (NAME:...): initial=DELAY, factor=F, max=DELAY and attempts=N, after
+    /// which the bridge stops, e.g. Blender:initial=200ms,attempts=5
+    #[arg(long, value_name = "[NAME:]SETTINGS", help_heading = "Outputs")]
+    pub backoff: Vec<BackoffSpec>,
+
+    /// Stop with an error as soon as a service can't be reached or is lost,
+    /// instead of waiting for it, e.g. in CI
+    #[arg(long, help_heading = "Outputs")]
+    pub fail_fast: bool,
+
+    /// How long connecting to a service may take before the attempt counts
+    /// as failed
+    #[arg(long, value_name = "TIME", default_value = "5s", value_parser = parse_duration, help_heading = "Outputs")]
+    pub connect_timeout: Duration,
+
+    /// Start forwarding right away and connect to the services in the
+    /// background, sending to each once it's reachable
+    #[arg(long, conflicts_with = "fail_fast", help_heading = "Outputs")]
+    pub lazy_connect: bool,
+
+    /// Seconds without data after which TCP services, MQTT and rosbridge
+    /// get a heartbeat, and a few of which a dead connection is noticed
+    /// in; 0 turns heartbeats off
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0, value_parser = parse_seconds, help_heading = "Outputs")]
+    pub heartbeat: f64,
+
+    /// Encrypt the connections to TCP services with TLS, checking their
+    /// certificate against the system's trusted ones or --tls-ca (needs a
+    /// bridge built with --features tls)
+    #[arg(long, help_heading = "Outputs")]
+    pub tls: bool,
+
+    /// PEM file with the certificates TCP services are checked against, e.g.
+    /// a self-signed one
+    #[arg(long, value_name = "FILE", requires = "tls", help_heading = "Outputs")]
+    pub tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate (chain) presented to TCP services that ask for
+    /// one
+    #[arg(long, value_name = "FILE", requires_all = ["tls", "tls_key"], help_heading = "Outputs")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --tls-cert
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        help_heading = "Outputs"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    /// Accept TCP clients on this port and stream to all of them
+    #[arg(
+        long,
+        value_name = "PORT",
+        env = "MICROBLENDER_LISTEN",
+        help_heading = "Outputs"
+    )]
+    pub listen: Option<u16>,
+
+    /// Address the TCP server binds to
+    #[arg(
+        long,
+        default_value = "127.0.0.1",
+        env = "MICROBLENDER_LISTEN_BIND",
+        help_heading = "Outputs"
+    )]
+    pub listen_bind: String,
+
+    /// Only stream to --listen clients that send {"auth":"TOKEN"} as their
+    /// first line
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        requires = "listen",
+        env = "MICROBLENDER_AUTH_TOKEN",
+        hide_env_values = true,
+        help_heading = "Outputs"
+    )]
+    pub auth_token: Option<String>,
+
+    /// Don't advertise the --listen server on the local network via mDNS
+    #[arg(long, env = "MICROBLENDER_NO_MDNS", value_parser = BoolishValueParser::new(), help_heading = "Outputs")]
+    pub no_mdns: bool,
+
+    /// Accept clients on this Unix domain socket and stream to all of them
+    /// (on Windows, write to this named pipe instead)
+    #[arg(long, value_name = "PATH", help_heading = "Outputs")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Serve the stream to browsers over WebSocket on this port
+    #[arg(long, env = "MICROBLENDER_WEBSOCKET_PORT", help_heading = "Outputs")]
+    pub websocket_port: Option<u16>,
+
+    /// Address the WebSocket server binds to
+    #[arg(long, default_value = "127.0.0.1", help_heading = "Outputs")]
+    pub websocket_bind: String,
+
+    /// Publish on a ZeroMQ PUB socket bound to this port
+    #[arg(long, value_name = "PORT", help_heading = "Outputs")]
+    pub zmq_port: Option<u16>,
+
+    /// Address the ZeroMQ PUB socket binds to
+    #[arg(long, default_value = "127.0.0.1", help_heading = "Outputs")]
+    pub zmq_bind: String,
+
+    /// Topic frame sent with every ZeroMQ message, for subscribers to
+    /// filter on
+    #[arg(long, default_value = "microbit", help_heading = "Outputs")]
+    pub zmq_topic: String,
+
+    /// Serve the gRPC service of proto/microblender.proto on this port
+    #[arg(long, value_name = "PORT", help_heading = "Outputs")]
+    pub grpc_port: Option<u16>,
+
+    /// Address the gRPC server binds to
+    #[arg(long, default_value = "127.0.0.1", help_heading = "Outputs")]
+    pub grpc_bind: String,
+
+    /// Publish every message to an MQTT broker, e.g. mqtt://localhost:1883
+    #[arg(long, env = "MICROBLENDER_MQTT_URL", help_heading = "Outputs")]
+    pub mqtt_url: Option<String>,
+
+    /// Topic the MQTT messages are published on
+    #[arg(
+        long,
+        default_value = "microbit/orientation",
+        env = "MICROBLENDER_MQTT_TOPIC",
+        help_heading = "Outputs"
+    )]
+    pub mqtt_topic: String,
+
+    /// Client identifier presented to the MQTT broker
+    #[arg(long, default_value = "microblender-bridge", help_heading = "Outputs")]
+    pub mqtt_client_id: String,
+
+    /// Publish sensor_msgs/msg/Imu messages to ROS 2 through a rosbridge
+    /// server, e.g. ws://localhost:9090
+    #[arg(long, value_name = "URL", help_heading = "Outputs")]
+    pub rosbridge_url: Option<String>,
+
+    /// ROS topic the IMU messages are published on
+    #[arg(long, default_value = "/microbit/imu", help_heading = "Outputs")]
+    pub rosbridge_topic: String,
+
+    /// frame_id in the header of the IMU messages
+    #[arg(long, default_value = "microbit", help_heading = "Outputs")]
+    pub rosbridge_frame_id: String,
+
+    /// Standard deviation of the angles in degrees, for the orientation
+    /// covariance
+    #[arg(long, value_name = "DEG", default_value_t = 1.0, value_parser = parse_noise, help_heading = "Outputs")]
+    pub orientation_noise: f64,
+
+    /// Standard deviation of the accelerometer in m/s², for the linear
+    /// acceleration covariance
+    #[arg(long, value_name = "M/S2", default_value_t = 0.1, value_parser = parse_noise, help_heading = "Outputs")]
+    pub accel_noise: f64,
+
+    /// Send OSC messages over UDP to this port
+    #[arg(long, help_heading = "Outputs")]
+    pub osc_port: Option<u16>,
+
+    /// Host receiving the OSC messages
+    #[arg(long, default_value = "127.0.0.1", help_heading = "Outputs")]
+    pub osc_host: String,
+
+    /// Address prefix of the OSC messages, e.g. /microbit/pitch
+    #[arg(long, default_value = "/microbit", help_heading = "Outputs")]
+    pub osc_prefix: String,
+
+    /// Send every message as a UDP datagram to host:port (repeatable)
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        env = "MICROBLENDER_UDP",
+        value_delimiter = ',',
+        help_heading = "Outputs"
+    )]
+    pub udp: Vec<String>,
+
+    /// Write every message as a CSV row to this file
+    #[arg(long, value_name = "FILE", help_heading = "Outputs")]
+    pub csv: Option<PathBuf>,
+
+    /// Keep a record of every session as JSONL files in this directory
+    #[arg(
+        long,
+        value_name = "DIR",
+        env = "MICROBLENDER_LOG_DATA",
+        help_heading = "Outputs"
+    )]
+    pub log_data: Option<PathBuf>,
+
+    /// Start a new --log-data file once the current one reaches this size
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "log_data",
+        help_heading = "Outputs"
+    )]
+    pub rotate_size: u64,
+
+    /// Also start a new --log-data file after this many minutes
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "log_data",
+        help_heading = "Outputs"
+    )]
+    pub rotate_minutes: Option<u64>,
+}
+
+/// Makes the options apply after any command too, e.g. `run --blender`.
+/// `--format` comes before the command only, as `bake` has one of its own.
+fn shared(arg: clap::Arg) -> clap::Arg {
+    if arg.get_id() == "formats" {
+        return arg;
+    }
+    arg.global(true)
+}
+
+// Without a command the bridge forwards, like `run`
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Forward the boards' messages to the outputs (the default)
+    Run,
+    /// Forward while showing the live dashboard, like --monitor
+    Monitor,
+    /// Zero the boards once they're held still in the position that
+    /// should read zero, and check that they do
+    Calibrate {
+        #[command(flatten)]
+        options: CalibrateOptions,
+    },
+    /// Record timestamped messages to a file while forwarding them
+    Record {
+        /// File the recording is written to, as Arrow if it ends in .arrow,
+        /// .feather or .ipc
+        file: PathBuf,
+
+        /// Only record, without forwarding to any output
+        #[arg(long)]
+        no_forward: bool,
+    },
+    /// Send a recording to the outputs instead of reading from the Microbit
+    Replay {
+        /// Recording created with the `record` command
+        file: PathBuf,
+
+        #[command(flatten)]
+        options: ReplayOptions,
+    },
+    /// Measure the round trip to the boards and to the TCP services
+    Latency {
+        #[command(flatten)]
+        options: LatencyOptions,
+    },
+    /// Convert a recording to a BVH motion capture file
+    Export {
+        /// Recording created with the `record` command
+        file: PathBuf,
+
+        /// BVH file to write
+        output: PathBuf,
+
+        /// Frames per second of the BVH file
+        #[arg(long, default_value_t = 50.0, value_parser = parse_fps)]
+        fps: f64,
+
+        /// Board to export from a recording of several (its `device` name)
+        #[arg(long)]
+        device: Option<String>,
+    },
+    /// Turn a recording into Blender keyframes, as a script or as JSON
+    Bake {
+        /// Recording created with the `record` command
+        file: PathBuf,
+
+        /// Script (.py) or JSON (.json) file to write
+        output: PathBuf,
+
+        #[command(flatten)]
+        options: BakeOptions,
+    },
+    /// List the serial ports with their USB details, pointing out Microbits
+    ListPorts,
+    /// Check the installation by sending synthetic frames through the
+    /// bridge to a TCP sink of its own, without a Microbit
+    Selftest,
+    /// List the bridges in server mode on the local network
+    Discover {
+        /// Seconds to wait for answers
+        #[arg(long, default_value_t = 3.0, value_parser = parse_seconds)]
+        timeout: f64,
+    },
+    /// Install, remove or run the bridge as a Windows service
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServiceAction {
+    /// Install the service, started with the system and running the bridge
+    /// with the options that follow, e.g. `service install --listen 7000`
+    /// (with absolute paths)
+    Install,
+    /// Stop and remove the service
+    Uninstall,
+    /// Run as the service with the options that follow; only the service
+    /// manager starts this
+    Run,
+}
+
+#[derive(clap::Args, Clone, Copy, Debug)]
+pub struct ReplayOptions {
+    /// Playback speed, e.g. 0.5 for half speed or 2 for double speed
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    pub speed: f64,
+
+    /// Start over at the end of the recording until interrupted
+    #[arg(long = "loop")]
+    pub looping: bool,
+
+    /// Skip the messages recorded before this many seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0, value_parser = parse_seconds)]
+    pub start: f64,
+
+    /// Stop at the messages recorded after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub end: Option<f64>,
+}
+
+fn parse_speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(format!("expected a positive factor, got '{}'", value)),
+    }
+}
+
+fn parse_fps(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 && fps <= 1000.0 => Ok(fps),
+        _ => Err(format!(
+            "expected frames per second up to 1000, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_angle_limit(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(limit) if limit.is_finite() && limit > 0.0 => Ok(limit),
+        _ => Err(format!(
+            "expected a positive angle in degrees, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("expected a time in seconds, got '{}'", value)),
+    }
+}
+
+/// Parses `20ms`, `0.5s` or a plain number of milliseconds.
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(millis) = value.strip_suffix("ms") {
+        (millis, 0.001)
+    } else if let Some(seconds) = value.strip_suffix('s') {
+        (seconds, 1.0)
+    } else {
+        (value, 0.001)
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => {
+            Ok(Duration::from_secs_f64(number * scale))
+        }
+        _ => Err(format!(
+            "expected an interval like 20ms or 0.5s, got '{}'",
+            value
+        )),
+    }
+}
+
+/// Parses `30s`, `5m`, `1h`, `500ms` or a plain number of seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(millis) = value.strip_suffix("ms") {
+        (millis, 0.001)
+    } else if let Some(seconds) = value.strip_suffix('s') {
+        (seconds, 1.0)
+    } else if let Some(minutes) = value.strip_suffix('m') {
+        (minutes, 60.0)
+    } else if let Some(hours) = value.strip_suffix('h') {
+        (hours, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => {
+            Ok(Duration::from_secs_f64(number * scale))
+        }
+        _ => Err(format!(
+            "expected a duration like 30s, 5m or 1h, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_epsilon(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(epsilon) if epsilon.is_finite() && epsilon >= 0.0 => Ok(epsilon),
+        _ => Err(format!(
+            "expected a tolerance of 0 or more, got '{}'",
+            value
+        )),
+    }
+}
+
+fn parse_noise(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(deviation) if deviation.is_finite() && deviation >= 0.0 => Ok(deviation),
+        _ => Err(format!("expected a standard deviation, got '{}'", value)),
+    }
+}
+
+/// All TCP services to forward to, with `--blender` and `--visualizer` being
+/// shorthands for the two default ones.
+pub fn tcp_sinks(args: &Args) -> Vec<SinkSpec> {
+    let mut sinks = Vec::new();
+    if args.blender {
+        sinks.push(SinkSpec::new("Blender", &args.host, args.blender_port));
+    }
+    if args.visualizer {
+        sinks.push(SinkSpec::new("Visualizer", &args.host, args.viz_port));
+    }
+    sinks.extend(args.sinks.iter().cloned());
+    sinks
+}
+
+/// The serial line settings given on the command line.
+pub fn line_settings(args: &Args) -> LineSettings {
+    LineSettings {
+        baud: args.baud,
+        data_bits: args.data_bits,
+        parity: args.parity,
+        stop_bits: args.stop_bits,
+        flow_control: args.flow_control,
+        timeout: args.read_timeout,
+        dtr: args.dtr,
+        rts: args.rts,
+    }
+}
+
+/// Turns the commands that are shorthands for options into those options.
+pub fn apply_command(args: &mut Args) {
+    if matches!(args.command, Some(Command::Monitor)) {
+        args.monitor = true;
+    }
+}
+
+pub fn validate_configuration(args: &Args) -> Result<(), String> {
+    let sinks = tcp_sinks(args);
+    for (i, sink) in sinks.iter().enumerate() {
+        for other in &sinks[..i] {
+            if sink.name == other.name {
+                return Err(format!("Sink name '{}' is used more than once", sink.name));
+            }
+            if sink.address() == other.address() {
+                return Err(format!(
+                    "{} and {} must use different addresses",
+                    other.name, sink.name
+                ));
+            }
+        }
+    }
+
+    for (i, group) in args.failover.iter().enumerate() {
+        if sinks
+            .iter()
+            .any(|sink| sink.name.eq_ignore_ascii_case(&group.name))
+        {
+            return Err(format!(
+                "Failover group '{}' has the name of a sink",
+                group.name
+            ));
+        }
+        for member in &group.members {
+            if !sinks
+                .iter()
+                .any(|sink| sink.name.eq_ignore_ascii_case(member))
+            {
+                return Err(format!(
+                    "Failover group '{}' names '{}', which isn't a TCP sink",
+                    group.name, member
+                ));
+            }
+            if args.failover[..i]
+                .iter()
+                .any(|other| other.contains(member))
+            {
+                return Err(format!("'{}' is in more than one failover group", member));
+            }
+        }
+    }
+
+    for command in &args.commands {
+        command::frame(command).map_err(|e| format!("Invalid command '{}': {}", command, e))?;
+    }
+
+    if !args.commands.is_empty() && (args.simulate.is_some() || args.input.is_some()) {
+        return Err("Commands need a Microbit, not --simulate or --input".to_string());
+    }
+    if (args.monitor || args.plot) && (args.repl || args.daemon) {
+        return Err("The monitor can't be combined with --repl or --daemon".to_string());
+    }
+
+    if args.input.as_deref() == Some(Path::new("-")) {
+        if args.repl {
+            return Err("--repl and --input - can't both read stdin".to_string());
+        }
+        if args.hotkeys {
+            return Err("--hotkeys and --input - can't both read stdin".to_string());
+        }
+    }
+
+    if args.tls && !cfg!(feature = "tls") {
+        return Err("--tls needs a bridge built with --features tls".to_string());
+    }
+    if args.gui && !cfg!(feature = "gui") {
+        return Err("--gui needs a bridge built with --features gui".to_string());
+    }
+
+    let has_output = !args.commands.is_empty()
+        || !sinks.is_empty()
+        || args.listen.is_some()
+        || args.unix_socket.is_some()
+        || args.websocket_port.is_some()
+        || args.zmq_port.is_some()
+        || args.grpc_port.is_some()
+        || args.mqtt_url.is_some()
+        || args.rosbridge_url.is_some()
+        || args.osc_port.is_some()
+        || !args.udp.is_empty()
+        || args.csv.is_some()
+        || args.log_data.is_some()
+        || args.gui
+        || matches!(
+            args.command,
+            Some(Command::Record {
+                no_forward: true,
+                ..
+            }) | Some(Command::Latency { .. })
+                | Some(Command::Calibrate { .. })
+        );
+    if !has_output {
+        return Err(
+            "At least one output must be specified (e.g. --blender or --visualizer, see --help)"
+                .to_string(),
+        );
+    }
+
+    if matches!(args.command, Some(Command::Calibrate { .. }))
+        && (args.simulate.is_some() || args.input.is_some())
+    {
+        return Err("calibrate needs a board, not --simulate or --input".to_string());
+    }
+
+    if let Some(Command::Replay { options, .. }) = &args.command {
+        if options.end.is_some_and(|end| end <= options.start) {
+            return Err("--end must be later than --start".to_string());
+        }
+    }
+
+    if args.require_crc && args.wire_format != WireFormat::Json {
+        return Err("--require-crc only applies to --wire-format json".to_string());
+    }
+    if args.input_format != InputFormat::Json {
+        if args.wire_format != WireFormat::Json {
+            return Err("--input-format only applies to --wire-format json".to_string());
+        }
+        if args.simulate.is_some() {
+            return Err("The simulator sends JSON, --input-format doesn't apply".to_string());
+        }
+    }
+    Ok(())
+}
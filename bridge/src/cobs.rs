@@ -0,0 +1,44 @@
+//! Consistent Overhead Byte Stuffing, used to delimit binary frames on the
+//! serial link with `0x00` bytes.
+
+/// Appends received bytes to `buffer` and returns every complete frame,
+/// still COBS encoded and without its `0x00` delimiter.
+pub fn split_frames(data: &[u8], buffer: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    for &byte in data {
+        if byte == 0 {
+            if !buffer.is_empty() {
+                frames.push(std::mem::take(buffer));
+            }
+        } else {
+            buffer.push(byte);
+        }
+    }
+    frames
+}
+
+/// Decodes a single frame (without the trailing delimiter).
+pub fn decode(frame: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(frame.len());
+    let mut i = 0;
+
+    while i < frame.len() {
+        let code = frame[i] as usize;
+        if code == 0 {
+            return Err("unexpected zero byte in COBS frame".to_string());
+        }
+        let end = i + code;
+        if end > frame.len() {
+            return Err("truncated COBS frame".to_string());
+        }
+
+        decoded.extend_from_slice(&frame[i + 1..end]);
+        // A block shorter than 255 bytes stands for data followed by a zero,
+        // except for the very last block
+        if code < 0xFF && end < frame.len() {
+            decoded.push(0);
+        }
+        i = end;
+    }
+    Ok(decoded)
+}
@@ -0,0 +1,173 @@
+//! Commands sent from the bridge to the board, like `tare` or `rate 25`.
+//!
+//! A command is a single line of printable ASCII, `NAME [ARGS]`, followed
+//! by the CRC16 of that text as `*XXXX` and a newline: the same framing the
+//! board uses for its JSON lines. The board answers with a
+//! `{"ack":"COMMAND"}` or `{"nack":"REASON"}` message.
+
+use crate::crc;
+use crate::handshake;
+use crate::{info, warn};
+use serde_json::Value;
+use serialport::SerialPort;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Longest command the firmware's receive buffer accepts, without the
+/// checksum.
+pub const MAX_COMMAND_LEN: usize = 59;
+
+/// Frames a command for the serial link, rejecting anything the board
+/// couldn't parse.
+pub fn frame(command: &str) -> Result<Vec<u8>, String> {
+    let command = command.trim();
+    if command.is_empty() {
+        return Err("empty command".to_string());
+    }
+    if command.len() > MAX_COMMAND_LEN {
+        return Err(format!(
+            "command is longer than {} characters",
+            MAX_COMMAND_LEN
+        ));
+    }
+    if let Some(c) = command.chars().find(|c| !c.is_ascii_graphic() && *c != ' ') {
+        return Err(format!("invalid character {:?} in command", c));
+    }
+    if command.contains('*') {
+        return Err("'*' is reserved for the checksum".to_string());
+    }
+
+    Ok(format!("{}*{:04X}\n", command, crc::crc16(command.as_bytes())).into_bytes())
+}
+
+/// Turns a control message from a service, like `{"cmd":"tare"}` or
+/// `{"cmd":"rate","args":[25]}`, into command text.
+pub fn from_json(message: &Value) -> Result<String, String> {
+    let name = message
+        .get("cmd")
+        .and_then(Value::as_str)
+        .ok_or("expected an object with a \"cmd\" string")?;
+
+    let mut command = name.to_string();
+    let args = match message.get("args") {
+        None => Vec::new(),
+        Some(Value::Array(args)) => args.clone(),
+        Some(arg) => vec![arg.clone()],
+    };
+    for arg in args {
+        match arg {
+            Value::String(arg) => command.push_str(&format!(" {}", arg)),
+            Value::Number(arg) => command.push_str(&format!(" {}", arg)),
+            other => return Err(format!("unsupported argument {}", other)),
+        }
+    }
+
+    frame(&command)?;
+    Ok(command)
+}
+
+/// Relays the control messages a service sends, one JSON object per line,
+/// to the boards until the connection closes.
+pub fn relay(service: &str, commands: impl BufRead, link: &BoardLink) {
+    for line in commands.lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message = serde_json::from_str(&line).map_err(|e| e.to_string());
+        if let Some(answer) = message.as_ref().ok().and_then(handshake::answer) {
+            if let Err(reason) = answer {
+                warn!(sink: service, "{} rejected the bridge: {}", service, reason);
+            }
+            continue;
+        }
+
+        let command = message
+            .and_then(|message| from_json(&message))
+            .and_then(|command| Ok((link.send(&command)?, command)));
+        match command {
+            Ok((boards, command)) => info!(
+                sink: service,
+                "Relayed '{}' from {} to {} board(s)",
+                command, service, boards
+            ),
+            Err(e) => warn!(
+                sink: service,
+                "Ignoring command from {} ({}): {}",
+                service,
+                e,
+                line
+            ),
+        }
+    }
+}
+
+/// The board's answer to a command, if `message` is one.
+pub fn reply(message: &Value) -> Option<String> {
+    if let Some(command) = message.get("ack") {
+        return Some(format!("'{}' done", command.as_str()?));
+    }
+    let reason = message.get("nack")?;
+    Some(format!("command failed: {}", reason.as_str()?))
+}
+
+/// Write access to the serial ports of all connected boards, shared between
+/// the threads reading them and whoever sends commands.
+#[derive(Clone, Default)]
+pub struct BoardLink {
+    ports: Arc<Mutex<HashMap<String, Box<dyn SerialPort>>>>,
+}
+
+impl BoardLink {
+    /// Registers (or after a reconnect, replaces) the port of a board.
+    pub fn attach(&self, path: &str, port: &dyn SerialPort) {
+        match port.try_clone() {
+            Ok(port) => {
+                self.ports.lock().unwrap().insert(path.to_string(), port);
+            }
+            Err(e) => warn!("Can't send commands to {}: {}", path, e),
+        }
+    }
+
+    /// Sends a command to every board, returning how many it reached.
+    pub fn send(&self, command: &str) -> Result<usize, String> {
+        let frame = frame(command)?;
+        let mut ports = self.ports.lock().unwrap();
+        if ports.is_empty() {
+            return Err("no board connected".to_string());
+        }
+
+        let mut sent = 0;
+        for (path, port) in ports.iter_mut() {
+            match port.write_all(&frame).and_then(|()| port.flush()) {
+                Ok(()) => sent += 1,
+                Err(e) => warn!("Sending '{}' to {} failed: {}", command, path, e),
+            }
+        }
+        Ok(sent)
+    }
+}
+
+/// Reads commands from stdin, one per line, and sends them to the boards.
+pub fn spawn_repl(link: BoardLink) {
+    info!("Type a command (e.g. tare) and press Enter to send it to the board");
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match link.send(&line) {
+                Ok(boards) => info!("Sent '{}' to {} board(s)", line.trim(), boards),
+                Err(e) => warn!("Can't send '{}': {}", line.trim(), e),
+            }
+        }
+    });
+}
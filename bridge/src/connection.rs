@@ -0,0 +1,817 @@
+//! TCP connections to downstream services that survive the service
+//! restarting.
+//!
+//! A connection notices a dead service by reading from it (the service
+//! closing its side ends the read) and by sending heartbeats while no data
+//! flows, which the kernel gives up on after a few missed intervals.
+//!
+//! With `--tls` every connection is encrypted, and the greeting and data
+//! only go out once the service's certificate checked out.
+
+use crate::cli::{self, Args};
+use crate::command::{self, BoardLink};
+use crate::encoding::Encoding;
+use crate::handshake;
+use crate::sink::{Sink, SinkStats};
+use crate::{info, warn};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tls")]
+pub use crate::tls::Connector;
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+
+/// Stands in for the TLS settings in a bridge built without the `tls`
+/// feature, which never has any.
+#[cfg(not(feature = "tls"))]
+pub enum Connector {}
+
+/// Heartbeats that may go unacknowledged before a service counts as dead.
+const MISSED_HEARTBEATS: u32 = 3;
+
+/// What to do with messages that arrive while a service is reconnecting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ReconnectPolicy {
+    /// Discard messages until the connection is back
+    Drop,
+    /// Keep the most recent messages and send them once reconnected
+    Buffer,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectConfig {
+    initial_delay: Duration,
+    max_delay: Duration,
+    /// What the delay is multiplied with after every failed attempt.
+    factor: f64,
+    /// Failed attempts in a row after which the sink gives up and stops
+    /// the bridge.
+    max_attempts: Option<u32>,
+    /// Stop the bridge instead of waiting for an unreachable service.
+    fail_fast: bool,
+    /// How long a single connection attempt may take.
+    connect_timeout: Duration,
+    /// Connect in the background instead of waiting for the service at
+    /// startup.
+    lazy: bool,
+    policy: ReconnectPolicy,
+    buffer_size: usize,
+    heartbeat: Option<Duration>,
+}
+
+impl ReconnectConfig {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            initial_delay: Duration::from_millis(args.reconnect_delay_ms),
+            max_delay: Duration::from_millis(args.reconnect_max_delay_ms),
+            factor: 2.0,
+            max_attempts: None,
+            fail_fast: args.fail_fast,
+            connect_timeout: args.connect_timeout,
+            lazy: args.lazy_connect,
+            policy: args.reconnect_policy,
+            buffer_size: args.reconnect_buffer,
+            heartbeat: (args.heartbeat > 0.0).then(|| Duration::from_secs_f64(args.heartbeat)),
+        }
+    }
+
+    /// The configuration of the sink `name`, with the `--backoff` for all
+    /// sinks and then the one naming it applied.
+    pub fn for_sink(mut self, name: &str, specs: &[BackoffSpec]) -> Self {
+        let global = specs.iter().filter(|spec| spec.sink.is_none());
+        let named = specs.iter().filter(|spec| {
+            spec.sink
+                .as_deref()
+                .is_some_and(|sink| sink.eq_ignore_ascii_case(name))
+        });
+        for spec in global.chain(named) {
+            self.initial_delay = spec.initial.unwrap_or(self.initial_delay);
+            self.factor = spec.factor.unwrap_or(self.factor);
+            self.max_delay = spec.max.unwrap_or(self.max_delay);
+            self.max_attempts = spec.attempts.or(self.max_attempts);
+        }
+        self
+    }
+
+    /// The same configuration, connecting in the background as with
+    /// `--lazy-connect`.
+    pub fn in_background(mut self) -> Self {
+        self.lazy = true;
+        self
+    }
+
+    /// The delay after `delay` when another attempt failed.
+    fn next_delay(&self, delay: Duration) -> Duration {
+        delay.mul_f64(self.factor).min(self.max_delay)
+    }
+
+    fn exhausted(&self, attempts: u32) -> bool {
+        self.fail_fast || self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+/// `--backoff [NAME:]initial=500ms,factor=2,max=30s,attempts=10`: how
+/// reconnecting backs off, for all sinks or a named one. Settings left out
+/// keep their defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BackoffSpec {
+    sink: Option<String>,
+    initial: Option<Duration>,
+    factor: Option<f64>,
+    max: Option<Duration>,
+    attempts: Option<u32>,
+}
+
+impl FromStr for BackoffSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, settings) = match spec.rsplit_once(':') {
+            Some((sink, settings)) => (Some(sink.to_string()), settings),
+            None => (None, spec),
+        };
+        if settings.is_empty() {
+            return Err(format!("'{}' changes no setting", spec));
+        }
+        let mut backoff = BackoffSpec {
+            sink,
+            ..Default::default()
+        };
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", setting))?;
+            match key.trim() {
+                "initial" => backoff.initial = Some(cli::parse_duration(value)?),
+                "max" => backoff.max = Some(cli::parse_duration(value)?),
+                "factor" => match value.parse::<f64>() {
+                    Ok(factor) if factor.is_finite() && factor >= 1.0 => {
+                        backoff.factor = Some(factor)
+                    }
+                    _ => return Err(format!("factor must be at least 1, got '{}'", value)),
+                },
+                "attempts" => match value.parse::<u32>() {
+                    Ok(attempts) if attempts > 0 => backoff.attempts = Some(attempts),
+                    _ => return Err(format!("attempts must be at least 1, got '{}'", value)),
+                },
+                key => {
+                    return Err(format!(
+                        "unknown setting '{}', expected initial, factor, max or attempts",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(backoff)
+    }
+}
+
+/// The TLS settings for `--tls`, if given.
+pub fn tls_connector(args: &Args) -> io::Result<Option<Arc<Connector>>> {
+    if !args.tls {
+        return Ok(None);
+    }
+    #[cfg(feature = "tls")]
+    {
+        let connector = Connector::new(
+            args.tls_ca.as_deref(),
+            args.tls_cert.as_deref(),
+            args.tls_key.as_deref(),
+        )?;
+        info!("Encrypting the connections to TCP services with TLS");
+        Ok(Some(Arc::new(connector)))
+    }
+    #[cfg(not(feature = "tls"))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--tls needs a bridge built with --features tls",
+    ))
+}
+
+/// The socket of a connection to a service, encrypted with `--tls`.
+pub enum ServiceStream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+}
+
+impl ServiceStream {
+    /// Encrypts the new connection `tcp` to `host` if `tls` says so.
+    fn secure(
+        tcp: TcpStream,
+        host: &str,
+        tls: Option<&Connector>,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        match tls {
+            None => Ok(ServiceStream::Plain(tcp)),
+            #[cfg(feature = "tls")]
+            Some(connector) => connector
+                .connect(host, tcp, timeout)
+                .map(ServiceStream::Tls),
+            #[cfg(not(feature = "tls"))]
+            Some(connector) => {
+                let _ = (host, timeout);
+                match *connector {}
+            }
+        }
+    }
+
+    /// The underlying TCP socket.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            ServiceStream::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => stream.tcp(),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            ServiceStream::Plain(stream) => stream.try_clone().map(ServiceStream::Plain),
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => Ok(ServiceStream::Tls(stream.clone())),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            ServiceStream::Plain(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    /// Closes both directions, which also ends a read on another thread.
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            ServiceStream::Plain(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => stream.shutdown(),
+        }
+    }
+}
+
+impl Read for ServiceStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ServiceStream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ServiceStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ServiceStream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ServiceStream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            ServiceStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A TCP connection to one downstream service that re-establishes itself
+/// with exponential backoff when the peer goes away.
+pub struct ServiceConnection {
+    name: String,
+    host: String,
+    addr: String,
+    stream: Option<ServiceStream>,
+    config: ReconnectConfig,
+    retry_delay: Duration,
+    next_attempt: Instant,
+    /// Failed reconnect attempts since the connection was lost.
+    attempts: u32,
+    /// The attempt running in the background, if any.
+    connecting: Option<Receiver<io::Result<ServiceStream>>>,
+    /// Whether the service was ever reached, so the first connection made
+    /// in the background doesn't count as a reconnect.
+    connected_once: bool,
+    pending: VecDeque<Vec<u8>>,
+    encoding: Encoding,
+    tls: Option<Arc<Connector>>,
+    greeting: Vec<u8>,
+    require_ack: bool,
+    relay: Option<BoardLink>,
+    /// Sent after `config.heartbeat` without any other data.
+    heartbeat: Vec<u8>,
+    last_write: Instant,
+    /// Whether the current stream has a thread reading it, and whether
+    /// that thread saw the service close its side.
+    reading: bool,
+    closed: Arc<AtomicBool>,
+    reconnects: u64,
+}
+
+impl ServiceConnection {
+    /// Connects to the service, or with `--lazy-connect` returns right away
+    /// and connects in the background once the connection is first used.
+    pub fn connect(host: &str, port: u16, name: &str, config: ReconnectConfig) -> io::Result<Self> {
+        let addr = format!("{}:{}", host, port);
+        let stream = if config.lazy {
+            info!(sink: name, "Connecting to {} at {} in the background", name, addr);
+            None
+        } else {
+            let stream = connect_to_service(host, port, name, &config)?;
+            watch_for_timeouts(&stream, config);
+            Some(ServiceStream::Plain(stream))
+        };
+        Ok(Self {
+            name: name.to_string(),
+            host: host.to_string(),
+            addr,
+            connected_once: stream.is_some(),
+            stream,
+            config,
+            retry_delay: config.initial_delay,
+            next_attempt: Instant::now(),
+            attempts: 0,
+            connecting: None,
+            pending: VecDeque::new(),
+            encoding: Encoding::Json,
+            tls: None,
+            greeting: Vec::new(),
+            require_ack: false,
+            relay: None,
+            heartbeat: Vec::new(),
+            last_write: Instant::now(),
+            reading: false,
+            closed: Arc::new(AtomicBool::new(false)),
+            reconnects: 0,
+        })
+    }
+
+    /// Encodes the messages as `encoding` instead of JSON lines.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Encrypts this and every later connection with TLS. Comes before the
+    /// greeting, which has to go out encrypted.
+    pub fn with_tls(mut self, connector: Arc<Connector>) -> io::Result<Self> {
+        if let Some(ServiceStream::Plain(stream)) = self.stream.take() {
+            let stream = ServiceStream::secure(
+                stream,
+                &self.host,
+                Some(&connector),
+                self.config.connect_timeout,
+            )
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("TLS with {} at {} failed: {}", self.name, self.addr, e),
+                )
+            })?;
+            self.stream = Some(stream);
+        }
+        self.tls = Some(connector);
+        Ok(self)
+    }
+
+    /// Sends `greeting` right away and again after every reconnect, for
+    /// protocols that expect a hello before any data.
+    pub fn with_greeting(mut self, greeting: Vec<u8>) -> io::Result<Self> {
+        if let Some(stream) = &mut self.stream {
+            stream.write_all(&greeting)?;
+        }
+        self.greeting = greeting;
+        Ok(self)
+    }
+
+    /// Waits for the service to acknowledge the greeting (see
+    /// `handshake::await_ack`) before sending data, on this and every later
+    /// connection.
+    pub fn with_required_ack(mut self) -> io::Result<Self> {
+        if let Some(stream) = &mut self.stream {
+            handshake::await_ack(stream, &self.name)?;
+        }
+        self.require_ack = true;
+        Ok(self)
+    }
+
+    /// Relays the commands the service sends back (see
+    /// `command::relay`) to the boards, on this and every later connection.
+    pub fn with_command_relay(mut self, link: BoardLink) -> Self {
+        self.relay = Some(link);
+        self
+    }
+
+    /// Sends `heartbeat` whenever no data went out for the configured
+    /// heartbeat interval. It has to be something the service ignores.
+    pub fn with_heartbeat(mut self, heartbeat: Vec<u8>) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Reads everything the service sends, relaying its commands if asked
+    /// to, until the service closes its side.
+    fn spawn_reader(&mut self) {
+        let Some(stream) = &self.stream else {
+            return;
+        };
+        let mut stream = match stream.try_clone() {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(sink: self.name, "Can't read from {}: {}", self.name, e);
+                return;
+            }
+        };
+        let closed = Arc::new(AtomicBool::new(false));
+        self.closed = Arc::clone(&closed);
+
+        let name = self.name.clone();
+        let relay = self.relay.clone();
+        thread::spawn(move || {
+            match relay {
+                Some(link) => command::relay(&name, BufReader::new(stream), &link),
+                None => {
+                    let _ = io::copy(&mut stream, &mut io::sink());
+                }
+            }
+            closed.store(true, Ordering::Relaxed);
+        });
+    }
+
+    /// Starts reading a new stream, and drops the stream once the service
+    /// closed it.
+    fn watch(&mut self) {
+        if self.stream.is_none() {
+            return;
+        }
+        if !self.reading {
+            self.reading = true;
+            self.spawn_reader();
+        } else if self.closed.load(Ordering::Relaxed) {
+            self.lose_connection("closed by the service");
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.transmit(data, true)
+    }
+
+    /// Sends a heartbeat if one is due, and reconnects while no data flows.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        self.watch();
+        if self.stream.is_none() {
+            return self.try_reconnect();
+        }
+
+        match self.config.heartbeat {
+            Some(interval)
+                if !self.heartbeat.is_empty() && self.last_write.elapsed() >= interval =>
+            {
+                let heartbeat = self.heartbeat.clone();
+                self.transmit(&heartbeat, false)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes `data`, keeping it for after the reconnect if `keep` and the
+    /// service is gone.
+    fn transmit(&mut self, data: &[u8], keep: bool) -> io::Result<()> {
+        self.watch();
+        if self.stream.is_none() {
+            self.try_reconnect()?;
+            self.watch();
+        }
+
+        let result = match &mut self.stream {
+            Some(stream) => {
+                flush_pending(stream, &mut self.pending).and_then(|_| stream.write_all(data))
+            }
+            None => {
+                if keep {
+                    self.hold(data);
+                }
+                return Ok(());
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                self.last_write = Instant::now();
+                Ok(())
+            }
+            Err(e) if is_disconnect(&e) => {
+                self.lose_connection(e);
+                if keep {
+                    self.hold(data);
+                }
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn lose_connection(&mut self, reason: impl std::fmt::Display) {
+        let next = if self.config.fail_fast {
+            ""
+        } else {
+            ", reconnecting..."
+        };
+        warn!(
+            sink: self.name,
+            "Lost connection to {} ({}){}",
+            self.name, reason, next
+        );
+        if let Some(stream) = self.stream.take() {
+            // Also ends the thread reading it
+            let _ = stream.shutdown();
+        }
+        self.retry_delay = self.config.initial_delay;
+        self.next_attempt = Instant::now() + self.retry_delay;
+        self.attempts = 0;
+    }
+
+    /// Starts a connection attempt in the background when the next one is
+    /// due, and takes over the connection once it succeeded. Fails once the
+    /// attempts are used up, or right away with `--fail-fast`.
+    fn try_reconnect(&mut self) -> io::Result<()> {
+        if self.config.fail_fast && self.connected_once {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "lost the connection with --fail-fast",
+            ));
+        }
+
+        let connected = match &self.connecting {
+            Some(attempt) => match attempt.try_recv() {
+                Ok(connected) => connected,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    Err(io::Error::other("the connection attempt was aborted"))
+                }
+            },
+            None => {
+                if Instant::now() >= self.next_attempt {
+                    self.start_attempt();
+                }
+                return Ok(());
+            }
+        };
+        self.connecting = None;
+
+        match connected {
+            Ok(stream) => {
+                if self.connected_once {
+                    info!(sink: self.name, "Reconnected to {} at {}", self.name, self.addr);
+                    self.reconnects += 1;
+                } else {
+                    info!(sink: self.name, "Connected to {} at {}", self.name, self.addr);
+                    self.connected_once = true;
+                }
+                watch_for_timeouts(stream.tcp(), self.config);
+                self.stream = Some(stream);
+                self.reading = false;
+                self.last_write = Instant::now();
+                self.retry_delay = self.config.initial_delay;
+                self.attempts = 0;
+            }
+            Err(e) => {
+                self.attempts += 1;
+                if self.config.exhausted(self.attempts) {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!(
+                            "gave up after {} reconnect attempt(s): {}",
+                            self.attempts, e
+                        ),
+                    ));
+                }
+                self.retry_delay = self.config.next_delay(self.retry_delay);
+                self.next_attempt = Instant::now() + self.retry_delay;
+                info!(
+                    sink: self.name,
+                    "Waiting for {}... ({}), next attempt in {:?}",
+                    self.name, e, self.retry_delay
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Connects and greets the service on another thread, so a slow or
+    /// unreachable service doesn't hold up the forwarding loop.
+    fn start_attempt(&mut self) {
+        let (sender, attempt) = mpsc::channel();
+        let addr = self.addr.clone();
+        let host = self.host.clone();
+        let name = self.name.clone();
+        let tls = self.tls.clone();
+        let greeting = self.greeting.clone();
+        let require_ack = self.require_ack;
+        let timeout = self.config.connect_timeout;
+        thread::spawn(move || {
+            let connected = open(&addr, timeout).and_then(|stream| {
+                let mut stream = ServiceStream::secure(stream, &host, tls.as_deref(), timeout)?;
+                stream.write_all(&greeting)?;
+                if require_ack {
+                    handshake::await_ack(&mut stream, &name)?;
+                }
+                Ok(stream)
+            });
+            // The connection may have been closed in the meantime
+            let _ = sender.send(connected);
+        });
+        self.connecting = Some(attempt);
+    }
+
+    fn hold(&mut self, data: &[u8]) {
+        if self.config.policy == ReconnectPolicy::Drop || self.config.buffer_size == 0 {
+            return;
+        }
+        if self.pending.len() == self.config.buffer_size {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(data.to_vec());
+    }
+}
+
+fn flush_pending(stream: &mut ServiceStream, pending: &mut VecDeque<Vec<u8>>) -> io::Result<()> {
+    while let Some(data) = pending.front() {
+        stream.write_all(data)?;
+        pending.pop_front();
+    }
+    Ok(())
+}
+
+/// Makes the kernel give up on a service that stops acknowledging data
+/// after a few heartbeat intervals, rather than retransmitting for many
+/// minutes. Writes then fail with `TimedOut`.
+#[cfg(target_os = "linux")]
+fn watch_for_timeouts(stream: &TcpStream, config: ReconnectConfig) {
+    use std::os::unix::io::AsRawFd;
+
+    let Some(interval) = config.heartbeat else {
+        return;
+    };
+    let timeout = (interval * MISSED_HEARTBEATS).as_millis() as libc::c_uint;
+    // SAFETY: the option value is a c_uint of the given size
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_USER_TIMEOUT,
+            &timeout as *const libc::c_uint as *const libc::c_void,
+            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        warn!(
+            "Can't limit the TCP timeout: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_for_timeouts(_stream: &TcpStream, _config: ReconnectConfig) {}
+
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+impl Sink for ServiceConnection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let data = self.encoding.frame(message)?;
+        self.write(&data)
+    }
+
+    fn send_batch(&mut self, messages: &[Value]) -> io::Result<()> {
+        let mut data = Vec::new();
+        for message in messages {
+            data.extend(self.encoding.frame(message)?);
+        }
+        self.write(&data)
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        self.keep_alive()
+    }
+
+    fn status(&self) -> String {
+        match (&self.stream, self.pending.len()) {
+            (Some(_), _) => "connected".to_string(),
+            (None, 0) if !self.connected_once => "connecting".to_string(),
+            (None, 0) => "reconnecting".to_string(),
+            (None, pending) => format!("reconnecting ({} buffered)", pending),
+        }
+    }
+
+    fn is_reachable(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn stats(&self) -> SinkStats {
+        SinkStats {
+            queued: self.pending.len(),
+            reconnects: self.reconnects,
+            ..SinkStats::default()
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            flush_pending(&mut stream, &mut self.pending)?;
+            stream.shutdown()?;
+        } else if !self.pending.is_empty() {
+            warn!(
+                sink: self.name,
+                "Discarding {} message(s) buffered for {}",
+                self.pending.len(),
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Connects to a service, backing off as `config` says while it can't be
+/// reached.
+pub fn connect_to_service(
+    host: &str,
+    port: u16,
+    service_name: &str,
+    config: &ReconnectConfig,
+) -> io::Result<TcpStream> {
+    let addr = format!("{}:{}", host, port);
+    info!(sink: service_name, "Attempting to connect to {} at {}", service_name, addr);
+
+    let mut delay = config.initial_delay;
+    let mut attempts = 0;
+    loop {
+        match open(&addr, config.connect_timeout) {
+            Ok(stream) => {
+                info!(sink: service_name, "Connected to {} at {}", service_name, addr);
+                return Ok(stream);
+            }
+            Err(e) => {
+                attempts += 1;
+                if config.exhausted(attempts) {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!(
+                            "can't connect to {} at {} after {} attempt(s): {}",
+                            service_name, addr, attempts, e
+                        ),
+                    ));
+                }
+                info!(
+                    sink: service_name,
+                    "Waiting for {}... ({}), next attempt in {:?}",
+                    service_name, e, delay
+                );
+                thread::sleep(delay);
+                delay = config.next_delay(delay);
+            }
+        }
+    }
+}
+
+/// Connects to `addr`, trying each of its addresses for at most `timeout`.
+fn open(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the host has no address")))
+}
@@ -0,0 +1,290 @@
+//! HTTP control API, so external tools and the Blender add-on can change
+//! the running bridge instead of restarting it with other options:
+//!
+//! ```text
+//! GET  /status       paused, messages received and forwarded, uptime,
+//!                    the last orientation forwarded and the recording
+//! POST /pause        stop forwarding
+//! POST /resume       forward again
+//! POST /tare         make the current orientation the new zero
+//! GET  /sinks        every sink with its status, queue, drops and reconnects
+//! GET  /filters      the filters, as given to --filter
+//! PUT  /filters      replace them with {"filters":["ema:alpha=0.3", ...]}
+//! POST /record       record to a file too, with {"file":"session.jsonl"}
+//! POST /record/stop  close that recording
+//! ```
+//!
+//! Every answer is a JSON object. Requests are handled by the forwarding
+//! loop between two messages, so they never race with it.
+//!
+//! A bridge built with `--features gui` also serves the `--gui` control
+//! panel on `GET /panel`.
+
+use crate::filter::FilterSpec;
+#[cfg(feature = "gui")]
+use crate::gui;
+use crate::{info, warn};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a request waits for the forwarding loop to get to it.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Largest request body accepted.
+const MAX_BODY: usize = 64 * 1024;
+
+/// What a control request asks the bridge for.
+#[derive(Debug)]
+pub enum ControlCommand {
+    Status,
+    Pause,
+    Resume,
+    Tare,
+    Sinks,
+    Filters,
+    SetFilters(Vec<FilterSpec>),
+    Record(PathBuf),
+    StopRecording,
+}
+
+/// A request waiting for the forwarding loop's answer.
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<(u16, Value)>,
+}
+
+impl ControlRequest {
+    pub fn reply(self, body: Value) {
+        // The client may have given up waiting
+        let _ = self.reply.send((200, body));
+    }
+
+    /// Answers with an error instead.
+    pub fn fail(self, status: u16, error: String) {
+        let _ = self.reply.send((status, json!({ "error": error })));
+    }
+}
+
+/// Accepts control requests and hands them to the forwarding loop.
+pub struct ControlServer {
+    requests: Receiver<ControlRequest>,
+    address: SocketAddr,
+}
+
+impl ControlServer {
+    /// Serves the API on `host:port`, any free port for 0. With a token,
+    /// clients have to send it as `Authorization: Bearer TOKEN`.
+    pub fn bind(host: &str, port: u16, token: Option<String>) -> io::Result<Self> {
+        let listener = TcpListener::bind((host, port))?;
+        let address = listener.local_addr()?;
+        info!("Control API listening on http://{}", address);
+
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Control API accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let sender = sender.clone();
+                let token = token.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, &sender, token.as_deref()) {
+                        warn!("Control API request failed: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(Self { requests, address })
+    }
+
+    /// The address the API is served on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The next request since the last call, if any.
+    pub fn poll(&self) -> Option<ControlRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+/// Answers one request and closes the connection.
+fn serve(
+    mut stream: TcpStream,
+    requests: &Sender<ControlRequest>,
+    token: Option<&str>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, 400, &json!({ "error": "malformed request" }));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut length = 0;
+    let mut authorized = token.is_none();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse().unwrap_or(0);
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorized |= token.is_some_and(|token| value.strip_prefix("Bearer ") == Some(token));
+        }
+    }
+    if length > MAX_BODY {
+        return respond(&mut stream, 413, &json!({ "error": "request too large" }));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    // The page only holds what any browser may see, the token is asked
+    // for by its requests
+    #[cfg(feature = "gui")]
+    if method == "GET" && path.split('?').next() == Some("/panel") {
+        return write_response(&mut stream, 200, "text/html; charset=utf-8", gui::PANEL);
+    }
+
+    if !authorized {
+        return respond(
+            &mut stream,
+            401,
+            &json!({ "error": "missing or wrong token" }),
+        );
+    }
+    let command = match route(&method, &path, &body) {
+        Ok(command) => command,
+        Err((status, error)) => return respond(&mut stream, status, &json!({ "error": error })),
+    };
+
+    let (reply, answer) = mpsc::channel();
+    if requests.send(ControlRequest { command, reply }).is_err() {
+        return respond(
+            &mut stream,
+            503,
+            &json!({ "error": "the bridge is shutting down" }),
+        );
+    }
+    match answer.recv_timeout(REPLY_TIMEOUT) {
+        Ok((status, body)) => respond(&mut stream, status, &body),
+        Err(_) => respond(
+            &mut stream,
+            503,
+            &json!({ "error": "the bridge didn't answer" }),
+        ),
+    }
+}
+
+/// Finds the command for a request, or the status and error to answer.
+fn route(method: &str, path: &str, body: &[u8]) -> Result<ControlCommand, (u16, String)> {
+    let path = path.split('?').next().unwrap_or_default();
+    let command = match (method, path.trim_end_matches('/')) {
+        ("GET", "" | "/status") => ControlCommand::Status,
+        ("POST", "/pause") => ControlCommand::Pause,
+        ("POST", "/resume") => ControlCommand::Resume,
+        ("POST", "/tare") => ControlCommand::Tare,
+        ("GET", "/sinks") => ControlCommand::Sinks,
+        ("GET", "/filters") => ControlCommand::Filters,
+        ("PUT" | "POST", "/filters") => ControlCommand::SetFilters(parse_filters(body)?),
+        ("POST", "/record") => ControlCommand::Record(parse_record(body)?),
+        ("POST", "/record/stop") => ControlCommand::StopRecording,
+        (
+            _,
+            "" | "/status" | "/pause" | "/resume" | "/tare" | "/sinks" | "/filters" | "/record"
+            | "/record/stop",
+        ) => return Err((405, format!("{} isn't supported on {}", method, path))),
+        _ => return Err((404, format!("no such endpoint {}", path))),
+    };
+    Ok(command)
+}
+
+/// Reads `{"filters":["SPEC", ...]}`.
+fn parse_filters(body: &[u8]) -> Result<Vec<FilterSpec>, (u16, String)> {
+    let bad_request = |error: String| (400, error);
+    let body: Value = serde_json::from_slice(body)
+        .map_err(|e| bad_request(format!("the body isn't JSON: {}", e)))?;
+    let specs = body
+        .get("filters")
+        .and_then(Value::as_array)
+        .ok_or_else(|| bad_request("expected {\"filters\":[...]}".to_string()))?;
+    specs
+        .iter()
+        .map(|spec| {
+            let spec = spec
+                .as_str()
+                .ok_or_else(|| bad_request("filters must be strings".to_string()))?;
+            spec.parse()
+                .map_err(|e| bad_request(format!("invalid filter '{}': {}", spec, e)))
+        })
+        .collect()
+}
+
+/// Reads `{"file":"PATH"}`.
+fn parse_record(body: &[u8]) -> Result<PathBuf, (u16, String)> {
+    let body: Value =
+        serde_json::from_slice(body).map_err(|e| (400, format!("the body isn't JSON: {}", e)))?;
+    match body.get("file").and_then(Value::as_str) {
+        Some(file) if !file.is_empty() => Ok(PathBuf::from(file)),
+        _ => Err((400, "expected {\"file\":\"PATH\"}".to_string())),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &Value) -> io::Result<()> {
+    write_response(stream, status, "application/json", &body.to_string())
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "Service Unavailable",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
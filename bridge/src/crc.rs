@@ -0,0 +1,70 @@
+//! Verification of the CRC16 the firmware appends to each JSON line as
+//! `*XXXX`, so frames corrupted by line noise are dropped instead of being
+//! forwarded as subtly wrong values.
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF), the same
+/// variant as computed by the firmware.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub struct CrcChecker {
+    /// Whether lines without a checksum (from older firmware) are rejected.
+    required: bool,
+    corrupted: u64,
+}
+
+impl CrcChecker {
+    pub fn new(required: bool) -> Self {
+        Self {
+            required,
+            corrupted: 0,
+        }
+    }
+
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    /// Returns the line without its checksum, or an error (counting the
+    /// frame as corrupted) if the checksum doesn't match.
+    pub fn check<'a>(&mut self, line: &'a str) -> Result<&'a str, String> {
+        let result = match split_checksum(line) {
+            Some((payload, expected)) => match crc16(payload.as_bytes()) {
+                actual if actual == expected => Ok(payload),
+                actual => Err(format!(
+                    "checksum {:04X} does not match {:04X}",
+                    actual, expected
+                )),
+            },
+            None if self.required => Err("missing checksum".to_string()),
+            None => Ok(line),
+        };
+
+        if result.is_err() {
+            self.corrupted += 1;
+        }
+        result
+    }
+}
+
+/// Splits `payload*XXXX` into the payload and the checksum.
+fn split_checksum(line: &str) -> Option<(&str, u16)> {
+    let (payload, checksum) = line.rsplit_once('*')?;
+    if checksum.len() != 4 {
+        return None;
+    }
+    let checksum = u16::from_str_radix(checksum, 16).ok()?;
+    Some((payload, checksum))
+}
@@ -0,0 +1,132 @@
+//! Writes the stream as CSV rows for spreadsheets and pandas.
+//!
+//! Columns are created from the fields actually seen: nested objects are
+//! flattened into dotted names (`a.b`), and when a field shows up for the
+//! first time it is appended as a new column and the header is rewritten.
+//! Earlier rows simply end before the new columns.
+
+use crate::info;
+use crate::sink::Sink;
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const TIMESTAMP_COLUMN: &str = "timestamp";
+
+pub struct CsvSink {
+    path: PathBuf,
+    file: BufWriter<File>,
+    columns: Vec<String>,
+}
+
+impl CsvSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        info!("Writing CSV to {}", path.display());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            columns: Vec::new(),
+        })
+    }
+
+    /// Replaces the header line once new columns were added.
+    fn rewrite_header(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let contents = fs::read_to_string(&self.path)?;
+        let rows = contents.split_once('\n').map_or("", |(_, rows)| rows);
+
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        writeln!(file, "{}", self.header())?;
+        file.write_all(rows.as_bytes())?;
+        self.file = file;
+        Ok(())
+    }
+
+    fn header(&self) -> String {
+        std::iter::once(TIMESTAMP_COLUMN)
+            .chain(self.columns.iter().map(String::as_str))
+            .map(escape)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl Sink for CsvSink {
+    fn name(&self) -> &str {
+        "CSV"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let mut fields = Map::new();
+        flatten("", message, &mut fields);
+
+        let known = self.columns.len();
+        for name in fields.keys() {
+            if !self.columns.contains(name) {
+                self.columns.push(name.clone());
+            }
+        }
+        if known == 0 {
+            writeln!(self.file, "{}", self.header())?;
+        } else if self.columns.len() > known {
+            self.rewrite_header()?;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut row = vec![format!("{:.3}", timestamp)];
+        row.extend(self.columns.iter().map(|column| match fields.get(column) {
+            Some(Value::String(text)) => escape(text),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }));
+
+        writeln!(self.file, "{}", row.join(","))?;
+        self.file.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, fields: &mut Map<String, Value>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&join(key), value, fields);
+            }
+        }
+        Value::Array(items) => {
+            for (index, value) in items.iter().enumerate() {
+                flatten(&join(&index.to_string()), value, fields);
+            }
+        }
+        value => {
+            fields.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
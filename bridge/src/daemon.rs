@@ -0,0 +1,131 @@
+//! Running unattended in the background, e.g. on a Raspberry Pi that only
+//! forwards a Microbit to a workstation: `--daemon` detaches the bridge
+//! from the terminal, with its output going to `--log-file`, and
+//! `--pid-file` tells scripts which process to stop.
+
+use crate::cli::Args;
+use crate::info;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file holding the bridge's process ID, removed again on exit.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the current process ID to `path`, unless another bridge that
+    /// is still running wrote it.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Some(pid) = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| text.trim().parse::<u32>().ok())
+        {
+            if is_running(pid) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} belongs to the running process {}", path.display(), pid),
+                ));
+            }
+        }
+
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Detaches from the terminal with `--daemon` and writes the `--pid-file`.
+/// Must run before any thread is started, as only the calling thread
+/// survives the fork.
+pub fn start(args: &Args) -> io::Result<Option<PidFile>> {
+    if args.daemon {
+        let log = match &args.log_file {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+            None => OpenOptions::new().write(true).open(NULL_DEVICE)?,
+        };
+        detach(log, args.log_file.as_deref())?;
+    }
+    args.pid_file.as_deref().map(PidFile::create).transpose()
+}
+
+#[cfg(unix)]
+const NULL_DEVICE: &str = "/dev/null";
+#[cfg(not(unix))]
+const NULL_DEVICE: &str = "NUL";
+
+/// Forks, lets the parent exit and starts a new session without a
+/// terminal, with stdin from /dev/null and stdout and stderr going to `log`.
+#[cfg(unix)]
+fn detach(log: File, log_path: Option<&Path>) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let null = File::open(NULL_DEVICE)?;
+    // SAFETY: no other thread exists yet, so the child is a complete copy
+    // of the process
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        pid => {
+            match log_path {
+                Some(path) => info!(
+                    "Running in the background as process {}, logging to {}",
+                    pid,
+                    path.display()
+                ),
+                None => info!("Running in the background as process {}", pid),
+            }
+            std::process::exit(0);
+        }
+    }
+
+    // SAFETY: plain system calls on descriptors owned by this function
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        for (from, to) in [
+            (null.as_raw_fd(), libc::STDIN_FILENO),
+            (log.as_raw_fd(), libc::STDOUT_FILENO),
+            (log.as_raw_fd(), libc::STDERR_FILENO),
+        ] {
+            if libc::dup2(from, to) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn detach(_log: File, _log_path: Option<&Path>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--daemon is only available on Unix",
+    ))
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    if pid == 0 {
+        return false;
+    }
+    // SAFETY: signal 0 only checks whether the process exists
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    // Another user's process can't be signalled, but exists all the same
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
@@ -0,0 +1,137 @@
+//! Keeps a record of every session without `record`: messages are appended
+//! to JSONL files in a directory, starting a new file once the current one
+//! is too big or too old.
+//!
+//! Entries use the recording format with the wall-clock `time` added, so
+//! each file can be replayed as it is:
+//!
+//! ```text
+//! {"data":{"x":1.5,"y":-3.0,"z":0.0},"t":0.02,"time":1718000000.52}
+//! ```
+
+use crate::info;
+use crate::sink::Sink;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+pub struct DataLog {
+    dir: PathBuf,
+    max_size: u64,
+    max_age: Option<Duration>,
+    file: LineWriter<File>,
+    size: u64,
+    start: Instant,
+}
+
+impl DataLog {
+    pub fn create(dir: &Path, max_size_mb: u64, max_age: Option<Duration>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_size: max_size_mb * 1024 * 1024,
+            max_age,
+            file: open_log_file(dir)?,
+            size: 0,
+            start: Instant::now(),
+        })
+    }
+
+    fn rotate_if_due(&mut self) -> io::Result<()> {
+        let too_big = self.size >= self.max_size;
+        let too_old = self.max_age.is_some_and(|age| self.start.elapsed() >= age);
+        if too_big || too_old {
+            self.file.flush()?;
+            self.file = open_log_file(&self.dir)?;
+            self.size = 0;
+            self.start = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, mut entry: Value) -> io::Result<()> {
+        self.rotate_if_due()?;
+        if let Value::Object(fields) = &mut entry {
+            fields.insert("t".to_string(), self.start.elapsed().as_secs_f64().into());
+            fields.insert("time".to_string(), unix_time().as_secs_f64().into());
+        }
+
+        let line = format!("{}\n", entry);
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl Sink for DataLog {
+    fn name(&self) -> &str {
+        "Data log"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.append(json!({ "data": message }))
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.append(json!({ "marker": label }))
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Opens a new file named after the current UTC time, e.g.
+/// `session-20240610-143005.jsonl`.
+fn open_log_file(dir: &Path) -> io::Result<LineWriter<File>> {
+    let stamp = utc_stamp(unix_time().as_secs());
+    let mut path = dir.join(format!("session-{}.jsonl", stamp));
+    // Rotating twice within a second must not overwrite the earlier file
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("session-{}-{}.jsonl", stamp, n));
+    }
+
+    info!("Logging data to {}", path.display());
+    Ok(LineWriter::new(File::create(path)?))
+}
+
+/// Formats seconds since the epoch as `YYYYMMDD-HHMMSS` in UTC.
+fn utc_stamp(secs: u64) -> String {
+    let (days, time) = (secs / SECONDS_PER_DAY, secs % SECONDS_PER_DAY);
+
+    // Civil date from the day count, after Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
@@ -0,0 +1,101 @@
+//! Encodings of the messages on the stream sinks (the TCP outputs, the
+//! server and the local socket), for consumers that would rather not parse
+//! JSON text. Every encoding frames the messages so they can be told apart
+//! on the stream:
+//!
+//! - `json`: a line of JSON per message, as always.
+//! - `protobuf`: the `Orientation` message of `proto/microblender.proto`,
+//!   prefixed with its length as a varint, the way protobuf's delimited
+//!   readers (`parseDelimitedFrom`, `protodelim`) expect it.
+//! - `msgpack`: a MessagePack map per message, with the same fields as the
+//!   JSON. MessagePack values delimit themselves, so they follow each other
+//!   without a prefix.
+//! - `cbor`: a CBOR map per message, with the same fields as the JSON and
+//!   the numbers as 64-bit floats, prefixed with its length as a 32-bit
+//!   big-endian integer so constrained consumers can read it into a buffer
+//!   before decoding.
+
+use crate::cbor;
+use crate::msgpack;
+use crate::protobuf;
+use crate::sink;
+use clap::ValueEnum;
+use serde_json::Value;
+use std::io;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// A line of JSON per message
+    #[default]
+    Json,
+    /// Length-delimited `Orientation` messages
+    Protobuf,
+    /// MessagePack maps
+    Msgpack,
+    /// Length-prefixed CBOR maps
+    Cbor,
+}
+
+impl Encoding {
+    /// `message` encoded and framed for a stream.
+    pub fn frame(self, message: &Value) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Json => sink::to_json_line(message),
+            Encoding::Protobuf => {
+                let encoded = protobuf::encode_orientation(message)?;
+                let mut data = Vec::with_capacity(encoded.len() + 2);
+                protobuf::put_varint(&mut data, encoded.len() as u64);
+                data.extend(encoded);
+                Ok(data)
+            }
+            Encoding::Msgpack => Ok(msgpack::encode(message)),
+            Encoding::Cbor => {
+                let encoded = cbor::encode(message);
+                let mut data = Vec::with_capacity(encoded.len() + 4);
+                data.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+                data.extend(encoded);
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// `--encoding ENCODING` for all stream sinks, or `--encoding NAME=ENCODING`
+/// for a single one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodingSpec {
+    sink: Option<String>,
+    encoding: Encoding,
+}
+
+impl FromStr for EncodingSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, encoding) = match spec.rsplit_once('=') {
+            Some((sink, encoding)) => (Some(sink.to_string()), encoding),
+            None => (None, spec),
+        };
+        let encoding = Encoding::from_str(encoding, true).map_err(|_| {
+            format!(
+                "invalid encoding '{}', expected json, protobuf, msgpack or cbor",
+                encoding
+            )
+        })?;
+        Ok(Self { sink, encoding })
+    }
+}
+
+/// The encoding `specs` give the sink called `name`, JSON by default.
+pub fn encoding_for(name: &str, specs: &[EncodingSpec]) -> Encoding {
+    let named = specs.iter().find(|spec| {
+        spec.sink
+            .as_deref()
+            .is_some_and(|sink| sink.eq_ignore_ascii_case(name))
+    });
+    let global = specs.iter().find(|spec| spec.sink.is_none());
+    named
+        .or(global)
+        .map_or_else(Encoding::default, |spec| spec.encoding)
+}
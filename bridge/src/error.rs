@@ -0,0 +1,90 @@
+//! The errors that stop the bridge, by what went wrong: the options, a
+//! serial port, a file to parse or a sink. `main` reports them and exits
+//! with their `exit_code`, and programs embedding the bridge can match on
+//! them instead of on message text.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BridgeError {
+    /// The options contradict each other or are incomplete.
+    #[error("{0}")]
+    Config(String),
+
+    /// No board was connected at startup.
+    #[error("No Microbit found. Is it connected? Use --port to select a port manually, or --wait-for-device to wait for it")]
+    NoDevice,
+
+    /// A board was unplugged with `--exit-on-disconnect`.
+    #[error("Lost serial port {port}: {reason}")]
+    SerialLost { port: String, reason: String },
+
+    /// A serial port couldn't be opened.
+    #[error("Failed to open serial port {port}: {source}")]
+    Serial {
+        port: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A file given in the options couldn't be read or parsed, e.g. a
+    /// `--format-file` or `--script`.
+    #[error("Can't load {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A sink couldn't be started, e.g. because its port is taken.
+    #[error("Can't start the outputs: {0}")]
+    SinkSetup(#[source] io::Error),
+
+    /// A sink failed while forwarding.
+    #[error("{sink}: {source}")]
+    Sink {
+        sink: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Anything else, e.g. a failing subcommand.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl BridgeError {
+    /// The status the process exits with, so scripts and service managers
+    /// can tell failures apart:
+    ///
+    /// - 1: anything else
+    /// - 2: the options are wrong, as for clap's own usage errors
+    /// - 3: no board was found or its port couldn't be opened
+    /// - 4: a board was lost with `--exit-on-disconnect`
+    /// - 5: a sink couldn't be started or failed
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            BridgeError::Config(_) | BridgeError::Parse { .. } => 2,
+            BridgeError::NoDevice | BridgeError::Serial { .. } => 3,
+            BridgeError::SerialLost { .. } => 4,
+            BridgeError::SinkSetup(_) | BridgeError::Sink { .. } => 5,
+            BridgeError::Io(_) => 1,
+        }
+    }
+
+    pub fn parse(path: &Path, source: io::Error) -> Self {
+        BridgeError::Parse {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn sink(name: &str, source: io::Error) -> Self {
+        BridgeError::Sink {
+            sink: name.to_string(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Rules turning the orientation stream into discrete events, e.g.
+//! `--event "tilt_left=x < -60 for 200ms"` adds `{"event":"tilt_left"}` to
+//! the stream once `x` stayed below -60° for 200 ms.
+//!
+//! Conditions are expressions of the `--script` language with the
+//! message's fields as variables. An event fires once per time its
+//! condition starts holding; the condition has to stop holding before the
+//! event can fire again. Only orientation messages are checked, so the
+//! telemetry in between neither fires nor interrupts a rule.
+
+use crate::cli;
+use crate::script::Condition;
+use crate::subscribe::MessageKind;
+use crate::transform::Transform;
+use crate::{debug, info};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct EventRule {
+    name: String,
+    condition: Condition,
+    /// How long the condition has to hold.
+    hold: Duration,
+}
+
+impl FromStr for EventRule {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=CONDITION [for DURATION], got '{}'", spec))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("event rule '{}' has no name", spec));
+        }
+
+        let (condition, hold) = match rest.rsplit_once(" for ") {
+            Some((condition, hold)) => (condition, cli::parse_interval(hold.trim())?),
+            None => (rest, Duration::ZERO),
+        };
+        let condition = Condition::parse(condition)
+            .map_err(|e| format!("invalid condition for event '{}': {}", name, e))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            condition,
+            hold,
+        })
+    }
+}
+
+/// Where a rule stands for one board.
+#[derive(Clone, Copy, Default)]
+struct RuleState {
+    /// Since when the condition holds.
+    since: Option<Instant>,
+    fired: bool,
+}
+
+pub struct EventRules {
+    rules: Vec<EventRule>,
+    /// Per rule, the state for every board by its `device` field.
+    states: Vec<HashMap<String, RuleState>>,
+}
+
+impl EventRules {
+    pub fn new(rules: &[EventRule]) -> Self {
+        for rule in rules {
+            info!("Emitting '{}' events", rule.name);
+        }
+        Self {
+            rules: rules.to_vec(),
+            states: vec![HashMap::new(); rules.len()],
+        }
+    }
+}
+
+impl Transform for EventRules {
+    fn transform(&mut self, message: Value) -> Vec<Value> {
+        if MessageKind::of(&message) != MessageKind::Orientation {
+            return vec![message];
+        }
+        let device = message.get("device").cloned();
+        let key = device.as_ref().and_then(Value::as_str).unwrap_or_default();
+        let now = Instant::now();
+
+        let mut messages = Vec::new();
+        for (rule, states) in self.rules.iter().zip(&mut self.states) {
+            let holds = rule.condition.holds(&message).unwrap_or_else(|e| {
+                debug!("Event '{}' not checked: {}", rule.name, e);
+                false
+            });
+            let state = states.entry(key.to_string()).or_default();
+            if !holds {
+                *state = RuleState::default();
+                continue;
+            }
+
+            let since = *state.since.get_or_insert(now);
+            if !state.fired && now.duration_since(since) >= rule.hold {
+                state.fired = true;
+                let mut event = json!({ "event": rule.name });
+                if let Some(device) = &device {
+                    event["device"] = device.clone();
+                }
+                messages.push(event);
+            }
+        }
+
+        messages.insert(0, message);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn events(messages: &[Value]) -> Vec<&Value> {
+        messages
+            .iter()
+            .filter(|m| m.get("event").is_some())
+            .collect()
+    }
+
+    #[test]
+    fn telemetry_does_not_interrupt_a_held_condition() {
+        let rule: EventRule = "tilt=x < -60 for 300ms".parse().unwrap();
+        let mut rules = EventRules::new(&[rule]);
+
+        let mut fired = Vec::new();
+        for _ in 0..8 {
+            fired.extend(rules.transform(json!({ "x": -70.0, "y": 0.0, "z": 0.0 })));
+            fired.extend(rules.transform(json!({ "temp": 21 })));
+            thread::sleep(Duration::from_millis(50));
+        }
+        fired.extend(rules.transform(json!({ "x": -70.0, "y": 0.0, "z": 0.0 })));
+
+        assert_eq!(events(&fired), [&json!({ "event": "tilt" })]);
+    }
+
+    #[test]
+    fn telemetry_is_passed_on_unchecked() {
+        let rule: EventRule = "hot=temp > 30".parse().unwrap();
+        let mut rules = EventRules::new(&[rule]);
+
+        assert_eq!(
+            rules.transform(json!({ "temp": 35 })),
+            [json!({ "temp": 35 })]
+        );
+    }
+
+    #[test]
+    fn an_event_fires_again_only_after_the_condition_stopped_holding() {
+        let rule: EventRule = "tilt=x < -60".parse().unwrap();
+        let mut rules = EventRules::new(&[rule]);
+        let mut fired = Vec::new();
+        for x in [-70.0, -70.0, 0.0, -70.0] {
+            fired.extend(rules.transform(json!({ "x": x, "y": 0.0, "z": 0.0 })));
+        }
+
+        assert_eq!(events(&fired).len(), 2);
+    }
+}
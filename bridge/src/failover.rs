@@ -0,0 +1,186 @@
+//! Failover groups (`--failover`): several TCP services standing in for
+//! one consumer, e.g. Blender on this machine and, while that one is down,
+//! Blender on another. The messages go to the first member in the group's
+//! order that is connected, and back to a higher one as soon as it is
+//! again. The others stay connected in the background so switching over
+//! doesn't lose a message to connecting.
+
+use crate::sink::{Sink, SinkStats};
+use crate::{error, info, warn};
+use serde_json::Value;
+use std::io;
+use std::str::FromStr;
+
+/// `--failover NAME=PRIMARY,FALLBACK,...`: the TCP sinks standing in for
+/// the consumer `NAME`, highest priority first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailoverSpec {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+impl FailoverSpec {
+    pub fn contains(&self, sink: &str) -> bool {
+        self.members
+            .iter()
+            .any(|member| member.eq_ignore_ascii_case(sink))
+    }
+}
+
+impl FromStr for FailoverSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, members) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=PRIMARY,FALLBACK, got '{}'", spec))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("failover group '{}' has no name", spec));
+        }
+        let members: Vec<String> = members
+            .split(',')
+            .map(str::trim)
+            .filter(|member| !member.is_empty())
+            .map(str::to_string)
+            .collect();
+        if members.len() < 2 {
+            return Err(format!(
+                "failover group '{}' needs at least two sinks",
+                name
+            ));
+        }
+        for (i, member) in members.iter().enumerate() {
+            if members[..i]
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(member))
+            {
+                return Err(format!("'{}' is listed twice in '{}'", member, name));
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            members,
+        })
+    }
+}
+
+/// Sends to the highest-priority reachable member of a group.
+pub struct FailoverSink {
+    name: String,
+    members: Vec<Box<dyn Sink>>,
+    /// The member the messages currently go to.
+    active: usize,
+}
+
+impl FailoverSink {
+    pub fn new(name: &str, members: Vec<Box<dyn Sink>>) -> Self {
+        Self {
+            name: name.to_string(),
+            members,
+            active: 0,
+        }
+    }
+
+    /// Switches to the first reachable member. While none is, the messages
+    /// keep going to the current one, which holds or drops them as its
+    /// `--reconnect-policy` says.
+    fn select(&mut self) {
+        let Some(best) = self.members.iter().position(|member| member.is_reachable()) else {
+            return;
+        };
+        if best == self.active {
+            return;
+        }
+        let (from, to) = (self.members[self.active].name(), self.members[best].name());
+        if best > self.active {
+            warn!(sink: self.name, "{} is unreachable, failing over to {}", from, to);
+        } else {
+            info!(sink: self.name, "{} is back, failing back from {}", to, from);
+        }
+        self.active = best;
+    }
+}
+
+impl Sink for FailoverSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.select();
+        self.members[self.active].send(message)
+    }
+
+    fn send_batch(&mut self, messages: &[Value]) -> io::Result<()> {
+        self.select();
+        self.members[self.active].send_batch(messages)
+    }
+
+    fn status(&self) -> String {
+        let active = &self.members[self.active];
+        format!("{} via {}", active.status(), active.name())
+    }
+
+    fn is_reachable(&self) -> bool {
+        self.members.iter().any(|member| member.is_reachable())
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.members.iter().map(|member| member.stats()).fold(
+            SinkStats::default(),
+            |total, stats| SinkStats {
+                queued: total.queued + stats.queued,
+                reconnects: total.reconnects + stats.reconnects,
+                ..total
+            },
+        )
+    }
+
+    /// Keeps every member connected, so the standby ones are ready.
+    fn tick(&mut self) -> io::Result<()> {
+        for member in &mut self.members {
+            member.tick()?;
+        }
+        self.select();
+        Ok(())
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.members[self.active].mark(label)
+    }
+
+    /// Closes every member, carrying on past the ones that fail.
+    fn close(&mut self) -> io::Result<()> {
+        for member in &mut self.members {
+            if let Err(e) = member.close() {
+                error!(sink: member.name(), "Error closing {}: {}", member.name(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the members of each group in `sinks` with the group, which
+/// takes the place of its first member.
+pub fn group(sinks: Vec<Box<dyn Sink>>, specs: &[FailoverSpec]) -> Vec<Box<dyn Sink>> {
+    let mut sinks: Vec<Option<Box<dyn Sink>>> = sinks.into_iter().map(Some).collect();
+    for spec in specs {
+        let mut members = Vec::new();
+        let mut first = None;
+        for name in &spec.members {
+            let Some(position) = sinks.iter().position(|sink| {
+                sink.as_ref()
+                    .is_some_and(|sink| sink.name().eq_ignore_ascii_case(name))
+            }) else {
+                continue;
+            };
+            first.get_or_insert(position);
+            members.extend(sinks[position].take());
+        }
+        if let Some(first) = first {
+            sinks[first] = Some(Box::new(FailoverSink::new(&spec.name, members)));
+        }
+    }
+    sinks.into_iter().flatten().collect()
+}
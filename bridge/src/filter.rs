@@ -0,0 +1,479 @@
+//! Smoothing filters applied to the orientation between parsing and
+//! forwarding.
+//!
+//! Filters are chained in the order given on the command line. Each device
+//! gets its own chain so readings from different boards never mix.
+
+use crate::units;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+/// Message fields the filters operate on.
+const FILTERED_FIELDS: [&str; 3] = ["x", "y", "z"];
+
+pub trait Filter: Send {
+    /// Filters one value of `field`, returning the value to forward.
+    fn apply(&mut self, field: &str, value: f64) -> f64;
+}
+
+/// A filter as selected with `--filter name[:key=value,...]`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterSpec {
+    None,
+    /// An exponential moving average, with the alpha of each of
+    /// `FILTERED_FIELDS`.
+    LowPass {
+        alphas: [f64; 3],
+    },
+    Kalman {
+        process_noise: f64,
+        measurement_noise: f64,
+    },
+    DeadZone {
+        threshold: f64,
+        release: f64,
+    },
+    Hampel {
+        window: usize,
+        sigmas: f64,
+        min_deviation: f64,
+        mode: OutlierMode,
+    },
+}
+
+/// What an outlier filter puts in place of a rejected value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutlierMode {
+    /// The last value that wasn't rejected
+    Hold,
+    /// The nearest value that wouldn't have been rejected
+    Clamp,
+}
+
+impl FilterSpec {
+    fn build(&self) -> Option<Box<dyn Filter>> {
+        match *self {
+            FilterSpec::None => None,
+            FilterSpec::LowPass { alphas } => Some(Box::new(LowPass::new(alphas))),
+            FilterSpec::Kalman {
+                process_noise,
+                measurement_noise,
+            } => Some(Box::new(Kalman::new(process_noise, measurement_noise))),
+            FilterSpec::DeadZone { threshold, release } => {
+                Some(Box::new(DeadZone::new(threshold, release)))
+            }
+            FilterSpec::Hampel {
+                window,
+                sigmas,
+                min_deviation,
+                mode,
+            } => Some(Box::new(Hampel {
+                window,
+                sigmas,
+                min_deviation,
+                mode,
+                state: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl FromStr for FilterSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+        let params = parse_params(params)?;
+        let param = |key: &str, default: f64| -> Result<f64, String> {
+            match params.get(key) {
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| format!("invalid value '{}' for {}", value, key)),
+                None => Ok(default),
+            }
+        };
+        let allow = |known: &[&str]| -> Result<(), String> {
+            match params.keys().find(|key| !known.contains(&key.as_str())) {
+                Some(key) => Err(format!("unknown parameter '{}' for filter {}", key, name)),
+                None => Ok(()),
+            }
+        };
+
+        match name {
+            "none" => {
+                allow(&[])?;
+                Ok(FilterSpec::None)
+            }
+            "lowpass" | "ema" => {
+                allow(&["alpha", "x", "y", "z"])?;
+                let alpha = param("alpha", 0.2)?;
+                let alphas = [param("x", alpha)?, param("y", alpha)?, param("z", alpha)?];
+                if !alphas.iter().all(|&alpha| alpha > 0.0 && alpha <= 1.0) {
+                    return Err(format!("{} alpha must be in (0, 1]", name));
+                }
+                Ok(FilterSpec::LowPass { alphas })
+            }
+            "kalman" => {
+                allow(&["q", "r"])?;
+                let process_noise = param("q", 0.5)?;
+                let measurement_noise = param("r", 4.0)?;
+                if process_noise <= 0.0 || measurement_noise <= 0.0 {
+                    return Err("kalman q and r must be positive".to_string());
+                }
+                Ok(FilterSpec::Kalman {
+                    process_noise,
+                    measurement_noise,
+                })
+            }
+            "deadzone" => {
+                allow(&["threshold", "release"])?;
+                let threshold = param("threshold", 1.0)?;
+                let release = param("release", threshold / 2.0)?;
+                if threshold <= 0.0 || release <= 0.0 {
+                    return Err("deadzone threshold and release must be positive".to_string());
+                }
+                if release > threshold {
+                    return Err("deadzone release can't be larger than the threshold".to_string());
+                }
+                Ok(FilterSpec::DeadZone { threshold, release })
+            }
+            "hampel" => {
+                allow(&["window", "k", "min", "mode"])?;
+                let window = param("window", 7.0)?;
+                let sigmas = param("k", 3.0)?;
+                let min_deviation = param("min", 5.0)?;
+                if window.fract() != 0.0 || !(3.0..=100.0).contains(&window) {
+                    return Err("hampel window must be a whole number from 3 to 100".to_string());
+                }
+                if sigmas <= 0.0 || min_deviation < 0.0 {
+                    return Err("hampel k must be positive and min not negative".to_string());
+                }
+                let mode = match params.get("mode").map(String::as_str) {
+                    None | Some("hold") => OutlierMode::Hold,
+                    Some("clamp") => OutlierMode::Clamp,
+                    Some(mode) => {
+                        return Err(format!(
+                            "unknown hampel mode '{}', expected hold or clamp",
+                            mode
+                        ))
+                    }
+                };
+                Ok(FilterSpec::Hampel {
+                    window: window as usize,
+                    sigmas,
+                    min_deviation,
+                    mode,
+                })
+            }
+            _ => Err(format!(
+                "unknown filter '{}', expected none, lowpass, ema, kalman, deadzone or hampel",
+                name
+            )),
+        }
+    }
+}
+
+/// Writes the spec the way `--filter` takes it, e.g. `kalman:q=0.5,r=4`.
+impl fmt::Display for FilterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterSpec::None => write!(f, "none"),
+            FilterSpec::LowPass { alphas: [x, y, z] } if x == y && y == z => {
+                write!(f, "ema:alpha={}", x)
+            }
+            FilterSpec::LowPass { alphas: [x, y, z] } => write!(f, "ema:x={},y={},z={}", x, y, z),
+            FilterSpec::Kalman {
+                process_noise,
+                measurement_noise,
+            } => write!(f, "kalman:q={},r={}", process_noise, measurement_noise),
+            FilterSpec::DeadZone { threshold, release } => {
+                write!(f, "deadzone:threshold={},release={}", threshold, release)
+            }
+            FilterSpec::Hampel {
+                window,
+                sigmas,
+                min_deviation,
+                mode,
+            } => {
+                let mode = match mode {
+                    OutlierMode::Hold => "hold",
+                    OutlierMode::Clamp => "clamp",
+                };
+                write!(
+                    f,
+                    "hampel:window={},k={},min={},mode={}",
+                    window, sigmas, min_deviation, mode
+                )
+            }
+        }
+    }
+}
+
+/// Parses `key=value,key=value` filter parameters.
+fn parse_params(params: &str) -> Result<HashMap<String, String>, String> {
+    params
+        .split(',')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            param
+                .split_once('=')
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| format!("expected key=value, got '{}'", param))
+        })
+        .collect()
+}
+
+pub struct FilterPipeline {
+    specs: Vec<FilterSpec>,
+    chains: HashMap<String, Vec<Box<dyn Filter>>>,
+    /// The last yaw per device as received and as unwrapped. The filters
+    /// see the yaw continue past ±180° rather than jump to the other end,
+    /// so smoothing a board turning through south doesn't swing it around.
+    headings: HashMap<String, (f64, f64)>,
+}
+
+impl FilterPipeline {
+    pub fn new(specs: &[FilterSpec]) -> Self {
+        Self {
+            specs: specs.to_vec(),
+            chains: HashMap::new(),
+            headings: HashMap::new(),
+        }
+    }
+
+    pub fn specs(&self) -> &[FilterSpec] {
+        &self.specs
+    }
+
+    /// Replaces the filters, which start over from the next message.
+    pub fn set_specs(&mut self, specs: &[FilterSpec]) {
+        *self = Self::new(specs);
+    }
+
+    pub fn apply(&mut self, message: &mut Value) {
+        if self.specs.is_empty() {
+            return;
+        }
+        let Value::Object(fields) = message else {
+            return;
+        };
+
+        let device = fields
+            .get("device")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let specs = &self.specs;
+        let chain = self
+            .chains
+            .entry(device.clone())
+            .or_insert_with(|| specs.iter().filter_map(FilterSpec::build).collect());
+
+        for field in FILTERED_FIELDS {
+            let Some(mut value) = fields.get(field).and_then(Value::as_f64) else {
+                continue;
+            };
+            let heading = field == "z";
+            if heading {
+                let (last, unwrapped) = self
+                    .headings
+                    .entry(device.clone())
+                    .or_insert((value, value));
+                *unwrapped += units::wrap_degrees(value - *last);
+                *last = value;
+                value = *unwrapped;
+            }
+            for filter in chain.iter_mut() {
+                value = filter.apply(field, value);
+            }
+            if heading {
+                value = units::wrap_degrees(value);
+            }
+            fields.insert(field.to_string(), value.into());
+        }
+    }
+}
+
+/// Exponential low-pass, or moving average: `y += alpha * (x - y)`.
+struct LowPass {
+    alphas: [f64; 3],
+    state: HashMap<String, f64>,
+}
+
+impl LowPass {
+    fn new(alphas: [f64; 3]) -> Self {
+        Self {
+            alphas,
+            state: HashMap::new(),
+        }
+    }
+}
+
+impl Filter for LowPass {
+    fn apply(&mut self, field: &str, value: f64) -> f64 {
+        let alpha = FILTERED_FIELDS
+            .iter()
+            .position(|&filtered| filtered == field)
+            .map_or(1.0, |axis| self.alphas[axis]);
+        let state = self.state.entry(field.to_string()).or_insert(value);
+        *state += alpha * (value - *state);
+        *state
+    }
+}
+
+/// One-dimensional Kalman filter per field with a constant-value model.
+struct Kalman {
+    process_noise: f64,
+    measurement_noise: f64,
+    state: HashMap<String, (f64, f64)>,
+}
+
+impl Kalman {
+    fn new(process_noise: f64, measurement_noise: f64) -> Self {
+        Self {
+            process_noise,
+            measurement_noise,
+            state: HashMap::new(),
+        }
+    }
+}
+
+impl Filter for Kalman {
+    fn apply(&mut self, field: &str, value: f64) -> f64 {
+        let (estimate, error) = self
+            .state
+            .entry(field.to_string())
+            .or_insert((value, self.measurement_noise));
+
+        *error += self.process_noise;
+        let gain = *error / (*error + self.measurement_noise);
+        *estimate += gain * (value - *estimate);
+        *error *= 1.0 - gain;
+        *estimate
+    }
+}
+
+/// Samples the input has to stay within the release band before a dead
+/// zone holds it again.
+const DEAD_ZONE_SETTLE: u32 = 5;
+
+/// Holds a field's value until the input moves more than `threshold` away
+/// from it, then follows the input until it settles within `release` of one
+/// position. Settling on a smaller band than the one that started the
+/// movement keeps noise at the edge of the zone from toggling between the
+/// two.
+struct DeadZone {
+    threshold: f64,
+    release: f64,
+    state: HashMap<String, DeadZoneState>,
+}
+
+enum DeadZoneState {
+    Holding(f64),
+    /// Following the input, with where it last moved to and how many
+    /// samples it stayed there since.
+    Moving {
+        anchor: f64,
+        still: u32,
+    },
+}
+
+impl DeadZone {
+    fn new(threshold: f64, release: f64) -> Self {
+        Self {
+            threshold,
+            release,
+            state: HashMap::new(),
+        }
+    }
+}
+
+impl Filter for DeadZone {
+    fn apply(&mut self, field: &str, value: f64) -> f64 {
+        let state = self
+            .state
+            .entry(field.to_string())
+            .or_insert(DeadZoneState::Holding(value));
+
+        match state {
+            DeadZoneState::Holding(held) if (value - *held).abs() <= self.threshold => *held,
+            DeadZoneState::Holding(_) => {
+                *state = DeadZoneState::Moving {
+                    anchor: value,
+                    still: 0,
+                };
+                value
+            }
+            DeadZoneState::Moving { anchor, still } => {
+                if (value - *anchor).abs() > self.release {
+                    *anchor = value;
+                    *still = 0;
+                } else {
+                    *still += 1;
+                }
+                if *still >= DEAD_ZONE_SETTLE {
+                    *state = DeadZoneState::Holding(value);
+                }
+                value
+            }
+        }
+    }
+}
+
+/// Scales the median absolute deviation to the standard deviation of
+/// normally distributed noise.
+const MAD_SCALE: f64 = 1.4826;
+
+/// Hampel filter: rejects a value that lies more than `sigmas` standard
+/// deviations, estimated from the median absolute deviation, from the
+/// median of the last `window` values. `min_deviation` keeps a board lying
+/// still, whose readings barely deviate at all, from rejecting every
+/// movement. Rejected values still enter the window, so a real jump is
+/// accepted once it makes up half of it.
+struct Hampel {
+    window: usize,
+    sigmas: f64,
+    min_deviation: f64,
+    mode: OutlierMode,
+    /// Per field, the recent values and the last one passed on.
+    state: HashMap<String, (VecDeque<f64>, f64)>,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+impl Filter for Hampel {
+    fn apply(&mut self, field: &str, value: f64) -> f64 {
+        let (recent, last) = self
+            .state
+            .entry(field.to_string())
+            .or_insert_with(|| (VecDeque::new(), value));
+
+        let mut output = value;
+        if recent.len() == self.window {
+            let mut values: Vec<f64> = recent.iter().copied().collect();
+            let center = median(&mut values);
+            let mut deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+            let limit = (self.sigmas * MAD_SCALE * median(&mut deviations)).max(self.min_deviation);
+            if (value - center).abs() > limit {
+                output = match self.mode {
+                    OutlierMode::Hold => *last,
+                    OutlierMode::Clamp => value.clamp(center - limit, center + limit),
+                };
+            }
+            recent.pop_front();
+        }
+        recent.push_back(value);
+        *last = output;
+        output
+    }
+}
@@ -0,0 +1,202 @@
+//! Per-sink output schemas, so every consumer can get the fields it
+//! expects from the same message: renamed, filtered and rounded.
+//!
+//! Formats can also be kept in a file given with `--format-file`, one
+//! `--format` value per line:
+//!
+//! ```text
+//! # Blender's naming for the whole setup
+//! pitch=x,roll=y,yaw=z,-seq,-ts
+//! Visualizer:+x,+y,decimals=1
+//! ```
+
+use crate::info;
+use crate::sink::Sink;
+use crate::transform::{self, Transform};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// `--format pitch=x,roll=y,-seq,decimals=1` for all sinks, or
+/// `--format NAME:...` for a single one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Format {
+    sink: Option<String>,
+    /// Output field and the input field it's taken from.
+    renames: Vec<(String, String)>,
+    /// Fields kept, besides renamed ones, if not empty.
+    include: Vec<String>,
+    exclude: Vec<String>,
+    decimals: Option<i32>,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, rules) = match spec.split_once(':') {
+            Some((sink, rules)) => (Some(sink.to_string()), rules),
+            None => (None, spec),
+        };
+
+        let mut format = Self {
+            sink,
+            renames: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            decimals: None,
+        };
+        for rule in rules.split(',').map(str::trim) {
+            let invalid = || {
+                format!(
+                    "invalid format rule '{}', expected NEW=FIELD, +FIELD, -FIELD or decimals=N",
+                    rule
+                )
+            };
+            if let Some(field) = rule.strip_prefix('+') {
+                format.include.push(field.to_string());
+            } else if let Some(field) = rule.strip_prefix('-') {
+                format.exclude.push(field.to_string());
+            } else if let Some(decimals) = rule.strip_prefix("decimals=") {
+                let decimals = decimals.parse().map_err(|_| invalid())?;
+                if !(0..=15).contains(&decimals) {
+                    return Err(format!(
+                        "decimals must be between 0 and 15, got {}",
+                        decimals
+                    ));
+                }
+                format.decimals = Some(decimals);
+            } else {
+                let (target, source) = rule.split_once('=').ok_or_else(invalid)?;
+                if target.trim().is_empty() || source.trim().is_empty() {
+                    return Err(invalid());
+                }
+                format
+                    .renames
+                    .push((target.trim().to_string(), source.trim().to_string()));
+            }
+        }
+        if [&format.include, &format.exclude]
+            .iter()
+            .any(|fields| fields.iter().any(String::is_empty))
+        {
+            return Err(format!("invalid format '{}', missing a field name", spec));
+        }
+        Ok(format)
+    }
+}
+
+impl Transform for Format {
+    fn transform(&mut self, message: Value) -> Vec<Value> {
+        let Value::Object(mut fields) = message else {
+            return vec![message];
+        };
+
+        // Take every source first, so swapping two names works
+        let renamed: Vec<_> = self
+            .renames
+            .iter()
+            .filter_map(|(target, source)| Some((target.clone(), fields.remove(source)?)))
+            .collect();
+        if !self.include.is_empty() {
+            fields.retain(|name, _| self.include.contains(name));
+        }
+        fields.extend(renamed);
+        for name in &self.exclude {
+            fields.remove(name);
+        }
+
+        if let Some(decimals) = self.decimals {
+            round(&mut fields, decimals);
+        }
+        vec![Value::Object(fields)]
+    }
+}
+
+impl Format {
+    /// The names `fields` go by after formatting.
+    pub fn fields(&self, fields: &[&str]) -> Vec<String> {
+        let renamed: Vec<String> = self
+            .renames
+            .iter()
+            .filter(|(_, source)| fields.contains(&source.as_str()))
+            .map(|(target, _)| target.clone())
+            .collect();
+        let mut fields: Vec<String> = fields
+            .iter()
+            .filter(|name| !self.renames.iter().any(|(_, source)| source == *name))
+            .filter(|name| self.include.is_empty() || self.include.iter().any(|kept| kept == *name))
+            .map(|name| name.to_string())
+            .collect();
+        for target in renamed {
+            if !fields.contains(&target) {
+                fields.push(target);
+            }
+        }
+        fields.retain(|name| !self.exclude.contains(name));
+        fields
+    }
+}
+
+/// Reads the formats in the file at `path`, skipping blank lines and `#`
+/// comments.
+pub fn load(path: &Path) -> io::Result<Vec<Format>> {
+    let contents = fs::read_to_string(path)?;
+    let formats = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            line.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number, e),
+                )
+            })
+        })
+        .collect::<io::Result<Vec<Format>>>()?;
+
+    info!("Loaded {} format(s) from {}", formats.len(), path.display());
+    Ok(formats)
+}
+
+/// Rounds every fractional number to `decimals` places.
+fn round(fields: &mut Map<String, Value>, decimals: i32) {
+    let scale = 10f64.powi(decimals);
+    for value in fields.values_mut() {
+        if !value.is_f64() {
+            continue;
+        }
+        if let Some(number) = value.as_f64() {
+            *value = ((number * scale).round() / scale).into();
+        }
+    }
+}
+
+/// Wraps `sink` so it receives messages in the format given for it, if
+/// any. A format given for the sink's name takes precedence over a global
+/// one.
+pub fn apply(sink: Box<dyn Sink>, formats: &[Format]) -> Box<dyn Sink> {
+    match for_sink(formats, sink.name()) {
+        Some(format) => {
+            info!("Formatting messages for {}", sink.name());
+            transform::wrap(sink, format.clone())
+        }
+        None => sink,
+    }
+}
+
+/// The format of `formats` for the sink called `name`, if any.
+pub fn for_sink<'a>(formats: &'a [Format], name: &str) -> Option<&'a Format> {
+    let named = formats.iter().find(|format| {
+        format
+            .sink
+            .as_deref()
+            .is_some_and(|sink| sink.eq_ignore_ascii_case(name))
+    });
+    let global = formats.iter().find(|format| format.sink.is_none());
+    named.or(global)
+}
@@ -0,0 +1,128 @@
+//! Splitting the serial byte stream into messages.
+
+use crate::serial::WireFormat;
+use crate::{binary, cbor, cobs};
+
+/// Longest message kept waiting for its delimiter. The firmware's messages
+/// are a few hundred bytes at most, so anything longer means the delimiter
+/// was lost or the port carries something else.
+pub const MAX_FRAME_LEN: usize = 1024;
+
+/// Appends received bytes to `buffer` and returns every line they
+/// complete, trimmed, skipping blank ones. The rest of an incomplete line
+/// stays in `buffer` for the next read.
+pub fn split_lines(data: &[u8], buffer: &mut Vec<u8>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for &byte in data {
+        if byte == b'\n' {
+            // Bytes are only decoded once the line is complete, so a
+            // character split across reads survives
+            let line = String::from_utf8_lossy(buffer).trim().to_string();
+            buffer.clear();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        } else {
+            buffer.push(byte);
+        }
+    }
+    lines
+}
+
+/// Converts a COBS frame holding a CBOR message into a JSON line.
+pub fn decode_frame(frame: &[u8]) -> Result<String, String> {
+    let message = cbor::decode(&cobs::decode(frame)?)?;
+    Ok(message.to_string())
+}
+
+/// Turns the bytes read from one board into JSON lines, keeping whatever
+/// belongs to an incomplete message for the next read.
+///
+/// A message that grows past `MAX_FRAME_LEN` is dropped together with
+/// everything up to the next delimiter, so a lost delimiter or a stream
+/// joined mid-message costs that one message rather than all that follow.
+pub struct Framer {
+    format: WireFormat,
+    line: Vec<u8>,
+    frame: Vec<u8>,
+    /// Skipping the rest of an overlong message.
+    discarding: bool,
+    resyncs: u64,
+}
+
+impl Framer {
+    pub fn new(format: WireFormat) -> Self {
+        Self {
+            format,
+            line: Vec::new(),
+            frame: Vec::new(),
+            discarding: false,
+            resyncs: 0,
+        }
+    }
+
+    /// Returns every message completed by `data`, in order, or why a frame
+    /// couldn't be decoded.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<String, String>> {
+        if self.format == WireFormat::Binary {
+            // No delimiter, the frames are found by their sync bytes and
+            // carry their length
+            return binary::split_frames(data, &mut self.frame)
+                .into_iter()
+                .map(|message| message.map(|message| message.to_string()))
+                .collect();
+        }
+
+        let delimiter = match self.format {
+            WireFormat::Json => b'\n',
+            _ => 0,
+        };
+        let mut messages = Vec::new();
+        for segment in data.split_inclusive(|&byte| byte == delimiter) {
+            let complete = segment.last() == Some(&delimiter);
+            if self.discarding {
+                self.discarding = !complete;
+                continue;
+            }
+
+            let pending = match self.format {
+                WireFormat::Json => self.line.len(),
+                _ => self.frame.len(),
+            };
+            if pending + segment.len() - complete as usize > MAX_FRAME_LEN {
+                self.clear();
+                self.discarding = !complete;
+                self.resyncs += 1;
+                messages.push(Err(format!(
+                    "no delimiter within {} bytes, skipped to the next one ({} resync(s) so far)",
+                    MAX_FRAME_LEN, self.resyncs
+                )));
+                continue;
+            }
+
+            match self.format {
+                WireFormat::Json => {
+                    messages.extend(split_lines(segment, &mut self.line).into_iter().map(Ok))
+                }
+                _ => messages.extend(
+                    cobs::split_frames(segment, &mut self.frame)
+                        .iter()
+                        .map(|frame| decode_frame(frame)),
+                ),
+            }
+        }
+        messages
+    }
+
+    /// How often an overlong message was dropped.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// Forgets a partially received message, e.g. after a reconnect.
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.frame.clear();
+        self.discarding = false;
+    }
+}
@@ -0,0 +1,119 @@
+//! Orientation from the raw accelerometer and magnetometer readings the
+//! firmware sends along (`ax`..`az`, `mx`..`mz`), smoothed before the
+//! angles are computed rather than after, with the heading from a
+//! tilt-compensated compass like the firmware's.
+//!
+//! Messages with angular velocities (`gx`..`gz`, from a gyroscope on the
+//! edge connector) get a complementary filter on top: the angles follow
+//! the gyroscope over short times, and the accelerometer and compass keep
+//! them from drifting.
+
+use crate::transform::Transform;
+use crate::units;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const ACCEL_FIELDS: [&str; 3] = ["ax", "ay", "az"];
+const MAG_FIELDS: [&str; 3] = ["mx", "my", "mz"];
+/// Optional angular velocities of `x`, `y` and `z`, in degrees per second.
+pub const GYRO_FIELDS: [&str; 3] = ["gx", "gy", "gz"];
+
+/// Weight of a new reading in the smoothed vectors. The vectors are
+/// smoothed rather than the angles, so the heading can't jump at ±180°.
+const SMOOTHING: f64 = 0.3;
+/// Weight of the gyroscope's prediction against the absolute angles.
+const GYRO_WEIGHT: f64 = 0.98;
+/// Longest time the gyroscope is integrated over, so a gap in the stream
+/// doesn't make the prediction run off.
+const MAX_GYRO_STEP: Duration = Duration::from_millis(100);
+
+type Vector = [f64; 3];
+
+#[derive(Default)]
+pub struct Fusion {
+    /// Smoothed accelerometer and magnetometer vectors per device.
+    state: HashMap<String, (Vector, Vector)>,
+    /// When each device's gyroscope was last read, and the angles then.
+    gyro: HashMap<String, (Instant, Vector)>,
+}
+
+impl Transform for Fusion {
+    fn transform(&mut self, mut message: Value) -> Vec<Value> {
+        let Value::Object(fields) = &mut message else {
+            return vec![message];
+        };
+        let (Some(accel), Some(mag)) = (vector(fields, ACCEL_FIELDS), vector(fields, MAG_FIELDS))
+        else {
+            // Older firmware, keep the board's own angles
+            return vec![message];
+        };
+
+        let device = fields
+            .get("device")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let (accel, mag) = match self.state.get_mut(device) {
+            Some((smoothed_accel, smoothed_mag)) => {
+                smooth(smoothed_accel, accel);
+                smooth(smoothed_mag, mag);
+                (*smoothed_accel, *smoothed_mag)
+            }
+            None => {
+                self.state.insert(device.to_string(), (accel, mag));
+                (accel, mag)
+            }
+        };
+
+        let (pitch, roll, heading) = orientation(accel, mag);
+        let mut angles = [pitch, roll, heading].map(f64::to_degrees);
+        if let Some(rates) = vector(fields, GYRO_FIELDS) {
+            let now = Instant::now();
+            if let Some((last, previous)) = self.gyro.get(device) {
+                let step = now.duration_since(*last).min(MAX_GYRO_STEP).as_secs_f64();
+                for ((angle, previous), rate) in angles.iter_mut().zip(previous).zip(rates) {
+                    let predicted = previous + rate * step;
+                    *angle = units::wrap_degrees(
+                        *angle + GYRO_WEIGHT * units::wrap_degrees(predicted - *angle),
+                    );
+                }
+            }
+            self.gyro.insert(device.to_string(), (now, angles));
+        }
+
+        for (field, angle) in ["x", "y", "z"].into_iter().zip(angles) {
+            fields.insert(field.to_string(), angle.into());
+        }
+        vec![message]
+    }
+}
+
+fn vector(fields: &serde_json::Map<String, Value>, names: [&str; 3]) -> Option<Vector> {
+    let mut vector = [0.0; 3];
+    for (component, name) in vector.iter_mut().zip(names) {
+        *component = fields.get(name)?.as_f64()?;
+    }
+    Some(vector)
+}
+
+fn smooth(smoothed: &mut Vector, reading: Vector) {
+    for (smoothed, reading) in smoothed.iter_mut().zip(reading) {
+        *smoothed += SMOOTHING * (reading - *smoothed);
+    }
+}
+
+/// Pitch and roll as defined by the firmware, plus the heading in radians.
+fn orientation([ax, ay, az]: Vector, [mx, my, mz]: Vector) -> (f64, f64, f64) {
+    let pitch = ay.atan2(ax.hypot(az));
+    let roll = ax.atan2(ay.hypot(az));
+
+    // Rotate the magnetic field back into the horizontal plane, using the
+    // tilt around the x axis (phi) and y axis (theta)
+    let phi = ay.atan2(az);
+    let theta = (-ax).atan2(ay * phi.sin() + az * phi.cos());
+    let horizontal_x =
+        mx * theta.cos() + my * theta.sin() * phi.sin() + mz * theta.sin() * phi.cos();
+    let horizontal_y = mz * phi.sin() - my * phi.cos();
+
+    (pitch, roll, horizontal_y.atan2(horizontal_x))
+}
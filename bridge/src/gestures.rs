@@ -0,0 +1,307 @@
+//! Recognition of gestures in the motion of each board, added to the
+//! stream as `{"gesture":"shake","confidence":0.8}` after the message that
+//! completed it.
+//!
+//! Flips, shakes and double taps are found in the accelerometer readings
+//! (`ax`, `ay`, `az` in milli-g), circles in the tilt. Without accelerometer
+//! readings, as from the simulator, shakes are found in the orientation
+//! turning back and forth instead.
+
+use crate::info;
+use crate::transform::Transform;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::TAU;
+use std::time::Instant;
+
+/// Seconds of motion kept for the detectors.
+const HISTORY: f64 = 2.0;
+/// Gestures less certain than this aren't reported.
+const MIN_CONFIDENCE: f64 = 0.5;
+
+/// Acceleration of a board lying still, in milli-g.
+const GRAVITY: f64 = 1000.0;
+/// How far `az` has to point up before and down after a flip.
+const FLIP_LEVEL: f64 = 700.0;
+/// Seconds a flip may take.
+const FLIP_TIME: f64 = 1.0;
+/// Acceleration beyond gravity that counts as a jolt of a shake.
+const SHAKE_JOLT: f64 = 800.0;
+/// Jolts that make a shake within a second.
+const SHAKE_JOLTS: usize = 4;
+/// Times the yaw has to turn around within a second for a shake.
+const SHAKE_SWINGS: usize = 3;
+/// Smallest swing of the yaw counted for a shake, in degrees.
+const SHAKE_SWING: f64 = 10.0;
+/// Acceleration beyond gravity that counts as a tap.
+const TAP_JOLT: f64 = 1200.0;
+/// Shortest and longest time between the taps of a double tap, in seconds.
+const TAP_GAP: (f64, f64) = (0.1, 0.5);
+/// Smallest tilt at which the tilt's direction counts for a circle.
+const CIRCLE_TILT: f64 = 10.0;
+
+#[derive(Clone, Copy)]
+struct Sample {
+    /// Seconds since the detector started.
+    t: f64,
+    pitch: f64,
+    roll: f64,
+    yaw: f64,
+    accel: Option<[f64; 3]>,
+}
+
+impl Sample {
+    /// The acceleration beyond gravity, in milli-g.
+    fn jolt(&self) -> Option<f64> {
+        self.accel
+            .map(|[x, y, z]| ((x * x + y * y + z * z).sqrt() - GRAVITY).abs())
+    }
+}
+
+/// What's known about one board's motion.
+#[derive(Default)]
+struct Board {
+    history: VecDeque<Sample>,
+    /// When each gesture was last recognized.
+    last: HashMap<&'static str, f64>,
+}
+
+impl Board {
+    fn recognize(&mut self) -> Vec<(&'static str, f64, Option<&'static str>)> {
+        let now = self.history.back().map_or(0.0, |sample| sample.t);
+        let detected = [
+            ("flip", self.flip(), None),
+            ("double_tap", self.double_tap(), None),
+            ("shake", self.shake(), None),
+            match self.circle() {
+                Some((confidence, direction)) => ("circle", Some(confidence), Some(direction)),
+                None => ("circle", None, None),
+            },
+        ];
+
+        let mut recognized = Vec::new();
+        for (name, confidence, detail) in detected {
+            let Some(confidence) = confidence.filter(|&c| c >= MIN_CONFIDENCE) else {
+                continue;
+            };
+            self.last.insert(name, now);
+            recognized.push((name, confidence.min(1.0), detail));
+        }
+        recognized
+    }
+
+    /// The samples of the last `seconds`, leaving out the ones that were
+    /// already part of the last time `gesture` was recognized.
+    fn since(&self, gesture: &str, seconds: f64) -> impl Iterator<Item = &Sample> {
+        let now = self.history.back().map_or(0.0, |sample| sample.t);
+        let start = self.last.get(gesture).copied().unwrap_or(f64::MIN);
+        self.history
+            .iter()
+            .filter(move |sample| now - sample.t <= seconds && sample.t > start)
+    }
+
+    /// `az` went from pointing up to pointing down, or the other way.
+    fn flip(&self) -> Option<f64> {
+        let now = self.history.back()?.accel?[2];
+        if now.abs() < FLIP_LEVEL {
+            return None;
+        }
+        let before = self
+            .since("flip", FLIP_TIME)
+            .filter_map(|sample| sample.accel)
+            .map(|[_, _, z]| z)
+            .find(|z| z.signum() != now.signum() && z.abs() >= FLIP_LEVEL)?;
+        Some(before.abs().min(now.abs()) / GRAVITY)
+    }
+
+    /// The times at which the jolt rose above `threshold` in the last
+    /// `seconds`, with the highest jolt of each.
+    fn jolts(&self, gesture: &str, seconds: f64, threshold: f64) -> Vec<(f64, f64)> {
+        let mut jolts: Vec<(f64, f64)> = Vec::new();
+        let mut above = false;
+        for sample in self.since(gesture, seconds) {
+            let jolt = sample.jolt().unwrap_or(0.0);
+            match (above, jolt > threshold) {
+                (false, true) => jolts.push((sample.t, jolt)),
+                (true, true) => {
+                    if let Some(last) = jolts.last_mut() {
+                        last.1 = last.1.max(jolt);
+                    }
+                }
+                _ => {}
+            }
+            above = jolt > threshold;
+        }
+        jolts
+    }
+
+    /// Two short taps close together, with no jolt shortly before or after.
+    fn double_tap(&self) -> Option<f64> {
+        let taps = self.jolts("double_tap", HISTORY, TAP_JOLT);
+        let [.., (first, first_jolt), (second, second_jolt)] = taps[..] else {
+            return None;
+        };
+        if taps.len() > 2 && first - taps[taps.len() - 3].0 <= TAP_GAP.1 {
+            return None;
+        }
+        // Only once no third tap can follow, or the start of a shake would
+        // count as well
+        if self.history.back()?.t - second <= TAP_GAP.1
+            || !(TAP_GAP.0..=TAP_GAP.1).contains(&(second - first))
+        {
+            return None;
+        }
+        Some(first_jolt.min(second_jolt) / (TAP_JOLT * 2.0) + 0.5)
+    }
+
+    /// Several jolts within a second, or without an accelerometer, the
+    /// board turning back and forth.
+    fn shake(&self) -> Option<f64> {
+        let (count, needed) = if self.history.back()?.accel.is_some() {
+            (self.jolts("shake", 1.0, SHAKE_JOLT).len(), SHAKE_JOLTS)
+        } else {
+            (
+                swings(self.since("shake", 1.0).map(|sample| sample.yaw)),
+                SHAKE_SWINGS,
+            )
+        };
+        (count >= needed).then(|| count as f64 / (needed * 2) as f64 + 0.5)
+    }
+
+    /// The direction of the tilt went once around, with a steady tilt.
+    fn circle(&self) -> Option<(f64, &'static str)> {
+        let tilts: Vec<(f64, f64)> = self
+            .since("circle", HISTORY)
+            .map(|sample| (sample.roll, sample.pitch))
+            .filter(|(roll, pitch)| roll.hypot(*pitch) >= CIRCLE_TILT)
+            .collect();
+        if tilts.len() < 2 {
+            return None;
+        }
+
+        let mut turned = 0.0;
+        for pair in tilts.windows(2) {
+            let [(x0, y0), (x1, y1)] = [pair[0], pair[1]];
+            let mut step = y1.atan2(x1) - y0.atan2(x0);
+            if step > TAU / 2.0 {
+                step -= TAU;
+            } else if step < -TAU / 2.0 {
+                step += TAU;
+            }
+            turned += step;
+        }
+        if turned.abs() < TAU * 0.9 {
+            return None;
+        }
+
+        // A circle rather than an ellipse squashed flat
+        let radii: Vec<f64> = tilts.iter().map(|(x, y)| x.hypot(*y)).collect();
+        let mean = radii.iter().sum::<f64>() / radii.len() as f64;
+        let spread =
+            (radii.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / radii.len() as f64).sqrt();
+        let confidence = (turned.abs() / TAU).min(1.0) * (1.0 - spread / mean).max(0.0);
+        let direction = if turned > 0.0 {
+            "counterclockwise"
+        } else {
+            "clockwise"
+        };
+        Some((confidence, direction))
+    }
+}
+
+/// How often `angles` turned around after moving at least `SHAKE_SWING`.
+fn swings(angles: impl Iterator<Item = f64>) -> usize {
+    let mut swings = 0;
+    let mut extreme = None::<f64>;
+    let mut rising = None::<bool>;
+    for angle in angles {
+        let Some(from) = extreme else {
+            extreme = Some(angle);
+            continue;
+        };
+        let moved = angle - from;
+        match rising {
+            Some(true) if angle >= from => extreme = Some(angle),
+            Some(false) if angle <= from => extreme = Some(angle),
+            _ if moved.abs() >= SHAKE_SWING => {
+                if rising.is_some() {
+                    swings += 1;
+                }
+                rising = Some(moved > 0.0);
+                extreme = Some(angle);
+            }
+            _ => {}
+        }
+    }
+    swings
+}
+
+pub struct GestureDetector {
+    started: Instant,
+    /// Per board, by its `device` field.
+    boards: HashMap<String, Board>,
+}
+
+impl GestureDetector {
+    pub fn new() -> Self {
+        info!("Recognizing gestures");
+        Self {
+            started: Instant::now(),
+            boards: HashMap::new(),
+        }
+    }
+}
+
+impl Default for GestureDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transform for GestureDetector {
+    fn transform(&mut self, message: Value) -> Vec<Value> {
+        let field = |name| message.get(name).and_then(Value::as_f64);
+        let (Some(pitch), Some(roll)) = (field("x"), field("y")) else {
+            return vec![message];
+        };
+        let accel = match (field("ax"), field("ay"), field("az")) {
+            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+            _ => None,
+        };
+        let sample = Sample {
+            t: self.started.elapsed().as_secs_f64(),
+            pitch,
+            roll,
+            yaw: field("z").unwrap_or(0.0),
+            accel,
+        };
+
+        let device = message.get("device").cloned();
+        let key = device.as_ref().and_then(Value::as_str).unwrap_or_default();
+        let board = self.boards.entry(key.to_string()).or_default();
+        board.history.push_back(sample);
+        while board
+            .history
+            .front()
+            .is_some_and(|oldest| sample.t - oldest.t > HISTORY)
+        {
+            board.history.pop_front();
+        }
+
+        let mut messages = vec![message.clone()];
+        for (name, confidence, direction) in board.recognize() {
+            let mut gesture = json!({
+                "gesture": name,
+                "confidence": (confidence * 100.0).round() / 100.0,
+            });
+            if let Some(direction) = direction {
+                gesture["direction"] = direction.into();
+            }
+            if let Some(device) = &device {
+                gesture["device"] = device.clone();
+            }
+            messages.push(gesture);
+        }
+        messages
+    }
+}
@@ -0,0 +1,536 @@
+//! A gRPC server for the `microblender.Bridge` service defined in
+//! `proto/microblender.proto`, over cleartext HTTP/2:
+//!
+//! - `StreamOrientation` streams every forwarded message until the client
+//!   cancels.
+//! - `Control` tares the boards or limits the rate of the streams.
+//!
+//! Messages are encoded by hand (see `protobuf`), like the other protocols
+//! of the bridge.
+//! A stream whose client doesn't keep up (its flow-control window is used
+//! up) skips messages instead of queueing them.
+
+use crate::command::BoardLink;
+use crate::http2::{self, Frame, HpackDecoder};
+use crate::protobuf::{
+    self, put_bytes_field, put_varint_field, take, take_varint, FIXED32, FIXED64, LENGTH_DELIMITED,
+    VARINT,
+};
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const STREAM_PATH: &str = "/microblender.Bridge/StreamOrientation";
+const CONTROL_PATH: &str = "/microblender.Bridge/Control";
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// gRPC status codes
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const UNIMPLEMENTED: u32 = 12;
+
+/// One client connection, shared by the thread reading its requests and
+/// the sink writing the streams.
+struct Peer {
+    stream: TcpStream,
+    /// How much more DATA the client accepts on the connection, and on
+    /// each of its orientation streams.
+    window: i64,
+    initial_window: i64,
+    subscriptions: HashMap<u32, i64>,
+}
+
+impl Peer {
+    fn write_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        http2::write_frame(&mut self.stream, kind, flags, stream, payload)
+    }
+
+    fn start_stream(&mut self, id: u32) -> io::Result<()> {
+        let headers =
+            http2::encode_headers(&[(":status", "200"), ("content-type", "application/grpc")]);
+        self.write_frame(http2::HEADERS, http2::END_HEADERS, id, &headers)?;
+        self.subscriptions.insert(id, self.initial_window);
+        Ok(())
+    }
+
+    /// Sends a message on an orientation stream, unless the client's
+    /// window is too small for it.
+    fn send_message(&mut self, id: u32, message: &[u8]) -> io::Result<()> {
+        let data = grpc_message(message);
+        let size = data.len() as i64;
+        let Some(window) = self.subscriptions.get_mut(&id) else {
+            return Ok(());
+        };
+        if size > *window || size > self.window || data.len() > http2::MAX_FRAME_SIZE {
+            return Ok(());
+        }
+        *window -= size;
+        self.window -= size;
+        self.write_frame(http2::DATA, 0, id, &data)
+    }
+
+    /// Answers a unary call with `reply`, or with only an error status.
+    fn respond(&mut self, id: u32, reply: Result<Vec<u8>, (u32, String)>) -> io::Result<()> {
+        match reply {
+            Ok(message) => {
+                let headers = http2::encode_headers(&[
+                    (":status", "200"),
+                    ("content-type", "application/grpc"),
+                ]);
+                self.write_frame(http2::HEADERS, http2::END_HEADERS, id, &headers)?;
+                self.write_frame(http2::DATA, 0, id, &grpc_message(&message))?;
+                self.end_stream(id, OK, "")
+            }
+            Err((status, reason)) => {
+                let status = status.to_string();
+                let headers = http2::encode_headers(&[
+                    (":status", "200"),
+                    ("content-type", "application/grpc"),
+                    ("grpc-status", &status),
+                    ("grpc-message", &reason),
+                ]);
+                let flags = http2::END_HEADERS | http2::END_STREAM;
+                self.write_frame(http2::HEADERS, flags, id, &headers)
+            }
+        }
+    }
+
+    fn end_stream(&mut self, id: u32, status: u32, reason: &str) -> io::Result<()> {
+        let status = status.to_string();
+        let trailers = http2::encode_headers(&[("grpc-status", &status), ("grpc-message", reason)]);
+        let flags = http2::END_HEADERS | http2::END_STREAM;
+        self.write_frame(http2::HEADERS, flags, id, &trailers)
+    }
+}
+
+/// Server-side limit on the rate of the orientation streams, set with the
+/// `Control` call.
+#[derive(Clone, Copy)]
+struct StreamRate {
+    interval: Option<Duration>,
+    next_send: Instant,
+}
+
+pub struct GrpcServer {
+    peers: Arc<Mutex<Vec<Arc<Mutex<Peer>>>>>,
+    rate: Arc<Mutex<StreamRate>>,
+}
+
+impl GrpcServer {
+    pub fn bind(host: &str, port: u16, link: BoardLink) -> io::Result<Self> {
+        let listener = TcpListener::bind((host, port))?;
+        info!("gRPC server listening on {}:{}", host, port);
+
+        let peers = Arc::new(Mutex::new(Vec::new()));
+        let rate = Arc::new(Mutex::new(StreamRate {
+            interval: None,
+            next_send: Instant::now(),
+        }));
+        let server = Self {
+            peers: Arc::clone(&peers),
+            rate: Arc::clone(&rate),
+        };
+        thread::spawn(move || accept_clients(listener, peers, rate, link));
+        Ok(server)
+    }
+
+    fn due(&self) -> bool {
+        let mut rate = self.rate.lock().unwrap();
+        let Some(interval) = rate.interval else {
+            return true;
+        };
+        let now = Instant::now();
+        if now < rate.next_send {
+            return false;
+        }
+        rate.next_send = (rate.next_send + interval).max(now);
+        true
+    }
+}
+
+impl Sink for GrpcServer {
+    fn name(&self) -> &str {
+        "gRPC"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        if !self.due() {
+            return Ok(());
+        }
+        let orientation = protobuf::encode_orientation(message)?;
+
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|peer| {
+            let mut peer = peer.lock().unwrap();
+            let ids: Vec<u32> = peer.subscriptions.keys().copied().collect();
+            let sent = ids
+                .into_iter()
+                .try_for_each(|id| peer.send_message(id, &orientation));
+            match sent {
+                Ok(()) => true,
+                Err(e) => {
+                    info!(sink: "gRPC", "Client disconnected ({})", e);
+                    let _ = peer.stream.shutdown(Shutdown::Both);
+                    false
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        let peers = self.peers.lock().unwrap();
+        let streams: usize = peers
+            .iter()
+            .map(|peer| peer.lock().unwrap().subscriptions.len())
+            .sum();
+        match streams {
+            1 => "1 stream".to_string(),
+            streams => format!("{} streams", streams),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        for peer in self.peers.lock().unwrap().drain(..) {
+            let mut peer = peer.lock().unwrap();
+            let ids: Vec<u32> = peer.subscriptions.drain().map(|(id, _)| id).collect();
+            // Clients that already went away don't need telling
+            for id in ids {
+                let _ = peer.end_stream(id, OK, "");
+            }
+            let _ = peer.write_frame(http2::GOAWAY, 0, 0, &[0; 8]);
+            let _ = peer.stream.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    }
+}
+
+fn accept_clients(
+    listener: TcpListener,
+    peers: Arc<Mutex<Vec<Arc<Mutex<Peer>>>>>,
+    rate: Arc<Mutex<StreamRate>>,
+    link: BoardLink,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(sink: "gRPC", "Accepting client failed: {}", e);
+                continue;
+            }
+        };
+
+        let (peers, rate, link) = (Arc::clone(&peers), Arc::clone(&rate), link.clone());
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_default();
+            info!(sink: "gRPC", "Client connected from {}", peer);
+            if let Err(e) = serve_client(stream, &peers, &rate, &link) {
+                warn!(sink: "gRPC", "Connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn serve_client(
+    stream: TcpStream,
+    peers: &Mutex<Vec<Arc<Mutex<Peer>>>>,
+    rate: &Mutex<StreamRate>,
+    link: &BoardLink,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = stream.try_clone()?;
+    let mut preface = [0; 24];
+    reader.read_exact(&mut preface)?;
+    if &preface != http2::PREFACE {
+        return Err(invalid("not an HTTP/2 client (TLS isn't supported)"));
+    }
+
+    let peer = Arc::new(Mutex::new(Peer {
+        stream,
+        window: http2::DEFAULT_WINDOW,
+        initial_window: http2::DEFAULT_WINDOW,
+        subscriptions: HashMap::new(),
+    }));
+    peer.lock()
+        .unwrap()
+        .write_frame(http2::SETTINGS, 0, 0, &[])?;
+    peers.lock().unwrap().push(Arc::clone(&peer));
+
+    let result = Connection {
+        peer: &peer,
+        rate,
+        link,
+        decoder: HpackDecoder::default(),
+        requests: HashMap::new(),
+        headers: None,
+    }
+    .run(&mut reader);
+    peers
+        .lock()
+        .unwrap()
+        .retain(|known| !Arc::ptr_eq(known, &peer));
+    result
+}
+
+/// A call whose request is still being received.
+struct Request {
+    path: String,
+    body: Vec<u8>,
+}
+
+/// The request side of one client connection.
+struct Connection<'a> {
+    peer: &'a Mutex<Peer>,
+    rate: &'a Mutex<StreamRate>,
+    link: &'a BoardLink,
+    decoder: HpackDecoder,
+    requests: HashMap<u32, Request>,
+    /// A header block continued in CONTINUATION frames, with the flags of
+    /// its HEADERS frame.
+    headers: Option<(u32, u8, Vec<u8>)>,
+}
+
+impl Connection<'_> {
+    fn run(&mut self, reader: &mut TcpStream) -> io::Result<()> {
+        loop {
+            let frame = match http2::read_frame(reader) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match frame.kind {
+                http2::HEADERS => {
+                    let block = frame.content()?.to_vec();
+                    self.headers = Some((frame.stream, frame.flags, block));
+                    if frame.flags & http2::END_HEADERS != 0 {
+                        self.start_request()?;
+                    }
+                }
+                http2::CONTINUATION => {
+                    let Some((_, _, block)) = &mut self.headers else {
+                        return Err(invalid("CONTINUATION without HEADERS"));
+                    };
+                    block.extend_from_slice(&frame.payload);
+                    if frame.flags & http2::END_HEADERS != 0 {
+                        self.start_request()?;
+                    }
+                }
+                http2::DATA => self.receive_data(&frame)?,
+                http2::SETTINGS if frame.flags & http2::ACK == 0 => self.apply_settings(&frame)?,
+                http2::PING if frame.flags & http2::ACK == 0 => {
+                    let mut peer = self.peer.lock().unwrap();
+                    peer.write_frame(http2::PING, http2::ACK, 0, &frame.payload)?;
+                }
+                http2::WINDOW_UPDATE => {
+                    let increment = frame
+                        .payload
+                        .get(..4)
+                        .ok_or_else(|| invalid("short frame"))?;
+                    let increment =
+                        i64::from(u32::from_be_bytes(increment.try_into().unwrap()) & 0x7FFF_FFFF);
+                    let mut peer = self.peer.lock().unwrap();
+                    match frame.stream {
+                        0 => peer.window += increment,
+                        id => {
+                            if let Some(window) = peer.subscriptions.get_mut(&id) {
+                                *window += increment;
+                            }
+                        }
+                    }
+                }
+                http2::RST_STREAM => {
+                    self.requests.remove(&frame.stream);
+                    let mut peer = self.peer.lock().unwrap();
+                    if peer.subscriptions.remove(&frame.stream).is_some() {
+                        info!(sink: "gRPC", "Orientation stream {} cancelled", frame.stream);
+                    }
+                }
+                http2::GOAWAY => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    fn start_request(&mut self) -> io::Result<()> {
+        let Some((id, flags, block)) = self.headers.take() else {
+            return Ok(());
+        };
+        let headers = self.decoder.decode(&block).map_err(|e| invalid(&e))?;
+        let path = headers
+            .into_iter()
+            .find(|(name, _)| name == ":path")
+            .map(|(_, path)| path)
+            .unwrap_or_default();
+
+        self.requests.insert(
+            id,
+            Request {
+                path,
+                body: Vec::new(),
+            },
+        );
+        if flags & http2::END_STREAM != 0 {
+            self.finish_request(id)?;
+        }
+        Ok(())
+    }
+
+    fn receive_data(&mut self, frame: &Frame) -> io::Result<()> {
+        let content = frame.content()?;
+        if let Some(request) = self.requests.get_mut(&frame.stream) {
+            request.body.extend_from_slice(content);
+        }
+
+        // Give back the window right away, requests are tiny
+        if !frame.payload.is_empty() {
+            let increment = (frame.payload.len() as u32).to_be_bytes();
+            let mut peer = self.peer.lock().unwrap();
+            peer.write_frame(http2::WINDOW_UPDATE, 0, 0, &increment)?;
+            if frame.flags & http2::END_STREAM == 0 {
+                peer.write_frame(http2::WINDOW_UPDATE, 0, frame.stream, &increment)?;
+            }
+        }
+
+        if frame.flags & http2::END_STREAM != 0 {
+            self.finish_request(frame.stream)?;
+        }
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut peer = self.peer.lock().unwrap();
+        for setting in frame.payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            if id == http2::SETTINGS_INITIAL_WINDOW_SIZE {
+                // Applies to the streams already open as well
+                let change = i64::from(value) - peer.initial_window;
+                peer.initial_window = i64::from(value);
+                for window in peer.subscriptions.values_mut() {
+                    *window += change;
+                }
+            }
+        }
+        peer.write_frame(http2::SETTINGS, http2::ACK, 0, &[])
+    }
+
+    fn finish_request(&mut self, id: u32) -> io::Result<()> {
+        let Some(request) = self.requests.remove(&id) else {
+            return Ok(());
+        };
+        let mut peer = self.peer.lock().unwrap();
+        match request.path.as_str() {
+            STREAM_PATH => {
+                info!(sink: "gRPC", "Orientation stream {} started", id);
+                peer.start_stream(id)
+            }
+            CONTROL_PATH => {
+                let reply = self.control(&request.body);
+                peer.respond(id, reply)
+            }
+            path => peer.respond(id, Err((UNIMPLEMENTED, format!("unknown method {}", path)))),
+        }
+    }
+
+    /// Carries out a `Control` call, returning the encoded `ControlReply`.
+    fn control(&self, body: &[u8]) -> Result<Vec<u8>, (u32, String)> {
+        let bad_request = |reason: String| (INVALID_ARGUMENT, reason);
+        let message = body
+            .get(5..)
+            .filter(|_| body[0] == 0)
+            .ok_or_else(|| bad_request("expected an uncompressed message".to_string()))?;
+
+        let (ok, text) = match decode_control(message).map_err(bad_request)? {
+            Control::Tare => match self.link.send("tare") {
+                Ok(boards) => (true, format!("Sent 'tare' to {} board(s)", boards)),
+                Err(e) => (false, e),
+            },
+            Control::Rate(hz) => {
+                let mut rate = self.rate.lock().unwrap();
+                if hz > 0.0 {
+                    rate.interval = Some(Duration::from_secs_f64(1.0 / hz));
+                    (true, format!("Streaming at up to {} Hz", hz))
+                } else {
+                    rate.interval = None;
+                    (true, "Streaming every message".to_string())
+                }
+            }
+            Control::Nothing => (false, "no action given".to_string()),
+        };
+        info!(sink: "gRPC", "Control: {}", text);
+
+        let mut reply = Vec::new();
+        put_varint_field(&mut reply, 1, u64::from(ok));
+        put_bytes_field(&mut reply, 2, text.as_bytes());
+        Ok(reply)
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Prefixes a message with gRPC's compressed flag and length.
+fn grpc_message(message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(message.len() + 5);
+    data.push(0);
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+enum Control {
+    Tare,
+    Rate(f64),
+    Nothing,
+}
+
+/// Decodes a `ControlRequest`, whose last action wins as for a oneof.
+fn decode_control(mut message: &[u8]) -> Result<Control, String> {
+    let mut control = Control::Nothing;
+    while !message.is_empty() {
+        let key = take_varint(&mut message)?;
+        let (field, wire_type) = (key >> 3, key & 0x7);
+        match (field, wire_type) {
+            (1, VARINT) => {
+                if take_varint(&mut message)? != 0 {
+                    control = Control::Tare;
+                }
+            }
+            (2, FIXED64) => {
+                let hz = f64::from_le_bytes(take(&mut message, 8)?.try_into().unwrap());
+                if !hz.is_finite() || hz < 0.0 {
+                    return Err(format!("invalid rate {}", hz));
+                }
+                control = Control::Rate(hz);
+            }
+            // Unknown fields are skipped
+            (_, VARINT) => {
+                take_varint(&mut message)?;
+            }
+            (_, FIXED64) => {
+                take(&mut message, 8)?;
+            }
+            (_, LENGTH_DELIMITED) => {
+                let length = take_varint(&mut message)? as usize;
+                take(&mut message, length)?;
+            }
+            (_, FIXED32) => {
+                take(&mut message, 4)?;
+            }
+            _ => return Err(format!("unsupported wire type {}", wire_type)),
+        }
+    }
+    Ok(control)
+}
@@ -0,0 +1,45 @@
+//! The `--gui` control panel: a page served by the control API on
+//! `/panel`, opened in the default browser. It polls the API for the
+//! orientation and the sinks, and drives the filters, tare and recording
+//! through it, so it can't do anything a script couldn't.
+
+use crate::{info, warn};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// The panel, served on `GET /panel`.
+pub const PANEL: &str = include_str!("../web/panel.html");
+
+/// Opens the panel of the control API at `address` in the default browser,
+/// printing its address for when that fails.
+pub fn open(mut address: SocketAddr) {
+    if address.ip().is_unspecified() {
+        address.set_ip(Ipv4Addr::LOCALHOST.into());
+    }
+    let url = format!("http://{}/panel", address);
+    info!("Control panel at {}", url);
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    let opened = command
+        .arg(&url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match opened {
+        // Reaped aside, the opener returns once the browser has the page
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => warn!("Couldn't open a browser ({}), open {} yourself", e, url),
+    }
+}
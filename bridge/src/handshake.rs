@@ -0,0 +1,152 @@
+//! The hello a TCP service gets before any data, so it can tell which
+//! bridge it talks to and reject versions it doesn't understand:
+//!
+//! ```text
+//! {"hello":{"protocol":1,"bridge":"0.1.0","fields":["x","y","z","ax","ay","az","mx","my","mz","seq"],"optional":["qw","qx","qy","qz","gx","gy","gz"],"units":"deg","source":"serial","devices":["/dev/ttyACM0"]}}
+//! ```
+//!
+//! `fields` are the ones the orientation messages carry and `optional` the
+//! ones only some boards send: the rates of a gyroscope, and a quaternion
+//! from firmware built with `quaternion`, which sends it in place of `x`,
+//! `y` and `z` unless `--fusion` computes them. Both are named as the
+//! sink's `--format` has them. `devices` are the serial ports the bridge
+//! opened.
+//!
+//! A service answers with `{"hello_ack":{"protocol":1}}`, or with
+//! `{"hello_reject":"reason"}` and closes the connection. The bridge only
+//! waits for the answer with `--require-ack`.
+
+use crate::cli::{Args, Command};
+use crate::connection::ServiceStream;
+use crate::format::{self, Format};
+use crate::timestamp::TimestampMode;
+use crate::units::{self, Unit};
+use serde_json::{json, Value};
+use std::io::{self, Read};
+use std::time::Duration;
+
+/// Version of the message format, raised on changes that break consumers.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long a service has to answer the hello with `--require-ack`.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest line accepted, so a peer sending something else entirely can't
+/// keep the bridge reading.
+const MAX_LINE_LEN: usize = 1024;
+
+/// The raw readings the firmware sends along with the angles.
+const RAW_FIELDS: [&str; 6] = ["ax", "ay", "az", "mx", "my", "mz"];
+/// The quaternion firmware built with `quaternion` sends in place of the
+/// angles.
+const QUATERNION_FIELDS: [&str; 4] = ["qw", "qx", "qy", "qz"];
+/// The angular velocities of a gyroscope on the edge connector.
+const GYRO_FIELDS: [&str; 3] = ["gx", "gy", "gz"];
+
+/// The hello for the sink `name`, which gets `formats`.
+pub fn hello(args: &Args, name: &str, formats: &[Format]) -> Value {
+    let mut fields = vec!["x", "y", "z"];
+    fields.extend(RAW_FIELDS);
+    fields.push("seq");
+    let optional: Vec<&str> = QUATERNION_FIELDS.into_iter().chain(GYRO_FIELDS).collect();
+    if args.all_devices || args.port.len() > 1 {
+        fields.push("device");
+    }
+    match args.timestamp {
+        Some(TimestampMode::Monotonic) => fields.push("t_mono"),
+        Some(TimestampMode::Wall) => fields.push("t_wall"),
+        Some(TimestampMode::Both) => fields.extend(["t_mono", "t_wall"]),
+        None => {}
+    }
+
+    let units = match units::unit_for(name, &args.units) {
+        Unit::Deg => "deg",
+        Unit::Rad => "rad",
+    };
+    let source = if matches!(args.command, Some(Command::Replay { .. })) {
+        "replay"
+    } else if args.simulate.is_some() {
+        "simulator"
+    } else if args.input.is_some() {
+        "input"
+    } else {
+        "serial"
+    };
+
+    let (fields, optional) = match format::for_sink(formats, name) {
+        Some(format) => (format.fields(&fields), format.fields(&optional)),
+        None => (
+            fields.into_iter().map(String::from).collect(),
+            optional.into_iter().map(String::from).collect(),
+        ),
+    };
+
+    json!({
+        "hello": {
+            "protocol": PROTOCOL_VERSION,
+            "bridge": env!("CARGO_PKG_VERSION"),
+            "fields": fields,
+            "optional": optional,
+            "units": units,
+            "source": source,
+            "devices": args.port,
+        }
+    })
+}
+
+/// Waits for the service's answer to the hello.
+pub fn await_ack(stream: &mut ServiceStream, name: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+    let line = read_line(stream);
+    stream.set_read_timeout(None)?;
+    let line = line.map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} didn't acknowledge the hello", name),
+        ),
+        _ => e,
+    })?;
+
+    match answer(&serde_json::from_str(&line)?) {
+        Some(Ok(())) => Ok(()),
+        Some(Err(reason)) => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("{} rejected the bridge: {}", name, reason),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected an answer to the hello from {}", name),
+        )),
+    }
+}
+
+/// What a service sent back about the hello, for the command relay that
+/// reads its later lines: `None` for anything that isn't about the hello.
+pub fn answer(message: &Value) -> Option<Result<(), String>> {
+    if message.get("hello_ack").is_some() {
+        return Some(Ok(()));
+    }
+    let reason = message.get("hello_reject")?;
+    Some(Err(reason.as_str().unwrap_or_default().to_string()))
+}
+
+/// Reads up to a newline byte by byte, leaving whatever follows unread
+/// (for the hello's answer, the commands for the relay).
+pub fn read_line(stream: &mut impl Read) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while line.len() < MAX_LINE_LEN {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if byte[0] == b'\n' {
+            return String::from_utf8(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        line.push(byte[0]);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "line is too long",
+    ))
+}
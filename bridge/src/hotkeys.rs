@@ -0,0 +1,92 @@
+//! Single-key commands typed into the terminal while the bridge runs.
+
+use crate::info;
+use std::io::{self, Read};
+use std::mem::MaybeUninit;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    /// `z`: take the current orientation as zero
+    Tare,
+    /// `p`: pause or resume forwarding
+    Pause,
+    /// `m`: drop a marker into recordings
+    Marker,
+}
+
+impl Hotkey {
+    fn from_key(key: u8) -> Option<Self> {
+        match key.to_ascii_lowercase() {
+            b'z' => Some(Self::Tare),
+            b'p' => Some(Self::Pause),
+            b'm' => Some(Self::Marker),
+            _ => None,
+        }
+    }
+}
+
+/// Reads keys from stdin without waiting for Enter, until dropped.
+pub struct Hotkeys {
+    keys: Receiver<Hotkey>,
+    /// Terminal settings to restore, `None` if stdin isn't a terminal.
+    original: Option<libc::termios>,
+}
+
+impl Hotkeys {
+    pub fn start() -> Self {
+        let original = unbuffer_stdin();
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            for key in io::stdin().lock().bytes() {
+                let Ok(key) = key else {
+                    return;
+                };
+                if let Some(hotkey) = Hotkey::from_key(key) {
+                    if sender.send(hotkey).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        info!("Hotkeys: z = zero orientation, p = pause/resume, m = marker");
+        Self { keys, original }
+    }
+
+    /// The next key pressed since the last call, if any.
+    pub fn poll(&self) -> Option<Hotkey> {
+        self.keys.try_recv().ok()
+    }
+}
+
+impl Drop for Hotkeys {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            // SAFETY: restores settings previously read by tcgetattr
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+    }
+}
+
+/// Turns off line buffering and echo on the terminal, returning the
+/// previous settings. Ctrl+C keeps working.
+fn unbuffer_stdin() -> Option<libc::termios> {
+    let mut original = MaybeUninit::uninit();
+    // SAFETY: tcgetattr fills the termios struct if it returns 0
+    let original = unsafe {
+        if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) != 0 {
+            return None;
+        }
+        original.assume_init()
+    };
+
+    let mut unbuffered = original;
+    unbuffered.c_lflag &= !(libc::ICANON | libc::ECHO);
+    unbuffered.c_cc[libc::VMIN] = 1;
+    unbuffered.c_cc[libc::VTIME] = 0;
+    // SAFETY: applies a copy of valid settings with two flags changed
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &unbuffered) };
+    Some(original)
+}
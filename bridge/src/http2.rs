@@ -0,0 +1,389 @@
+//! Just enough HTTP/2 (RFC 9113) over cleartext TCP for the gRPC server:
+//! frames, and decoding of HPACK header blocks (RFC 7541).
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// What a client sends before its first frame.
+pub const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+/// Flow-control window every connection and stream starts with.
+pub const DEFAULT_WINDOW: i64 = 65_535;
+/// Largest frame payload a peer must accept.
+pub const MAX_FRAME_SIZE: usize = 16_384;
+
+/// Size of the dynamic table until the peer's encoder says otherwise.
+const DEFAULT_TABLE_SIZE: usize = 4096;
+/// Per-entry overhead counted against the dynamic table size.
+const ENTRY_OVERHEAD: usize = 32;
+
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// The payload without padding and priority fields, for DATA and
+    /// HEADERS frames.
+    pub fn content(&self) -> io::Result<&[u8]> {
+        let mut content = &self.payload[..];
+        let mut padding = 0;
+        if self.flags & PADDED != 0 {
+            let (&length, rest) = content.split_first().ok_or_else(malformed)?;
+            padding = usize::from(length);
+            content = rest;
+        }
+        if self.kind == HEADERS && self.flags & PRIORITY != 0 {
+            content = content.get(5..).ok_or_else(malformed)?;
+        }
+        let end = content.len().checked_sub(padding).ok_or_else(malformed)?;
+        Ok(&content[..end])
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP/2 frame")
+}
+
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Frame> {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("HTTP/2 frame of {} bytes is too large", length),
+        ));
+    }
+
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload)?;
+    Ok(Frame {
+        kind: header[3],
+        flags: header[4],
+        stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF,
+        payload,
+    })
+}
+
+pub fn write_frame(
+    stream: &mut impl Write,
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: &[u8],
+) -> io::Result<()> {
+    let length = (payload.len() as u32).to_be_bytes();
+    let mut frame = Vec::with_capacity(payload.len() + 9);
+    frame.extend_from_slice(&length[1..]);
+    frame.extend([kind, flags]);
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+/// Encodes headers as literals without indexing, so the peer's decoder
+/// state never depends on what we sent before.
+pub fn encode_headers(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        block.push(0x00);
+        for text in [name, value] {
+            encode_integer(&mut block, 0x00, 7, text.len());
+            block.extend_from_slice(text.as_bytes());
+        }
+    }
+    block
+}
+
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix_bits: u32, mut value: usize) {
+    let max_prefix = (1 << prefix_bits) - 1;
+    if value < max_prefix {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Decodes the header blocks of one connection, which share a dynamic
+/// table.
+pub struct HpackDecoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for HpackDecoder {
+    fn default() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+}
+
+impl HpackDecoder {
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut headers = Vec::new();
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = decode_integer(&mut block, 7)?;
+                headers.push(self.entry(index)?);
+            } else if first & 0xC0 == 0x40 {
+                let header = self.literal(&mut block, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if first & 0xE0 == 0x20 {
+                let size = decode_integer(&mut block, 5)?;
+                if size > DEFAULT_TABLE_SIZE {
+                    return Err(format!("dynamic table size {} is too large", size));
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // Literal without indexing or never indexed
+                headers.push(self.literal(&mut block, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), String> {
+        if index == 0 {
+            return Err("header index 0".to_string());
+        }
+        if let Some((name, value)) = STATIC_TABLE.get(index - 1) {
+            return Ok((name.to_string(), value.to_string()));
+        }
+        self.table
+            .get(index - 1 - STATIC_TABLE.len())
+            .cloned()
+            .ok_or_else(|| format!("header index {} out of range", index))
+    }
+
+    fn literal(&self, block: &mut &[u8], prefix_bits: u32) -> Result<(String, String), String> {
+        let name = match decode_integer(block, prefix_bits)? {
+            0 => decode_string(block)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, decode_string(block)?))
+    }
+
+    fn insert(&mut self, (name, value): (String, String)) {
+        self.size += name.len() + value.len() + ENTRY_OVERHEAD;
+        self.table.push_front((name, value));
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn decode_integer(block: &mut &[u8], prefix_bits: u32) -> Result<usize, String> {
+    let truncated = || "truncated header block".to_string();
+    let (&first, rest) = block.split_first().ok_or_else(truncated)?;
+    *block = rest;
+
+    let max_prefix = (1 << prefix_bits) - 1;
+    let mut value = usize::from(first) & max_prefix;
+    if value < max_prefix {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or_else(truncated)?;
+        *block = rest;
+        if shift > 28 {
+            return Err("header integer too large".to_string());
+        }
+        value += usize::from(byte & 0x7F) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<String, String> {
+    let huffman = block.first().is_some_and(|first| first & 0x80 != 0);
+    let length = decode_integer(block, 7)?;
+    if block.len() < length {
+        return Err("truncated header block".to_string());
+    }
+    let (text, rest) = block.split_at(length);
+    *block = rest;
+
+    let text = if huffman {
+        decode_huffman(text)?
+    } else {
+        text.to_vec()
+    };
+    String::from_utf8(text).map_err(|_| "header is not UTF-8".to_string())
+}
+
+fn decode_huffman(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    let (mut code, mut length) = (0u32, 0u8);
+    for bit in data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+    {
+        code = (code << 1) | u32::from(bit);
+        length += 1;
+        let symbol = (0..HUFFMAN_CODES.len())
+            .find(|&symbol| HUFFMAN_LENGTHS[symbol] == length && HUFFMAN_CODES[symbol] == code);
+        match symbol {
+            Some(256) => return Err("EOS in Huffman string".to_string()),
+            Some(symbol) => {
+                decoded.push(symbol as u8);
+                (code, length) = (0, 0);
+            }
+            None if length > 30 => return Err("invalid Huffman code".to_string()),
+            None => {}
+        }
+    }
+
+    // Only a prefix of EOS, which is all ones, may pad the last byte
+    if length > 7 || code != (1 << length) - 1 {
+        return Err("invalid Huffman padding".to_string());
+    }
+    Ok(decoded)
+}
+
+/// The HPACK static table (RFC 7541, Appendix A).
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Huffman codes of the 256 octets and EOS (RFC 7541, Appendix B).
+const HUFFMAN_CODES: [u32; 257] = [
+    0x1ff8, 0x7fffd8, 0xfffffe2, 0xfffffe3, 0xfffffe4, 0xfffffe5, 0xfffffe6, 0xfffffe7, 0xfffffe8,
+    0xffffea, 0x3ffffffc, 0xfffffe9, 0xfffffea, 0x3ffffffd, 0xfffffeb, 0xfffffec, 0xfffffed,
+    0xfffffee, 0xfffffef, 0xffffff0, 0xffffff1, 0xffffff2, 0x3ffffffe, 0xffffff3, 0xffffff4,
+    0xffffff5, 0xffffff6, 0xffffff7, 0xffffff8, 0xffffff9, 0xffffffa, 0xffffffb, 0x14, 0x3f8,
+    0x3f9, 0xffa, 0x1ff9, 0x15, 0xf8, 0x7fa, 0x3fa, 0x3fb, 0xf9, 0x7fb, 0xfa, 0x16, 0x17, 0x18,
+    0x0, 0x1, 0x2, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x5c, 0xfb, 0x7ffc, 0x20, 0xffb,
+    0x3fc, 0x1ffa, 0x21, 0x5d, 0x5e, 0x5f, 0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0xfc, 0x73, 0xfd, 0x1ffb, 0x7fff0,
+    0x1ffc, 0x3ffc, 0x22, 0x7ffd, 0x3, 0x23, 0x4, 0x24, 0x5, 0x25, 0x26, 0x27, 0x6, 0x74, 0x75,
+    0x28, 0x29, 0x2a, 0x7, 0x2b, 0x76, 0x2c, 0x8, 0x9, 0x2d, 0x77, 0x78, 0x79, 0x7a, 0x7b, 0x7ffe,
+    0x7fc, 0x3ffd, 0x1ffd, 0xffffffc, 0xfffe6, 0x3fffd2, 0xfffe7, 0xfffe8, 0x3fffd3, 0x3fffd4,
+    0x3fffd5, 0x7fffd9, 0x3fffd6, 0x7fffda, 0x7fffdb, 0x7fffdc, 0x7fffdd, 0x7fffde, 0xffffeb,
+    0x7fffdf, 0xffffec, 0xffffed, 0x3fffd7, 0x7fffe0, 0xffffee, 0x7fffe1, 0x7fffe2, 0x7fffe3,
+    0x7fffe4, 0x1fffdc, 0x3fffd8, 0x7fffe5, 0x3fffd9, 0x7fffe6, 0x7fffe7, 0xffffef, 0x3fffda,
+    0x1fffdd, 0xfffe9, 0x3fffdb, 0x3fffdc, 0x7fffe8, 0x7fffe9, 0x1fffde, 0x7fffea, 0x3fffdd,
+    0x3fffde, 0xfffff0, 0x1fffdf, 0x3fffdf, 0x7fffeb, 0x7fffec, 0x1fffe0, 0x1fffe1, 0x3fffe0,
+    0x1fffe2, 0x7fffed, 0x3fffe1, 0x7fffee, 0x7fffef, 0xfffea, 0x3fffe2, 0x3fffe3, 0x3fffe4,
+    0x7ffff0, 0x3fffe5, 0x3fffe6, 0x7ffff1, 0x3ffffe0, 0x3ffffe1, 0xfffeb, 0x7fff1, 0x3fffe7,
+    0x7ffff2, 0x3fffe8, 0x1ffffec, 0x3ffffe2, 0x3ffffe3, 0x3ffffe4, 0x7ffffde, 0x7ffffdf,
+    0x3ffffe5, 0xfffff1, 0x1ffffed, 0x7fff2, 0x1fffe3, 0x3ffffe6, 0x7ffffe0, 0x7ffffe1, 0x3ffffe7,
+    0x7ffffe2, 0xfffff2, 0x1fffe4, 0x1fffe5, 0x3ffffe8, 0x3ffffe9, 0xffffffd, 0x7ffffe3, 0x7ffffe4,
+    0x7ffffe5, 0xfffec, 0xfffff3, 0xfffed, 0x1fffe6, 0x3fffe9, 0x1fffe7, 0x1fffe8, 0x7ffff3,
+    0x3fffea, 0x3fffeb, 0x1ffffee, 0x1ffffef, 0xfffff4, 0xfffff5, 0x3ffffea, 0x7ffff4, 0x3ffffeb,
+    0x7ffffe6, 0x3ffffec, 0x3ffffed, 0x7ffffe7, 0x7ffffe8, 0x7ffffe9, 0x7ffffea, 0x7ffffeb,
+    0xffffffe, 0x7ffffec, 0x7ffffed, 0x7ffffee, 0x7ffffef, 0x7fffff0, 0x3ffffee, 0x3fffffff,
+];
+
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
@@ -0,0 +1,179 @@
+//! The `latency` command: how long the round trip to the boards and to the
+//! TCP services takes, to judge how directly the motion comes across.
+//!
+//! Boards answer the `ping` command with `{"ack":"ping"}`. Services take
+//! part by answering a `{"ping":N}` line with `{"pong":N}`, as the Blender
+//! add-on and the visualizer do.
+
+use crate::cli::{self, Args};
+use crate::command;
+use crate::crc::CrcChecker;
+use crate::framing::Framer;
+use crate::handshake;
+use crate::serial::WireFormat;
+use crate::sink::{self, SinkSpec};
+use crate::{info, warn};
+use serde_json::{json, Value};
+use serialport::{ClearBuffer, SerialPort};
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an answer is waited for before the ping counts as lost.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(1);
+/// Pings lost in a row, without any answered, before giving up on a
+/// service that doesn't take part.
+const GIVE_UP_AFTER: u32 = 3;
+
+#[derive(clap::Args, Clone, Copy, Debug)]
+pub struct LatencyOptions {
+    /// Pings sent to every board and service
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    pub count: u32,
+
+    /// Pause between pings, e.g. 20ms
+    #[arg(long, value_name = "INTERVAL", default_value = "20ms", value_parser = cli::parse_interval)]
+    pub interval: Duration,
+}
+
+/// Pings every board, then every TCP service, printing the percentiles of
+/// the round trips.
+pub fn run(
+    ports: Vec<(String, Box<dyn SerialPort>)>,
+    args: &Args,
+    options: &LatencyOptions,
+) -> io::Result<()> {
+    for (path, mut port) in ports {
+        info!("Pinging the board on {}", path);
+        let samples = ping_board(port.as_mut(), args.wire_format, options)?;
+        report(&path, samples, options.count);
+    }
+
+    for spec in cli::tcp_sinks(args) {
+        info!(sink: spec.name, "Pinging {} at {}", spec.name, spec.address());
+        match ping_service(&spec, args, options) {
+            Ok(samples) => report(&spec.name, samples, options.count),
+            Err(e) => warn!(sink: spec.name, "Can't measure {}: {}", spec.name, e),
+        }
+    }
+    Ok(())
+}
+
+fn ping_board(
+    port: &mut dyn SerialPort,
+    format: WireFormat,
+    options: &LatencyOptions,
+) -> io::Result<Vec<Duration>> {
+    let ping = command::frame("ping").map_err(io::Error::other)?;
+    let mut framer = Framer::new(format);
+    let mut crc = CrcChecker::new(false);
+    let mut buffer = [0; 1000];
+    let mut samples = Vec::new();
+    port.clear(ClearBuffer::Input)?;
+
+    for _ in 0..options.count {
+        port.write_all(&ping)?;
+        port.flush()?;
+        let sent = Instant::now();
+
+        'answer: while sent.elapsed() < ANSWER_TIMEOUT {
+            let count = match port.read(&mut buffer) {
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            for line in framer.push(&buffer[..count]).into_iter().flatten() {
+                let answered = crc
+                    .check(&line)
+                    .ok()
+                    .and_then(|line| serde_json::from_str::<Value>(line).ok())
+                    .is_some_and(|message| message.get("ack") == Some(&json!("ping")));
+                if answered {
+                    samples.push(sent.elapsed());
+                    break 'answer;
+                }
+            }
+        }
+        thread::sleep(options.interval);
+    }
+    Ok(samples)
+}
+
+fn ping_service(
+    spec: &SinkSpec,
+    args: &Args,
+    options: &LatencyOptions,
+) -> io::Result<Vec<Duration>> {
+    let mut stream = TcpStream::connect(spec.address())?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(ANSWER_TIMEOUT))?;
+    if !args.no_hello {
+        stream.write_all(&sink::to_json_line(&handshake::hello(
+            args,
+            &spec.name,
+            &args.formats,
+        ))?)?;
+    }
+
+    let mut samples = Vec::new();
+    for i in 0..options.count {
+        if samples.is_empty() && i == GIVE_UP_AFTER {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no answer, does it reply to {\"ping\":N} with {\"pong\":N}?",
+            ));
+        }
+
+        stream.write_all(&sink::to_json_line(&json!({ "ping": i }))?)?;
+        let sent = Instant::now();
+        while sent.elapsed() < ANSWER_TIMEOUT {
+            let line = match handshake::read_line(&mut stream) {
+                Ok(line) => line,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            };
+            let message = serde_json::from_str::<Value>(&line).unwrap_or_default();
+            if let Some(Err(reason)) = handshake::answer(&message) {
+                return Err(io::Error::other(format!("rejected the bridge: {}", reason)));
+            }
+            if message.get("pong").and_then(Value::as_u64) == Some(u64::from(i)) {
+                samples.push(sent.elapsed());
+                break;
+            }
+        }
+        thread::sleep(options.interval);
+    }
+    Ok(samples)
+}
+
+fn report(name: &str, mut samples: Vec<Duration>, sent: u32) {
+    if samples.is_empty() {
+        warn!("{}: none of the {} pings were answered", name, sent);
+        return;
+    }
+
+    samples.sort();
+    // Nearest-rank percentiles
+    let percentile = |p: f64| {
+        let rank = (p * samples.len() as f64).ceil() as usize;
+        samples[rank.clamp(1, samples.len()) - 1].as_secs_f64() * 1000.0
+    };
+    println!(
+        "{}: {}/{} answered, p50 {:.2} ms, p90 {:.2} ms, p99 {:.2} ms, max {:.2} ms",
+        name,
+        samples.len(),
+        sent,
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0)
+    );
+}
@@ -0,0 +1,82 @@
+//! Forwards the orientation data of one or more Microbits from the serial
+//! port to Blender, the visualizer and other sinks.
+
+pub mod arrow;
+pub mod bake;
+pub mod batch;
+pub mod binary;
+pub mod bridge;
+pub mod bvh;
+pub mod calibrate;
+pub mod cbor;
+pub mod change;
+pub mod cli;
+pub mod cobs;
+pub mod command;
+pub mod connection;
+pub mod control;
+pub mod crc;
+pub mod csv;
+pub mod daemon;
+pub mod datalog;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod failover;
+pub mod filter;
+pub mod format;
+pub mod framing;
+pub mod fusion;
+pub mod gestures;
+pub mod grpc;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod handshake;
+pub mod hotkeys;
+pub mod http2;
+pub mod latency;
+pub mod local;
+pub mod log;
+pub mod manager;
+pub mod mdns;
+pub mod monitor;
+pub mod mqtt;
+pub mod msgpack;
+pub mod osc;
+pub mod parser;
+pub mod protobuf;
+pub mod queue;
+pub mod rate;
+pub mod recording;
+pub mod remap;
+pub mod resample;
+pub mod rosbridge;
+pub mod sanity;
+pub mod script;
+pub mod selftest;
+pub mod sequence;
+pub mod serial;
+pub mod server;
+pub mod service;
+pub mod shutdown;
+pub mod simulator;
+pub mod sink;
+pub mod source;
+pub mod stats;
+pub mod subscribe;
+pub mod systemd;
+pub mod tare;
+pub mod timestamp;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transform;
+pub mod udp;
+pub mod units;
+pub mod validate;
+pub mod websocket;
+pub mod worker;
+pub mod zmq;
+
+pub use bridge::run;
+pub use cli::Args;
+pub use error::BridgeError;
@@ -0,0 +1,202 @@
+//! Local IPC without a TCP port: the same stream of messages as the
+//! server mode, over a Unix domain socket the bridge listens on, or on
+//! Windows written to a named pipe created by the consumer.
+
+use crate::encoding::Encoding;
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+pub use self::unix::LocalSocket;
+#[cfg(windows)]
+pub use self::windows::LocalSocket;
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::fs;
+    use std::net::Shutdown;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Broadcasts to every client connected to the socket at `path`.
+    pub struct LocalSocket {
+        path: PathBuf,
+        clients: Arc<Mutex<Vec<UnixStream>>>,
+        encoding: Encoding,
+    }
+
+    impl LocalSocket {
+        pub fn bind(path: &Path) -> io::Result<Self> {
+            // A socket left behind by an earlier run would make bind fail
+            if UnixStream::connect(path).is_err() {
+                let _ = fs::remove_file(path);
+            }
+            let listener = UnixListener::bind(path)?;
+            info!("Listening for clients on {}", path.display());
+
+            let clients = Arc::new(Mutex::new(Vec::new()));
+            let accepted = Arc::clone(&clients);
+            thread::spawn(move || accept_clients(listener, accepted));
+
+            Ok(Self {
+                path: path.to_path_buf(),
+                clients,
+                encoding: Encoding::Json,
+            })
+        }
+
+        /// Encodes the messages as `encoding` instead of JSON lines.
+        pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+            self.encoding = encoding;
+            self
+        }
+    }
+
+    impl Sink for LocalSocket {
+        fn name(&self) -> &str {
+            "Unix socket"
+        }
+
+        fn send(&mut self, message: &Value) -> io::Result<()> {
+            let data = self.encoding.frame(message)?;
+            let mut clients = self.clients.lock().unwrap();
+
+            clients.retain_mut(|client| match client.write_all(&data) {
+                Ok(()) => true,
+                Err(e) => {
+                    info!(sink: "Unix socket", "Client disconnected ({})", e);
+                    false
+                }
+            });
+            Ok(())
+        }
+
+        fn status(&self) -> String {
+            match self.clients.lock().unwrap().len() {
+                1 => "1 client".to_string(),
+                clients => format!("{} clients", clients),
+            }
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            for client in self.clients.lock().unwrap().drain(..) {
+                // Clients that already went away don't need telling
+                let _ = client.shutdown(Shutdown::Both);
+            }
+            fs::remove_file(&self.path)
+        }
+    }
+
+    fn accept_clients(listener: UnixListener, clients: Arc<Mutex<Vec<UnixStream>>>) {
+        for stream in listener.incoming() {
+            let stream = match stream.and_then(|stream| {
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Ok(stream)
+            }) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(sink: "Unix socket", "Accepting client failed: {}", e);
+                    continue;
+                }
+            };
+
+            info!(sink: "Unix socket", "Client connected");
+            clients.lock().unwrap().push(stream);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::*;
+    use std::fs::{File, OpenOptions};
+    use std::time::{Duration, Instant};
+
+    const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Writes to the named pipe at `path` (e.g. `\\.\pipe\microbit`),
+    /// reopening it whenever the consumer restarts.
+    pub struct LocalSocket {
+        path: PathBuf,
+        pipe: Option<File>,
+        next_attempt: Instant,
+        encoding: Encoding,
+    }
+
+    impl LocalSocket {
+        pub fn bind(path: &Path) -> io::Result<Self> {
+            info!("Writing to named pipe {}", path.display());
+            let mut socket = Self {
+                path: path.to_path_buf(),
+                pipe: None,
+                next_attempt: Instant::now(),
+                encoding: Encoding::Json,
+            };
+            socket.open();
+            Ok(socket)
+        }
+
+        /// Encodes the messages as `encoding` instead of JSON lines.
+        pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+            self.encoding = encoding;
+            self
+        }
+
+        fn open(&mut self) {
+            if Instant::now() < self.next_attempt {
+                return;
+            }
+            match OpenOptions::new().write(true).open(&self.path) {
+                Ok(pipe) => {
+                    info!(sink: "Named pipe", "Connected to {}", self.path.display());
+                    self.pipe = Some(pipe);
+                }
+                Err(_) => self.next_attempt = Instant::now() + RETRY_INTERVAL,
+            }
+        }
+    }
+
+    impl Sink for LocalSocket {
+        fn name(&self) -> &str {
+            "Named pipe"
+        }
+
+        fn send(&mut self, message: &Value) -> io::Result<()> {
+            if self.pipe.is_none() {
+                self.open();
+            }
+            let Some(pipe) = &mut self.pipe else {
+                return Ok(());
+            };
+
+            let data = self.encoding.frame(message)?;
+            if let Err(e) = pipe.write_all(&data) {
+                warn!(sink: "Named pipe", "Lost {} ({})", self.path.display(), e);
+                self.pipe = None;
+            }
+            Ok(())
+        }
+
+        fn status(&self) -> String {
+            match self.pipe {
+                Some(_) => "connected".to_string(),
+                None => "waiting".to_string(),
+            }
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            match self.pipe.take() {
+                Some(mut pipe) => pipe.flush(),
+                None => Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Console output with adjustable verbosity, as text or JSON lines.
+//!
+//! Events are logged with the `error!`, `warn!`, `info!`, `debug!` and
+//! `trace!` macros. Events that concern one sink name it first, so the JSON
+//! output of a long session can be filtered per sink:
+//!
+//! ```text
+//! info!(sink: self.name, "Reconnected at {}", self.addr);
+//! ```
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Plain messages for people
+    #[default]
+    Text,
+    /// One JSON object per event, for log collectors
+    Json,
+}
+
+struct Config {
+    level: Level,
+    format: LogFormat,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+/// Where events go instead of the console, e.g. the Windows event log.
+static OUTPUT: OnceLock<fn(Level, &str)> = OnceLock::new();
+/// Whether the console ends in a status line the next event has to start
+/// below of.
+static STATUS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Sets the verbosity from the number of `-v` and `-q` flags, `info` being
+/// the default. Only the first call has an effect.
+pub fn init(verbose: u8, quiet: u8, format: LogFormat) {
+    let level = match i16::from(verbose) - i16::from(quiet) {
+        ..=-2 => Level::Error,
+        -1 => Level::Warn,
+        0 => Level::Info,
+        1 => Level::Debug,
+        _ => Level::Trace,
+    };
+    let _ = CONFIG.set(Config { level, format });
+}
+
+/// Hands every event to `output` from now on instead of printing it, for
+/// a bridge without a console. Only the first call has an effect.
+pub fn redirect(output: fn(Level, &str)) {
+    let _ = OUTPUT.set(output);
+}
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(|| Config {
+        level: Level::Info,
+        format: LogFormat::Text,
+    })
+}
+
+/// Writes one event, used by the logging macros.
+#[doc(hidden)]
+pub fn write(level: Level, sink: Option<&str>, message: fmt::Arguments) {
+    let config = config();
+    if level > config.level {
+        return;
+    }
+
+    let line = match config.format {
+        LogFormat::Text if level == Level::Error => format!("Error: {}", message),
+        LogFormat::Text => message.to_string(),
+        LogFormat::Json => {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            let mut event = json!({
+                "time": time,
+                "level": level.name(),
+                "message": message.to_string(),
+            });
+            if let (Some(sink), Value::Object(fields)) = (sink, &mut event) {
+                fields.insert("sink".to_string(), sink.into());
+            }
+            event.to_string()
+        }
+    };
+
+    if let Some(output) = OUTPUT.get() {
+        output(level, &line);
+        return;
+    }
+    let lead = if STATUS_SHOWN.swap(false, Ordering::Relaxed) {
+        "\n"
+    } else {
+        ""
+    };
+    if level <= Level::Warn {
+        eprintln!("{}{}", lead, line);
+    } else {
+        println!("{}{}", lead, line);
+    }
+}
+
+/// Shows `message` in place of the previous status line. Only shown on a
+/// text console at the default verbosity or above, and not in a log file.
+pub fn status(message: fmt::Arguments) -> io::Result<()> {
+    let config = config();
+    if config.format != LogFormat::Text || config.level < Level::Info {
+        return Ok(());
+    }
+
+    let mut stdout = io::stdout().lock();
+    if !stdout.is_terminal() {
+        return Ok(());
+    }
+    write!(stdout, "{}\r", message)?;
+    STATUS_SHOWN.store(true, Ordering::Relaxed);
+    stdout.flush()
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_event {
+    ($level:ident, sink: $sink:expr, $($arg:tt)+) => {
+        $crate::log::write(
+            $crate::log::Level::$level,
+            Some(::core::convert::AsRef::<str>::as_ref(&$sink)),
+            format_args!($($arg)+),
+        )
+    };
+    ($level:ident, $($arg:tt)+) => {
+        $crate::log::write($crate::log::Level::$level, None, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log_event!(Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log_event!(Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log_event!(Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log_event!(Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log_event!(Trace, $($arg)+) };
+}
@@ -0,0 +1,340 @@
+//! Creation of the configured sinks and delivery of messages to all of them.
+
+use crate::arrow::{self, ArrowRecorder};
+use crate::batch::{self, BatchConfig};
+use crate::cli::{self, Args, Command};
+use crate::command::BoardLink;
+use crate::connection::{self, ReconnectConfig, ServiceConnection};
+use crate::csv::CsvSink;
+use crate::datalog::DataLog;
+use crate::encoding;
+use crate::error::BridgeError;
+use crate::failover;
+use crate::format::{self, Format};
+use crate::grpc::GrpcServer;
+use crate::handshake;
+use crate::local::LocalSocket;
+use crate::mdns::Advertiser;
+use crate::mqtt::MqttSink;
+use crate::osc::OscSink;
+use crate::queue;
+use crate::rate;
+use crate::recording::Recorder;
+use crate::remap;
+use crate::resample;
+use crate::rosbridge::{self, RosbridgeSink};
+use crate::server::TcpServer;
+use crate::sink::{Sink, SinkStats};
+use crate::subscribe;
+use crate::systemd;
+use crate::udp::UdpSink;
+use crate::units;
+use crate::websocket::WebSocketServer;
+use crate::zmq::ZmqPublisher;
+use crate::{error, warn};
+use serde_json::{json, Value};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct ConnectionManager {
+    sinks: Vec<Box<dyn Sink>>,
+    /// The file recorded to through `start_recording`, and the index of its
+    /// recorder in `sinks`.
+    recording: Option<(PathBuf, usize)>,
+}
+
+impl ConnectionManager {
+    pub fn new(args: &Args, link: &BoardLink) -> Result<Self, BridgeError> {
+        let mut formats = args.formats.clone();
+        if let Some(path) = &args.format_file {
+            formats.extend(format::load(path).map_err(|e| BridgeError::parse(path, e))?);
+        }
+
+        let no_forward = matches!(
+            args.command,
+            Some(Command::Record {
+                no_forward: true,
+                ..
+            })
+        );
+        // Every wrapper sees a message before the ones it wraps, so these
+        // apply bottom to top: the subscription first and the format last
+        let process = |sink| {
+            let sink = format::apply(sink, &formats);
+            let sink = remap::apply(sink, &args.maps);
+            let sink = units::convert(sink, &args.units);
+            let sink = resample::apply(sink, &args.resample);
+            let sink = rate::limit(sink, &args.max_rate, args.rate_mode);
+            subscribe::apply(sink, &args.subscriptions)
+        };
+
+        // The recording keeps the messages as they come, like the ones
+        // `start_recording` starts, and files get every message
+        let mut sinks: Vec<Box<dyn Sink>> = Self::create_recorder(args)
+            .map_err(BridgeError::SinkSetup)?
+            .into_iter()
+            .map(|sink| queue::wrap_file(sink, args.queue_size, &args.queues))
+            .collect();
+        sinks.extend(
+            Self::create_files(args, no_forward)
+                .map_err(BridgeError::SinkSetup)?
+                .into_iter()
+                .map(process)
+                .map(|sink| queue::wrap_file(sink, args.queue_size, &args.queues)),
+        );
+        if !no_forward {
+            sinks.extend(
+                Self::create_sinks(args, link, &formats)
+                    .map_err(BridgeError::SinkSetup)?
+                    .into_iter()
+                    .map(process)
+                    .map(|sink| queue::wrap(sink, args.queue_size, args.overflow, &args.queues)),
+            );
+        }
+
+        Ok(Self {
+            sinks,
+            recording: None,
+        })
+    }
+
+    /// The recorder of the `record` command.
+    fn create_recorder(args: &Args) -> io::Result<Vec<Box<dyn Sink>>> {
+        let Some(Command::Record { file, .. }) = &args.command else {
+            return Ok(Vec::new());
+        };
+        let recorder: Box<dyn Sink> = if arrow::is_arrow(file) {
+            Box::new(ArrowRecorder::create(file)?)
+        } else {
+            Box::new(Recorder::create(file)?)
+        };
+        Ok(vec![recorder])
+    }
+
+    /// The sinks writing files, the CSV only when forwarding.
+    fn create_files(args: &Args, no_forward: bool) -> io::Result<Vec<Box<dyn Sink>>> {
+        let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+        if let Some(dir) = &args.log_data {
+            let max_age = args
+                .rotate_minutes
+                .map(|minutes| Duration::from_secs(minutes * 60));
+            sinks.push(Box::new(DataLog::create(dir, args.rotate_size, max_age)?));
+        }
+        if let Some(path) = args.csv.as_ref().filter(|_| !no_forward) {
+            sinks.push(Box::new(CsvSink::create(path)?));
+        }
+        Ok(sinks)
+    }
+
+    /// The sinks sending the messages on, to services and clients.
+    fn create_sinks(
+        args: &Args,
+        link: &BoardLink,
+        formats: &[Format],
+    ) -> io::Result<Vec<Box<dyn Sink>>> {
+        let config = ReconnectConfig::from_args(args);
+        let batching = BatchConfig {
+            size: args.batch.map(|size| size as usize),
+            interval: args.batch_interval,
+            format: args.batch_format,
+        };
+        let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+
+        if let Some(port) = args.listen {
+            let server = match systemd::listener() {
+                Some(listener) => TcpServer::serve(listener, args.auth_token.clone()),
+                None => TcpServer::bind(&args.listen_bind, port, args.auth_token.clone())?,
+            };
+            let mut server = server.with_encoding(encoding::encoding_for("Server", &args.encoding));
+            if !args.no_mdns {
+                match Advertiser::start(&args.listen_bind, port, args.auth_token.is_some()) {
+                    Ok(advertiser) => server = server.with_advertiser(advertiser),
+                    Err(e) => warn!("Can't advertise the server via mDNS: {}", e),
+                }
+            }
+            sinks.push(batch::wrap(Box::new(server), batching));
+        }
+
+        if let Some(path) = &args.unix_socket {
+            let socket = LocalSocket::bind(path)?;
+            let encoding = encoding::encoding_for(socket.name(), &args.encoding);
+            sinks.push(Box::new(socket.with_encoding(encoding)));
+        }
+
+        if let Some(port) = args.websocket_port {
+            let mut server = WebSocketServer::bind(&args.websocket_bind, port)?;
+            if !args.no_hello {
+                let hello = handshake::hello(args, server.name(), formats);
+                server = server.with_greeting(&hello)?;
+            }
+            sinks.push(Box::new(server));
+        }
+
+        if let Some(port) = args.zmq_port {
+            sinks.push(Box::new(ZmqPublisher::bind(
+                &args.zmq_bind,
+                port,
+                &args.zmq_topic,
+            )?));
+        }
+
+        if let Some(port) = args.grpc_port {
+            sinks.push(Box::new(GrpcServer::bind(
+                &args.grpc_bind,
+                port,
+                link.clone(),
+            )?));
+        }
+
+        if let Some(url) = &args.mqtt_url {
+            sinks.push(Box::new(MqttSink::connect(
+                url,
+                &args.mqtt_topic,
+                &args.mqtt_client_id,
+                config.for_sink("MQTT", &args.backoff),
+            )?));
+        }
+
+        if let Some(url) = &args.rosbridge_url {
+            let noise = rosbridge::Noise {
+                orientation: args.orientation_noise,
+                acceleration: args.accel_noise,
+            };
+            sinks.push(Box::new(RosbridgeSink::connect(
+                url,
+                &args.rosbridge_topic,
+                &args.rosbridge_frame_id,
+                noise,
+                config.for_sink("rosbridge", &args.backoff),
+            )?));
+        }
+
+        if let Some(port) = args.osc_port {
+            sinks.push(Box::new(OscSink::new(
+                &args.osc_host,
+                port,
+                &args.osc_prefix,
+            )?));
+        }
+
+        for target in &args.udp {
+            sinks.push(Box::new(UdpSink::new(target)?));
+        }
+
+        let mut services: Vec<Box<dyn Sink>> = Vec::new();
+        let tls = connection::tls_connector(args)?;
+        for spec in cli::tcp_sinks(args) {
+            let encoding = encoding::encoding_for(&spec.name, &args.encoding);
+            let mut config = config.for_sink(&spec.name, &args.backoff);
+            // A failover member that's down must not hold up the others
+            if args.failover.iter().any(|group| group.contains(&spec.name)) {
+                config = config.in_background();
+            }
+            let mut connection =
+                ServiceConnection::connect(&spec.host, spec.port, &spec.name, config)?
+                    .with_encoding(encoding)
+                    .with_heartbeat(encoding.frame(&json!({ "heartbeat": true }))?);
+            if let Some(connector) = &tls {
+                connection = connection.with_tls(Arc::clone(connector))?;
+            }
+            if !args.no_hello {
+                let hello = handshake::hello(args, &spec.name, formats);
+                connection = connection.with_greeting(encoding.frame(&hello)?)?;
+            }
+            if args.require_ack {
+                connection = connection.with_required_ack()?;
+            }
+            if args.relay_commands {
+                connection = connection.with_command_relay(link.clone());
+            }
+            services.push(Box::new(connection));
+        }
+        sinks.extend(
+            failover::group(services, &args.failover)
+                .into_iter()
+                .map(|sink| batch::wrap(sink, batching)),
+        );
+
+        Ok(sinks)
+    }
+
+    /// Records every message to `file` besides sending it to the sinks, as
+    /// the `record` command does, until `stop_recording`.
+    pub fn start_recording(&mut self, file: &Path) -> io::Result<()> {
+        if let Some((recording, _)) = &self.recording {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("already recording to {}", recording.display()),
+            ));
+        }
+        let recorder: Box<dyn Sink> = if arrow::is_arrow(file) {
+            Box::new(ArrowRecorder::create(file)?)
+        } else {
+            Box::new(Recorder::create(file)?)
+        };
+        self.recording = Some((file.to_path_buf(), self.sinks.len()));
+        self.sinks.push(recorder);
+        Ok(())
+    }
+
+    /// Closes the recording `start_recording` started, returning its file.
+    pub fn stop_recording(&mut self) -> io::Result<Option<PathBuf>> {
+        let Some((file, index)) = self.recording.take() else {
+            return Ok(None);
+        };
+        self.sinks.remove(index).close()?;
+        Ok(Some(file))
+    }
+
+    pub fn recording(&self) -> Option<&Path> {
+        self.recording.as_ref().map(|(file, _)| file.as_path())
+    }
+
+    pub fn statuses(&self) -> Vec<(String, String)> {
+        self.sinks
+            .iter()
+            .map(|sink| (sink.name().to_string(), sink.status()))
+            .collect()
+    }
+
+    pub fn stats(&self) -> Vec<(String, SinkStats)> {
+        self.sinks
+            .iter()
+            .map(|sink| (sink.name().to_string(), sink.stats()))
+            .collect()
+    }
+
+    pub fn tick(&mut self) -> Result<(), BridgeError> {
+        for sink in &mut self.sinks {
+            sink.tick().map_err(|e| BridgeError::sink(sink.name(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn mark(&mut self, label: &str) -> Result<(), BridgeError> {
+        for sink in &mut self.sinks {
+            sink.mark(label)
+                .map_err(|e| BridgeError::sink(sink.name(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Flushes and closes every sink, carrying on past the ones that fail.
+    pub fn close(&mut self) {
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.close() {
+                error!(sink: sink.name(), "Error closing {}: {}", sink.name(), e);
+            }
+        }
+    }
+
+    pub fn forward_data(&mut self, message: &Value) -> Result<(), BridgeError> {
+        for sink in &mut self.sinks {
+            sink.send(message)
+                .map_err(|e| BridgeError::sink(sink.name(), e))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,753 @@
+//! Zeroconf for server mode: the bridge announces its TCP server on the
+//! local network as `_microblender._tcp`, and the `discover` command lists
+//! the bridges that answer. Only as much multicast DNS (RFC 6762) and
+//! DNS-SD (RFC 6763) as that needs is implemented.
+
+use crate::handshake::PROTOCOL_VERSION;
+use crate::{debug, info, warn};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const SERVICE: [&str; 3] = ["_microblender", "_tcp", "local"];
+/// Seconds other hosts may keep the records.
+const TTL: u32 = 120;
+/// The most a one-shot querier may keep them, per the RFC.
+const LEGACY_TTL: u32 = 10;
+/// How often the advertiser checks whether it should stop.
+const POLL: Duration = Duration::from_millis(250);
+/// How much later the announcement is repeated, in case it was lost.
+const REANNOUNCE: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Marks records only this host has, so caches replace their old copies.
+const CACHE_FLUSH: u16 = 0x8000;
+const FLAGS_RESPONSE: u16 = 0x8400;
+
+/// A domain name as its labels, e.g. `["_tcp", "local"]`.
+type Name = Vec<String>;
+
+fn service_name() -> Name {
+    SERVICE.iter().map(|label| label.to_string()).collect()
+}
+
+fn same_name(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// What is announced about the TCP server.
+struct Service {
+    instance: Name,
+    host: Name,
+    address: Ipv4Addr,
+    port: u16,
+    txt: Vec<String>,
+}
+
+impl Service {
+    fn is_asked_for(&self, (name, kind): &(Name, u16)) -> bool {
+        let asks = |wanted: &[u16]| *kind == TYPE_ANY || wanted.contains(kind);
+        (same_name(name, &service_name()) && asks(&[TYPE_PTR]))
+            || (same_name(name, &self.instance) && asks(&[TYPE_SRV, TYPE_TXT]))
+            || (same_name(name, &self.host) && asks(&[TYPE_A]))
+    }
+
+    /// All records in one response. A one-shot query from a port other
+    /// than 5353 gets its ID and question back, and shorter-lived records.
+    fn response(&self, ttl: u32, legacy: Option<(u16, &(Name, u16))>) -> Vec<u8> {
+        let (id, question) = match legacy {
+            Some((id, question)) => (id, Some(question)),
+            None => (0, None),
+        };
+        let (ttl, unique) = match legacy {
+            Some(_) => (ttl.min(LEGACY_TTL), CLASS_IN),
+            None => (ttl, CLASS_IN | CACHE_FLUSH),
+        };
+
+        let mut packet = Vec::new();
+        for value in [id, FLAGS_RESPONSE, question.is_some() as u16, 4, 0, 0] {
+            packet.extend(value.to_be_bytes());
+        }
+        if let Some((name, kind)) = question {
+            write_name(&mut packet, name);
+            packet.extend(kind.to_be_bytes());
+            packet.extend(CLASS_IN.to_be_bytes());
+        }
+
+        let mut instance = Vec::new();
+        write_name(&mut instance, &self.instance);
+        write_record(
+            &mut packet,
+            &service_name(),
+            TYPE_PTR,
+            CLASS_IN,
+            ttl,
+            &instance,
+        );
+
+        let mut srv = [0, 0, 0, 0].to_vec();
+        srv.extend(self.port.to_be_bytes());
+        write_name(&mut srv, &self.host);
+        write_record(&mut packet, &self.instance, TYPE_SRV, unique, ttl, &srv);
+
+        let mut txt = Vec::new();
+        for entry in &self.txt {
+            txt.push(entry.len() as u8);
+            txt.extend(entry.as_bytes());
+        }
+        write_record(&mut packet, &self.instance, TYPE_TXT, unique, ttl, &txt);
+
+        let address = self.address.octets();
+        write_record(&mut packet, &self.host, TYPE_A, unique, ttl, &address);
+        packet
+    }
+}
+
+fn write_name(packet: &mut Vec<u8>, name: &[String]) {
+    for label in name {
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+fn write_record(
+    packet: &mut Vec<u8>,
+    name: &[String],
+    kind: u16,
+    class: u16,
+    ttl: u32,
+    data: &[u8],
+) {
+    write_name(packet, name);
+    packet.extend(kind.to_be_bytes());
+    packet.extend(class.to_be_bytes());
+    packet.extend(ttl.to_be_bytes());
+    packet.extend((data.len() as u16).to_be_bytes());
+    packet.extend(data);
+}
+
+/// Answers queries for the TCP server until stopped, then tells the
+/// network it's gone.
+pub struct Advertiser {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Advertiser {
+    /// Announces the server listening on `bind`:`port`, noting in its TXT
+    /// record whether clients need the auth token.
+    pub fn start(bind: &str, port: u16, auth: bool) -> io::Result<Self> {
+        let address = advertised_address(bind)?;
+        if address.is_loopback() {
+            warn!(
+                "Advertising {} via mDNS, which only this machine can connect to; use --listen-bind 0.0.0.0 to serve the network",
+                address
+            );
+        }
+
+        let host = hostname();
+        let service = Service {
+            instance: [format!("{}-{}", host, port)]
+                .into_iter()
+                .chain(service_name())
+                .collect(),
+            host: vec![host, "local".to_string()],
+            address,
+            port,
+            txt: vec![
+                format!("version={}", env!("CARGO_PKG_VERSION")),
+                format!("protocol={}", PROTOCOL_VERSION),
+                format!("auth={}", if auth { "required" } else { "none" }),
+            ],
+        };
+
+        let socket = shared_socket()?;
+        socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_read_timeout(Some(POLL))?;
+        socket.send_to(&service.response(TTL, None), (GROUP, PORT))?;
+        info!(
+            "Advertising {} via mDNS as {}",
+            service.address,
+            service.instance.join(".")
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || advertise(&socket, &service, &stop))
+        };
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn advertise(socket: &UdpSocket, service: &Service, stop: &AtomicBool) {
+    let mut reannounce = Some(Instant::now() + REANNOUNCE);
+    let mut buffer = [0; 9000];
+    while !stop.load(Ordering::Relaxed) {
+        if reannounce.is_some_and(|at| Instant::now() >= at) {
+            reannounce = None;
+            send(socket, &service.response(TTL, None), (GROUP, PORT).into());
+        }
+
+        let (length, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => {
+                warn!("mDNS advertising stopped: {}", e);
+                return;
+            }
+        };
+        let Some(query) = parse(&buffer[..length]).filter(|message| !message.response) else {
+            continue;
+        };
+        let Some(question) = query.questions.iter().find(|q| service.is_asked_for(q)) else {
+            continue;
+        };
+
+        debug!("Answering mDNS query from {}", source);
+        if source.port() == PORT {
+            send(socket, &service.response(TTL, None), (GROUP, PORT).into());
+        } else {
+            send(
+                socket,
+                &service.response(TTL, Some((query.id, question))),
+                source,
+            );
+        }
+    }
+
+    // A TTL of 0 makes the others forget the bridge right away
+    send(socket, &service.response(0, None), (GROUP, PORT).into());
+}
+
+fn send(socket: &UdpSocket, packet: &[u8], to: SocketAddr) {
+    if let Err(e) = socket.send_to(packet, to) {
+        debug!("Sending mDNS response to {} failed: {}", to, e);
+    }
+}
+
+/// The IPv4 address the server is reachable at. When it listens on every
+/// interface, that's the one multicast goes out on.
+fn advertised_address(bind: &str) -> io::Result<Ipv4Addr> {
+    let address = (bind, 0)
+        .to_socket_addrs()?
+        .find_map(|address| match address.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no IPv4 address to advertise", bind),
+            )
+        })?;
+    if !address.is_unspecified() {
+        return Ok(address);
+    }
+
+    // Connecting a UDP socket sends nothing, but picks the interface
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((GROUP, PORT))?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Ok(ip),
+        _ => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no network interface to advertise on",
+        )),
+    }
+}
+
+/// The first label of this machine's name, usable as `NAME.local`.
+fn hostname() -> String {
+    let name = system_hostname();
+    let label: String = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .take(50)
+        .collect();
+    if label.is_empty() {
+        "microblender".to_string()
+    } else {
+        label
+    }
+}
+
+#[cfg(unix)]
+fn system_hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: gethostname writes at most the buffer's length
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return String::new();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn system_hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Binds the mDNS port alongside the system's own responder, if it has one.
+#[cfg(unix)]
+fn shared_socket() -> io::Result<UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: the descriptor is checked and then owned by the UdpSocket,
+    // and every option and address is passed with its own size
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = UdpSocket::from_raw_fd(fd);
+
+        let on: libc::c_int = 1;
+        let mut options = vec![libc::SO_REUSEADDR];
+        // The BSDs, macOS included, only share multicast ports this way
+        if cfg!(not(target_os = "linux")) {
+            options.push(libc::SO_REUSEPORT);
+        }
+        for option in options {
+            let result = libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &on as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut address: libc::sockaddr_in = std::mem::zeroed();
+        address.sin_family = libc::AF_INET as libc::sa_family_t;
+        address.sin_port = PORT.to_be();
+        address.sin_addr.s_addr = libc::INADDR_ANY;
+        let result = libc::bind(
+            fd,
+            &address as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        );
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(not(unix))]
+fn shared_socket() -> io::Result<UdpSocket> {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))
+}
+
+/// A bridge that answered `discover`.
+#[derive(Debug)]
+pub struct Found {
+    pub instance: String,
+    pub host: String,
+    pub address: Option<Ipv4Addr>,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+/// Asks the network for bridges and collects the answers for `timeout`.
+pub fn browse(timeout: Duration) -> io::Result<Vec<Found>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let mut query = Vec::new();
+    for value in [0, 0, 1, 0, 0, 0] {
+        query.extend(u16::to_be_bytes(value));
+    }
+    write_name(&mut query, &service_name());
+    query.extend(TYPE_PTR.to_be_bytes());
+    query.extend(CLASS_IN.to_be_bytes());
+
+    let deadline = Instant::now() + timeout;
+    let mut repeat = Some(Instant::now() + REANNOUNCE);
+    socket.send_to(&query, (GROUP, PORT))?;
+
+    let mut records = Vec::new();
+    let mut buffer = [0; 9000];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if repeat.is_some_and(|at| Instant::now() >= at) {
+            repeat = None;
+            socket.send_to(&query, (GROUP, PORT))?;
+        }
+        socket.set_read_timeout(Some(left.clamp(Duration::from_millis(1), POLL)))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((length, _)) => {
+                if let Some(message) = parse(&buffer[..length]).filter(|m| m.response) {
+                    records.extend(message.records);
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(collect(&records))
+}
+
+/// Puts together what each advertised instance's records say about it.
+fn collect(records: &[(Name, Data)]) -> Vec<Found> {
+    let mut found: Vec<Found> = Vec::new();
+    for (name, data) in records {
+        let Data::Ptr(instance) = data else {
+            continue;
+        };
+        let label = instance.first().cloned().unwrap_or_default();
+        if !same_name(name, &service_name()) || found.iter().any(|f| f.instance == label) {
+            continue;
+        }
+
+        let Some((port, target)) = records.iter().find_map(|(name, data)| match data {
+            Data::Srv { port, target } if same_name(name, instance) => Some((*port, target)),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let address = records.iter().find_map(|(name, data)| match data {
+            Data::A(address) if same_name(name, target) => Some(*address),
+            _ => None,
+        });
+        let txt = records
+            .iter()
+            .find_map(|(name, data)| match data {
+                Data::Txt(txt) if same_name(name, instance) => Some(txt.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        found.push(Found {
+            instance: label,
+            host: target.join("."),
+            address,
+            port,
+            txt,
+        });
+    }
+    found
+}
+
+/// Lists the bridges on the network, for the `discover` command.
+pub fn discover(timeout: Duration) -> io::Result<()> {
+    info!("Looking for bridges for {:.1}s", timeout.as_secs_f64());
+    let found = browse(timeout)?;
+    if found.is_empty() {
+        info!("No bridges found");
+    }
+    for bridge in found {
+        let address = match bridge.address {
+            Some(address) => format!("{}:{}", address, bridge.port),
+            None => format!("{}:{}", bridge.host, bridge.port),
+        };
+        println!("{}  {}  {}", bridge.instance, address, bridge.txt.join(" "));
+    }
+    Ok(())
+}
+
+enum Data {
+    A(Ipv4Addr),
+    Ptr(Name),
+    Srv { port: u16, target: Name },
+    Txt(Vec<String>),
+    Other,
+}
+
+struct Message {
+    id: u16,
+    response: bool,
+    questions: Vec<(Name, u16)>,
+    /// Answers and additional records alike.
+    records: Vec<(Name, Data)>,
+}
+
+/// Reads a message, or gives `None` for anything malformed.
+fn parse(packet: &[u8]) -> Option<Message> {
+    let mut reader = Reader { packet, pos: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let questions = reader.u16()?;
+    let records = (0..3).try_fold(0, |total, _| Some(total + reader.u16()?))?;
+
+    let questions = (0..questions)
+        .map(|_| {
+            let name = reader.name()?;
+            let kind = reader.u16()?;
+            reader.u16()?;
+            Some((name, kind))
+        })
+        .collect::<Option<_>>()?;
+
+    let records = (0..records)
+        .map(|_| {
+            let name = reader.name()?;
+            let kind = reader.u16()?;
+            reader.u16()?;
+            reader.u32()?;
+            let length = reader.u16()? as usize;
+            let end = reader
+                .pos
+                .checked_add(length)
+                .filter(|&end| end <= packet.len())?;
+            let data = match kind {
+                TYPE_A => Data::A(Ipv4Addr::from(<[u8; 4]>::try_from(reader.bytes(4)?).ok()?)),
+                TYPE_PTR => Data::Ptr(reader.name()?),
+                TYPE_SRV => {
+                    reader.bytes(4)?;
+                    let port = reader.u16()?;
+                    Data::Srv {
+                        port,
+                        target: reader.name()?,
+                    }
+                }
+                TYPE_TXT => {
+                    let mut entries = Vec::new();
+                    while reader.pos < end {
+                        let length = reader.bytes(1)?[0] as usize;
+                        entries.push(String::from_utf8_lossy(reader.bytes(length)?).into_owned());
+                    }
+                    Data::Txt(entries)
+                }
+                _ => Data::Other,
+            };
+            reader.pos = end;
+            Some((name, data))
+        })
+        .collect::<Option<_>>()?;
+
+    Some(Message {
+        id,
+        response: flags & 0x8000 != 0,
+        questions,
+        records,
+    })
+}
+
+struct Reader<'a> {
+    packet: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.packet.get(self.pos..self.pos.checked_add(count)?)?;
+        self.pos += count;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// Reads a name, following compression pointers to earlier ones.
+    fn name(&mut self) -> Option<Name> {
+        let mut name = Vec::new();
+        let mut pos = self.pos;
+        let mut resume = None;
+        // Pointers must lead backwards, so a loop can't go on forever
+        let mut limit = pos;
+        loop {
+            let length = *self.packet.get(pos)? as usize;
+            if length & 0xC0 == 0xC0 {
+                let target = ((length & 0x3F) << 8) | *self.packet.get(pos + 1)? as usize;
+                if target >= limit {
+                    return None;
+                }
+                resume.get_or_insert(pos + 2);
+                limit = target;
+                pos = target;
+            } else if length == 0 {
+                self.pos = resume.unwrap_or(pos + 1);
+                return Some(name);
+            } else {
+                let label = self.packet.get(pos + 1..pos + 1 + length)?;
+                name.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + length;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(text: &str) -> Name {
+        text.split('.').map(str::to_string).collect()
+    }
+
+    fn service() -> Service {
+        Service {
+            instance: name("studio._microblender._tcp.local"),
+            host: name("studio.local"),
+            address: Ipv4Addr::new(192, 168, 1, 20),
+            port: 9000,
+            txt: vec!["version=2".to_string(), "auth=none".to_string()],
+        }
+    }
+
+    /// The class and TTL of every record in `packet`, which parse() leaves
+    /// out.
+    fn classes_and_ttls(packet: &[u8]) -> Vec<(u16, u32)> {
+        let mut reader = Reader { packet, pos: 4 };
+        let questions = reader.u16().unwrap();
+        let records: u16 = (0..3).map(|_| reader.u16().unwrap()).sum();
+        for _ in 0..questions {
+            reader.name().unwrap();
+            reader.bytes(4).unwrap();
+        }
+        (0..records)
+            .map(|_| {
+                reader.name().unwrap();
+                reader.u16().unwrap();
+                let class = reader.u16().unwrap();
+                let ttl = reader.u32().unwrap();
+                let length = reader.u16().unwrap() as usize;
+                reader.bytes(length).unwrap();
+                (class, ttl)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_announcement_describes_the_whole_service() {
+        let packet = service().response(TTL, None);
+        let message = parse(&packet).unwrap();
+        assert!(message.response);
+        assert_eq!(message.id, 0);
+        assert!(message.questions.is_empty());
+
+        let found = collect(&message.records);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].instance, "studio");
+        assert_eq!(found[0].host, "studio.local");
+        assert_eq!(found[0].address, Some(Ipv4Addr::new(192, 168, 1, 20)));
+        assert_eq!(found[0].port, 9000);
+        assert_eq!(found[0].txt, ["version=2", "auth=none"]);
+    }
+
+    #[test]
+    fn only_the_shared_record_is_announced_without_cache_flush() {
+        let packet = service().response(TTL, None);
+        let unique = CLASS_IN | CACHE_FLUSH;
+        assert_eq!(
+            classes_and_ttls(&packet),
+            [(CLASS_IN, TTL), (unique, TTL), (unique, TTL), (unique, TTL)]
+        );
+
+        let goodbye = service().response(0, None);
+        assert!(classes_and_ttls(&goodbye).iter().all(|&(_, ttl)| ttl == 0));
+    }
+
+    #[test]
+    fn a_one_shot_query_gets_its_id_and_question_back() {
+        let question = (service_name(), TYPE_PTR);
+        let packet = service().response(TTL, Some((0x1234, &question)));
+        let message = parse(&packet).unwrap();
+        assert_eq!(message.id, 0x1234);
+        assert_eq!(message.questions, [question]);
+        assert_eq!(collect(&message.records).len(), 1);
+        // Short-lived, and not flushing anyone's cache
+        assert!(classes_and_ttls(&packet)
+            .iter()
+            .all(|&(class, ttl)| class == CLASS_IN && ttl == LEGACY_TTL));
+    }
+
+    #[test]
+    fn queries_for_the_service_instance_or_host_are_answered() {
+        let service = service();
+        let asked = |text: &str, kind| service.is_asked_for(&(name(text), kind));
+
+        assert!(asked("_microblender._tcp.local", TYPE_PTR));
+        assert!(asked("_MicroBlender._TCP.local", TYPE_PTR));
+        assert!(asked("studio._microblender._tcp.local", TYPE_SRV));
+        assert!(asked("studio._microblender._tcp.local", TYPE_TXT));
+        assert!(asked("studio.local", TYPE_A));
+        assert!(asked("studio.local", TYPE_ANY));
+
+        assert!(!asked("_microblender._tcp.local", TYPE_SRV));
+        assert!(!asked("studio.local", TYPE_PTR));
+        assert!(!asked("_http._tcp.local", TYPE_PTR));
+        assert!(!asked("other.local", TYPE_A));
+    }
+
+    #[test]
+    fn a_query_is_parsed_with_its_questions() {
+        let mut query = Vec::new();
+        for value in [7, 0, 2, 0, 0, 0] {
+            query.extend(u16::to_be_bytes(value));
+        }
+        write_name(&mut query, &service_name());
+        query.extend(TYPE_PTR.to_be_bytes());
+        query.extend(CLASS_IN.to_be_bytes());
+        // The second question points back at "local" in the first
+        query.extend([6]);
+        query.extend(b"studio");
+        query.extend([0xC0, 12 + 1 + 13 + 1 + 4]);
+        query.extend(TYPE_A.to_be_bytes());
+        query.extend(CLASS_IN.to_be_bytes());
+
+        let message = parse(&query).unwrap();
+        assert!(!message.response);
+        assert_eq!(message.id, 7);
+        assert_eq!(
+            message.questions,
+            [(service_name(), TYPE_PTR), (name("studio.local"), TYPE_A)]
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_ignored() {
+        // Cut short, and a name pointing at itself
+        let packet = service().response(TTL, None);
+        assert!(parse(&packet[..packet.len() - 1]).is_none());
+
+        let mut query = Vec::new();
+        for value in [0, 0, 1, 0, 0, 0] {
+            query.extend(u16::to_be_bytes(value));
+        }
+        query.extend([0xC0, 12, 0, 1, 0, 1]);
+        assert!(parse(&query).is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Terminal dashboard showing the live orientation, message rate, sink
+//! status and recent events, redrawn in place.
+//!
+//! With `--plot` the orientation is shown as rolling charts of the last
+//! few seconds instead of gauges, drawn with braille characters so each
+//! character cell holds 2×4 points.
+
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const MAX_EVENTS: usize = 8;
+const GAUGE_WIDTH: usize = 41;
+
+/// Width of a chart in characters, two samples each.
+const PLOT_WIDTH: usize = 60;
+/// Height of a chart in characters, four points each.
+const PLOT_HEIGHT: usize = 4;
+/// Bits of the braille dots, by column and then row from the top.
+const BRAILLE_DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/// Gauges as (label, message field, full-scale angle).
+const GAUGES: [(&str, &str, f64); 3] = [
+    ("Pitch", "x", 90.0),
+    ("Roll", "y", 90.0),
+    ("Yaw", "z", 180.0),
+];
+
+pub struct Monitor {
+    start: Instant,
+    last_draw: Instant,
+    last_message: Option<(Instant, Value)>,
+    arrivals: VecDeque<Instant>,
+    total: u64,
+    dropped: u64,
+    sinks: Vec<(String, String)>,
+    events: VecDeque<String>,
+    /// The board's latest temperature in °C.
+    temperature: Option<f64>,
+    /// The latest angles per gauge, with `--plot`.
+    history: Option<VecDeque<[Option<f64>; 3]>>,
+}
+
+impl Monitor {
+    // Not `Default`, creating a monitor takes over the terminal
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        // Hide the cursor and switch to the alternate screen
+        print!("\x1b[?25l\x1b[?1049h");
+        let now = Instant::now();
+        Self {
+            start: now,
+            last_draw: now - REDRAW_INTERVAL,
+            last_message: None,
+            arrivals: VecDeque::new(),
+            total: 0,
+            dropped: 0,
+            sinks: Vec::new(),
+            events: VecDeque::new(),
+            temperature: None,
+            history: None,
+        }
+    }
+
+    /// Shows rolling charts of the angles instead of gauges.
+    pub fn with_plot(mut self) -> Self {
+        self.history = Some(VecDeque::with_capacity(PLOT_WIDTH * 2));
+        self
+    }
+
+    pub fn record_message(&mut self, message: &Value) {
+        if let Some(temperature) = message.get("temp").and_then(Value::as_f64) {
+            self.temperature = Some(temperature);
+        }
+        if let Some(history) = &mut self.history {
+            // Events and gestures carry no angles
+            if message.get("x").is_some() {
+                if history.len() == PLOT_WIDTH * 2 {
+                    history.pop_front();
+                }
+                history.push_back(GAUGES.map(|(_, field, _)| message.get(field)?.as_f64()));
+            }
+        }
+        let now = Instant::now();
+        self.arrivals.push_back(now);
+        self.last_message = Some((now, message.clone()));
+        self.total += 1;
+    }
+
+    /// Sets the number of messages lost on the serial link.
+    pub fn set_dropped(&mut self, dropped: u64) {
+        self.dropped = dropped;
+    }
+
+    pub fn record_event(&mut self, event: impl Into<String>) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        self.events
+            .push_back(format!("[{:>8.1}s] {}", elapsed, event.into()));
+        if self.events.len() > MAX_EVENTS {
+            self.events.pop_front();
+        }
+    }
+
+    /// Updates the sink list, turning status changes into events.
+    pub fn update_sinks(&mut self, sinks: Vec<(String, String)>) {
+        for (name, status) in &sinks {
+            let previous = self.sinks.iter().find(|(known, _)| known == name);
+            if previous.is_some_and(|(_, old)| old != status) {
+                self.record_event(format!("{}: {}", name, status));
+            }
+        }
+        self.sinks = sinks;
+    }
+
+    /// Redraws the dashboard, at most every `REDRAW_INTERVAL`.
+    pub fn draw(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        if now.duration_since(self.last_draw) < REDRAW_INTERVAL {
+            return Ok(());
+        }
+        self.last_draw = now;
+
+        while self
+            .arrivals
+            .front()
+            .is_some_and(|arrival| now.duration_since(*arrival) > RATE_WINDOW)
+        {
+            self.arrivals.pop_front();
+        }
+
+        let mut screen = String::from("\x1b[H\x1b[2J");
+        let _ = writeln!(
+            screen,
+            " Microblender bridge monitor          (Ctrl+C to exit)\n"
+        );
+
+        let message = self.last_message.as_ref().map(|(_, message)| message);
+        for (i, (label, field, range)) in GAUGES.into_iter().enumerate() {
+            let value = message.and_then(|message| message.get(field)?.as_f64());
+            let value_text = value.map_or("     -".to_string(), |value| format!("{:>7.1}°", value));
+            match &self.history {
+                Some(history) => {
+                    let values: Vec<Option<f64>> = history.iter().map(|angles| angles[i]).collect();
+                    for (row, line) in chart(&values, range).into_iter().enumerate() {
+                        let text = match row {
+                            0 => format!("{:>+5.0}°", range),
+                            1 => value_text.clone(),
+                            _ if row == PLOT_HEIGHT - 1 => format!("{:>+5.0}°", -range),
+                            _ => String::new(),
+                        };
+                        let label = if row == 0 { label } else { "" };
+                        let _ = writeln!(screen, " {:<6}|{}| {}", label, line, text);
+                    }
+                    let _ = writeln!(screen);
+                }
+                None => {
+                    let _ = writeln!(
+                        screen,
+                        " {:<6}{} {}",
+                        label,
+                        gauge(value.unwrap_or(0.0), range),
+                        value_text
+                    );
+                }
+            }
+        }
+
+        let since_last = self
+            .last_message
+            .as_ref()
+            .map_or("never".to_string(), |(at, _)| {
+                format!("{:.1}s ago", now.duration_since(*at).as_secs_f64())
+            });
+        let _ = writeln!(
+            screen,
+            "\n Rate {:>5.1} msg/s   Total {}   Dropped {}   Last message {}",
+            self.arrivals.len() as f64 / RATE_WINDOW.as_secs_f64(),
+            self.total,
+            self.dropped,
+            since_last
+        );
+        if let Some(temperature) = self.temperature {
+            let _ = writeln!(screen, " Board temperature {:.1}°C", temperature);
+        }
+
+        let _ = writeln!(screen, "\n Sinks");
+        for (name, status) in &self.sinks {
+            let _ = writeln!(screen, "   {:<24} {}", name, status);
+        }
+
+        let _ = writeln!(screen, "\n Recent events");
+        for event in &self.events {
+            let _ = writeln!(screen, "   {}", event);
+        }
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        // Restore the normal screen and the cursor
+        print!("\x1b[?1049l\x1b[?25h");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// A horizontal bar growing from the center towards `value`.
+fn gauge(value: f64, range: f64) -> String {
+    let center = GAUGE_WIDTH / 2;
+    let offset = (value / range).clamp(-1.0, 1.0) * center as f64;
+    let position = (center as f64 + offset).round() as usize;
+    let (from, to) = (position.min(center), position.max(center));
+
+    let bar: String = (0..GAUGE_WIDTH)
+        .map(|i| match i {
+            _ if i == center => '|',
+            _ if i >= from && i <= to => '=',
+            _ => ' ',
+        })
+        .collect();
+    format!("[{}]", bar)
+}
+
+/// A braille chart of `values` between `-range` and `range`, oldest on the
+/// left, as `PLOT_HEIGHT` lines of `PLOT_WIDTH` characters. Consecutive
+/// points are joined by a vertical stroke so fast changes stay visible.
+fn chart(values: &[Option<f64>], range: f64) -> Vec<String> {
+    let rows = PLOT_HEIGHT * 4;
+    let mut cells = vec![[0u8; PLOT_WIDTH]; PLOT_HEIGHT];
+    // Right-aligned, so the chart scrolls in from the right
+    let skip = (PLOT_WIDTH * 2).saturating_sub(values.len());
+
+    let mut previous: Option<usize> = None;
+    for (i, value) in values.iter().enumerate() {
+        let Some(value) = value else {
+            previous = None;
+            continue;
+        };
+        let scaled = (range - value.clamp(-range, range)) / (2.0 * range);
+        let row = (scaled * (rows - 1) as f64).round() as usize;
+        let (from, to) = match previous {
+            Some(previous) => (row.min(previous), row.max(previous)),
+            None => (row, row),
+        };
+        let column = skip + i;
+        for row in from..=to {
+            cells[row / 4][column / 2] |= BRAILLE_DOTS[column % 2][row % 4];
+        }
+        previous = Some(row);
+    }
+
+    cells
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|&dots| match dots {
+                    0 => ' ',
+                    dots => char::from_u32(0x2800 + u32::from(dots)).unwrap_or(' '),
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,214 @@
+//! Publishes messages to an MQTT broker using a minimal MQTT 3.1.1 client
+//! (QoS 0 only, which is all a live sensor stream needs).
+
+use crate::connection::{ReconnectConfig, ServiceConnection};
+use crate::sink::{Sink, SinkStats};
+use serde_json::Value;
+use std::io;
+
+const DEFAULT_PORT: u16 = 1883;
+
+const CONNECT: u8 = 0x10;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xC0;
+const DISCONNECT: u8 = 0xE0;
+const PROTOCOL_LEVEL: u8 = 4; // MQTT 3.1.1
+const CLEAN_SESSION: u8 = 0x02;
+
+pub struct MqttSink {
+    connection: ServiceConnection,
+    topic: String,
+}
+
+impl MqttSink {
+    pub fn connect(
+        url: &str,
+        topic: &str,
+        client_id: &str,
+        config: ReconnectConfig,
+    ) -> io::Result<Self> {
+        let (host, port) = parse_url(url)?;
+        let connection = ServiceConnection::connect(&host, port, "MQTT", config)?
+            .with_greeting(connect_packet(client_id))?
+            .with_heartbeat(packet(PINGREQ, &[]));
+
+        Ok(Self {
+            connection,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+impl Sink for MqttSink {
+    fn name(&self) -> &str {
+        "MQTT"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let payload = serde_json::to_vec(message)?;
+        self.connection
+            .write(&publish_packet(&self.topic, &payload))
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        self.connection.keep_alive()
+    }
+
+    fn status(&self) -> String {
+        self.connection.status()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.connection.stats()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.connection.write(&packet(DISCONNECT, &[]))?;
+        self.connection.close()
+    }
+}
+
+/// Accepts `mqtt://host[:port]` as well as a bare `host[:port]`.
+fn parse_url(url: &str) -> io::Result<(String, u16)> {
+    let address = url.strip_prefix("mqtt://").unwrap_or(url);
+    let address = address.trim_end_matches('/');
+
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid MQTT port in {}", url),
+                )
+            })?;
+            Ok((host.to_string(), port))
+        }
+        None => Ok((address.to_string(), DEFAULT_PORT)),
+    }
+}
+
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_string(&mut body, "MQTT");
+    body.push(PROTOCOL_LEVEL);
+    body.push(CLEAN_SESSION);
+    // Keep alive disabled: the broker would otherwise drop us whenever the
+    // Microbit is unplugged for a while and nothing gets published.
+    body.extend_from_slice(&0u16.to_be_bytes());
+    push_string(&mut body, client_id);
+
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+    push_string(&mut body, topic);
+    body.extend_from_slice(payload);
+
+    packet(PUBLISH, &body)
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(header);
+
+    // Remaining length, 7 bits per byte with a continuation bit
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn push_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The fixed header's remaining length of a packet with `length` bytes
+    /// after it.
+    fn remaining_length(length: usize) -> Vec<u8> {
+        let packet = packet(PUBLISH, &vec![0; length]);
+        packet[1..packet.len() - length].to_vec()
+    }
+
+    #[test]
+    fn the_connect_packet_asks_for_a_clean_session_without_keep_alive() {
+        let packet = connect_packet("bridge");
+        assert_eq!(
+            packet,
+            [
+                &[CONNECT, 18][..],
+                &[0, 4],
+                b"MQTT",
+                &[PROTOCOL_LEVEL, CLEAN_SESSION, 0, 0],
+                &[0, 6],
+                b"bridge",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn the_publish_packet_holds_the_topic_and_payload() {
+        let packet = publish_packet("microbit/orientation", b"{}");
+        assert_eq!(
+            packet,
+            [&[PUBLISH, 24, 0, 20][..], b"microbit/orientation", b"{}"].concat()
+        );
+    }
+
+    #[test]
+    fn remaining_lengths_take_more_bytes_past_127() {
+        // The boundaries of MQTT 3.1.1, section 2.2.3
+        assert_eq!(remaining_length(0), [0x00]);
+        assert_eq!(remaining_length(127), [0x7F]);
+        assert_eq!(remaining_length(128), [0x80, 0x01]);
+        assert_eq!(remaining_length(16_383), [0xFF, 0x7F]);
+        assert_eq!(remaining_length(16_384), [0x80, 0x80, 0x01]);
+        assert_eq!(remaining_length(2_097_152), [0x80, 0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn a_long_message_is_published_whole() {
+        let payload = vec![b'x'; 300];
+        let packet = publish_packet("t", &payload);
+        // Topic length, topic and payload: 303 bytes
+        assert_eq!(packet[..5], [PUBLISH, 0xAF, 0x02, 0, 1]);
+        assert_eq!(packet[5], b't');
+        assert_eq!(packet[6..], payload[..]);
+    }
+
+    #[test]
+    fn pings_and_disconnects_have_no_body() {
+        assert_eq!(packet(PINGREQ, &[]), [0xC0, 0x00]);
+        assert_eq!(packet(DISCONNECT, &[]), [0xE0, 0x00]);
+    }
+
+    #[test]
+    fn urls_default_to_the_mqtt_port() {
+        assert_eq!(
+            parse_url("mqtt://broker.local/").unwrap(),
+            ("broker.local".to_string(), DEFAULT_PORT)
+        );
+        assert_eq!(
+            parse_url("localhost:1884").unwrap(),
+            ("localhost".to_string(), 1884)
+        );
+        assert!(parse_url("mqtt://localhost:mqtt").is_err());
+    }
+}
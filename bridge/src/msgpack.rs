@@ -0,0 +1,114 @@
+//! Encoding of messages as MessagePack, for `--encoding msgpack`. Integers
+//! take the smallest form that holds them and every other number is a
+//! float 64, so decoders get the same values as from the JSON.
+
+use serde_json::Value;
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    put_value(&mut buffer, value);
+    buffer
+}
+
+fn put_value(buffer: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buffer.push(0xC0),
+        Value::Bool(false) => buffer.push(0xC2),
+        Value::Bool(true) => buffer.push(0xC3),
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                put_unsigned(buffer, value);
+            } else if let Some(value) = number.as_i64() {
+                put_signed(buffer, value);
+            } else {
+                buffer.push(0xCB);
+                buffer.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(text) => {
+            put_string_header(buffer, text.len());
+            buffer.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            put_container_header(buffer, items.len(), 0x90, [0xDC, 0xDD]);
+            for item in items {
+                put_value(buffer, item);
+            }
+        }
+        Value::Object(fields) => {
+            put_container_header(buffer, fields.len(), 0x80, [0xDE, 0xDF]);
+            for (name, field) in fields {
+                put_value(buffer, &Value::from(name.as_str()));
+                put_value(buffer, field);
+            }
+        }
+    }
+}
+
+fn put_unsigned(buffer: &mut Vec<u8>, value: u64) {
+    if value < 0x80 {
+        buffer.push(value as u8);
+    } else if let Ok(value) = u8::try_from(value) {
+        buffer.push(0xCC);
+        buffer.push(value);
+    } else if let Ok(value) = u16::try_from(value) {
+        buffer.push(0xCD);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = u32::try_from(value) {
+        buffer.push(0xCE);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    } else {
+        buffer.push(0xCF);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// A negative integer, the others are unsigned.
+fn put_signed(buffer: &mut Vec<u8>, value: i64) {
+    if value >= -32 {
+        buffer.push(value as u8);
+    } else if let Ok(value) = i8::try_from(value) {
+        buffer.push(0xD0);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = i16::try_from(value) {
+        buffer.push(0xD1);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = i32::try_from(value) {
+        buffer.push(0xD2);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    } else {
+        buffer.push(0xD3);
+        buffer.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn put_string_header(buffer: &mut Vec<u8>, length: usize) {
+    if length < 32 {
+        buffer.push(0xA0 | length as u8);
+    } else if let Ok(length) = u8::try_from(length) {
+        buffer.push(0xD9);
+        buffer.push(length);
+    } else {
+        put_long_length(buffer, length, [0xDA, 0xDB]);
+    }
+}
+
+/// The header of an array or map of `length` items, given the marker of
+/// its fixed form and of its 16- and 32-bit forms.
+fn put_container_header(buffer: &mut Vec<u8>, length: usize, fixed: u8, markers: [u8; 2]) {
+    if length < 16 {
+        buffer.push(fixed | length as u8);
+    } else {
+        put_long_length(buffer, length, markers);
+    }
+}
+
+fn put_long_length(buffer: &mut Vec<u8>, length: usize, [short, long]: [u8; 2]) {
+    if let Ok(length) = u16::try_from(length) {
+        buffer.push(short);
+        buffer.extend_from_slice(&length.to_be_bytes());
+    } else {
+        buffer.push(long);
+        buffer.extend_from_slice(&(length as u32).to_be_bytes());
+    }
+}
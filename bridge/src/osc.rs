@@ -0,0 +1,81 @@
+//! Sends pitch/roll/yaw as Open Sound Control messages over UDP, for tools
+//! like TouchDesigner, Max/MSP and Pure Data.
+
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::io;
+use std::net::UdpSocket;
+
+/// Which message field feeds which OSC address (appended to the prefix).
+const AXES: [(&str, &str); 6] = [
+    ("x", "pitch"),
+    ("y", "roll"),
+    ("z", "yaw"),
+    ("gx", "pitch_rate"),
+    ("gy", "roll_rate"),
+    ("gz", "yaw_rate"),
+];
+
+pub struct OscSink {
+    socket: UdpSocket,
+    target: String,
+    addresses: Vec<(&'static str, String)>,
+}
+
+impl OscSink {
+    pub fn new(host: &str, port: u16, prefix: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        let target = format!("{}:{}", host, port);
+        let prefix = prefix.trim_end_matches('/');
+        let addresses = AXES
+            .iter()
+            .map(|(field, name)| (*field, format!("{}/{}", prefix, name)))
+            .collect();
+
+        info!("Sending OSC to {} under {}/", target, prefix);
+        Ok(Self {
+            socket,
+            target,
+            addresses,
+        })
+    }
+}
+
+impl Sink for OscSink {
+    fn name(&self) -> &str {
+        "OSC"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        for (field, address) in &self.addresses {
+            if let Some(value) = message.get(field).and_then(Value::as_f64) {
+                let packet = osc_message(address, value as f32);
+                // UDP has no connection to lose; a receiver that isn't running
+                // yet is not an error.
+                if let Err(e) = self.socket.send_to(&packet, &self.target) {
+                    warn!(sink: "OSC", "OSC send to {} failed: {}", self.target, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a message with a single float argument.
+fn osc_message(address: &str, value: f32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(address.len() + 12);
+    push_padded(&mut packet, address.as_bytes());
+    push_padded(&mut packet, b",f");
+    packet.extend_from_slice(&value.to_be_bytes());
+    packet
+}
+
+/// OSC strings are NUL terminated and padded to a multiple of four bytes.
+fn push_padded(packet: &mut Vec<u8>, data: &[u8]) {
+    packet.extend_from_slice(data);
+    packet.push(0);
+    while !packet.len().is_multiple_of(4) {
+        packet.push(0);
+    }
+}
@@ -0,0 +1,153 @@
+//! Parsers for the text lines of boards that don't send JSON, selected with
+//! `--input-format`. Every parser turns a line into the same JSON object the
+//! stock firmware sends, so everything after it works unchanged:
+//!
+//! - `json`: `{"x":12.3,"y":-4.5,"z":90}`, as always.
+//! - `kv`: `P:12.3 R:-4.5 Y:90` or `pitch=12.3, roll=-4.5`. `P`/`pitch`,
+//!   `R`/`roll` and `Y`/`yaw`/`H`/`heading` become `x`, `y` and `z`, any
+//!   other key is kept in lower case.
+//! - `csv`: `12.3,-4.5,90,17`, the values in the order of `--input-columns`
+//!   (default `x,y,z,seq`). NMEA-like sentences such as
+//!   `$MBORI,12.3,-4.5,90*2C` work too: the talker field is dropped and the
+//!   checksum after `*`, if any, has to match.
+
+use clap::ValueEnum;
+use serde_json::{Map, Number, Value};
+
+/// Default `--input-columns`.
+pub const DEFAULT_COLUMNS: &str = "x,y,z,seq";
+
+/// Turns one line from a board into a message.
+pub trait FrameParser: Send {
+    /// The message in `line`, or why there is none.
+    fn parse(&self, line: &str) -> Result<Value, String>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// A JSON object per line
+    #[default]
+    Json,
+    /// KEY:VALUE or KEY=VALUE pairs, e.g. "P:12.3 R:-4.5"
+    Kv,
+    /// Comma-separated values in the order of --input-columns, or NMEA-like
+    /// sentences
+    Csv,
+}
+
+impl InputFormat {
+    /// The parser for this format; `columns` only matters for CSV.
+    pub fn parser(self, columns: &[String]) -> Box<dyn FrameParser> {
+        match self {
+            InputFormat::Json => Box::new(JsonParser),
+            InputFormat::Kv => Box::new(KeyValueParser),
+            InputFormat::Csv => Box::new(CsvParser {
+                columns: columns.to_vec(),
+            }),
+        }
+    }
+}
+
+pub struct JsonParser;
+
+impl FrameParser for JsonParser {
+    fn parse(&self, line: &str) -> Result<Value, String> {
+        serde_json::from_str(line).map_err(|e| format!("invalid JSON: {}", e))
+    }
+}
+
+pub struct KeyValueParser;
+
+impl FrameParser for KeyValueParser {
+    fn parse(&self, line: &str) -> Result<Value, String> {
+        let mut fields = Map::new();
+        for pair in line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+            .filter(|pair| !pair.is_empty())
+        {
+            let (key, value) = pair
+                .split_once([':', '='])
+                .ok_or_else(|| format!("'{}' isn't KEY:VALUE", pair))?;
+            if key.is_empty() {
+                return Err(format!("'{}' has no key", pair));
+            }
+            let key = match key.to_ascii_lowercase().as_str() {
+                "p" | "pitch" => "x".to_string(),
+                "r" | "roll" => "y".to_string(),
+                "y" | "yaw" | "h" | "heading" => "z".to_string(),
+                key => key.to_string(),
+            };
+            fields.insert(key, value_of(value));
+        }
+        if fields.is_empty() {
+            return Err("no KEY:VALUE pairs".to_string());
+        }
+        Ok(Value::Object(fields))
+    }
+}
+
+pub struct CsvParser {
+    columns: Vec<String>,
+}
+
+impl FrameParser for CsvParser {
+    fn parse(&self, line: &str) -> Result<Value, String> {
+        let values = match line.strip_prefix('$') {
+            Some(sentence) => {
+                let sentence = nmea_payload(sentence)?;
+                // The first field names the talker and sentence
+                sentence.split_once(',').map_or("", |(_, values)| values)
+            }
+            None => line,
+        };
+
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+        if values.len() > self.columns.len() {
+            return Err(format!(
+                "{} values but only {} --input-columns",
+                values.len(),
+                self.columns.len()
+            ));
+        }
+        let mut fields = Map::new();
+        for (column, value) in self.columns.iter().zip(values) {
+            // Empty fields are left out, as NMEA does for missing data
+            if !value.is_empty() {
+                fields.insert(column.clone(), value_of(value));
+            }
+        }
+        if fields.is_empty() {
+            return Err("no values".to_string());
+        }
+        Ok(Value::Object(fields))
+    }
+}
+
+/// The part of an NMEA sentence (without the `$`) before its checksum,
+/// after checking the checksum if there is one.
+fn nmea_payload(sentence: &str) -> Result<&str, String> {
+    let Some((payload, checksum)) = sentence.split_once('*') else {
+        return Ok(sentence);
+    };
+    let expected = u8::from_str_radix(checksum.trim(), 16)
+        .map_err(|_| format!("invalid checksum '{}'", checksum))?;
+    let actual = payload.bytes().fold(0, |sum, byte| sum ^ byte);
+    if actual != expected {
+        return Err(format!(
+            "checksum {:02X} doesn't match {:02X}",
+            expected, actual
+        ));
+    }
+    Ok(payload)
+}
+
+/// A number if `text` is one, otherwise a string.
+fn value_of(text: &str) -> Value {
+    if let Ok(integer) = text.parse::<i64>() {
+        return integer.into();
+    }
+    text.parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map_or_else(|| Value::String(text.to_string()), Value::Number)
+}
@@ -0,0 +1,85 @@
+//! The protobuf wire format, written by hand for the messages of
+//! `proto/microblender.proto`: the gRPC service and `--encoding protobuf`
+//! both send `Orientation` messages.
+
+use serde_json::Value;
+use std::io;
+
+/// Protobuf wire types
+pub const VARINT: u64 = 0;
+pub const FIXED64: u64 = 1;
+pub const LENGTH_DELIMITED: u64 = 2;
+pub const FIXED32: u64 = 5;
+
+/// The schema, for consumers generating their decoders from the crate.
+pub const SCHEMA: &str = include_str!("../proto/microblender.proto");
+
+/// Encodes a message as `Orientation`, keeping the complete JSON for the
+/// fields the schema doesn't know.
+pub fn encode_orientation(message: &Value) -> io::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    for (field, name) in [(1, "x"), (2, "y"), (3, "z")] {
+        if let Some(angle) = message.get(name).and_then(Value::as_f64) {
+            put_key(&mut encoded, field, FIXED64);
+            encoded.extend_from_slice(&angle.to_le_bytes());
+        }
+    }
+    if let Some(seq) = message.get("seq").and_then(Value::as_u64) {
+        put_varint_field(&mut encoded, 4, seq);
+    }
+    if let Some(device) = message.get("device").and_then(Value::as_str) {
+        put_bytes_field(&mut encoded, 5, device.as_bytes());
+    }
+    put_bytes_field(&mut encoded, 6, &serde_json::to_vec(message)?);
+    for (field, name) in [(7, "gx"), (8, "gy"), (9, "gz")] {
+        if let Some(rate) = message.get(name).and_then(Value::as_f64) {
+            put_key(&mut encoded, field, FIXED64);
+            encoded.extend_from_slice(&rate.to_le_bytes());
+        }
+    }
+    Ok(encoded)
+}
+
+pub fn take<'a>(message: &mut &'a [u8], length: usize) -> Result<&'a [u8], String> {
+    if message.len() < length {
+        return Err("truncated message".to_string());
+    }
+    let (taken, rest) = message.split_at(length);
+    *message = rest;
+    Ok(taken)
+}
+
+pub fn take_varint(message: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(message, 1)?[0];
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint too long".to_string())
+}
+
+pub fn put_key(buffer: &mut Vec<u8>, field: u64, wire_type: u64) {
+    put_varint(buffer, field << 3 | wire_type);
+}
+
+pub fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub fn put_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    put_key(buffer, field, VARINT);
+    put_varint(buffer, value);
+}
+
+pub fn put_bytes_field(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_key(buffer, field, LENGTH_DELIMITED);
+    put_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
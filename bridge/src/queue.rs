@@ -0,0 +1,232 @@
+//! A bounded queue and a thread of its own for every sink, so a consumer
+//! that stalls only holds up itself instead of every other sink and the
+//! serial reader.
+//!
+//! A sink's errors are handed back to the forwarding loop on its next call,
+//! which at the latest is the next tick, and stop the bridge like those of
+//! an unqueued sink. So does a sink whose thread panicked.
+
+use crate::info;
+use crate::sink::{Sink, SinkStats};
+use crate::worker::{Job, Shared, Worker};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::io;
+use std::str::FromStr;
+
+/// What happens to a message for a sink whose queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued message to make room, keeping the stream
+    /// current (markers are kept)
+    DropOldest,
+    /// Discard the new message, keeping what's queued
+    DropNewest,
+    /// Wait for room, holding up the bridge like an unqueued sink
+    Block,
+}
+
+/// `--queue [NAME:]size=N,overflow=POLICY`: the queue of all sinks or of a
+/// named one, overriding `--queue-size` and `--overflow`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueSpec {
+    sink: Option<String>,
+    size: Option<usize>,
+    overflow: Option<OverflowPolicy>,
+}
+
+impl FromStr for QueueSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        // Names like "UDP host:port" contain colons, settings never do
+        let (sink, settings) = match spec.rsplit_once(':') {
+            Some((sink, settings)) => (Some(sink.to_string()), settings),
+            None => (None, spec),
+        };
+        if settings.is_empty() {
+            return Err(format!("'{}' changes no setting", spec));
+        }
+        let mut queue = QueueSpec {
+            sink,
+            ..Default::default()
+        };
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", setting))?;
+            match key.trim() {
+                "size" => {
+                    queue.size = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid queue size '{}'", value))?,
+                    )
+                }
+                "overflow" => queue.overflow = Some(OverflowPolicy::from_str(value, true)?),
+                key => {
+                    return Err(format!(
+                        "unknown setting '{}', expected size or overflow",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(queue)
+    }
+}
+
+pub struct QueuedSink {
+    name: String,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// Messages discarded as the queue was full.
+    dropped: u64,
+    worker: Worker,
+}
+
+/// Queues a sink writing a file like `wrap`, but blocking rather than
+/// losing messages when the queue is full: only a spec naming the sink
+/// changes its overflow policy.
+pub fn wrap_file(sink: Box<dyn Sink>, capacity: usize, specs: &[QueueSpec]) -> Box<dyn Sink> {
+    let specs: Vec<QueueSpec> = specs
+        .iter()
+        .map(|spec| QueueSpec {
+            overflow: spec.overflow.filter(|_| spec.sink.is_some()),
+            ..spec.clone()
+        })
+        .collect();
+    wrap(sink, capacity, OverflowPolicy::Block, &specs)
+}
+
+/// Moves `sink` onto its own thread behind a queue of `capacity` messages,
+/// or leaves it as it is for a capacity of 0. `specs` can change both for
+/// all sinks or this one.
+pub fn wrap(
+    sink: Box<dyn Sink>,
+    mut capacity: usize,
+    mut policy: OverflowPolicy,
+    specs: &[QueueSpec],
+) -> Box<dyn Sink> {
+    let global = specs.iter().filter(|spec| spec.sink.is_none());
+    let named = specs.iter().filter(|spec| {
+        spec.sink
+            .as_deref()
+            .is_some_and(|name| name.eq_ignore_ascii_case(sink.name()))
+    });
+    for spec in global.chain(named) {
+        capacity = spec.size.unwrap_or(capacity);
+        policy = spec.overflow.unwrap_or(policy);
+    }
+    if capacity == 0 {
+        return sink;
+    }
+
+    Box::new(QueuedSink {
+        name: sink.name().to_string(),
+        capacity,
+        policy,
+        dropped: 0,
+        worker: Worker::spawn(sink, work),
+    })
+}
+
+impl QueuedSink {
+    fn push(&mut self, job: Job) -> io::Result<()> {
+        let shared = self.worker.shared();
+        let mut state = shared.lock();
+        state.check()?;
+
+        while state.jobs.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    // Markers and ticks aren't data, only messages give way.
+                    // A queue of nothing else takes the job past its size.
+                    let oldest = state
+                        .jobs
+                        .iter()
+                        .position(|job| matches!(job, Job::Send(_)));
+                    let Some(oldest) = oldest else {
+                        break;
+                    };
+                    state.jobs.remove(oldest);
+                    self.dropped += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return Ok(());
+                }
+                OverflowPolicy::Block => {
+                    state = shared.wait(state);
+                    state.check()?;
+                }
+            }
+        }
+        state.jobs.push_back(job);
+        shared.notify();
+        Ok(())
+    }
+}
+
+impl Sink for QueuedSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.push(Job::Send(message.clone()))
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        // A sink busy with messages doesn't need ticking, but its errors
+        // shouldn't wait for the next message
+        let mut state = self.worker.lock();
+        state.check()?;
+        if state.jobs.is_empty() {
+            drop(state);
+            self.push(Job::Tick)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        match self.dropped {
+            0 => self.worker.status(),
+            dropped => format!("{}, {} dropped", self.worker.status(), dropped),
+        }
+    }
+
+    fn stats(&self) -> SinkStats {
+        let queued = self.worker.lock().jobs.len();
+        let stats = self.worker.stats();
+        SinkStats {
+            queued: stats.queued + queued,
+            capacity: self.capacity,
+            dropped: self.dropped,
+            ..stats
+        }
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.push(Job::Mark(label.to_string()))
+    }
+
+    /// Lets the sink work off its queue, then closes it. Reports the first
+    /// error of the last messages, if any.
+    fn close(&mut self) -> io::Result<()> {
+        if self.dropped > 0 && self.worker.is_running() {
+            info!(sink: self.name, "{} message(s) for {} dropped as its queue was full", self.dropped, self.name);
+        }
+        self.worker.close()
+    }
+}
+
+/// Feeds the queued jobs to the sink until the queue is closed and empty,
+/// then hands the sink back for closing.
+fn work(mut sink: Box<dyn Sink>, shared: &Shared) -> Box<dyn Sink> {
+    while let Some(job) = shared.next_job() {
+        let result = job.run(sink.as_mut());
+        shared.done(sink.as_ref(), result);
+    }
+    sink
+}
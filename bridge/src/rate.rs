@@ -0,0 +1,196 @@
+//! Limits how often messages are passed on to a sink, for consumers that
+//! can't (or don't need to) keep up with the board's output data rate.
+
+use crate::info;
+use crate::sink::{Sink, SinkStats};
+use crate::subscribe::MessageKind;
+use crate::units;
+use clap::ValueEnum;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const ANGLE_FIELDS: [&str; 3] = ["x", "y", "z"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RateMode {
+    /// Forward the latest message and skip the ones in between
+    Decimate,
+    /// Forward the average of the readings since the last message
+    Average,
+}
+
+/// `--max-rate HZ` for all sinks, or `--max-rate NAME=HZ` for a single one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateSpec {
+    sink: Option<String>,
+    hz: f64,
+}
+
+impl FromStr for RateSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, hz) = match spec.rsplit_once('=') {
+            Some((sink, hz)) => (Some(sink.to_string()), hz),
+            None => (None, spec),
+        };
+        let hz: f64 = hz
+            .parse()
+            .map_err(|_| format!("invalid rate '{}', expected HZ or NAME=HZ", spec))?;
+        if !(hz.is_finite() && hz > 0.0) {
+            return Err("rate must be a positive number of Hz".to_string());
+        }
+        Ok(Self { sink, hz })
+    }
+}
+
+impl RateSpec {
+    /// The rate of `specs` that applies to the sink called `name`. A rate
+    /// given for the sink's name takes precedence over a global one.
+    pub fn for_sink(specs: &[RateSpec], name: &str) -> Option<f64> {
+        let named = specs.iter().find(|spec| {
+            spec.sink
+                .as_deref()
+                .is_some_and(|sink| sink.eq_ignore_ascii_case(name))
+        });
+        let global = specs.iter().find(|spec| spec.sink.is_none());
+        named.or(global).map(|spec| spec.hz)
+    }
+}
+
+/// Wraps `sink` in a rate limiter if one of `specs` applies to it.
+pub fn limit(sink: Box<dyn Sink>, specs: &[RateSpec], mode: RateMode) -> Box<dyn Sink> {
+    match RateSpec::for_sink(specs, sink.name()) {
+        Some(hz) => {
+            info!("Limiting {} to {} Hz", sink.name(), hz);
+            Box::new(RateLimited::new(sink, hz, mode))
+        }
+        None => sink,
+    }
+}
+
+struct RateLimited {
+    inner: Box<dyn Sink>,
+    interval: Duration,
+    next_send: Instant,
+    mode: RateMode,
+    /// Sum and number of the readings of each field since the last message.
+    sums: HashMap<String, (f64, u32)>,
+    /// The first value of each angle in the averaged messages. The others
+    /// are averaged as turns from it, so angles on both sides of ±180°
+    /// don't average to 0.
+    first_angles: HashMap<String, f64>,
+}
+
+impl RateLimited {
+    fn new(inner: Box<dyn Sink>, hz: f64, mode: RateMode) -> Self {
+        Self {
+            inner,
+            interval: Duration::from_secs_f64(1.0 / hz),
+            next_send: Instant::now(),
+            mode,
+            sums: HashMap::new(),
+            first_angles: HashMap::new(),
+        }
+    }
+
+    fn accumulate(&mut self, message: &Value) {
+        let Value::Object(fields) = message else {
+            return;
+        };
+        for (key, value) in fields {
+            let angle = ANGLE_FIELDS.contains(&key.as_str());
+            // Counters like `seq` and the raw readings stay whole numbers,
+            // taken from the latest message
+            if !angle && (value.is_i64() || value.is_u64()) {
+                continue;
+            }
+            let Some(mut value) = value.as_f64() else {
+                continue;
+            };
+            if angle {
+                let first = *self.first_angles.entry(key.clone()).or_insert(value);
+                value = first + units::wrap_degrees(value - first);
+            }
+            let (sum, count) = self.sums.entry(key.clone()).or_default();
+            *sum += value;
+            *count += 1;
+        }
+    }
+
+    /// The latest message with its readings replaced by the averages.
+    fn averaged(&mut self, latest: &Value) -> Value {
+        let mut message = latest.clone();
+        if let Value::Object(fields) = &mut message {
+            for (key, (sum, count)) in self.sums.drain() {
+                let Some(field) = fields.get_mut(&key) else {
+                    continue;
+                };
+                let mut average = sum / f64::from(count);
+                if ANGLE_FIELDS.contains(&key.as_str()) {
+                    average = units::wrap_degrees(average);
+                }
+                *field = average.into();
+            }
+        }
+        self.sums.clear();
+        self.first_angles.clear();
+        message
+    }
+}
+
+impl Sink for RateLimited {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        // Events and gestures happen once, so none of them may be skipped
+        if MessageKind::of(message) != MessageKind::Orientation {
+            return self.inner.send(message);
+        }
+
+        if self.mode == RateMode::Average {
+            self.accumulate(message);
+        }
+
+        let now = Instant::now();
+        if now < self.next_send {
+            return Ok(());
+        }
+        // Keep the average rate exact even though messages only arrive at
+        // the board's sample times, but don't try to catch up after a gap.
+        self.next_send = (self.next_send + self.interval).max(now);
+
+        match self.mode {
+            RateMode::Decimate => self.inner.send(message),
+            RateMode::Average => {
+                let message = self.averaged(message);
+                self.inner.send(&message)
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        self.inner.status()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.inner.stats()
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        self.inner.tick()
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.inner.mark(label)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+}
@@ -0,0 +1,185 @@
+//! Recording of the message stream to a file and reading it back.
+//!
+//! A recording is newline-delimited JSON, one entry per message, where `t` is
+//! the time in seconds since the recording started:
+//!
+//! ```text
+//! {"data":{"x":1.5,"y":-3.0,"z":0.0},"t":0.02}
+//! ```
+//!
+//! Markers dropped with the `m` hotkey are entries with a `marker` label
+//! instead of `data`, and are skipped on replay.
+
+use crate::resample;
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Lines, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub struct Recorder {
+    file: LineWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        info!("Recording to {}", path.display());
+
+        Ok(Self {
+            file: LineWriter::new(file),
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Sink for Recorder {
+    fn name(&self) -> &str {
+        "Recorder"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let entry = json!({
+            "t": self.start.elapsed().as_secs_f64(),
+            "data": message,
+        });
+        writeln!(self.file, "{}", entry)
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        let entry = json!({
+            "t": self.start.elapsed().as_secs_f64(),
+            "marker": label,
+        });
+        writeln!(self.file, "{}", entry)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()
+    }
+}
+
+/// Iterates over the entries of a recording as (offset, message) pairs.
+pub struct Recording {
+    lines: Lines<BufReader<File>>,
+    line_number: usize,
+}
+
+impl Recording {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(Self {
+            lines: BufReader::new(file).lines(),
+            line_number: 0,
+        })
+    }
+
+    fn parse_entry(&self, line: &str) -> io::Result<(Duration, Value)> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid recording entry on line {}", self.line_number),
+            )
+        };
+
+        let mut entry: Value = serde_json::from_str(line).map_err(|_| invalid())?;
+        let offset = entry
+            .get("t")
+            .and_then(Value::as_f64)
+            .filter(|t| t.is_finite() && *t >= 0.0)
+            .ok_or_else(invalid)?;
+        let data = entry.get_mut("data").ok_or_else(invalid)?.take();
+
+        Ok((Duration::from_secs_f64(offset), data))
+    }
+}
+
+fn is_marker(line: &str) -> bool {
+    serde_json::from_str::<Value>(line).is_ok_and(|entry| entry.get("marker").is_some())
+}
+
+impl Iterator for Recording {
+    type Item = io::Result<(Duration, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.line_number += 1;
+
+            if line.trim().is_empty() || is_marker(&line) {
+                continue;
+            }
+            return Some(self.parse_entry(&line));
+        }
+    }
+}
+
+/// The orientation of the board `device`, or of the first one recorded,
+/// every `frame_time` seconds from the start of its messages until their
+/// end, interpolated between the recorded messages. For exports to formats
+/// that need a constant frame rate.
+pub fn frames(path: &Path, frame_time: f64, device: Option<&str>) -> io::Result<Vec<Value>> {
+    let mut samples: Vec<(Duration, Value)> = Vec::new();
+    let mut chosen = device.map(str::to_string);
+    let mut others = false;
+    for entry in Recording::open(path)? {
+        let (offset, message) = entry?;
+        if message.get("x").and_then(Value::as_f64).is_none() {
+            continue;
+        }
+        let from = message
+            .get("device")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let chosen = chosen.get_or_insert_with(|| from.to_string());
+        if from != chosen {
+            others = true;
+            continue;
+        }
+        // Entries recorded out of order would run time backwards
+        if samples.last().is_some_and(|(last, _)| offset < *last) {
+            continue;
+        }
+        samples.push((offset, message));
+    }
+    if others && device.is_none() {
+        warn!(
+            "The recording holds several boards, exporting {} (choose another with --device)",
+            chosen.as_deref().unwrap_or_default()
+        );
+    }
+
+    let (Some((first, _)), Some((last, _))) = (samples.first(), samples.last()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no orientation to export", path.display()),
+        ));
+    };
+    let count = ((*last - *first).as_secs_f64() / frame_time).floor() as usize + 1;
+
+    let mut frames = Vec::with_capacity(count);
+    let mut next = 0;
+    for frame in 0..count {
+        let time = *first + Duration::from_secs_f64(frame as f64 * frame_time);
+        while next + 1 < samples.len() && samples[next + 1].0 <= time {
+            next += 1;
+        }
+        frames.push(match samples.get(next + 1) {
+            Some((to_time, to)) => {
+                let (from_time, from) = &samples[next];
+                let progress =
+                    (time - *from_time).as_secs_f64() / (*to_time - *from_time).as_secs_f64();
+                resample::interpolate(from, to, progress)
+            }
+            None => samples[next].1.clone(),
+        });
+    }
+    Ok(frames)
+}
@@ -0,0 +1,109 @@
+//! Remapping and sign flipping of the axes, for consumers whose coordinate
+//! frame doesn't match the board's.
+
+use crate::info;
+use crate::sink::Sink;
+use crate::transform::{self, Transform};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Names the board's axes can be referred to by besides their field names.
+const AXIS_ALIASES: [(&str, &str); 3] = [("pitch", "x"), ("roll", "y"), ("yaw", "z")];
+
+/// `--map x=roll,y=-pitch` for all sinks, or `--map NAME:x=roll,...` for a
+/// single one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AxisMap {
+    sink: Option<String>,
+    axes: Vec<Axis>,
+}
+
+/// One output field, taken from a (possibly negated) input field.
+#[derive(Clone, Debug, PartialEq)]
+struct Axis {
+    target: String,
+    source: String,
+    negate: bool,
+}
+
+impl FromStr for AxisMap {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, axes) = match spec.split_once(':') {
+            Some((sink, axes)) => (Some(sink.to_string()), axes),
+            None => (None, spec),
+        };
+
+        let axes = axes
+            .split(',')
+            .map(|axis| {
+                let (target, source) = axis
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid axis '{}', expected FIELD=[-]SOURCE", axis))?;
+                let (negate, source) = match source.trim().strip_prefix('-') {
+                    Some(source) => (true, source),
+                    None => (false, source.trim()),
+                };
+                let source = AXIS_ALIASES
+                    .iter()
+                    .find(|(alias, _)| alias.eq_ignore_ascii_case(source))
+                    .map_or(source, |(_, field)| field);
+                if target.trim().is_empty() || source.is_empty() {
+                    return Err(format!("invalid axis '{}', expected FIELD=[-]SOURCE", axis));
+                }
+
+                Ok(Axis {
+                    target: target.trim().to_string(),
+                    source: source.to_string(),
+                    negate,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self { sink, axes })
+    }
+}
+
+impl Transform for AxisMap {
+    fn transform(&mut self, mut message: Value) -> Vec<Value> {
+        let Value::Object(fields) = &mut message else {
+            return vec![message];
+        };
+
+        // Read every source first, so swapping two axes works
+        let values: Vec<_> = self
+            .axes
+            .iter()
+            .map(|axis| {
+                let value = fields.get(&axis.source)?.as_f64()?;
+                Some(if axis.negate { -value } else { value })
+            })
+            .collect();
+        for (axis, value) in self.axes.iter().zip(values) {
+            if let Some(value) = value {
+                fields.insert(axis.target.clone(), value.into());
+            }
+        }
+        vec![message]
+    }
+}
+
+/// Wraps `sink` so it receives remapped axes if one of `maps` applies to
+/// it. A map given for the sink's name takes precedence over a global one.
+pub fn apply(sink: Box<dyn Sink>, maps: &[AxisMap]) -> Box<dyn Sink> {
+    let named = maps.iter().find(|map| {
+        map.sink
+            .as_deref()
+            .is_some_and(|name| name.eq_ignore_ascii_case(sink.name()))
+    });
+    let global = maps.iter().find(|map| map.sink.is_none());
+
+    match named.or(global) {
+        Some(map) => {
+            info!("Remapping axes for {}", sink.name());
+            transform::wrap(sink, map.clone())
+        }
+        None => sink,
+    }
+}
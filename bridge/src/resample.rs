@@ -0,0 +1,299 @@
+//! Resampling onto a fixed clock, for consumers that render at their own
+//! frame rate and would rather get a fresh orientation every frame than the
+//! board's samples with the jitter of the serial line.
+//!
+//! The output runs slightly behind the board, by about two sample
+//! intervals, so every output time lies between two samples to interpolate
+//! between. Angles are interpolated along the shorter way around and
+//! quaternions (`qw`, `qx`, `qy`, `qz`) with slerp. Events and gestures are
+//! passed on as they come.
+
+use crate::info;
+use crate::rate::RateSpec;
+use crate::sink::{Sink, SinkStats};
+use crate::subscribe::MessageKind;
+use crate::units;
+use crate::worker::{Job, Shared, Worker};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Fields holding angles in degrees, which wrap around at ±180°.
+const ANGLE_FIELDS: [&str; 3] = ["x", "y", "z"];
+const QUATERNION_FIELDS: [&str; 4] = ["qw", "qx", "qy", "qz"];
+/// Sample intervals the output runs behind the board.
+const DELAY_INTERVALS: f64 = 2.0;
+/// Interval assumed for a board until its samples show the real one.
+const INITIAL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long the last sample is repeated after a board went silent.
+const STALE: Duration = Duration::from_millis(500);
+
+/// Moves `sink` onto a thread that sends it interpolated messages at the
+/// rate one of `specs` sets for it.
+pub fn apply(sink: Box<dyn Sink>, specs: &[RateSpec]) -> Box<dyn Sink> {
+    match RateSpec::for_sink(specs, sink.name()) {
+        Some(hz) => {
+            info!("Resampling {} to {} Hz", sink.name(), hz);
+            Box::new(Resampled::new(sink, Duration::from_secs_f64(1.0 / hz)))
+        }
+        None => sink,
+    }
+}
+
+/// The samples of one board.
+struct Board {
+    /// Arrival times and messages, oldest first.
+    samples: VecDeque<(Instant, Value)>,
+    /// Smoothed time between samples.
+    interval: Duration,
+}
+
+impl Board {
+    fn push(&mut self, message: Value) {
+        let now = Instant::now();
+        if let Some((last, _)) = self.samples.back() {
+            let gap = now.duration_since(*last).min(STALE);
+            self.interval = self.interval.mul_f64(0.9) + gap.mul_f64(0.1);
+        }
+        self.samples.push_back((now, message));
+    }
+
+    /// The message for `now`, dropping the samples that are no longer
+    /// needed.
+    fn sample(&mut self, now: Instant) -> Option<Value> {
+        let (newest, _) = self.samples.back()?;
+        if now.duration_since(*newest) > STALE {
+            self.samples.clear();
+            return None;
+        }
+
+        let time = now.checked_sub(self.interval.mul_f64(DELAY_INTERVALS))?;
+        while self.samples.len() > 1 && self.samples[1].0 <= time {
+            self.samples.pop_front();
+        }
+        match (self.samples.front(), self.samples.get(1)) {
+            (Some((_, only)), None) => Some(only.clone()),
+            (Some((from_time, from)), Some((to_time, to))) => {
+                // Until the board sent enough to run behind it, time is
+                // before both samples
+                let span = to_time.duration_since(*from_time).as_secs_f64();
+                let progress = time.saturating_duration_since(*from_time).as_secs_f64() / span;
+                Some(interpolate(from, to, progress.clamp(0.0, 1.0)))
+            }
+            (None, _) => None,
+        }
+    }
+}
+
+/// The message `progress` of the way from `from` to `to`, with the fields
+/// that aren't interpolated taken from `to`.
+pub fn interpolate(from: &Value, to: &Value, progress: f64) -> Value {
+    let (Value::Object(from), Value::Object(to)) = (from, to) else {
+        return to.clone();
+    };
+    let mut message = to.clone();
+
+    for (key, value) in to {
+        // Counters like `seq` stay whole numbers
+        if value.is_i64() || value.is_u64() || QUATERNION_FIELDS.contains(&key.as_str()) {
+            continue;
+        }
+        let (Some(a), Some(b)) = (from.get(key).and_then(Value::as_f64), value.as_f64()) else {
+            continue;
+        };
+        let value = if ANGLE_FIELDS.contains(&key.as_str()) {
+            units::wrap_degrees(a + units::wrap_degrees(b - a) * progress)
+        } else {
+            a + (b - a) * progress
+        };
+        message.insert(key.clone(), value.into());
+    }
+
+    if let (Some(a), Some(b)) = (quaternion(from), quaternion(to)) {
+        for (key, value) in QUATERNION_FIELDS.iter().zip(slerp(a, b, progress)) {
+            message.insert(key.to_string(), value.into());
+        }
+    }
+    Value::Object(message)
+}
+
+fn quaternion(fields: &Map<String, Value>) -> Option<[f64; 4]> {
+    let mut quaternion = [0.0; 4];
+    for (value, key) in quaternion.iter_mut().zip(QUATERNION_FIELDS) {
+        *value = fields.get(key)?.as_f64()?;
+    }
+    Some(quaternion)
+}
+
+/// Spherical linear interpolation between two unit quaternions.
+fn slerp(a: [f64; 4], mut b: [f64; 4], progress: f64) -> [f64; 4] {
+    let mut dot: f64 = a.iter().zip(&b).map(|(a, b)| a * b).sum();
+    // q and -q are the same rotation, take the shorter way
+    if dot < 0.0 {
+        b = b.map(|value| -value);
+        dot = -dot;
+    }
+    let (weight_a, weight_b) = if dot > 0.9995 {
+        // Too close for the angle to be accurate, and lerp is as good
+        (1.0 - progress, progress)
+    } else {
+        let angle = dot.acos();
+        (
+            ((1.0 - progress) * angle).sin() / angle.sin(),
+            (progress * angle).sin() / angle.sin(),
+        )
+    };
+    let mut result = [0.0; 4];
+    for (i, value) in result.iter_mut().enumerate() {
+        *value = weight_a * a[i] + weight_b * b[i];
+    }
+    let norm = result.iter().map(|value| value * value).sum::<f64>().sqrt();
+    result.map(|value| value / norm)
+}
+
+/// Messages and calls waiting for the next output, past which the
+/// forwarding loop waits for the clock instead of piling up more.
+const MAX_JOBS: usize = 1024;
+
+/// Per board, by its `device` field.
+type Boards = Arc<Mutex<HashMap<String, Board>>>;
+
+fn lock(boards: &Mutex<HashMap<String, Board>>) -> MutexGuard<'_, HashMap<String, Board>> {
+    // A panicking sink never holds the lock, so the boards are whole
+    boards.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Resampled {
+    name: String,
+    boards: Boards,
+    clock: Worker,
+}
+
+impl Resampled {
+    fn new(sink: Box<dyn Sink>, interval: Duration) -> Self {
+        let name = sink.name().to_string();
+        let boards = Boards::default();
+        let clock = {
+            let boards = Arc::clone(&boards);
+            Worker::spawn(sink, move |sink, shared| {
+                run(sink, shared, &boards, interval)
+            })
+        };
+        Self {
+            name,
+            boards,
+            clock,
+        }
+    }
+
+    fn push(&self, job: Job) -> io::Result<()> {
+        let shared = self.clock.shared();
+        let mut state = shared.lock();
+        state.check()?;
+        while state.jobs.len() >= MAX_JOBS {
+            state = shared.wait(state);
+            state.check()?;
+        }
+        state.jobs.push_back(job);
+        Ok(())
+    }
+}
+
+/// Sends the sink an interpolated message per board every `interval`,
+/// until the resampler is closed, then hands the sink back for closing.
+fn run(
+    mut sink: Box<dyn Sink>,
+    shared: &Shared,
+    boards: &Mutex<HashMap<String, Board>>,
+    interval: Duration,
+) -> Box<dyn Sink> {
+    let mut next = Instant::now();
+    loop {
+        let (jobs, closing) = shared.take_jobs();
+        let messages: Vec<Value> = {
+            let now = Instant::now();
+            lock(boards)
+                .values_mut()
+                .filter_map(|board| board.sample(now))
+                .collect()
+        };
+
+        let mut result = Ok(());
+        for job in jobs {
+            result = result.and_then(|_| job.run(sink.as_mut()));
+        }
+        for message in &messages {
+            result = result.and_then(|_| sink.send(message));
+        }
+        shared.done(sink.as_ref(), result);
+        if closing {
+            return sink;
+        }
+
+        // Keep to the clock, but don't try to catch up after a stall
+        next = (next + interval).max(Instant::now());
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl Sink for Resampled {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        if MessageKind::of(message) != MessageKind::Orientation {
+            return self.push(Job::Send(message.clone()));
+        }
+
+        self.clock.lock().check()?;
+        let device = message
+            .get("device")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        lock(&self.boards)
+            .entry(device.to_string())
+            .or_insert_with(|| Board {
+                samples: VecDeque::new(),
+                interval: INITIAL_INTERVAL,
+            })
+            .push(message.clone());
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        self.clock.status()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.clock.stats()
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        let mut state = self.clock.lock();
+        state.check()?;
+        if !state.jobs.iter().any(|job| matches!(job, Job::Tick)) {
+            state.jobs.push_back(Job::Tick);
+        }
+        Ok(())
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.push(Job::Mark(label.to_string()))
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.clock.close()
+    }
+}
+
+impl Drop for Resampled {
+    /// Stops the clock of a sink that wasn't closed, e.g. when the bridge
+    /// stops on another sink's error, after it passed on what's pending.
+    fn drop(&mut self) {
+        let _ = self.clock.join();
+    }
+}
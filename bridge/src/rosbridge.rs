@@ -0,0 +1,250 @@
+//! Publishes the orientation to ROS 2 as `sensor_msgs/msg/Imu` through a
+//! rosbridge WebSocket server, e.g. one started with
+//! `ros2 launch rosbridge_server rosbridge_websocket_launch.xml`.
+//!
+//! The covariances come from the configured noise: the orientation's from
+//! the angle noise, the linear acceleration's from the accelerometer noise.
+//! The angular velocity comes from the optional gyroscope fields, and is
+//! marked unknown without them.
+
+use crate::connection::{ReconnectConfig, ServiceConnection};
+use crate::fusion::GYRO_FIELDS;
+use crate::sink::{Sink, SinkStats};
+use crate::websocket;
+use serde_json::{json, Value};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_PORT: u16 = 9090;
+const MESSAGE_TYPE: &str = "sensor_msgs/msg/Imu";
+/// The accelerometer reports milli-g, ROS wants m/s².
+const MILLI_G: f64 = 9.80665 / 1000.0;
+/// A covariance whose first element is -1 tells ROS the value is unknown.
+const UNKNOWN: [f64; 9] = [-1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+
+/// Standard deviations the covariances are filled from.
+#[derive(Clone, Copy, Debug)]
+pub struct Noise {
+    /// Of each angle, in degrees.
+    pub orientation: f64,
+    /// Of each acceleration axis, in m/s².
+    pub acceleration: f64,
+}
+
+pub struct RosbridgeSink {
+    connection: ServiceConnection,
+    topic: String,
+    frame_id: String,
+    noise: Noise,
+    /// State of the xorshift generator for the frame masks.
+    rng: u64,
+}
+
+impl RosbridgeSink {
+    pub fn connect(
+        url: &str,
+        topic: &str,
+        frame_id: &str,
+        noise: Noise,
+        config: ReconnectConfig,
+    ) -> io::Result<Self> {
+        let (host, port, path) = parse_url(url)?;
+        let mut sink = Self {
+            connection: ServiceConnection::connect(&host, port, "rosbridge", config)?,
+            topic: topic.to_string(),
+            frame_id: frame_id.to_string(),
+            noise,
+            rng: seed(),
+        };
+
+        // Every new connection has to upgrade to WebSocket and advertise
+        // the topic before publishing
+        let mut greeting = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}:{}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            path,
+            host,
+            port,
+            websocket::base64_encode(&sink.random_bytes::<16>())
+        )
+        .into_bytes();
+        let advertise = json!({ "op": "advertise", "topic": topic, "type": MESSAGE_TYPE });
+        greeting.extend(sink.frame(websocket::TEXT, &serde_json::to_vec(&advertise)?));
+        let ping = sink.frame(websocket::PING, &[]);
+        sink.connection = sink
+            .connection
+            .with_greeting(greeting)?
+            .with_heartbeat(ping);
+        Ok(sink)
+    }
+
+    fn frame(&mut self, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = self.random_bytes();
+        websocket::frame(opcode, payload, Some(mask))
+    }
+
+    fn random_bytes<const N: usize>(&mut self) -> [u8; N] {
+        [0; N].map(|_| {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            self.rng as u8
+        })
+    }
+
+    fn imu_message(&self, message: &Value) -> Value {
+        let angle = |name| message.get(name).and_then(Value::as_f64).unwrap_or(0.0);
+        let [x, y, z, w] = quaternion(angle("x"), angle("y"), angle("z"));
+
+        let acceleration = ["ax", "ay", "az"].map(|name| message.get(name).and_then(Value::as_f64));
+        let (linear_acceleration, acceleration_covariance) = match acceleration {
+            [Some(ax), Some(ay), Some(az)] => (
+                [ax * MILLI_G, ay * MILLI_G, az * MILLI_G],
+                diagonal(self.noise.acceleration.powi(2)),
+            ),
+            _ => ([0.0; 3], UNKNOWN),
+        };
+
+        let rates = GYRO_FIELDS.map(|name| message.get(name).and_then(Value::as_f64));
+        let (angular_velocity, angular_velocity_covariance) = match rates {
+            // A covariance of zeros means the data is there but its
+            // covariance isn't known
+            [Some(gx), Some(gy), Some(gz)] => ([gx, gy, gz].map(f64::to_radians), [0.0; 9]),
+            _ => ([0.0; 3], UNKNOWN),
+        };
+
+        // Angles in degrees, but the covariance is in rad²
+        let orientation_variance = self.noise.orientation.to_radians().powi(2);
+
+        json!({
+            "header": {
+                "stamp": stamp(message),
+                "frame_id": self.frame_id,
+            },
+            "orientation": { "x": x, "y": y, "z": z, "w": w },
+            "orientation_covariance": diagonal(orientation_variance),
+            "angular_velocity": {
+                "x": angular_velocity[0],
+                "y": angular_velocity[1],
+                "z": angular_velocity[2],
+            },
+            "angular_velocity_covariance": angular_velocity_covariance,
+            "linear_acceleration": {
+                "x": linear_acceleration[0],
+                "y": linear_acceleration[1],
+                "z": linear_acceleration[2],
+            },
+            "linear_acceleration_covariance": acceleration_covariance,
+        })
+    }
+}
+
+impl Sink for RosbridgeSink {
+    fn name(&self) -> &str {
+        "rosbridge"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let publish = json!({
+            "op": "publish",
+            "topic": self.topic,
+            "msg": self.imu_message(message),
+        });
+        let frame = self.frame(websocket::TEXT, &serde_json::to_vec(&publish)?);
+        self.connection.write(&frame)
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        self.connection.keep_alive()
+    }
+
+    fn status(&self) -> String {
+        self.connection.status()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.connection.stats()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let unadvertise = json!({ "op": "unadvertise", "topic": self.topic });
+        let mut data = self.frame(websocket::TEXT, &serde_json::to_vec(&unadvertise)?);
+        data.extend(self.frame(websocket::CLOSE, &[]));
+        self.connection.write(&data)?;
+        self.connection.close()
+    }
+}
+
+/// Accepts `ws://host[:port][/path]` as well as a bare `host[:port]`.
+fn parse_url(url: &str) -> io::Result<(String, u16, String)> {
+    let address = url.strip_prefix("ws://").unwrap_or(url);
+    let (address, path) = match address.find('/') {
+        Some(i) => (&address[..i], &address[i..]),
+        None => (address, "/"),
+    };
+
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid rosbridge port in {}", url),
+                )
+            })?;
+            Ok((host.to_string(), port, path.to_string()))
+        }
+        None => Ok((address.to_string(), DEFAULT_PORT, path.to_string())),
+    }
+}
+
+/// The orientation as a quaternion `[x, y, z, w]`, with the angles in
+/// degrees applied in X, Y, Z order as Blender does.
+fn quaternion(x: f64, y: f64, z: f64) -> [f64; 4] {
+    let (sx, cx) = (x.to_radians() / 2.0).sin_cos();
+    let (sy, cy) = (y.to_radians() / 2.0).sin_cos();
+    let (sz, cz) = (z.to_radians() / 2.0).sin_cos();
+    [
+        sx * cy * cz - cx * sy * sz,
+        cx * sy * cz + sx * cy * sz,
+        cx * cy * sz - sx * sy * cz,
+        cx * cy * cz + sx * sy * sz,
+    ]
+}
+
+fn diagonal(variance: f64) -> [f64; 9] {
+    let mut covariance = [0.0; 9];
+    for i in 0..3 {
+        covariance[i * 4] = variance;
+    }
+    covariance
+}
+
+/// The message's wall-clock timestamp if it has one, or now.
+fn stamp(message: &Value) -> Value {
+    let time = message
+        .get("t_wall")
+        .and_then(Value::as_f64)
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+    json!({
+        "sec": time.trunc() as i64,
+        "nanosec": (time.fract() * 1e9) as u32,
+    })
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    // xorshift gets stuck on zero
+    nanos | 1
+}
@@ -0,0 +1,78 @@
+//! Sanity checks on the values of every message, so a sensor glitch can't
+//! turn a model upside down: angles that aren't finite numbers reject the
+//! message, and angles beyond the limit are clamped to it. The components
+//! of a quaternion are checked the same way, against ±1.
+//!
+//! Unlike `--strict`, messages without angles pass, e.g. events.
+
+use crate::fusion::GYRO_FIELDS;
+use serde_json::Value;
+
+/// The angles the firmware sends, in degrees.
+const ANGLE_FIELDS: [&str; 3] = ["x", "y", "z"];
+/// The quaternion firmware built with `quaternion` sends instead, whose
+/// components never exceed 1.
+const QUATERNION_FIELDS: [&str; 4] = ["qw", "qx", "qy", "qz"];
+/// Default largest magnitude of an angle. The firmware's pitch and roll
+/// stay within ±90° and its heading within ±180°.
+pub const DEFAULT_ANGLE_LIMIT: f64 = 180.0;
+/// Largest angular velocity in degrees per second, the full scale of
+/// common gyroscopes.
+const GYRO_LIMIT: f64 = 2000.0;
+
+pub struct Sanity {
+    angle_limit: f64,
+    rejected: u64,
+    clamped: u64,
+}
+
+impl Sanity {
+    pub fn new(angle_limit: f64) -> Self {
+        Self {
+            angle_limit,
+            rejected: 0,
+            clamped: 0,
+        }
+    }
+
+    /// Messages rejected for a value that isn't a finite number.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Values clamped to their limit.
+    pub fn clamped(&self) -> u64 {
+        self.clamped
+    }
+
+    /// Clamps the message's angles and angular velocities in place and
+    /// returns the fields that saturated, or why the message is rejected.
+    pub fn check(&mut self, message: &mut Value) -> Result<Vec<&'static str>, String> {
+        let Value::Object(fields) = message else {
+            return Ok(Vec::new());
+        };
+
+        let limits = ANGLE_FIELDS
+            .into_iter()
+            .map(|name| (name, self.angle_limit))
+            .chain(QUATERNION_FIELDS.into_iter().map(|name| (name, 1.0)))
+            .chain(GYRO_FIELDS.into_iter().map(|name| (name, GYRO_LIMIT)));
+        let mut saturated = Vec::new();
+        for (name, limit) in limits {
+            let Some(value) = fields.get_mut(name) else {
+                continue;
+            };
+            // Non-finite numbers arrive as null, e.g. from a CBOR frame
+            let Some(number) = value.as_f64().filter(|number| number.is_finite()) else {
+                self.rejected += 1;
+                return Err(format!("field '{}' is not a finite number", name));
+            };
+            if number.abs() > limit {
+                *value = limit.copysign(number).into();
+                saturated.push(name);
+            }
+        }
+        self.clamped += saturated.len() as u64;
+        Ok(saturated)
+    }
+}
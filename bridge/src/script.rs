@@ -0,0 +1,798 @@
+//! A small scripting language for one-off message transforms, loaded with
+//! `--script`. The syntax is a subset of Rhai:
+//!
+//! ```text
+//! // `msg` holds the message, the script's last value is forwarded
+//! let pitch = msg.x;
+//! msg.x = -msg.y;
+//! msg.y = pitch;
+//! if msg.x.abs() > 80 { return; }   // returning nothing drops the message
+//! msg.seq = ();                     // assigning () removes a field
+//! msg
+//! ```
+//!
+//! Supported are `let`, assignment (also `+=`, `-=`, `*=`, `/=`), `if`/`else`,
+//! `return`, numbers, strings, `true`/`false`, `()`, field access with `.`
+//! or `["name"]`, the usual arithmetic, comparison and logic operators and
+//! the functions listed in `call`, which may also be called as methods.
+//!
+//! This is an interpreter of its own rather than the `rhai` crate, which
+//! would pull a sizable dependency into every build for one option. Not
+//! supported from Rhai are loops (`while`, `loop`, `for`), `fn` and
+//! closures, `switch`, `const`, array and object map literals (`[..]`,
+//! `#{..}`), string escapes and interpolation, characters, exponents in
+//! numbers, bitwise operators, modules and any function not listed in
+//! `call`. Unlike Rhai, integers and floats mix freely in arithmetic, and
+//! reading a missing field gives `()` instead of an error.
+
+use crate::transform::Transform;
+use crate::{info, warn};
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub struct Script {
+    program: Vec<Statement>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let source = fs::read_to_string(path)?;
+        let script =
+            Self::parse(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        info!("Transforming messages with {}", path.display());
+        Ok(script)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+
+        let mut program = Vec::new();
+        while !parser.at_end() {
+            program.push(parser.statement()?);
+        }
+        Ok(Self { program })
+    }
+
+    /// Runs the script with `msg` bound to the message and returns the
+    /// script's value.
+    pub fn run(&self, message: Value) -> Result<Value, String> {
+        let mut scope = HashMap::new();
+        scope.insert("msg".to_string(), message);
+
+        match execute_block(&self.program, &mut scope)? {
+            Flow::Value(value) | Flow::Return(value) => Ok(value),
+        }
+    }
+}
+
+/// A single expression over the fields of a message, like
+/// `abs(x) > 60 && y < 10`, for rules that only need to test messages.
+/// The fields are variables of their own, `msg` is the whole message.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expr = parser.expression()?;
+        if !parser.at_end() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Whether the condition holds for `message`, or why it can't be
+    /// decided, like a field the message doesn't have.
+    pub fn holds(&self, message: &Value) -> Result<bool, String> {
+        let mut scope: Scope = match message {
+            Value::Object(fields) => fields.clone().into_iter().collect(),
+            _ => HashMap::new(),
+        };
+        scope.insert("msg".to_string(), message.clone());
+
+        match evaluate(&self.expr, &scope)? {
+            Value::Bool(holds) => Ok(holds),
+            other => Err(format!("condition must be true or false, got {}", other)),
+        }
+    }
+}
+
+impl Transform for Script {
+    fn transform(&mut self, message: Value) -> Vec<Value> {
+        match self.run(message) {
+            Ok(Value::Null) => Vec::new(),
+            Ok(message) => vec![message],
+            Err(e) => {
+                // A failing script must not leak unmapped data to the sinks
+                warn!("Script error, message dropped: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(Number),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Str(text) => write!(f, "\"{}\"", text),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Punct(punct) => write!(f, "{}", punct),
+        }
+    }
+}
+
+/// Punctuation, longest first so `==` isn't read as two `=`.
+const PUNCTUATION: [&str; 28] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "+", "-", "*", "/", "%", "<", ">",
+    "=", "!", "(", ")", "{", "}", "[", "]", ".", ",", ";",
+];
+
+/// Splits the source into tokens, each with its line number for errors.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = source;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\n' {
+            line += 1;
+            rest = &rest[1..];
+        } else if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let literal = rest[..end].replace('_', "");
+            let number = match literal.parse::<i64>() {
+                Ok(int) => Number::from(int),
+                Err(_) => literal
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(Number::from_f64)
+                    .ok_or_else(|| format!("line {}: invalid number '{}'", line, literal))?,
+            };
+            tokens.push((Token::Number(number), line));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push((Token::Ident(rest[..end].to_string()), line));
+            rest = &rest[end..];
+        } else if c == '"' {
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| format!("line {}: unterminated string", line))?;
+            tokens.push((Token::Str(rest[1..end + 1].to_string()), line));
+            rest = &rest[end + 2..];
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(**punct))
+                .ok_or_else(|| format!("line {}: unexpected character '{}'", line, c))?;
+            tokens.push((Token::Punct(punct), line));
+            rest = &rest[punct.len()..];
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Statement {
+    Let(String, Expr),
+    Assign(Expr, Option<&'static str>, Expr),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Value),
+    Variable(String),
+    Field(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators from the loosest to the tightest binding.
+const PRECEDENCE: [&[&str]; 6] = [
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn at_end(&mut self) -> bool {
+        while self.eat(";") {}
+        self.position == self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn error(&self, message: &str) -> String {
+        match self.tokens.get(self.position) {
+            Some((token, line)) => format!("line {}: {} near '{}'", line, message, token),
+            None => format!("{} at end of script", message),
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Ident(name)) if name == keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", punct)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        if self.eat_keyword("let") {
+            let name = self.ident()?;
+            self.expect("=")?;
+            return Ok(Statement::Let(name, self.expression()?));
+        }
+        if self.eat_keyword("if") {
+            return self.if_statement();
+        }
+        if self.eat_keyword("return") {
+            let value = match self.peek() {
+                None | Some(Token::Punct(";")) | Some(Token::Punct("}")) => None,
+                _ => Some(self.expression()?),
+            };
+            return Ok(Statement::Return(value));
+        }
+
+        let target = self.expression()?;
+        for operator in ["=", "+=", "-=", "*=", "/="] {
+            if self.eat(operator) {
+                if !matches!(target, Expr::Variable(_) | Expr::Field(..)) {
+                    return Err(self.error("can only assign to variables and fields"));
+                }
+                let operator = operator.strip_suffix('=').and_then(|op| {
+                    PRECEDENCE[4..]
+                        .iter()
+                        .flat_map(|level| level.iter())
+                        .find(|known| **known == op)
+                        .copied()
+                });
+                return Ok(Statement::Assign(target, operator, self.expression()?));
+            }
+        }
+        Ok(Statement::Expr(target))
+    }
+
+    fn if_statement(&mut self) -> Result<Statement, String> {
+        let condition = self.expression()?;
+        let then = self.block()?;
+        let otherwise = if !self.eat_keyword("else") {
+            Vec::new()
+        } else if self.eat_keyword("if") {
+            vec![self.if_statement()?]
+        } else {
+            self.block()?
+        };
+        Ok(Statement::If(condition, then, otherwise))
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, String> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        loop {
+            while self.eat(";") {}
+            if self.eat("}") {
+                return Ok(statements);
+            }
+            if self.peek().is_none() {
+                return Err(self.error("expected '}'"));
+            }
+            statements.push(self.statement()?);
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        'outer: loop {
+            for operator in PRECEDENCE[level] {
+                if self.eat(operator) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(operator, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for operator in ["-", "!"] {
+            if self.eat(operator) {
+                return Ok(Expr::Unary(operator, Box::new(self.unary()?)));
+            }
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(".") {
+                let name = self.ident()?;
+                if self.eat("(") {
+                    let mut args = vec![expr];
+                    args.extend(self.arguments()?);
+                    expr = Expr::Call(name, args);
+                } else {
+                    let name = Expr::Literal(Value::String(name));
+                    expr = Expr::Field(Box::new(expr), Box::new(name));
+                }
+            } else if self.eat("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                expr = Expr::Field(Box::new(expr), Box::new(index));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parses call arguments after the opening parenthesis.
+    fn arguments(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expression()?);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.error("expected an expression"))?;
+        self.position += 1;
+
+        match token {
+            Token::Number(number) => Ok(Expr::Literal(Value::Number(number))),
+            Token::Str(text) => Ok(Expr::Literal(Value::String(text))),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                _ if self.eat("(") => Ok(Expr::Call(name, self.arguments()?)),
+                _ => Ok(Expr::Variable(name)),
+            },
+            Token::Punct("(") => {
+                if self.eat(")") {
+                    return Ok(Expr::Literal(Value::Null));
+                }
+                let expr = self.expression()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected an expression"))
+            }
+        }
+    }
+}
+
+enum Flow {
+    Value(Value),
+    Return(Value),
+}
+
+type Scope = HashMap<String, Value>;
+
+fn execute_block(statements: &[Statement], scope: &mut Scope) -> Result<Flow, String> {
+    let mut last = Value::Null;
+    for statement in statements {
+        last = match execute(statement, scope)? {
+            Flow::Value(value) => value,
+            flow => return Ok(flow),
+        };
+    }
+    Ok(Flow::Value(last))
+}
+
+fn execute(statement: &Statement, scope: &mut Scope) -> Result<Flow, String> {
+    match statement {
+        Statement::Let(name, value) => {
+            let value = evaluate(value, scope)?;
+            scope.insert(name.clone(), value);
+        }
+        Statement::Assign(target, operator, value) => {
+            let mut value = evaluate(value, scope)?;
+            if let Some(operator) = operator {
+                value = binary(operator, evaluate(target, scope)?, value)?;
+            }
+            assign(target, value, scope)?;
+        }
+        Statement::If(condition, then, otherwise) => {
+            return match evaluate(condition, scope)? {
+                Value::Bool(true) => execute_block(then, scope),
+                Value::Bool(false) => execute_block(otherwise, scope),
+                other => Err(format!("if condition is {} instead of a bool", other)),
+            };
+        }
+        Statement::Return(value) => {
+            let value = match value {
+                Some(value) => evaluate(value, scope)?,
+                None => Value::Null,
+            };
+            return Ok(Flow::Return(value));
+        }
+        Statement::Expr(expr) => return Ok(Flow::Value(evaluate(expr, scope)?)),
+    }
+    Ok(Flow::Value(Value::Null))
+}
+
+fn assign(target: &Expr, value: Value, scope: &mut Scope) -> Result<(), String> {
+    // Resolve the chain of field names down to the variable
+    let mut path = Vec::new();
+    let mut expr = target;
+    while let Expr::Field(object, name) = expr {
+        path.push(field_name(evaluate(name, scope)?)?);
+        expr = object;
+    }
+    let Expr::Variable(variable) = expr else {
+        return Err("can only assign to variables and fields".to_string());
+    };
+
+    let Some((field, parents)) = path.split_first() else {
+        scope.insert(variable.clone(), value);
+        return Ok(());
+    };
+    let mut object = scope
+        .get_mut(variable)
+        .ok_or_else(|| format!("unknown variable '{}'", variable))?;
+    for parent in parents.iter().rev() {
+        object = object
+            .as_object_mut()
+            .ok_or_else(|| format!("cannot set '{}' on a non-map value", parent))?
+            .entry(parent.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    let fields = object
+        .as_object_mut()
+        .ok_or_else(|| format!("cannot set '{}' on a non-map value", field))?;
+    if value.is_null() {
+        fields.remove(field);
+    } else {
+        fields.insert(field.clone(), value);
+    }
+    Ok(())
+}
+
+fn field_name(name: Value) -> Result<String, String> {
+    match name {
+        Value::String(name) => Ok(name),
+        other => Err(format!("field name must be a string, got {}", other)),
+    }
+}
+
+fn evaluate(expr: &Expr, scope: &Scope) -> Result<Value, String> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Variable(name) => scope
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("unknown variable '{}'", name)),
+        Expr::Field(object, name) => {
+            let name = field_name(evaluate(name, scope)?)?;
+            match evaluate(object, scope)? {
+                Value::Object(mut fields) => Ok(fields.remove(&name).unwrap_or(Value::Null)),
+                other => Err(format!("cannot read '{}' of {}", name, other)),
+            }
+        }
+        Expr::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| evaluate(arg, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(name, &args)
+        }
+        Expr::Unary(operator, operand) => match (*operator, evaluate(operand, scope)?) {
+            ("!", Value::Bool(value)) => Ok(Value::Bool(!value)),
+            ("-", value) => binary("-", 0.into(), value),
+            (_, value) => Err(format!("cannot apply '{}' to {}", operator, value)),
+        },
+        Expr::Binary("&&", left, right) => match evaluate(left, scope)? {
+            Value::Bool(false) => Ok(Value::Bool(false)),
+            Value::Bool(true) => evaluate(right, scope),
+            other => Err(format!("'&&' needs bools, got {}", other)),
+        },
+        Expr::Binary("||", left, right) => match evaluate(left, scope)? {
+            Value::Bool(true) => Ok(Value::Bool(true)),
+            Value::Bool(false) => evaluate(right, scope),
+            other => Err(format!("'||' needs bools, got {}", other)),
+        },
+        Expr::Binary(operator, left, right) => {
+            binary(operator, evaluate(left, scope)?, evaluate(right, scope)?)
+        }
+    }
+}
+
+fn binary(operator: &str, left: Value, right: Value) -> Result<Value, String> {
+    match operator {
+        "==" => return Ok(Value::Bool(equal(&left, &right))),
+        "!=" => return Ok(Value::Bool(!equal(&left, &right))),
+        "+" => {
+            if let (Value::String(left), Value::String(right)) = (&left, &right) {
+                return Ok(Value::String(format!("{}{}", left, right)));
+            }
+        }
+        _ => (),
+    }
+
+    // Integers stay integers as far as possible, like the firmware's `seq`
+    if let (Some(a), Some(b)) = (left.as_i64(), right.as_i64()) {
+        let result = match operator {
+            "+" => a.checked_add(b),
+            "-" => a.checked_sub(b),
+            "*" => a.checked_mul(b),
+            "/" if b != 0 && a % b == 0 => Some(a / b),
+            "%" if b != 0 => Some(a % b),
+            _ => None,
+        };
+        if let Some(result) = result {
+            return Ok(result.into());
+        }
+    }
+
+    let (Some(a), Some(b)) = (left.as_f64(), right.as_f64()) else {
+        return Err(format!(
+            "cannot apply '{}' to {} and {}",
+            operator, left, right
+        ));
+    };
+    match operator {
+        "+" => number(a + b),
+        "-" => number(a - b),
+        "*" => number(a * b),
+        "/" => number(a / b),
+        "%" => number(a % b),
+        "<" => Ok(Value::Bool(a < b)),
+        "<=" => Ok(Value::Bool(a <= b)),
+        ">" => Ok(Value::Bool(a > b)),
+        ">=" => Ok(Value::Bool(a >= b)),
+        _ => Err(format!("unknown operator '{}'", operator)),
+    }
+}
+
+/// Numbers compare by value, so `1 == 1.0` holds.
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => left == right,
+    }
+}
+
+fn number(value: f64) -> Result<Value, String> {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| "arithmetic produced a non-finite number".to_string())
+}
+
+fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+    let numbers = args
+        .iter()
+        .map(|arg| {
+            arg.as_f64()
+                .ok_or_else(|| format!("{}() expects numbers, got {}", name, arg))
+        })
+        .collect::<Result<Vec<_>, _>>();
+
+    let result = match (name, args) {
+        ("is_def", [value]) => return Ok(Value::Bool(!value.is_null())),
+        ("to_string", [value]) => {
+            return Ok(match value {
+                Value::String(_) => value.clone(),
+                _ => Value::String(value.to_string()),
+            })
+        }
+        _ => match (name, numbers?.as_slice()) {
+            ("abs", [x]) => x.abs(),
+            ("sqrt", [x]) => x.sqrt(),
+            ("sin", [x]) => x.sin(),
+            ("cos", [x]) => x.cos(),
+            ("tan", [x]) => x.tan(),
+            ("atan", [x]) => x.atan(),
+            ("atan", [y, x]) => y.atan2(*x),
+            ("floor", [x]) => x.floor(),
+            ("ceiling", [x]) => x.ceil(),
+            ("round", [x]) => x.round(),
+            ("to_float", [x]) => *x,
+            ("to_int", [x]) => return Ok((x.trunc() as i64).into()),
+            ("to_degrees", [x]) => x.to_degrees(),
+            ("to_radians", [x]) => x.to_radians(),
+            ("min", [a, b]) => a.min(*b),
+            ("max", [a, b]) => a.max(*b),
+            ("clamp", [x, low, high]) if low <= high => x.clamp(*low, *high),
+            _ => {
+                return Err(format!(
+                    "unknown function {}() with {} argument(s)",
+                    name,
+                    args.len()
+                ))
+            }
+        },
+    };
+    number(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(source: &str, message: Value) -> Result<Value, String> {
+        Script::parse(source)?.run(message)
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition_and_comparison() {
+        assert_eq!(run("1 + 2 * 3", Value::Null), Ok(json!(7)));
+        assert_eq!(run("(1 + 2) * 3", Value::Null), Ok(json!(9)));
+        assert_eq!(
+            run("1 + 2 * 3 == 7 && !false", Value::Null),
+            Ok(json!(true))
+        );
+        assert_eq!(run("-2 * 3 - 1", Value::Null), Ok(json!(-7)));
+    }
+
+    #[test]
+    fn if_else_chains_pick_one_branch() {
+        let source = r#"
+            if msg.x > 10 { msg.band = "high" }
+            else if msg.x > 0 { msg.band = "low" }
+            else { msg.band = "none" }
+            msg.band
+        "#;
+        assert_eq!(run(source, json!({ "x": 20 })), Ok(json!("high")));
+        assert_eq!(run(source, json!({ "x": 5 })), Ok(json!("low")));
+        assert_eq!(run(source, json!({ "x": -5 })), Ok(json!("none")));
+    }
+
+    #[test]
+    fn returning_nothing_drops_the_message() {
+        let mut script = Script::parse("if msg.x.abs() > 80 { return; } msg").unwrap();
+        assert!(script.transform(json!({ "x": -90 })).is_empty());
+        assert_eq!(script.transform(json!({ "x": 10 })), [json!({ "x": 10 })]);
+    }
+
+    #[test]
+    fn fields_are_assigned_created_and_removed() {
+        let source = r#"
+            let pitch = msg.x;
+            msg.x = -msg.y;
+            msg.y = pitch;
+            msg.z += 1.5;
+            msg["extra"] = "yes";
+            msg.nested.level = 2;
+            msg.seq = ();
+            msg
+        "#;
+        let message = json!({ "x": 1, "y": 2, "z": 3, "seq": 7 });
+        assert_eq!(
+            run(source, message),
+            Ok(json!({ "x": -2, "y": 1, "z": 4.5, "extra": "yes", "nested": { "level": 2 } }))
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert!(run("1 / 0", Value::Null).is_err());
+        assert!(run("1.5 / 0", Value::Null).is_err());
+        assert!(run("5 % 0", Value::Null).is_err());
+        // Integers that don't divide evenly become floats
+        assert_eq!(run("7 / 2", Value::Null), Ok(json!(3.5)));
+    }
+
+    #[test]
+    fn undefined_variables_are_errors() {
+        let error = run("missing + 1", Value::Null).unwrap_err();
+        assert!(error.contains("unknown variable 'missing'"), "{}", error);
+        let error = run("nothing.x = 1", Value::Null).unwrap_err();
+        assert!(error.contains("unknown variable 'nothing'"), "{}", error);
+        // Fields the message doesn't have read as ()
+        assert_eq!(run("is_def(msg.gone)", json!({})), Ok(json!(false)));
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let error = Script::parse("let a = 1;\nlet b = (2 + ;").err().unwrap();
+        assert!(error.starts_with("line 2:"), "{}", error);
+        assert!(Script::parse("if true { 1").is_err());
+        assert!(Script::parse("1 + 2 = 3").is_err());
+        assert!(Script::parse("\"open").is_err());
+        assert!(Script::parse("msg.x @ 2").is_err());
+        assert!(Script::parse("while true { }").is_err());
+    }
+
+    #[test]
+    fn conditions_see_the_fields_as_variables() {
+        let condition = Condition::parse("abs(x) > 60 && y < 10").unwrap();
+        assert_eq!(condition.holds(&json!({ "x": -70, "y": 0 })), Ok(true));
+        assert_eq!(condition.holds(&json!({ "x": 10, "y": 0 })), Ok(false));
+        assert!(condition.holds(&json!({ "y": 0 })).is_err());
+        assert!(Condition::parse("x > 1 y").is_err());
+    }
+}
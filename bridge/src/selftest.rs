@@ -0,0 +1,133 @@
+//! The `selftest` command: checks an installation without a Microbit by
+//! sending synthetic frames through the whole bridge, from the CRC check
+//! and parsing over the transforms to a TCP sink, and comparing what
+//! arrives at a listener of its own byte for byte.
+
+use crate::bridge;
+use crate::cli::Args;
+use crate::command::BoardLink;
+use crate::crc;
+use crate::error::BridgeError;
+use crate::handshake::PROTOCOL_VERSION;
+use crate::info;
+use crate::sink;
+use crate::source::MockSource;
+use clap::Parser;
+use serde_json::{json, Value};
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+/// How long the listener waits for the bridge's output.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Orientations the self-test sends, as (x, y, z) in degrees.
+const ORIENTATIONS: [[f64; 3]; 3] = [
+    [10.0, -20.0, 30.0],
+    [45.5, 0.0, -179.9],
+    [-90.0, 12.25, 0.0],
+];
+
+pub fn run() -> Result<(), BridgeError> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let port = listener.local_addr()?.port();
+    let output = thread::spawn(move || -> io::Result<Vec<u8>> {
+        let (mut stream, _) = listener.accept()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data)?;
+        Ok(data)
+    });
+
+    info!("Running the self-test through 127.0.0.1:{}", port);
+    let args = Args::parse_from([
+        "selftest",
+        "--sink",
+        &format!("Selftest=tcp://127.0.0.1:{}", port),
+        "--units",
+        "rad",
+        "--require-crc",
+    ]);
+    let source = MockSource::new("selftest", frames());
+    bridge::run_sources(&args, vec![Box::new(source)], BoardLink::default())?;
+
+    let output = output
+        .join()
+        .map_err(|_| io::Error::other("the self-test listener failed"))??;
+    match verify(&output) {
+        Ok(()) => {
+            info!(
+                "Self-test passed: {} message(s) came through as expected",
+                ORIENTATIONS.len()
+            );
+            Ok(())
+        }
+        Err(reason) => Err(io::Error::other(format!("self-test failed: {}", reason)).into()),
+    }
+}
+
+/// The frames of a board sending `ORIENTATIONS` with checksums, mixed
+/// with frames the bridge has to drop.
+fn frames() -> Vec<String> {
+    let mut frames = Vec::new();
+    for (seq, [x, y, z]) in ORIENTATIONS.into_iter().enumerate() {
+        let payload = json!({ "x": x, "y": y, "z": z, "seq": seq }).to_string();
+        frames.push(format!(
+            "{}*{:04X}",
+            payload,
+            crc::crc16(payload.as_bytes())
+        ));
+        match seq {
+            // Corrupted on the way
+            0 => frames.push(format!("{}*0000", payload.replace("10.0", "19.0"))),
+            // Without a checksum, which --require-crc rejects
+            1 => frames.push(payload),
+            _ => {}
+        }
+    }
+    frames
+}
+
+/// Checks that the sink got the hello and exactly the expected messages,
+/// in radians.
+fn verify(output: &[u8]) -> Result<(), String> {
+    let text = std::str::from_utf8(output).map_err(|_| "the output isn't UTF-8".to_string())?;
+    let mut lines = text.split_inclusive('\n');
+
+    let hello: Value = lines
+        .next()
+        .and_then(|line| serde_json::from_str(line).ok())
+        .ok_or("no hello arrived")?;
+    if hello["hello"]["protocol"] != PROTOCOL_VERSION || hello["hello"]["units"] != "rad" {
+        return Err(format!("unexpected hello {}", hello));
+    }
+
+    let messages: Vec<&str> = lines
+        .filter(|line| !line.contains("\"heartbeat\""))
+        .collect();
+    if messages.len() != ORIENTATIONS.len() {
+        return Err(format!(
+            "expected {} messages, got {}",
+            ORIENTATIONS.len(),
+            messages.len()
+        ));
+    }
+    for (seq, ([x, y, z], line)) in ORIENTATIONS.into_iter().zip(messages).enumerate() {
+        let expected = json!({
+            "x": x.to_radians(),
+            "y": y.to_radians(),
+            "z": z.to_radians(),
+            "seq": seq,
+        });
+        let expected = sink::to_json_line(&expected).map_err(|e| e.to_string())?;
+        if line.as_bytes() != expected {
+            return Err(format!(
+                "expected {:?}, got {:?}",
+                String::from_utf8_lossy(&expected),
+                line
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! Detection of messages lost on the serial link, using the `seq` counter
+//! the firmware adds to every message.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Gaps larger than this are taken as the board having restarted (or the
+/// counter going backwards) rather than as lost messages.
+const MAX_GAP: u32 = u32::MAX / 2;
+
+#[derive(Default)]
+pub struct SequenceTracker {
+    /// Next expected sequence number per device.
+    expected: HashMap<String, u32>,
+    dropped: u64,
+}
+
+impl SequenceTracker {
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Checks a message's `seq` field, returning how many messages were lost
+    /// right before it. Messages without a sequence number are ignored.
+    pub fn check(&mut self, device: &str, message: &Value) -> u32 {
+        let Some(seq) = message.get("seq").and_then(Value::as_u64) else {
+            return 0;
+        };
+        // The firmware's counter is a wrapping u32
+        let seq = seq as u32;
+
+        let lost = match self
+            .expected
+            .insert(device.to_string(), seq.wrapping_add(1))
+        {
+            Some(expected) => match seq.wrapping_sub(expected) {
+                gap if gap > MAX_GAP => 0,
+                gap => gap,
+            },
+            None => 0,
+        };
+        self.dropped += u64::from(lost);
+        lost
+    }
+}
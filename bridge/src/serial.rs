@@ -0,0 +1,370 @@
+//! Finding and (re)opening the Microbit's serial port.
+
+use crate::crc::CrcChecker;
+use crate::framing::Framer;
+use crate::{debug, info, warn};
+use serde_json::Value;
+use serialport::{ClearBuffer, SerialPort, SerialPortType};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// USB vendor/product ID of the micro:bit's DAPLink interface.
+const MICROBIT_VID: u16 = 0x0d28;
+const MICROBIT_PID: u16 = 0x0204;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long DTR or RTS is dropped for when toggled.
+const TOGGLE_TIME: Duration = Duration::from_millis(100);
+
+/// Rates tried by `--detect-baud` after the configured one, most likely
+/// first.
+const COMMON_BAUD_RATES: [u32; 8] = [115200, 9600, 57600, 38400, 19200, 230400, 460800, 921600];
+/// How long each rate is listened to.
+const PROBE_TIME: Duration = Duration::from_millis(600);
+/// Valid messages needed to settle on a rate.
+const PROBE_MESSAGES: usize = 3;
+
+/// Encoding of the messages sent by the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum WireFormat {
+    /// Newline-terminated JSON text
+    Json,
+    /// COBS-framed CBOR, as sent by firmware built with the `cbor` feature
+    Cbor,
+    /// Fixed-layout frames with a CRC16, as sent by firmware built with the
+    /// `binary` feature
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlowControl {
+    None,
+    /// XON/XOFF
+    Software,
+    /// RTS/CTS
+    Hardware,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// What to do with a modem control line once the port is open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Signal {
+    /// Assert it
+    On,
+    /// Deassert it
+    Off,
+    /// Deassert it briefly, then assert it, which makes some adapters
+    /// start transmitting
+    Toggle,
+}
+
+/// How the board's port is opened, from the command line.
+#[derive(Clone, Copy, Debug)]
+pub struct LineSettings {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+    pub flow_control: FlowControl,
+    pub timeout: Duration,
+    pub dtr: Option<Signal>,
+    pub rts: Option<Signal>,
+}
+
+pub fn open_port(path: &str, settings: &LineSettings) -> serialport::Result<Box<dyn SerialPort>> {
+    let data_bits = match settings.data_bits {
+        5 => serialport::DataBits::Five,
+        6 => serialport::DataBits::Six,
+        7 => serialport::DataBits::Seven,
+        _ => serialport::DataBits::Eight,
+    };
+    let parity = match settings.parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Odd => serialport::Parity::Odd,
+        Parity::Even => serialport::Parity::Even,
+    };
+    let stop_bits = match settings.stop_bits {
+        2 => serialport::StopBits::Two,
+        _ => serialport::StopBits::One,
+    };
+    let flow_control = match settings.flow_control {
+        FlowControl::None => serialport::FlowControl::None,
+        FlowControl::Software => serialport::FlowControl::Software,
+        FlowControl::Hardware => serialport::FlowControl::Hardware,
+    };
+    let mut port = serialport::new(path, settings.baud)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .flow_control(flow_control)
+        .timeout(settings.timeout)
+        .open()?;
+
+    if let Some(signal) = settings.dtr {
+        set_signal(signal, |level| port.write_data_terminal_ready(level))
+            .map_err(|e| serialport::Error::new(e.kind, format!("can't set DTR: {}", e)))?;
+    }
+    if let Some(signal) = settings.rts {
+        set_signal(signal, |level| port.write_request_to_send(level))
+            .map_err(|e| serialport::Error::new(e.kind, format!("can't set RTS: {}", e)))?;
+    }
+
+    info!("Connected to Microbit on {}", path);
+    Ok(port)
+}
+
+fn set_signal(
+    signal: Signal,
+    mut write: impl FnMut(bool) -> serialport::Result<()>,
+) -> serialport::Result<()> {
+    match signal {
+        Signal::On => write(true),
+        Signal::Off => write(false),
+        Signal::Toggle => {
+            write(false)?;
+            thread::sleep(TOGGLE_TIME);
+            write(true)
+        }
+    }
+}
+
+/// Switches `port` through the common baud rates, starting with `baud`,
+/// and keeps the first at which the board's messages can be decoded. Stays
+/// at `baud` if none works.
+pub fn detect_baud(port: &mut dyn SerialPort, path: &str, baud: u32, format: WireFormat) -> u32 {
+    let rates =
+        std::iter::once(baud).chain(COMMON_BAUD_RATES.into_iter().filter(|&rate| rate != baud));
+    for rate in rates {
+        debug!("Trying {} baud on {}", rate, path);
+        let settled = port
+            .set_baud_rate(rate)
+            .and_then(|()| port.clear(ClearBuffer::Input))
+            .is_ok()
+            && probe(port, format);
+        if settled {
+            info!("Detected {} baud on {}", rate, path);
+            return rate;
+        }
+    }
+
+    warn!(
+        "No baud rate gave valid messages on {}, staying at {}",
+        path, baud
+    );
+    // A failed switch leaves the port at the last rate tried
+    if let Err(e) = port.set_baud_rate(baud) {
+        warn!("Can't set {} baud on {}: {}", baud, path, e);
+    }
+    baud
+}
+
+/// Whether the board's messages come through, rather than garbage.
+fn probe(port: &mut dyn SerialPort, format: WireFormat) -> bool {
+    let mut framer = Framer::new(format);
+    let mut crc = CrcChecker::new(false);
+    let mut buffer = [0; 1000];
+    let (mut valid, mut invalid) = (0, 0);
+
+    let end = Instant::now() + PROBE_TIME;
+    while Instant::now() < end {
+        let data = match port.read(&mut buffer) {
+            Ok(count) => &buffer[..count],
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(_) => return false,
+        };
+        for message in framer.push(data) {
+            let is_valid = message.is_ok_and(|line| {
+                crc.check(&line)
+                    .ok()
+                    .and_then(|payload| serde_json::from_str::<Value>(payload).ok())
+                    .is_some_and(|message| message.is_object())
+            });
+            if is_valid {
+                valid += 1;
+            } else {
+                invalid += 1;
+            }
+        }
+        // The first message may well have been cut off by the switch
+        if valid >= PROBE_MESSAGES && valid > invalid {
+            return true;
+        }
+    }
+    false
+}
+
+/// Paths of all connected devices with the Microbit's USB VID/PID.
+pub fn microbit_ports() -> Vec<String> {
+    serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|port| match port.port_type {
+            SerialPortType::UsbPort(info)
+                if info.vid == MICROBIT_VID && info.pid == MICROBIT_PID =>
+            {
+                Some(port.port_name)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Prints every serial port with what the system knows about its device,
+/// pointing out the Microbits, for the `list-ports` command.
+pub fn list_ports() -> io::Result<()> {
+    let ports = serialport::available_ports()?;
+    if ports.is_empty() {
+        info!("No serial ports found");
+        return Ok(());
+    }
+
+    let width = ports
+        .iter()
+        .map(|port| port.port_name.len())
+        .max()
+        .unwrap_or(0);
+    for port in &ports {
+        let details = match &port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                let mut details = format!("USB {:04x}:{:04x}", usb.vid, usb.pid);
+                for (label, value) in [
+                    ("manufacturer", &usb.manufacturer),
+                    ("product", &usb.product),
+                    ("serial", &usb.serial_number),
+                ] {
+                    if let Some(value) = value {
+                        details.push_str(&format!(", {} {}", label, value));
+                    }
+                }
+                if usb.vid == MICROBIT_VID && usb.pid == MICROBIT_PID {
+                    details.push_str("  <- Microbit");
+                }
+                details
+            }
+            SerialPortType::PciPort => "PCI".to_string(),
+            SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+            SerialPortType::Unknown => "unknown device".to_string(),
+        };
+        println!("{:width$}  {}", port.port_name, details, width = width);
+    }
+    Ok(())
+}
+
+/// Picks the Microbit's port, asking the user when several are connected.
+pub fn detect_port() -> Option<String> {
+    let mut ports = microbit_ports();
+
+    match ports.len() {
+        0 => None,
+        1 => {
+            let path = ports.remove(0);
+            info!("Detected Microbit on {}", path);
+            Some(path)
+        }
+        _ if !io::stdin().is_terminal() => {
+            let path = ports.remove(0);
+            info!("Found {} Microbits, using {}", ports.len() + 1, path);
+            Some(path)
+        }
+        _ => prompt_for_port(ports).ok(),
+    }
+}
+
+fn prompt_for_port(mut ports: Vec<String>) -> io::Result<String> {
+    println!("Found several Microbits:");
+    for (i, path) in ports.iter().enumerate() {
+        println!("  {}) {}", i + 1, path);
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("Select a port [1-{}]: ", ports.len());
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(choice) if (1..=ports.len()).contains(&choice) => {
+                return Ok(ports.swap_remove(choice - 1));
+            }
+            _ => println!("Please enter a number between 1 and {}", ports.len()),
+        }
+    }
+}
+
+/// Blocks until a Microbit is plugged in, giving the paths of all that are
+/// connected by then.
+pub fn wait_for_microbits() -> Vec<String> {
+    info!("Waiting for a Microbit to be connected...");
+
+    loop {
+        let ports = microbit_ports();
+        if !ports.is_empty() {
+            return ports;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Blocks until the port at `path` appears and can be opened.
+pub fn wait_for_port(path: &str, settings: &LineSettings) -> Box<dyn SerialPort> {
+    info!("Waiting for {} to be connected...", path);
+    poll_until_open(path, settings, false)
+}
+
+/// Blocks until the Microbit shows up again at the originally used path or,
+/// with `any_microbit`, as any device with the Microbit's USB VID/PID.
+pub fn wait_for_reconnect(
+    path: &str,
+    settings: &LineSettings,
+    any_microbit: bool,
+) -> Box<dyn SerialPort> {
+    info!("Waiting for Microbit to be reconnected...");
+    poll_until_open(path, settings, any_microbit)
+}
+
+/// Whether the port `path` is there: listed by the system, like `COM3` on
+/// Windows, which has no device file, or a device file, which also covers
+/// links like `/dev/serial/by-id/...` that aren't listed.
+fn port_present(path: &str) -> bool {
+    let name = path.trim_start_matches(r"\\.\");
+    Path::new(path).exists()
+        || serialport::available_ports().is_ok_and(|ports| {
+            ports.iter().any(|port| {
+                port.port_name
+                    .trim_start_matches(r"\\.\")
+                    .eq_ignore_ascii_case(name)
+            })
+        })
+}
+
+fn poll_until_open(path: &str, settings: &LineSettings, any_microbit: bool) -> Box<dyn SerialPort> {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let candidate = if port_present(path) {
+            Some(path.to_string())
+        } else if any_microbit {
+            microbit_ports().into_iter().next()
+        } else {
+            None
+        };
+
+        if let Some(path) = candidate {
+            match open_port(&path, settings) {
+                Ok(port) => return port,
+                Err(e) => debug!("Found {} but could not open it yet ({})", path, e),
+            }
+        }
+    }
+}
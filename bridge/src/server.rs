@@ -0,0 +1,194 @@
+//! Server mode: consumers connect to the bridge instead of the bridge
+//! connecting to them, so they can be started in any order.
+//!
+//! With a token, a client only gets data after presenting it in its first
+//! line as `{"auth":"TOKEN"}`; the bridge answers `{"auth":"ok"}`, or
+//! `{"auth":"failed"}` and closes the connection.
+
+use crate::encoding::Encoding;
+use crate::handshake;
+use crate::mdns::Advertiser;
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long a client has to present the token.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Broadcasts newline-delimited JSON to every connected client.
+pub struct TcpServer {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    advertiser: Option<Advertiser>,
+    encoding: Encoding,
+}
+
+impl TcpServer {
+    pub fn bind(host: &str, port: u16, token: Option<String>) -> io::Result<Self> {
+        let listener = TcpListener::bind((host, port))?;
+        info!("Listening for clients on {}:{}", host, port);
+        Ok(Self::serve(listener, token))
+    }
+
+    /// Accepts clients on a socket that is already listening, e.g. one
+    /// passed by systemd.
+    pub fn serve(listener: TcpListener, token: Option<String>) -> Self {
+        if token.is_some() {
+            info!("Clients have to present the auth token");
+        }
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = Arc::clone(&clients);
+        thread::spawn(move || accept_clients(listener, accepted, token));
+
+        Self {
+            clients,
+            advertiser: None,
+            encoding: Encoding::Json,
+        }
+    }
+
+    /// Keeps the server advertised on the network until it's closed.
+    pub fn with_advertiser(mut self, advertiser: Advertiser) -> Self {
+        self.advertiser = Some(advertiser);
+        self
+    }
+
+    /// Encodes the messages as `encoding` instead of JSON lines.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    fn broadcast(&self, data: &[u8]) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|client| match client.write_all(data) {
+            Ok(()) => true,
+            Err(e) => {
+                info!(sink: "Server", "Client disconnected ({})", e);
+                false
+            }
+        });
+    }
+}
+
+impl Sink for TcpServer {
+    fn name(&self) -> &str {
+        "Server"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.broadcast(&self.encoding.frame(message)?);
+        Ok(())
+    }
+
+    fn send_batch(&mut self, messages: &[Value]) -> io::Result<()> {
+        let mut data = Vec::new();
+        for message in messages {
+            data.extend(self.encoding.frame(message)?);
+        }
+        self.broadcast(&data);
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        match self.clients.lock().unwrap().len() {
+            1 => "1 client".to_string(),
+            clients => format!("{} clients", clients),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(advertiser) = &mut self.advertiser {
+            advertiser.stop();
+        }
+        for client in self.clients.lock().unwrap().drain(..) {
+            // Clients that already went away don't need telling
+            let _ = client.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    }
+}
+
+fn accept_clients(
+    listener: TcpListener,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    token: Option<String>,
+) {
+    let token = token.map(Arc::new);
+    for stream in listener.incoming() {
+        let stream = match stream.and_then(|stream| {
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        }) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(sink: "Server", "Accepting client failed: {}", e);
+                continue;
+            }
+        };
+
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let Some(token) = &token else {
+            info!(sink: "Server", "Client connected from {}", peer);
+            clients.lock().unwrap().push(stream);
+            continue;
+        };
+
+        // Waiting for the token must not hold up other clients
+        let (clients, token) = (Arc::clone(&clients), Arc::clone(token));
+        thread::spawn(move || {
+            let mut stream = stream;
+            match authenticate(&mut stream, &token) {
+                Ok(()) => {
+                    info!(sink: "Server", "Client connected from {}", peer);
+                    clients.lock().unwrap().push(stream);
+                }
+                Err(e) => {
+                    warn!(sink: "Server", "Rejected client {}: {}", peer, e);
+                    let _ = stream.write_all(b"{\"auth\":\"failed\"}\n");
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+            }
+        });
+    }
+}
+
+/// Expects `{"auth":"TOKEN"}` as the client's first line.
+fn authenticate(stream: &mut TcpStream, token: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let line = handshake::read_line(stream);
+    stream.set_read_timeout(None)?;
+
+    let presented = serde_json::from_str::<Value>(&line?)
+        .ok()
+        .and_then(|message| Some(message.get("auth")?.as_str()?.to_string()))
+        .unwrap_or_default();
+    if !same_token(presented.as_bytes(), token.as_bytes()) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "wrong or missing auth token",
+        ));
+    }
+    stream.write_all(b"{\"auth\":\"ok\"}\n")
+}
+
+/// Compares without stopping at the first difference, so the time taken
+/// doesn't tell how much of a guess was right.
+fn same_token(presented: &[u8], token: &[u8]) -> bool {
+    presented.len() == token.len()
+        && presented
+            .iter()
+            .zip(token)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
@@ -0,0 +1,337 @@
+//! Running as a Windows service, e.g. for a kiosk where a Microbit drives
+//! a projection without anyone logging in: `service install` registers the
+//! bridge with the options to run it with, the service manager starts it
+//! through `service run`, and its log goes to the Application event log.
+//!
+//! Installing and removing goes through `sc.exe` and `reg.exe`, which need
+//! an administrator's console.
+
+use crate::cli::ServiceAction;
+use std::io;
+
+pub fn run(action: &ServiceAction) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        // The bridge's options were checked along with the command, and
+        // the service gets them as they were typed
+        let options: Vec<String> = std::env::args()
+            .skip_while(|arg| arg != "service")
+            .skip(2)
+            .collect();
+        match action {
+            ServiceAction::Install => windows::install(&options),
+            ServiceAction::Uninstall => windows::uninstall(),
+            ServiceAction::Run => windows::run(options),
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = action;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "services are only available on Windows, use --daemon or a systemd unit instead",
+        ))
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use crate::bridge;
+    use crate::cli::Args;
+    use crate::error::BridgeError;
+    use crate::log::{self, Level};
+    use crate::{info, shutdown};
+    use clap::Parser;
+    use std::env;
+    use std::ffi::c_void;
+    use std::io;
+    use std::process::Command;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+    use std::sync::OnceLock;
+
+    /// Name of the service and of its event log source.
+    const SERVICE_NAME: &str = "microblender";
+    const DISPLAY_NAME: &str = "Microblender bridge";
+    const DESCRIPTION: &str = "Forwards Microbit orientation data to Blender and other consumers";
+    /// Registry key of the event log source.
+    const EVENT_SOURCE_KEY: &str =
+        r"HKLM\SYSTEM\CurrentControlSet\Services\EventLog\Application\microblender";
+    /// Has a message for every event ID that just shows the text, so the
+    /// events need no message file of their own.
+    const EVENT_MESSAGE_FILE: &str = r"%SystemRoot%\System32\EventCreate.exe";
+
+    const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+    const SERVICE_STOPPED: u32 = 1;
+    const SERVICE_START_PENDING: u32 = 2;
+    const SERVICE_STOP_PENDING: u32 = 3;
+    const SERVICE_RUNNING: u32 = 4;
+    const SERVICE_ACCEPT_STOP: u32 = 0x1;
+    const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+    const SERVICE_CONTROL_STOP: u32 = 1;
+    const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+    const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+    const NO_ERROR: u32 = 0;
+    const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+    const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+    const ERROR_FAILED_SERVICE_CONTROLLER_CONNECT: i32 = 1063;
+    const EVENTLOG_ERROR_TYPE: u16 = 0x1;
+    const EVENTLOG_WARNING_TYPE: u16 = 0x2;
+    const EVENTLOG_INFORMATION_TYPE: u16 = 0x4;
+
+    type Handle = *mut c_void;
+    type ServiceMain = unsafe extern "system" fn(u32, *mut *mut u16);
+    type HandlerEx = unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+    #[repr(C)]
+    struct ServiceTableEntry {
+        name: *mut u16,
+        main: Option<ServiceMain>,
+    }
+
+    #[repr(C)]
+    struct ServiceStatus {
+        service_type: u32,
+        current_state: u32,
+        controls_accepted: u32,
+        win32_exit_code: u32,
+        service_specific_exit_code: u32,
+        check_point: u32,
+        wait_hint: u32,
+    }
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn StartServiceCtrlDispatcherW(table: *const ServiceTableEntry) -> i32;
+        fn RegisterServiceCtrlHandlerExW(
+            name: *const u16,
+            handler: HandlerEx,
+            context: *mut c_void,
+        ) -> Handle;
+        fn SetServiceStatus(handle: Handle, status: *const ServiceStatus) -> i32;
+        fn RegisterEventSourceW(server: *const u16, source: *const u16) -> Handle;
+        fn ReportEventW(
+            log: Handle,
+            kind: u16,
+            category: u16,
+            event_id: u32,
+            user: *mut c_void,
+            strings: u16,
+            data_size: u32,
+            string_list: *const *const u16,
+            data: *mut c_void,
+        ) -> i32;
+    }
+
+    /// The bridge's options, from `service run`.
+    static OPTIONS: OnceLock<Vec<String>> = OnceLock::new();
+    /// The handle the service reports its state through.
+    static STATUS: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+    static EVENT_LOG: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// Runs a system tool, failing with its output if it fails.
+    fn system(program: &str, args: &[&str]) -> io::Result<()> {
+        let output = Command::new(program).args(args).output()?;
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(io::Error::other(format!(
+            "{} {} failed: {}",
+            program,
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&output.stdout).trim()
+        )))
+    }
+
+    pub fn install(options: &[String]) -> io::Result<()> {
+        let exe = env::current_exe()?;
+        let mut command = format!("\"{}\" service run", exe.display());
+        for option in options {
+            if option.contains(' ') {
+                command.push_str(&format!(" \"{}\"", option));
+            } else {
+                command.push_str(&format!(" {}", option));
+            }
+        }
+
+        system(
+            "sc.exe",
+            &[
+                "create",
+                SERVICE_NAME,
+                "binPath=",
+                &command,
+                "start=",
+                "auto",
+                "DisplayName=",
+                DISPLAY_NAME,
+            ],
+        )?;
+        system("sc.exe", &["description", SERVICE_NAME, DESCRIPTION])?;
+        for (value, kind, data) in [
+            ("EventMessageFile", "REG_EXPAND_SZ", EVENT_MESSAGE_FILE),
+            ("TypesSupported", "REG_DWORD", "7"),
+        ] {
+            system(
+                "reg.exe",
+                &[
+                    "add",
+                    EVENT_SOURCE_KEY,
+                    "/v",
+                    value,
+                    "/t",
+                    kind,
+                    "/d",
+                    data,
+                    "/f",
+                ],
+            )?;
+        }
+        info!("Installed the {} service running {}", SERVICE_NAME, command);
+        info!("Start it with: sc start {}", SERVICE_NAME);
+        Ok(())
+    }
+
+    pub fn uninstall() -> io::Result<()> {
+        // Fails if the service isn't running, which is fine
+        let _ = system("sc.exe", &["stop", SERVICE_NAME]);
+        system("sc.exe", &["delete", SERVICE_NAME])?;
+        let _ = system("reg.exe", &["delete", EVENT_SOURCE_KEY, "/f"]);
+        info!("Removed the {} service", SERVICE_NAME);
+        Ok(())
+    }
+
+    pub fn run(options: Vec<String>) -> io::Result<()> {
+        let _ = OPTIONS.set(options);
+        let mut name = wide(SERVICE_NAME);
+        let table = [
+            ServiceTableEntry {
+                name: name.as_mut_ptr(),
+                main: Some(service_main),
+            },
+            ServiceTableEntry {
+                name: ptr::null_mut(),
+                main: None,
+            },
+        ];
+        // SAFETY: the table ends with a null entry and outlives the call,
+        // which only returns once the service has stopped
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT) {
+                return Err(io::Error::other(
+                    "`service run` is started by the service manager, install the service with `service install`",
+                ));
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+        let name = wide(SERVICE_NAME);
+        // SAFETY: the name is a terminated UTF-16 string and the handler
+        // lives as long as the process
+        let status = unsafe {
+            RegisterServiceCtrlHandlerExW(name.as_ptr(), handle_control, ptr::null_mut())
+        };
+        if status.is_null() {
+            return;
+        }
+        STATUS.store(status, Ordering::SeqCst);
+        // SAFETY: the source name is a terminated UTF-16 string
+        let event_log = unsafe { RegisterEventSourceW(ptr::null(), name.as_ptr()) };
+        if !event_log.is_null() {
+            EVENT_LOG.store(event_log, Ordering::SeqCst);
+            log::redirect(report_event);
+        }
+
+        set_state(SERVICE_START_PENDING, NO_ERROR);
+        let mut args = vec!["microbit-simulator".to_string()];
+        args.extend(OPTIONS.get().cloned().unwrap_or_default());
+        let result = match Args::try_parse_from(args) {
+            Ok(args) => {
+                set_state(SERVICE_RUNNING, NO_ERROR);
+                bridge::run(args)
+            }
+            Err(e) => Err(BridgeError::Config(e.to_string())),
+        };
+        match result {
+            Ok(()) => set_state(SERVICE_STOPPED, NO_ERROR),
+            Err(e) => {
+                report_event(Level::Error, &format!("Error: {}", e));
+                set_state(SERVICE_STOPPED, ERROR_SERVICE_SPECIFIC_ERROR);
+            }
+        }
+    }
+
+    extern "system" fn handle_control(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_state(SERVICE_STOP_PENDING, NO_ERROR);
+                // The forwarding loop stops as if Ctrl+C had been pressed
+                shutdown::request();
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    fn set_state(state: u32, exit_code: u32) {
+        let controls_accepted = match state {
+            SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+            _ => 0,
+        };
+        let status = ServiceStatus {
+            service_type: SERVICE_WIN32_OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            win32_exit_code: exit_code,
+            service_specific_exit_code: u32::from(exit_code != NO_ERROR),
+            check_point: 0,
+            wait_hint: match state {
+                SERVICE_START_PENDING | SERVICE_STOP_PENDING => 10_000,
+                _ => 0,
+            },
+        };
+        // SAFETY: the handle came from RegisterServiceCtrlHandlerExW and
+        // the status is a complete SERVICE_STATUS
+        unsafe { SetServiceStatus(STATUS.load(Ordering::SeqCst), &status) };
+    }
+
+    /// Writes a log event to the Application event log.
+    fn report_event(level: Level, line: &str) {
+        let kind = match level {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let text = wide(line);
+        let strings = [text.as_ptr()];
+        // SAFETY: the handle came from RegisterEventSourceW and the one
+        // string is terminated UTF-16
+        unsafe {
+            ReportEventW(
+                EVENT_LOG.load(Ordering::SeqCst),
+                kind,
+                0,
+                1,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null_mut(),
+            )
+        };
+    }
+}
@@ -0,0 +1,35 @@
+//! Stopping cleanly on Ctrl+C or SIGTERM, so sinks are flushed and
+//! recordings end on a complete line.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    // A second Ctrl+C gets out even if shutting down hangs
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: _exit is async-signal-safe
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Catches SIGINT and SIGTERM from now on instead of dying on them.
+pub fn install() {
+    let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only touches an atomic and calls _exit
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Stops the bridge as if the user had asked to, e.g. once `--duration`
+/// is over.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether the user asked the bridge to stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,160 @@
+//! Synthetic orientation data standing in for a Microbit, so the path to
+//! Blender can be developed and demoed without hardware.
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::f64::consts::TAU;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time between messages, the firmware's 50 Hz output rate.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Largest step of the random walk per message, in degrees.
+const WALK_STEP: f64 = 1.5;
+/// How strongly the random walk is pulled back towards level per message.
+const WALK_RETURN: f64 = 0.01;
+
+/// Poses of the gesture script as (seconds to get there, [pitch, roll,
+/// yaw]), played in a loop.
+const GESTURES: [(f64, [f64; 3]); 18] = [
+    (1.0, [0.0, 0.0, 0.0]),
+    // Tilt forward and back
+    (1.0, [60.0, 0.0, 0.0]),
+    (0.5, [60.0, 0.0, 0.0]),
+    (1.0, [0.0, 0.0, 0.0]),
+    // Tilt left and right
+    (1.0, [0.0, -45.0, 0.0]),
+    (1.0, [0.0, 45.0, 0.0]),
+    (1.0, [0.0, 0.0, 0.0]),
+    // Nod
+    (0.25, [20.0, 0.0, 0.0]),
+    (0.25, [-20.0, 0.0, 0.0]),
+    (0.25, [20.0, 0.0, 0.0]),
+    (0.25, [0.0, 0.0, 0.0]),
+    // Shake
+    (0.2, [0.0, 0.0, 30.0]),
+    (0.2, [0.0, 0.0, -30.0]),
+    (0.2, [0.0, 0.0, 30.0]),
+    (0.2, [0.0, 0.0, 0.0]),
+    // Quarter turn and back
+    (2.0, [0.0, 0.0, 90.0]),
+    (1.0, [0.0, 0.0, 90.0]),
+    (2.0, [0.0, 0.0, 0.0]),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Pattern {
+    /// Pitch and roll swing back and forth while the yaw turns slowly
+    Sweep,
+    /// The orientation drifts randomly
+    Walk,
+    /// Tilts, a nod, a shake and a turn, over and over
+    Gestures,
+}
+
+/// An endless stream of messages in the firmware's format, one per
+/// `SAMPLE_INTERVAL`.
+pub struct Simulator {
+    pattern: Pattern,
+    seq: u32,
+    walk: [f64; 3],
+    rng: u64,
+}
+
+impl Simulator {
+    pub fn new(pattern: Pattern) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Self {
+            pattern,
+            seq: 0,
+            walk: [0.0; 3],
+            // xorshift gets stuck on zero
+            rng: seed | 1,
+        }
+    }
+
+    fn sweep(t: f64) -> [f64; 3] {
+        [
+            45.0 * (TAU * t / 4.0).sin(),
+            30.0 * (TAU * t / 6.0).sin(),
+            wrap_degrees(18.0 * t),
+        ]
+    }
+
+    fn walk(&mut self) -> [f64; 3] {
+        for axis in 0..3 {
+            let step = WALK_STEP * (2.0 * self.random() - 1.0);
+            self.walk[axis] += step - WALK_RETURN * self.walk[axis];
+        }
+        self.walk[0] = self.walk[0].clamp(-90.0, 90.0);
+        self.walk[1] = self.walk[1].clamp(-90.0, 90.0);
+        self.walk[2] = wrap_degrees(self.walk[2]);
+        self.walk
+    }
+
+    fn gestures(t: f64) -> [f64; 3] {
+        let total: f64 = GESTURES.iter().map(|(duration, _)| duration).sum();
+        let mut t = t % total;
+        let mut from = GESTURES[GESTURES.len() - 1].1;
+        for (duration, to) in GESTURES {
+            if t < duration {
+                // Ease in and out of every pose
+                let progress = t / duration;
+                let eased = progress * progress * (3.0 - 2.0 * progress);
+                return [0, 1, 2].map(|axis| from[axis] + (to[axis] - from[axis]) * eased);
+            }
+            t -= duration;
+            from = to;
+        }
+        from
+    }
+
+    /// A uniformly distributed number in [0, 1), from xorshift64*.
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+        bits as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Iterator for Simulator {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        let t = f64::from(self.seq) * SAMPLE_INTERVAL.as_secs_f64();
+        let [x, y, z] = match self.pattern {
+            Pattern::Sweep => Self::sweep(t),
+            Pattern::Walk => self.walk(),
+            Pattern::Gestures => Self::gestures(t),
+        };
+
+        let message = json!({
+            "x": round(x),
+            "y": round(y),
+            "z": round(z),
+            "seq": self.seq,
+        });
+        self.seq = self.seq.wrapping_add(1);
+        Some(message)
+    }
+}
+
+/// Rounds to the firmware's one decimal.
+fn round(angle: f64) -> f64 {
+    (angle * 10.0).round() / 10.0
+}
+
+/// Keeps an angle within (-180, 180].
+fn wrap_degrees(angle: f64) -> f64 {
+    let wrapped = angle.rem_euclid(360.0);
+    if wrapped > 180.0 {
+        wrapped - 360.0
+    } else {
+        wrapped
+    }
+}
@@ -0,0 +1,121 @@
+use serde_json::Value;
+use std::io;
+use std::str::FromStr;
+
+/// A destination for the messages read from the Microbit.
+pub trait Sink: Send {
+    /// Human-readable name used in log output.
+    fn name(&self) -> &str;
+
+    /// Delivers one message. Sinks are expected to deal with transient
+    /// failures (like a disconnected client) themselves; an error returned
+    /// here stops the bridge.
+    fn send(&mut self, message: &Value) -> io::Result<()>;
+
+    /// Delivers several messages, in a single write where the sink can.
+    fn send_batch(&mut self, messages: &[Value]) -> io::Result<()> {
+        messages.iter().try_for_each(|message| self.send(message))
+    }
+
+    /// Short description of the sink's state, shown by the monitor.
+    fn status(&self) -> String {
+        "active".to_string()
+    }
+
+    /// Whether a message sent now would reach the peer. Failover groups
+    /// skip the sinks that say no.
+    fn is_reachable(&self) -> bool {
+        true
+    }
+
+    /// Counters shown by `--stats-interval`.
+    fn stats(&self) -> SinkStats {
+        SinkStats::default()
+    }
+
+    /// Called regularly whether or not messages flow, for sinks that have
+    /// to keep their connection alive.
+    fn tick(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Records a marker the user dropped into the stream. Only sinks that
+    /// keep a record of the stream, like the recorder, care about them.
+    fn mark(&mut self, _label: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Flushes whatever is buffered and ends the session with the peer
+    /// when the bridge shuts down.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink's counters for the periodic statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    /// Messages waiting to be delivered.
+    pub queued: usize,
+    /// How often the connection had to be made again.
+    pub reconnects: u64,
+    /// Size of the sink's queue, 0 without one.
+    pub capacity: usize,
+    /// Messages discarded as the queue was full.
+    pub dropped: u64,
+}
+
+/// Serializes a message as a single newline-terminated JSON line.
+pub fn to_json_line(message: &Value) -> io::Result<Vec<u8>> {
+    let mut data = serde_json::to_vec(message)?;
+    data.push(b'\n');
+    Ok(data)
+}
+
+/// A named TCP destination given as `name=tcp://host:port`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SinkSpec {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl SinkSpec {
+    pub fn new(name: &str, host: &str, port: u16) -> Self {
+        Self {
+            name: name.to_string(),
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, url) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=tcp://HOST:PORT, got '{}'", spec))?;
+        if name.is_empty() {
+            return Err(format!("sink '{}' has no name", spec));
+        }
+
+        let address = url
+            .strip_prefix("tcp://")
+            .ok_or_else(|| format!("unsupported sink URL '{}', expected tcp://HOST:PORT", url))?;
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| format!("sink URL '{}' has no port", url))?;
+        let port = port
+            .trim_end_matches('/')
+            .parse()
+            .map_err(|_| format!("invalid port in sink URL '{}'", url))?;
+
+        Ok(Self::new(name, host, port))
+    }
+}
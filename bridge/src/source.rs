@@ -0,0 +1,258 @@
+//! Where the forwarding loop gets its messages from: a board's serial
+//! port, the simulator, a file or stdin, or lines handed over in memory.
+//!
+//! Every source runs on a thread of its own and yields the messages as
+//! JSON lines, so the loop treats them all the same.
+
+use crate::command::BoardLink;
+use crate::framing::Framer;
+use crate::serial::{self, LineSettings, WireFormat};
+use crate::simulator::{self, Pattern, Simulator};
+use crate::{info, warn};
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+/// Size of the chunks read from ports and files.
+const READ_SIZE: usize = 1000;
+
+/// A stream of messages for the forwarding loop.
+pub trait DataSource: Send {
+    /// Name of the source, and the `device` of its messages when they are
+    /// tagged.
+    fn name(&self) -> &str;
+
+    /// Waits for the next message, as a JSON line. Returns `None` once the
+    /// source has ended.
+    fn next_line(&mut self) -> Option<String>;
+
+    /// Why the source ended, if it failed rather than ran out.
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+/// A Microbit's serial port, reopened whenever the board is unplugged.
+pub struct SerialSource {
+    path: String,
+    settings: LineSettings,
+    port: Box<dyn SerialPort>,
+    any_microbit: bool,
+    link: BoardLink,
+    framer: Framer,
+    pending: VecDeque<String>,
+    buffer: Vec<u8>,
+    /// End instead of waiting for the board to be plugged back in.
+    exit_on_disconnect: bool,
+    failure: Option<String>,
+}
+
+impl SerialSource {
+    /// Reads from `port`, opened at `path`. With `any_microbit`, any
+    /// Microbit plugged in after a disconnect counts as the same board.
+    pub fn new(
+        path: String,
+        settings: LineSettings,
+        format: WireFormat,
+        port: Box<dyn SerialPort>,
+        any_microbit: bool,
+        link: BoardLink,
+    ) -> Self {
+        link.attach(&path, port.as_ref());
+        Self {
+            path,
+            settings,
+            port,
+            any_microbit,
+            link,
+            framer: Framer::new(format),
+            pending: VecDeque::new(),
+            buffer: vec![0; READ_SIZE],
+            exit_on_disconnect: false,
+            failure: None,
+        }
+    }
+
+    /// Ends the source with a failure when the port is lost, instead of
+    /// waiting for the board to come back.
+    pub fn with_exit_on_disconnect(mut self) -> Self {
+        self.exit_on_disconnect = true;
+        self
+    }
+}
+
+impl DataSource for SerialSource {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(line);
+            }
+
+            let received = match self.port.read(&mut self.buffer) {
+                Ok(t) => self.framer.push(&self.buffer[..t]),
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Vec::new(),
+                Err(e) if self.exit_on_disconnect => {
+                    self.failure = Some(e.to_string());
+                    return None;
+                }
+                Err(e) => {
+                    warn!("Serial error on {}: {}", self.path, e);
+                    // Whatever was half-received belongs to the old connection
+                    self.framer.clear();
+                    self.port =
+                        serial::wait_for_reconnect(&self.path, &self.settings, self.any_microbit);
+                    self.link.attach(&self.path, self.port.as_ref());
+                    Vec::new()
+                }
+            };
+            for line in received {
+                match line {
+                    Ok(line) => self.pending.push_back(line),
+                    Err(e) => warn!("Invalid frame on {}: {}", self.path, e),
+                }
+            }
+        }
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.failure.clone()
+    }
+}
+
+/// The board's output read from a file or stdin as fast as it comes, e.g.
+/// a capture of the serial port or another program's output.
+pub struct StreamSource {
+    name: String,
+    reader: Box<dyn Read + Send>,
+    framer: Framer,
+    pending: VecDeque<String>,
+    buffer: Vec<u8>,
+}
+
+impl StreamSource {
+    pub fn new(name: &str, reader: Box<dyn Read + Send>, format: WireFormat) -> Self {
+        Self {
+            name: name.to_string(),
+            reader,
+            framer: Framer::new(format),
+            pending: VecDeque::new(),
+            buffer: vec![0; READ_SIZE],
+        }
+    }
+
+    /// Reads the file at `path`, or stdin for `-`.
+    pub fn open(path: &Path, format: WireFormat) -> io::Result<Self> {
+        if path == Path::new("-") {
+            info!("Reading messages from stdin");
+            return Ok(Self::new("stdin", Box::new(io::stdin()), format));
+        }
+        let file = File::open(path)?;
+        info!("Reading messages from {}", path.display());
+        Ok(Self::new(
+            &path.display().to_string(),
+            Box::new(file),
+            format,
+        ))
+    }
+}
+
+impl DataSource for StreamSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(line);
+            }
+
+            let t = match self.reader.read(&mut self.buffer) {
+                Ok(0) => {
+                    info!("End of {}", self.name);
+                    return None;
+                }
+                Ok(t) => t,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Can't read {}: {}", self.name, e);
+                    return None;
+                }
+            };
+            for line in self.framer.push(&self.buffer[..t]) {
+                match line {
+                    Ok(line) => self.pending.push_back(line),
+                    Err(e) => warn!("Invalid frame in {}: {}", self.name, e),
+                }
+            }
+        }
+    }
+}
+
+/// Synthetic messages in real time, at the firmware's rate.
+pub struct SimulatedSource {
+    simulator: Simulator,
+    /// When the next message is due, from the first one asked for.
+    next: Option<Instant>,
+}
+
+impl SimulatedSource {
+    pub fn new(pattern: Pattern) -> Self {
+        info!("Simulating a Microbit ({:?})", pattern);
+        Self {
+            simulator: Simulator::new(pattern),
+            next: None,
+        }
+    }
+}
+
+impl DataSource for SimulatedSource {
+    fn name(&self) -> &str {
+        "simulator"
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        let next = self.next.get_or_insert_with(Instant::now);
+        *next += simulator::SAMPLE_INTERVAL;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+        Some(self.simulator.next()?.to_string())
+    }
+}
+
+/// Lines handed over in memory and given out without delay, so the
+/// forwarding loop can be driven deterministically.
+pub struct MockSource {
+    name: String,
+    lines: VecDeque<String>,
+}
+
+impl MockSource {
+    pub fn new<I>(name: &str, lines: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            name: name.to_string(),
+            lines: lines.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl DataSource for MockSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        self.lines.pop_front()
+    }
+}
@@ -0,0 +1,75 @@
+//! The `--stats-interval` report of throughput and health, to spot a
+//! long capture session degrading without watching every message.
+
+use crate::sink::SinkStats;
+use std::time::{Duration, Instant};
+
+pub struct Stats {
+    interval: Duration,
+    since: Instant,
+    /// Counted since the last report.
+    messages: u64,
+    bytes: u64,
+    errors: u64,
+}
+
+impl Stats {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            since: Instant::now(),
+            messages: 0,
+            bytes: 0,
+            errors: 0,
+        }
+    }
+
+    /// Counts a line received from a board.
+    pub fn count(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+
+    /// Counts a received line that was dropped as corrupted or invalid.
+    pub fn count_error(&mut self) {
+        self.errors += 1;
+    }
+
+    /// Sums up the time since the last report once the interval is over,
+    /// starting the next one.
+    pub fn report(&mut self, sinks: &[(String, SinkStats)]) -> Option<String> {
+        let elapsed = self.since.elapsed();
+        if elapsed < self.interval {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        let error_rate = match self.messages {
+            0 => 0.0,
+            messages => self.errors as f64 / messages as f64 * 100.0,
+        };
+        let mut report = format!(
+            "Stats: {:.1} msg/s, {:.1} KB/s, {:.1}% errors",
+            self.messages as f64 / seconds,
+            self.bytes as f64 / seconds / 1000.0,
+            error_rate
+        );
+        for (name, stats) in sinks {
+            match stats.capacity {
+                0 => report.push_str(&format!("; {}: {} queued", name, stats.queued)),
+                capacity => {
+                    report.push_str(&format!("; {}: {}/{} queued", name, stats.queued, capacity))
+                }
+            }
+            if stats.dropped > 0 {
+                report.push_str(&format!(", {} dropped", stats.dropped));
+            }
+            if stats.reconnects > 0 {
+                report.push_str(&format!(", {} reconnect(s)", stats.reconnects));
+            }
+        }
+
+        *self = Self::new(self.interval);
+        Some(report)
+    }
+}
@@ -0,0 +1,104 @@
+//! Choosing which kinds of messages a sink receives, e.g.
+//! `--subscribe Visualizer=orientation` keeps gestures, events and
+//! telemetry away from the visualizer while Blender still gets everything.
+
+use crate::info;
+use crate::sink::Sink;
+use crate::transform::{self, Transform};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MessageKind {
+    /// The board's readings, as well as anything derived from them that
+    /// isn't one of the other kinds
+    Orientation,
+    /// `{"event":...}` from --event rules
+    Events,
+    /// `{"gesture":...}` from --gestures
+    Gestures,
+    /// `{"temp":...}` and other readings the board sends now and then
+    Telemetry,
+}
+
+impl MessageKind {
+    pub fn of(message: &Value) -> Self {
+        if message.get("gesture").is_some() {
+            MessageKind::Gestures
+        } else if message.get("event").is_some() {
+            MessageKind::Events
+        } else if message.get("temp").is_some() {
+            MessageKind::Telemetry
+        } else {
+            MessageKind::Orientation
+        }
+    }
+}
+
+/// `--subscribe NAME=KIND[+KIND...]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    sink: String,
+    kinds: Vec<MessageKind>,
+}
+
+impl FromStr for Subscription {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, kinds) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid subscription '{}', expected NAME=KIND[+KIND]", spec))?;
+        if sink.trim().is_empty() {
+            return Err(format!("subscription '{}' has no sink name", spec));
+        }
+        let kinds = kinds
+            .split('+')
+            .map(|kind| {
+                MessageKind::from_str(kind.trim(), true).map_err(|_| {
+                    format!(
+                        "unknown message kind '{}', expected orientation, events, gestures or telemetry",
+                        kind.trim()
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            sink: sink.trim().to_string(),
+            kinds,
+        })
+    }
+}
+
+impl Transform for Subscription {
+    fn transform(&mut self, message: Value) -> Vec<Value> {
+        if self.kinds.contains(&MessageKind::of(&message)) {
+            vec![message]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Wraps `sink` so it only receives the kinds of messages it subscribed
+/// to. Sinks without a subscription receive everything.
+pub fn apply(sink: Box<dyn Sink>, subscriptions: &[Subscription]) -> Box<dyn Sink> {
+    match subscriptions
+        .iter()
+        .find(|subscription| subscription.sink.eq_ignore_ascii_case(sink.name()))
+    {
+        Some(subscription) => {
+            let kinds: Vec<_> = subscription
+                .kinds
+                .iter()
+                .filter_map(|kind| kind.to_possible_value())
+                .map(|kind| kind.get_name().to_string())
+                .collect();
+            info!("{} subscribed to {}", sink.name(), kinds.join(" and "));
+            transform::wrap(sink, subscription.clone())
+        }
+        None => sink,
+    }
+}
@@ -0,0 +1,116 @@
+//! Running as a systemd service: the `--listen` socket can be handed over
+//! by a socket unit, and the service manager learns through `sd_notify`
+//! when the bridge is ready, that it's still alive and when it stops.
+//!
+//! Both are plain environment variables and sockets, so the bridge behaves
+//! as before when it isn't started by systemd.
+
+use crate::{debug, info};
+use std::env;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+/// First file descriptor passed by socket activation.
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes over the listening socket passed by a systemd socket unit, if
+/// the bridge was started by one.
+#[cfg(unix)]
+pub fn listener() -> Option<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid = env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    let count = env::var("LISTEN_FDS").ok()?.parse::<u32>().ok()?;
+    if pid != std::process::id() || count == 0 {
+        return None;
+    }
+    // Processes started by the bridge mustn't take the socket as theirs
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if count > 1 {
+        info!("systemd passed {} sockets, using the first one", count);
+    }
+
+    // SAFETY: systemd passes the descriptors from 3 on, and the
+    // variables were removed so nothing else claims them
+    let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    match listener.local_addr() {
+        Ok(addr) => {
+            info!("Using the socket passed by systemd ({})", addr);
+            Some(listener)
+        }
+        Err(e) => {
+            info!("Ignoring the socket passed by systemd: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn listener() -> Option<TcpListener> {
+    None
+}
+
+/// Tells the service manager about a state change, e.g. `READY=1`. Does
+/// nothing unless systemd is waiting for it.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.to_str().and_then(|path| path.strip_prefix('@')) {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+
+            let addr = SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &addr);
+        }
+        socket.send_to(state.as_bytes(), &path)
+    });
+    if let Err(e) = result {
+        debug!("Can't notify systemd of {}: {}", state, e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Pings the systemd watchdog while the forwarding loop keeps running, so
+/// a hung bridge gets restarted.
+pub struct Watchdog {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Watchdog {
+    /// The watchdog configured with `WatchdogSec=` in the service, if any.
+    pub fn from_env() -> Option<Self> {
+        let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+        if let Ok(pid) = env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok()? != std::process::id() {
+                return None;
+            }
+        }
+        // Pinging at half the timeout leaves room for a slow iteration
+        let interval = Duration::from_micros(usec) / 2;
+        info!("Pinging the systemd watchdog every {:?}", interval);
+        Some(Self {
+            interval,
+            last: Instant::now(),
+        })
+    }
+
+    /// Pings the watchdog when it's due.
+    pub fn tick(&mut self) {
+        if self.last.elapsed() >= self.interval {
+            notify("WATCHDOG=1");
+            self.last = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Zeroing of the orientation on the host, relative to whatever it was
+//! when the `z` hotkey was pressed. Angles have the ones then subtracted,
+//! and a quaternion is turned back by the one then.
+
+use crate::units;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+const QUATERNION_FIELDS: [&str; 4] = ["qw", "qx", "qy", "qz"];
+
+type Quaternion = [f64; 4];
+
+#[derive(Default)]
+pub struct Tare {
+    /// Latest orientation per device, before subtracting the offsets.
+    latest: HashMap<String, [f64; 3]>,
+    offsets: HashMap<String, [f64; 3]>,
+    /// The same for the devices sending quaternions.
+    latest_quaternions: HashMap<String, Quaternion>,
+    zero_quaternions: HashMap<String, Quaternion>,
+}
+
+impl Tare {
+    /// Takes the latest orientation of every device as its new zero.
+    pub fn zero(&mut self) {
+        self.offsets = self.latest.clone();
+        self.zero_quaternions = self.latest_quaternions.clone();
+    }
+
+    pub fn apply(&mut self, message: &mut Value) {
+        let Value::Object(fields) = message else {
+            return;
+        };
+        let device = fields
+            .get("device")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        let latest = self.latest.entry(device.clone()).or_default();
+        let offsets = self.offsets.get(&device);
+        for (i, axis) in AXES.iter().enumerate() {
+            let Some(value) = fields.get(*axis).and_then(Value::as_f64) else {
+                continue;
+            };
+            latest[i] = value;
+            if let Some(offsets) = offsets {
+                let mut value = value - offsets[i];
+                if *axis == "z" {
+                    value = units::wrap_degrees(value);
+                }
+                fields.insert(axis.to_string(), value.into());
+            }
+        }
+
+        let quaternion = QUATERNION_FIELDS.map(|name| fields.get(name).and_then(Value::as_f64));
+        let [Some(w), Some(x), Some(y), Some(z)] = quaternion else {
+            return;
+        };
+        let quaternion = [w, x, y, z];
+        self.latest_quaternions.insert(device.clone(), quaternion);
+        if let Some(zero) = self.zero_quaternions.get(&device) {
+            let tared = multiply(conjugate(*zero), quaternion);
+            for (name, value) in QUATERNION_FIELDS.iter().zip(tared) {
+                fields.insert(name.to_string(), value.into());
+            }
+        }
+    }
+}
+
+fn conjugate([w, x, y, z]: Quaternion) -> Quaternion {
+    [w, -x, -y, -z]
+}
+
+/// The Hamilton product `a * b`, rotating by `b` and then by `a`.
+fn multiply(a: Quaternion, b: Quaternion) -> Quaternion {
+    let [aw, ax, ay, az] = a;
+    let [bw, bx, by, bz] = b;
+    [
+        aw * bw - ax * bx - ay * by - az * bz,
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn quaternion(message: &Value) -> Quaternion {
+        QUATERNION_FIELDS.map(|name| message[name].as_f64().unwrap())
+    }
+
+    fn close(a: Quaternion, b: Quaternion) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn angles_are_taken_relative_to_the_zero() {
+        let mut tare = Tare::default();
+        tare.apply(&mut json!({"x": 10.0, "y": -5.0, "z": 170.0}));
+        tare.zero();
+
+        let mut message = json!({"x": 15.0, "y": -5.0, "z": -170.0});
+        tare.apply(&mut message);
+        assert_eq!(message, json!({"x": 5.0, "y": 0.0, "z": 20.0}));
+    }
+
+    #[test]
+    fn a_quaternion_is_turned_back_by_the_zero() {
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        // 90° about x
+        let zero = json!({"qw": half, "qx": half, "qy": 0.0, "qz": 0.0});
+        let mut tare = Tare::default();
+        tare.apply(&mut zero.clone());
+        tare.zero();
+
+        let mut message = zero.clone();
+        tare.apply(&mut message);
+        assert!(close(quaternion(&message), [1.0, 0.0, 0.0, 0.0]));
+
+        // A further 90° about x makes 90° from the zero
+        let mut message = json!({"qw": 0.0, "qx": 1.0, "qy": 0.0, "qz": 0.0});
+        tare.apply(&mut message);
+        assert!(close(quaternion(&message), quaternion(&zero)));
+    }
+
+    #[test]
+    fn every_device_has_its_own_zero() {
+        let mut tare = Tare::default();
+        tare.apply(&mut json!({"device": "a", "qw": 0.0, "qx": 1.0, "qy": 0.0, "qz": 0.0}));
+        tare.zero();
+
+        let mut message = json!({"device": "b", "qw": 0.0, "qx": 1.0, "qy": 0.0, "qz": 0.0});
+        tare.apply(&mut message);
+        assert!(close(quaternion(&message), [0.0, 1.0, 0.0, 0.0]));
+    }
+}
@@ -0,0 +1,48 @@
+//! Host-side timestamps added to each message, so Blender scripts and
+//! recordings can reconstruct the timing independently of serial jitter.
+
+use serde_json::Value;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampMode {
+    /// Seconds since the bridge started, as `t_mono`
+    Monotonic,
+    /// Seconds since the UNIX epoch, as `t_wall`
+    Wall,
+    /// Both `t_mono` and `t_wall`
+    Both,
+}
+
+pub struct Timestamper {
+    mode: TimestampMode,
+    start: Instant,
+}
+
+impl Timestamper {
+    pub fn new(mode: TimestampMode) -> Self {
+        Self {
+            mode,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn apply(&self, message: &mut Value) {
+        let Value::Object(fields) = message else {
+            return;
+        };
+
+        if self.mode != TimestampMode::Wall {
+            fields.insert(
+                "t_mono".to_string(),
+                self.start.elapsed().as_secs_f64().into(),
+            );
+        }
+        if self.mode != TimestampMode::Monotonic {
+            let wall = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            fields.insert("t_wall".to_string(), wall.as_secs_f64().into());
+        }
+    }
+}
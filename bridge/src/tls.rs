@@ -0,0 +1,552 @@
+//! TLS for the connections to TCP services (`--tls`, cargo feature `tls`),
+//! on top of the system's OpenSSL (libssl 1.1 or 3), so no TLS stack has
+//! to be vendored.
+//!
+//! The socket is non-blocking once the handshake is done: the thread
+//! reading a connection waits for data without holding the session, so
+//! writes from the forwarding loop never wait for a service that has
+//! nothing to say.
+//!
+//! It needs a Unix system: Windows has no system OpenSSL to link, and the
+//! session waits for its socket with `poll`. A Windows bridge is built
+//! without the feature, and `--tls` is refused then.
+
+#[cfg(not(unix))]
+compile_error!("the tls feature links the system's OpenSSL and needs a Unix system");
+
+use std::ffi::{c_char, c_int, c_long, c_ulong, c_void, CStr, CString};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+#[repr(C)]
+struct SslMethod {
+    _private: [u8; 0],
+}
+#[repr(C)]
+struct SslCtx {
+    _private: [u8; 0],
+}
+#[repr(C)]
+struct Ssl {
+    _private: [u8; 0],
+}
+#[repr(C)]
+struct VerifyParam {
+    _private: [u8; 0],
+}
+
+#[link(name = "ssl")]
+#[link(name = "crypto")]
+extern "C" {
+    fn TLS_client_method() -> *const SslMethod;
+    fn SSL_CTX_new(method: *const SslMethod) -> *mut SslCtx;
+    fn SSL_CTX_free(ctx: *mut SslCtx);
+    fn SSL_CTX_ctrl(ctx: *mut SslCtx, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_CTX_set_verify(ctx: *mut SslCtx, mode: c_int, callback: *const c_void);
+    fn SSL_CTX_load_verify_locations(
+        ctx: *mut SslCtx,
+        file: *const c_char,
+        path: *const c_char,
+    ) -> c_int;
+    fn SSL_CTX_set_default_verify_paths(ctx: *mut SslCtx) -> c_int;
+    fn SSL_CTX_use_certificate_chain_file(ctx: *mut SslCtx, file: *const c_char) -> c_int;
+    fn SSL_CTX_use_PrivateKey_file(ctx: *mut SslCtx, file: *const c_char, kind: c_int) -> c_int;
+    fn SSL_CTX_check_private_key(ctx: *const SslCtx) -> c_int;
+    fn SSL_new(ctx: *mut SslCtx) -> *mut Ssl;
+    fn SSL_free(ssl: *mut Ssl);
+    fn SSL_ctrl(ssl: *mut Ssl, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_set_fd(ssl: *mut Ssl, fd: c_int) -> c_int;
+    fn SSL_set1_host(ssl: *mut Ssl, hostname: *const c_char) -> c_int;
+    fn SSL_get0_param(ssl: *mut Ssl) -> *mut VerifyParam;
+    fn SSL_connect(ssl: *mut Ssl) -> c_int;
+    fn SSL_read(ssl: *mut Ssl, buf: *mut c_void, num: c_int) -> c_int;
+    fn SSL_write(ssl: *mut Ssl, buf: *const c_void, num: c_int) -> c_int;
+    fn SSL_shutdown(ssl: *mut Ssl) -> c_int;
+    fn SSL_get_error(ssl: *const Ssl, ret: c_int) -> c_int;
+    fn SSL_get_verify_result(ssl: *const Ssl) -> c_long;
+    fn X509_VERIFY_PARAM_set1_ip_asc(param: *mut VerifyParam, ip: *const c_char) -> c_int;
+    fn X509_verify_cert_error_string(error: c_long) -> *const c_char;
+    fn ERR_get_error() -> c_ulong;
+    fn ERR_error_string_n(error: c_ulong, buf: *mut c_char, len: usize);
+    fn ERR_clear_error();
+}
+
+const SSL_ERROR_SSL: c_int = 1;
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+const SSL_VERIFY_PEER: c_int = 1;
+const SSL_FILETYPE_PEM: c_int = 1;
+const SSL_CTRL_MODE: c_int = 33;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const SSL_CTRL_CLEAR_MODE: c_int = 78;
+const SSL_CTRL_SET_MIN_PROTO_VERSION: c_int = 123;
+const SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER: c_long = 2;
+const SSL_MODE_AUTO_RETRY: c_long = 4;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+const TLS1_2_VERSION: c_long = 0x0303;
+
+/// The oldest error OpenSSL queued for this thread, as text.
+fn last_error(context: &str) -> io::Error {
+    // SAFETY: the buffer outlives the call and its length is passed along
+    let message = unsafe {
+        let code = ERR_get_error();
+        if code == 0 {
+            return io::Error::other(context.to_string());
+        }
+        let mut buffer = [0 as c_char; 256];
+        ERR_error_string_n(code, buffer.as_mut_ptr(), buffer.len());
+        CStr::from_ptr(buffer.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    };
+    io::Error::other(format!("{}: {}", context, message))
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+}
+
+/// The settings shared by every TLS connection: the trusted certificates
+/// and the client certificate, if any.
+pub struct Connector {
+    ctx: *mut SslCtx,
+}
+
+// SAFETY: the context isn't changed after `new`, and creating sessions from
+// it is thread-safe
+unsafe impl Send for Connector {}
+unsafe impl Sync for Connector {}
+
+impl Connector {
+    /// Verifies services against the certificates in `ca`, or the system's,
+    /// and presents the client certificate `cert` with its key `key`.
+    pub fn new(ca: Option<&Path>, cert: Option<&Path>, key: Option<&Path>) -> io::Result<Self> {
+        // SAFETY: the context is checked before use and freed on error by
+        // `Drop`, the strings outlive the calls taking them
+        unsafe {
+            let ctx = SSL_CTX_new(TLS_client_method());
+            if ctx.is_null() {
+                return Err(last_error("can't set up TLS"));
+            }
+            let connector = Self { ctx };
+
+            SSL_CTX_ctrl(
+                ctx,
+                SSL_CTRL_SET_MIN_PROTO_VERSION,
+                TLS1_2_VERSION,
+                ptr::null_mut(),
+            );
+            // Return to the caller after records without data (like session
+            // tickets) instead of blocking, and let retried writes come from
+            // a moved buffer
+            SSL_CTX_ctrl(
+                ctx,
+                SSL_CTRL_CLEAR_MODE,
+                SSL_MODE_AUTO_RETRY,
+                ptr::null_mut(),
+            );
+            SSL_CTX_ctrl(
+                ctx,
+                SSL_CTRL_MODE,
+                SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER,
+                ptr::null_mut(),
+            );
+            SSL_CTX_set_verify(ctx, SSL_VERIFY_PEER, ptr::null());
+
+            let trusted = match ca {
+                Some(ca) => {
+                    let ca = c_path(ca)?;
+                    SSL_CTX_load_verify_locations(ctx, ca.as_ptr(), ptr::null())
+                }
+                None => SSL_CTX_set_default_verify_paths(ctx),
+            };
+            if trusted != 1 {
+                return Err(last_error("can't load the trusted certificates"));
+            }
+
+            if let (Some(cert), Some(key)) = (cert, key) {
+                let (cert, key) = (c_path(cert)?, c_path(key)?);
+                if SSL_CTX_use_certificate_chain_file(ctx, cert.as_ptr()) != 1 {
+                    return Err(last_error("can't load the client certificate"));
+                }
+                if SSL_CTX_use_PrivateKey_file(ctx, key.as_ptr(), SSL_FILETYPE_PEM) != 1 {
+                    return Err(last_error("can't load the client key"));
+                }
+                if SSL_CTX_check_private_key(ctx) != 1 {
+                    return Err(last_error("the client key doesn't match its certificate"));
+                }
+            }
+            Ok(connector)
+        }
+    }
+
+    /// Runs the handshake with the service `host` over `tcp`, verifying its
+    /// certificate, within `timeout`.
+    pub fn connect(&self, host: &str, tcp: TcpStream, timeout: Duration) -> io::Result<TlsStream> {
+        let host = CString::new(host)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host contains a NUL byte"))?;
+        // SAFETY: the session is checked before use and owned by `Session`
+        // from then on, the strings outlive the calls taking them
+        let session = unsafe {
+            let ssl = SSL_new(self.ctx);
+            if ssl.is_null() {
+                return Err(last_error("can't start a TLS session"));
+            }
+            let session = Session {
+                ssl: Mutex::new(SslPtr(ssl)),
+                tcp,
+                read_timeout: Mutex::new(None),
+            };
+
+            let is_ip = host
+                .to_str()
+                .is_ok_and(|host| host.parse::<IpAddr>().is_ok());
+            let named = if is_ip {
+                X509_VERIFY_PARAM_set1_ip_asc(SSL_get0_param(ssl), host.as_ptr())
+            } else {
+                SSL_ctrl(
+                    ssl,
+                    SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                    TLSEXT_NAMETYPE_HOST_NAME,
+                    host.as_ptr() as *mut c_void,
+                );
+                SSL_set1_host(ssl, host.as_ptr())
+            };
+            if named != 1 || SSL_set_fd(ssl, session.tcp.as_raw_fd()) != 1 {
+                return Err(last_error("can't start a TLS session"));
+            }
+
+            session.tcp.set_read_timeout(Some(timeout))?;
+            session.tcp.set_write_timeout(Some(timeout))?;
+            ERR_clear_error();
+            if SSL_connect(ssl) != 1 {
+                let verified = SSL_get_verify_result(ssl);
+                if verified != 0 {
+                    let reason = CStr::from_ptr(X509_verify_cert_error_string(verified));
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("certificate rejected: {}", reason.to_string_lossy()),
+                    ));
+                }
+                return Err(last_error("TLS handshake failed"));
+            }
+            session
+        };
+        session.tcp.set_read_timeout(None)?;
+        session.tcp.set_write_timeout(None)?;
+        session.tcp.set_nonblocking(true)?;
+        Ok(TlsStream {
+            session: Arc::new(session),
+        })
+    }
+}
+
+impl Drop for Connector {
+    fn drop(&mut self) {
+        // SAFETY: sessions hold their own reference to the context
+        unsafe { SSL_CTX_free(self.ctx) };
+    }
+}
+
+struct SslPtr(*mut Ssl);
+
+// SAFETY: the session is only used behind the mutex
+unsafe impl Send for SslPtr {}
+
+impl Drop for SslPtr {
+    fn drop(&mut self) {
+        // SAFETY: the session is owned and no longer used
+        unsafe { SSL_free(self.0) };
+    }
+}
+
+struct Session {
+    ssl: Mutex<SslPtr>,
+    tcp: TcpStream,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Session {
+    fn lock(&self) -> MutexGuard<'_, SslPtr> {
+        self.ssl.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until the socket is readable (`libc::POLLIN`) or writable, or
+    /// `timeout` passed. Returns whether it got ready.
+    fn wait(&self, events: libc::c_short, timeout: Option<Duration>) -> io::Result<bool> {
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis().max(1) as c_int);
+        let mut poll = libc::pollfd {
+            fd: self.tcp.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        loop {
+            // SAFETY: a single valid pollfd is passed
+            match unsafe { libc::poll(&mut poll, 1, timeout) } {
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+                ready => return Ok(ready > 0),
+            }
+        }
+    }
+
+    /// What to do after an SSL call on `ssl` returned `result`: wait and
+    /// retry (`None`), or give up with the error.
+    fn after(&self, ssl: *mut Ssl, result: c_int, reading: bool) -> Option<io::Error> {
+        // SAFETY: `ssl` is the session's and locked by the caller
+        let error = unsafe { SSL_get_error(ssl, result) };
+        let (events, timeout) = match error {
+            SSL_ERROR_WANT_READ if reading => return None,
+            SSL_ERROR_WANT_READ => (libc::POLLIN, None),
+            SSL_ERROR_WANT_WRITE => (libc::POLLOUT, None),
+            SSL_ERROR_ZERO_RETURN => return Some(io::ErrorKind::UnexpectedEof.into()),
+            SSL_ERROR_SYSCALL => {
+                let e = io::Error::last_os_error();
+                return Some(match e.raw_os_error() {
+                    Some(0) | None => io::ErrorKind::UnexpectedEof.into(),
+                    _ => e,
+                });
+            }
+            SSL_ERROR_SSL => return Some(last_error("TLS error")),
+            _ => return Some(io::Error::other(format!("TLS error {}", error))),
+        };
+        self.wait(events, timeout).err()
+    }
+}
+
+/// An encrypted connection. Clones share the session, so one thread can
+/// read while another writes.
+#[derive(Clone)]
+pub struct TlsStream {
+    session: Arc<Session>,
+}
+
+impl TlsStream {
+    pub fn tcp(&self) -> &TcpStream {
+        &self.session.tcp
+    }
+
+    /// Like `TcpStream::set_read_timeout`, reads then fail with
+    /// `WouldBlock`.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self
+            .session
+            .read_timeout
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = timeout;
+        Ok(())
+    }
+
+    /// Tells the service the connection ends, then closes the socket.
+    pub fn shutdown(&self) -> io::Result<()> {
+        {
+            let ssl = self.session.lock();
+            // SAFETY: the session is locked; a non-blocking close notify
+            // that can't go out right away is skipped
+            unsafe {
+                ERR_clear_error();
+                SSL_shutdown(ssl.0);
+            }
+        }
+        self.session.tcp.shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        loop {
+            {
+                let ssl = self.session.lock();
+                // SAFETY: the session is locked and `buf` holds `len` bytes
+                let result = unsafe {
+                    ERR_clear_error();
+                    SSL_read(ssl.0, buf.as_mut_ptr() as *mut c_void, len)
+                };
+                if result > 0 {
+                    return Ok(result as usize);
+                }
+                match self.session.after(ssl.0, result, true) {
+                    Some(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                    Some(e) => return Err(e),
+                    None => {}
+                }
+            }
+            // Waiting for data without the session, so writes go on
+            let timeout = *self
+                .session
+                .read_timeout
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if !self.session.wait(libc::POLLIN, timeout)? {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        let ssl = self.session.lock();
+        loop {
+            // SAFETY: the session is locked and `buf` holds `len` bytes
+            let result = unsafe {
+                ERR_clear_error();
+                SSL_write(ssl.0, buf.as_ptr() as *const c_void, len)
+            };
+            if result > 0 {
+                return Ok(result as usize);
+            }
+            if let Some(e) = self.session.after(ssl.0, result, false) {
+                return Err(match e.kind() {
+                    io::ErrorKind::UnexpectedEof => io::ErrorKind::BrokenPipe.into(),
+                    _ => e,
+                });
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread::{self, JoinHandle};
+
+    extern "C" {
+        fn TLS_server_method() -> *const SslMethod;
+        fn SSL_accept(ssl: *mut Ssl) -> c_int;
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The self-signed certificate for localhost and 127.0.0.1, or its key.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name)
+    }
+
+    /// A service on a free port that answers the first thing it reads
+    /// with the same bytes, returning whether its handshake succeeded.
+    fn echo_service() -> (u16, JoinHandle<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let cert = c_path(&fixture("localhost.pem")).unwrap();
+            let key = c_path(&fixture("localhost.key")).unwrap();
+            // SAFETY: test code, every pointer is checked or comes from
+            // the calls right before
+            unsafe {
+                let ctx = SSL_CTX_new(TLS_server_method());
+                assert!(!ctx.is_null());
+                assert_eq!(SSL_CTX_use_certificate_chain_file(ctx, cert.as_ptr()), 1);
+                assert_eq!(
+                    SSL_CTX_use_PrivateKey_file(ctx, key.as_ptr(), SSL_FILETYPE_PEM),
+                    1
+                );
+                let ssl = SSL_new(ctx);
+                SSL_set_fd(ssl, tcp.as_raw_fd());
+                let accepted = SSL_accept(ssl) == 1;
+                if accepted {
+                    let mut buffer = [0u8; 256];
+                    let len = SSL_read(ssl, buffer.as_mut_ptr() as *mut c_void, 256);
+                    if len > 0 {
+                        SSL_write(ssl, buffer.as_ptr() as *const c_void, len);
+                    }
+                    SSL_shutdown(ssl);
+                }
+                SSL_free(ssl);
+                SSL_CTX_free(ctx);
+                accepted
+            }
+        });
+        (port, service)
+    }
+
+    fn connect(connector: &Connector, host: &str, port: u16) -> io::Result<TlsStream> {
+        let tcp = TcpStream::connect(("127.0.0.1", port))?;
+        connector.connect(host, tcp, TIMEOUT)
+    }
+
+    #[test]
+    fn data_goes_both_ways_once_the_service_is_trusted() {
+        let (port, service) = echo_service();
+        let connector = Connector::new(Some(&fixture("localhost.pem")), None, None).unwrap();
+        let mut stream = connect(&connector, "localhost", port).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        stream.write_all(b"{\"x\":1.0}\n").unwrap();
+        let mut echoed = [0u8; 10];
+        stream.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"{\"x\":1.0}\n");
+        assert!(service.join().unwrap());
+    }
+
+    #[test]
+    fn an_address_is_checked_against_the_certificates_ips() {
+        let (port, service) = echo_service();
+        let connector = Connector::new(Some(&fixture("localhost.pem")), None, None).unwrap();
+        let stream = connect(&connector, "127.0.0.1", port).unwrap();
+        stream.shutdown().unwrap();
+        assert!(service.join().unwrap());
+    }
+
+    #[test]
+    fn a_certificate_for_another_host_is_rejected() {
+        let (port, service) = echo_service();
+        let connector = Connector::new(Some(&fixture("localhost.pem")), None, None).unwrap();
+        let e = connect(&connector, "workstation", port).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("hostname mismatch"), "{}", e);
+        assert!(!service.join().unwrap());
+    }
+
+    #[test]
+    fn a_self_signed_certificate_needs_a_ca() {
+        let (port, service) = echo_service();
+        let connector = Connector::new(None, None, None).unwrap();
+        let e = connect(&connector, "localhost", port).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("self-signed"), "{}", e);
+        assert!(!service.join().unwrap());
+    }
+
+    #[test]
+    fn the_certificates_are_loaded_up_front() {
+        let connector = Connector::new(
+            Some(&fixture("localhost.pem")),
+            Some(&fixture("localhost.pem")),
+            Some(&fixture("localhost.key")),
+        );
+        assert!(connector.is_ok());
+
+        let e = Connector::new(Some(&fixture("missing.pem")), None, None)
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("trusted certificates"), "{}", e);
+    }
+}
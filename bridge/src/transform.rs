@@ -0,0 +1,167 @@
+//! The processing stages a message passes through between parsing and the
+//! sinks.
+
+use crate::change::ChangeFilter;
+use crate::cli::Args;
+use crate::error::BridgeError;
+use crate::events::EventRules;
+use crate::filter::{FilterPipeline, FilterSpec};
+use crate::fusion::Fusion;
+use crate::gestures::GestureDetector;
+use crate::script::Script;
+use crate::sink::{Sink, SinkStats};
+use serde_json::Value;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// One processing stage. A stage may change a message, drop it by
+/// returning nothing, or derive additional messages from it.
+pub trait Transform: Send {
+    fn transform(&mut self, message: Value) -> Vec<Value>;
+}
+
+impl Transform for FilterPipeline {
+    fn transform(&mut self, mut message: Value) -> Vec<Value> {
+        self.apply(&mut message);
+        vec![message]
+    }
+}
+
+/// The filters of a pipeline, which can be changed while it runs.
+pub type SharedFilters = Arc<Mutex<FilterPipeline>>;
+
+impl Transform for SharedFilters {
+    fn transform(&mut self, mut message: Value) -> Vec<Value> {
+        self.lock().unwrap().apply(&mut message);
+        vec![message]
+    }
+}
+
+/// A chain of transforms, each one applied to every output of the previous.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Transform>>,
+    filters: Option<SharedFilters>,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// The stages configured on the command line.
+    pub fn from_args(args: &Args) -> Result<Self, BridgeError> {
+        let mut builder = Self::builder();
+        if args.fusion {
+            builder = builder.stage(Fusion::default());
+        }
+        if let Some(path) = &args.script {
+            builder = builder.stage(Script::load(path).map_err(|e| BridgeError::parse(path, e))?);
+        }
+        // Always there, so filters can be added at runtime
+        builder = builder.filters(&args.filters);
+        if !args.events.is_empty() {
+            builder = builder.stage(EventRules::new(&args.events));
+        }
+        if args.gestures {
+            builder = builder.stage(GestureDetector::new());
+        }
+        // Last, so the filters have smoothed out the noise it compares
+        if let Some(epsilon) = args.send_on_change {
+            builder = builder.stage(ChangeFilter::new(epsilon, args.change_keepalive));
+        }
+        Ok(builder.build())
+    }
+
+    /// The filter stage, if the pipeline has one.
+    pub fn filters(&self) -> Option<&SharedFilters> {
+        self.filters.as_ref()
+    }
+
+    pub fn apply(&mut self, message: Value) -> Vec<Value> {
+        let mut messages = vec![message];
+        for stage in &mut self.stages {
+            messages = messages
+                .into_iter()
+                .flat_map(|message| stage.transform(message))
+                .collect();
+        }
+        messages
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineBuilder {
+    stages: Vec<Box<dyn Transform>>,
+    filters: Option<SharedFilters>,
+}
+
+impl PipelineBuilder {
+    /// Appends a stage after the ones added so far.
+    pub fn stage(mut self, stage: impl Transform + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Appends the filter stage, whose filters can be replaced later
+    /// through `Pipeline::filters`.
+    pub fn filters(mut self, specs: &[FilterSpec]) -> Self {
+        let filters = Arc::new(Mutex::new(FilterPipeline::new(specs)));
+        self.filters = Some(Arc::clone(&filters));
+        self.stage(filters)
+    }
+
+    pub fn build(self) -> Pipeline {
+        Pipeline {
+            stages: self.stages,
+            filters: self.filters,
+        }
+    }
+}
+
+/// Runs a transform on every message before it reaches `sink`, for
+/// processing that only some sinks need.
+pub fn wrap(sink: Box<dyn Sink>, transform: impl Transform + 'static) -> Box<dyn Sink> {
+    Box::new(TransformedSink {
+        inner: sink,
+        transform: Box::new(transform),
+    })
+}
+
+struct TransformedSink {
+    inner: Box<dyn Sink>,
+    transform: Box<dyn Transform>,
+}
+
+impl Sink for TransformedSink {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        for message in self.transform.transform(message.clone()) {
+            self.inner.send(&message)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        self.inner.status()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.inner.stats()
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        self.inner.tick()
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.inner.mark(label)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+}
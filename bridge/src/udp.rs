@@ -0,0 +1,53 @@
+//! Sends every message as a single UDP datagram, for game engines and other
+//! consumers that prefer dropping a sample over waiting for it.
+
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+pub struct UdpSink {
+    socket: UdpSocket,
+    target: SocketAddr,
+    name: String,
+}
+
+impl UdpSink {
+    pub fn new(target: &str) -> io::Result<Self> {
+        // Resolve once up front instead of on every datagram
+        let address = target.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("could not resolve {}", target),
+            )
+        })?;
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        info!("Sending UDP datagrams to {}", address);
+
+        Ok(Self {
+            socket,
+            target: address,
+            name: format!("UDP {}", target),
+        })
+    }
+}
+
+impl Sink for UdpSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let datagram = serde_json::to_vec(message)?;
+        if let Err(e) = self.socket.send_to(&datagram, self.target) {
+            warn!(sink: self.name, "UDP send to {} failed: {}", self.target, e);
+        }
+        Ok(())
+    }
+}
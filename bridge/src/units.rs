@@ -0,0 +1,92 @@
+//! Conversion of the angles from the firmware's degrees to radians, for
+//! consumers like Blender's Python API that expect them. Angular velocities
+//! go from degrees to radians per second along with them.
+
+use crate::fusion::GYRO_FIELDS;
+use crate::info;
+use crate::sink::Sink;
+use crate::transform::{self, Transform};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Message fields holding angles in degrees.
+const ANGLE_FIELDS: [&str; 3] = ["x", "y", "z"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Unit {
+    /// Degrees, as sent by the board
+    Deg,
+    /// Radians
+    Rad,
+}
+
+/// `--units UNIT` for all sinks, or `--units NAME=UNIT` for a single one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitSpec {
+    sink: Option<String>,
+    unit: Unit,
+}
+
+impl FromStr for UnitSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (sink, unit) = match spec.rsplit_once('=') {
+            Some((sink, unit)) => (Some(sink.to_string()), unit),
+            None => (None, spec),
+        };
+        let unit = Unit::from_str(unit, true)
+            .map_err(|_| format!("invalid unit '{}', expected deg or rad", unit))?;
+        Ok(Self { sink, unit })
+    }
+}
+
+struct ToRadians;
+
+impl Transform for ToRadians {
+    fn transform(&mut self, mut message: Value) -> Vec<Value> {
+        if let Value::Object(fields) = &mut message {
+            for name in ANGLE_FIELDS.into_iter().chain(GYRO_FIELDS) {
+                if let Some(degrees) = fields.get(name).and_then(Value::as_f64) {
+                    fields.insert(name.to_string(), degrees.to_radians().into());
+                }
+            }
+        }
+        vec![message]
+    }
+}
+
+/// `angle` in degrees moved into (-180, 180], for the yaw, which unlike
+/// pitch and roll goes all the way around.
+pub fn wrap_degrees(angle: f64) -> f64 {
+    let wrapped = angle.rem_euclid(360.0);
+    if wrapped > 180.0 {
+        wrapped - 360.0
+    } else {
+        wrapped
+    }
+}
+
+/// The unit `specs` ask for the sink `name`. A unit given for the sink's
+/// name takes precedence over a global one.
+pub fn unit_for(name: &str, specs: &[UnitSpec]) -> Unit {
+    let named = specs.iter().find(|spec| {
+        spec.sink
+            .as_deref()
+            .is_some_and(|sink| sink.eq_ignore_ascii_case(name))
+    });
+    let global = specs.iter().find(|spec| spec.sink.is_none());
+    named.or(global).map_or(Unit::Deg, |spec| spec.unit)
+}
+
+/// Wraps `sink` so it receives radians if one of `specs` asks for them.
+pub fn convert(sink: Box<dyn Sink>, specs: &[UnitSpec]) -> Box<dyn Sink> {
+    match unit_for(sink.name(), specs) {
+        Unit::Rad => {
+            info!("Sending radians to {}", sink.name());
+            transform::wrap(sink, ToRadians)
+        }
+        Unit::Deg => sink,
+    }
+}
@@ -0,0 +1,129 @@
+//! Strict checking of incoming messages against the expected schema.
+
+use crate::fusion::GYRO_FIELDS;
+use crate::info;
+use crate::subscribe::MessageKind;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+/// Fields every orientation message must carry as finite numbers: the
+/// angles, or a quaternion from firmware built with `quaternion`.
+const ANGLE_FIELDS: [&str; 3] = ["x", "y", "z"];
+const QUATERNION_FIELDS: [&str; 4] = ["qw", "qx", "qy", "qz"];
+
+pub struct Validator {
+    quarantine: Option<LineWriter<File>>,
+    violations: u64,
+}
+
+impl Validator {
+    pub fn new(quarantine: Option<&Path>) -> io::Result<Self> {
+        let quarantine = match quarantine {
+            Some(path) => {
+                info!("Quarantining rejected messages in {}", path.display());
+                Some(LineWriter::new(File::create(path)?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            quarantine,
+            violations: 0,
+        })
+    }
+
+    pub fn violations(&self) -> u64 {
+        self.violations
+    }
+
+    /// Checks a parsed message, or `None` for a line that isn't valid JSON.
+    /// Rejected lines are counted and written to the quarantine file.
+    pub fn check(&mut self, line: &str, message: Option<&Value>) -> Result<(), String> {
+        let result = match message {
+            Some(message) => check_schema(message),
+            None => Err("not valid JSON".to_string()),
+        };
+
+        if result.is_err() {
+            self.violations += 1;
+            if let Some(quarantine) = &mut self.quarantine {
+                writeln!(quarantine, "{}", line).map_err(|e| e.to_string())?;
+            }
+        }
+        result
+    }
+}
+
+fn check_schema(message: &Value) -> Result<(), String> {
+    let fields = message
+        .as_object()
+        .ok_or_else(|| "message is not a JSON object".to_string())?;
+    // Events and telemetry from the board carry no orientation
+    if MessageKind::of(message) != MessageKind::Orientation {
+        return Ok(());
+    }
+
+    let required = if QUATERNION_FIELDS
+        .iter()
+        .any(|name| fields.contains_key(*name))
+    {
+        &QUATERNION_FIELDS[..]
+    } else {
+        &ANGLE_FIELDS[..]
+    };
+    let optional = GYRO_FIELDS
+        .iter()
+        .filter(|name| fields.contains_key(**name));
+    for &name in required.iter().chain(optional) {
+        match fields.get(name) {
+            None => return Err(format!("missing field '{}'", name)),
+            Some(Value::Number(number)) => {
+                if !number.as_f64().is_some_and(f64::is_finite) {
+                    return Err(format!("field '{}' is not a finite number", name));
+                }
+            }
+            Some(_) => return Err(format!("field '{}' is not a number", name)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn angles_or_a_quaternion_make_an_orientation() {
+        assert_eq!(check_schema(&json!({"x": 1.0, "y": 2.0, "z": 3.0})), Ok(()));
+        assert_eq!(
+            check_schema(&json!({"qw": 0.9239, "qx": 0.3827, "qy": 0.0, "qz": 0.0, "seq": 4})),
+            Ok(())
+        );
+        assert_eq!(
+            check_schema(&json!({"seq": 4})),
+            Err("missing field 'x'".to_string())
+        );
+    }
+
+    #[test]
+    fn a_quaternion_needs_all_four_components() {
+        assert_eq!(
+            check_schema(&json!({"qw": 1.0, "qx": 0.0, "qy": 0.0})),
+            Err("missing field 'qz'".to_string())
+        );
+        assert_eq!(
+            check_schema(&json!({"qw": 1.0, "qx": 0.0, "qy": 0.0, "qz": "0"})),
+            Err("field 'qz' is not a number".to_string())
+        );
+    }
+
+    #[test]
+    fn events_and_telemetry_carry_no_orientation() {
+        assert_eq!(check_schema(&json!({"event": "tilt"})), Ok(()));
+        assert_eq!(check_schema(&json!({"temp": 21})), Ok(()));
+        assert!(check_schema(&json!([1, 2, 3])).is_err());
+    }
+}
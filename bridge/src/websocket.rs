@@ -0,0 +1,392 @@
+//! A minimal WebSocket (RFC 6455) server that broadcasts every message as a
+//! text frame to all connected browser clients.
+//!
+//! Plain HTTP requests for `/` get the dashboard instead: a single page
+//! showing the orientation as a 3D board, the message rate and the events,
+//! fed by the same stream.
+
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+/// A close frame without a status code.
+const CLOSE_FRAME: [u8; 2] = [0x88, 0x00];
+/// The page served to plain HTTP requests.
+const DASHBOARD: &str = include_str!("../web/dashboard.html");
+
+pub const TEXT: u8 = 0x1;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+
+pub struct WebSocketServer {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    /// Sent to every client as it connects.
+    greeting: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl WebSocketServer {
+    pub fn bind(host: &str, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((host, port))?;
+        info!("WebSocket server listening on ws://{}:{}", host, port);
+        info!("Dashboard at http://{}:{}/", host, port);
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let greeting = Arc::new(Mutex::new(None));
+        let accepted = Arc::clone(&clients);
+        let greet = Arc::clone(&greeting);
+        thread::spawn(move || accept_clients(listener, accepted, greet));
+
+        Ok(Self { clients, greeting })
+    }
+
+    /// Sends `message` to every client before any data, e.g. the hello
+    /// telling the dashboard the units.
+    pub fn with_greeting(self, message: &Value) -> io::Result<Self> {
+        *self.greeting.lock().unwrap() = Some(text_frame(&serde_json::to_string(message)?));
+        Ok(self)
+    }
+}
+
+impl Sink for WebSocketServer {
+    fn name(&self) -> &str {
+        "WebSocket"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let frame = text_frame(&serde_json::to_string(message)?);
+        let mut clients = self.clients.lock().unwrap();
+
+        // A client that can't keep up or went away is simply dropped, the
+        // browser side is expected to reconnect.
+        clients.retain_mut(|client| match client.write_all(&frame) {
+            Ok(()) => true,
+            Err(e) => {
+                info!(sink: "WebSocket", "WebSocket client disconnected ({})", e);
+                false
+            }
+        });
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        match self.clients.lock().unwrap().len() {
+            1 => "1 client".to_string(),
+            clients => format!("{} clients", clients),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        for mut client in self.clients.lock().unwrap().drain(..) {
+            // Clients that already went away don't need telling
+            let _ = client.write_all(&CLOSE_FRAME);
+            let _ = client.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    }
+}
+
+fn accept_clients(
+    listener: TcpListener,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    greeting: Arc<Mutex<Option<Vec<u8>>>>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(sink: "WebSocket", "WebSocket accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let clients = Arc::clone(&clients);
+        let greeting = greeting.lock().unwrap().clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_default();
+            match handshake(stream) {
+                Ok(Some(mut stream)) => {
+                    info!(sink: "WebSocket", "WebSocket client connected from {}", peer);
+                    if let Some(greeting) = greeting {
+                        if let Err(e) = stream.write_all(&greeting) {
+                            warn!(sink: "WebSocket", "Can't greet WebSocket client {}: {}", peer, e);
+                            return;
+                        }
+                    }
+                    clients.lock().unwrap().push(stream);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(sink: "WebSocket", "WebSocket handshake with {} failed: {}", peer, e)
+                }
+            }
+        });
+    }
+}
+
+/// Upgrades the connection to a WebSocket, or answers a plain HTTP request
+/// and returns `None`.
+fn handshake(mut stream: TcpStream) -> io::Result<Option<TcpStream>> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut key = None;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let Some(key) = key else {
+        serve_page(&mut stream, &request_line)?;
+        return Ok(None);
+    };
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )?;
+
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(Some(stream))
+}
+
+/// The `Sec-WebSocket-Accept` answering a client's `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+/// Answers a request that isn't a WebSocket upgrade: the dashboard for
+/// `GET /`, an error for anything else.
+fn serve_page(stream: &mut TcpStream, request_line: &str) -> io::Result<()> {
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let path = path.split('?').next().unwrap_or_default();
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/" | "/index.html") => ("200 OK", "text/html; charset=utf-8", DASHBOARD),
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n"),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Only GET is supported\n",
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Builds an unmasked, unfragmented text frame as sent by a server.
+fn text_frame(payload: &str) -> Vec<u8> {
+    frame(TEXT, payload.as_bytes(), None)
+}
+
+/// Builds an unfragmented frame, masked with `mask` as clients must.
+pub fn frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode); // FIN + opcode
+
+    let masked = if mask.is_some() { 0x80 } else { 0x00 };
+    match payload.len() {
+        len if len < 126 => frame.push(masked | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(masked | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(masked | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .zip(mask.iter().cycle())
+                    .map(|(byte, key)| byte ^ key),
+            );
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+/// SHA-1 as needed for the `Sec-WebSocket-Accept` header.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_accept_key_matches_the_rfc() {
+        // RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn sha1_and_base64_match_their_test_vectors() {
+        let hex: String = sha1(b"abc").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // Spans more than one block
+        let hex: String = sha1(&[b'a'; 1000])
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(hex, "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn short_frames_carry_their_length_in_the_header() {
+        // RFC 6455, section 5.7
+        assert_eq!(
+            frame(TEXT, b"Hello", None),
+            [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]
+        );
+        assert_eq!(
+            frame(TEXT, b"Hello", Some([0x37, 0xfa, 0x21, 0x3d])),
+            [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+        assert_eq!(frame(CLOSE, &[], None), CLOSE_FRAME);
+    }
+
+    #[test]
+    fn longer_frames_extend_the_length() {
+        let frame_of = |length| frame(TEXT, &vec![b'a'; length], None);
+
+        let frame = frame_of(125);
+        assert_eq!(frame[1], 125);
+        assert_eq!(frame.len(), 2 + 125);
+
+        let frame = frame_of(256);
+        assert_eq!(frame[1..4], [126, 0x01, 0x00]);
+        assert_eq!(frame.len(), 4 + 256);
+
+        let frame = frame_of(65_536);
+        assert_eq!(frame[1..10], [127, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00]);
+        assert_eq!(frame.len(), 10 + 65_536);
+    }
+}
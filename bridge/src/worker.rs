@@ -0,0 +1,234 @@
+//! A sink moved onto a thread of its own, fed with jobs by the forwarding
+//! loop: the queue (`--queue-size`) and the resampler (`--resample`) are
+//! both built on it.
+//!
+//! Errors of the sink are kept and reported by the next call from the
+//! forwarding loop, and so is a panic, which stops the thread.
+
+use crate::sink::{Sink, SinkStats};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// A call of the forwarding loop, passed on to the sink by its thread.
+pub enum Job {
+    Send(Value),
+    Mark(String),
+    Tick,
+}
+
+impl Job {
+    pub fn run(self, sink: &mut dyn Sink) -> io::Result<()> {
+        match self {
+            Job::Send(message) => sink.send(&message),
+            Job::Mark(label) => sink.mark(&label),
+            Job::Tick => sink.tick(),
+        }
+    }
+}
+
+pub struct State {
+    /// Jobs for the thread, oldest first.
+    pub jobs: VecDeque<Job>,
+    closing: bool,
+    /// The first error of the sink, reported on the next call.
+    error: Option<io::Error>,
+    /// Whether the thread ended without being closed, i.e. panicked.
+    stopped: bool,
+    /// The sink's status and counters, as of its last job.
+    status: String,
+    stats: SinkStats,
+}
+
+impl State {
+    /// The sink's error since the last call, if any.
+    pub fn check(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.stopped {
+            return Err(io::Error::other("its thread stopped"));
+        }
+        Ok(())
+    }
+}
+
+/// What the thread and the forwarding loop share.
+pub struct Shared {
+    state: Mutex<State>,
+    /// Signalled whenever a job is queued or taken.
+    changed: Condvar,
+}
+
+impl Shared {
+    pub fn lock(&self) -> MutexGuard<'_, State> {
+        // A panicking sink never holds the lock, so the state is whole
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits for the other side to queue or take a job.
+    pub fn wait<'a>(&self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Tells the other side the jobs changed.
+    pub fn notify(&self) {
+        self.changed.notify_all();
+    }
+
+    /// The next job, waiting for one, or `None` once the worker is closed
+    /// and every job done.
+    pub fn next_job(&self) -> Option<Job> {
+        let mut state = self.lock();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                self.notify();
+                return Some(job);
+            }
+            if state.closing {
+                return None;
+            }
+            state = self.wait(state);
+        }
+    }
+
+    /// Every job queued so far, and whether the worker is closed.
+    pub fn take_jobs(&self) -> (VecDeque<Job>, bool) {
+        let mut state = self.lock();
+        let jobs = std::mem::take(&mut state.jobs);
+        self.notify();
+        (jobs, state.closing)
+    }
+
+    /// Notes how the sink is after its jobs returned `result`.
+    pub fn done(&self, sink: &dyn Sink, result: io::Result<()>) {
+        let mut state = self.lock();
+        state.status = sink.status();
+        state.stats = sink.stats();
+        if let Err(e) = result {
+            state.error.get_or_insert(e);
+        }
+    }
+}
+
+/// Tells the forwarding loop when the sink panicked, instead of leaving it
+/// to hand jobs to a thread that stopped.
+struct PanicGuard<'a>(&'a Shared);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let mut state = self.0.lock();
+            state.stopped = true;
+            state
+                .error
+                .get_or_insert_with(|| io::Error::other("its thread panicked"));
+            self.0.notify();
+        }
+    }
+}
+
+pub struct Worker {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<Box<dyn Sink>>>,
+}
+
+impl Worker {
+    /// Runs `work` with `sink` on a thread of its own. `work` hands the
+    /// sink back once the worker is closed.
+    pub fn spawn<F>(sink: Box<dyn Sink>, work: F) -> Self
+    where
+        F: FnOnce(Box<dyn Sink>, &Shared) -> Box<dyn Sink> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                closing: false,
+                error: None,
+                stopped: false,
+                status: sink.status(),
+                stats: sink.stats(),
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let _guard = PanicGuard(&shared);
+                work(sink, &shared)
+            })
+        };
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn shared(&self) -> &Shared {
+        &self.shared
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.lock()
+    }
+
+    pub fn status(&self) -> String {
+        self.lock().status.clone()
+    }
+
+    pub fn stats(&self) -> SinkStats {
+        self.lock().stats
+    }
+
+    /// Whether the worker wasn't closed yet.
+    pub fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Asks the thread to finish once its jobs are done.
+    fn stop(&self) {
+        self.lock().closing = true;
+        self.shared.notify();
+    }
+
+    /// Stops the thread and waits for it, handing back the sink, or `None`
+    /// when that was done before.
+    pub fn join(&mut self) -> Option<io::Result<Box<dyn Sink>>> {
+        let thread = self.thread.take()?;
+        self.stop();
+        Some(
+            thread
+                .join()
+                .map_err(|_| io::Error::other("its thread panicked")),
+        )
+    }
+
+    /// Lets the thread finish its jobs, then closes the sink. Reports the
+    /// first error of the last jobs, if any.
+    pub fn close(&mut self) -> io::Result<()> {
+        let Some(sink) = self.join() else {
+            return Ok(());
+        };
+        let mut sink = sink?;
+        let error = self.lock().error.take();
+        let closed = sink.close();
+        match error {
+            Some(e) => Err(e),
+            None => closed,
+        }
+    }
+}
+
+impl Drop for Worker {
+    /// Ends the thread of a sink that wasn't closed, e.g. when the bridge
+    /// stops on another sink's error, once it finished its jobs.
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.stop();
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! A ZeroMQ PUB socket speaking ZMTP 3 over TCP with the NULL security
+//! mechanism. Any SUB socket (pyzmq, czmq, ...) can connect, subscribe and
+//! unsubscribe on its own.
+//!
+//! Every message is published as two frames, the topic and the JSON
+//! payload, and only sent to subscribers with a subscription that is a
+//! prefix of the topic, as a PUB socket would.
+
+use crate::sink::Sink;
+use crate::{info, warn};
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+const GREETING_LEN: usize = 64;
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+/// Subscriptions are messages starting with 1 (subscribe) or 0
+/// (unsubscribe) in ZMTP 3.0, and commands of their own since 3.1.
+const SUBSCRIBE: u8 = 1;
+const UNSUBSCRIBE: u8 = 0;
+
+struct Subscriber {
+    stream: TcpStream,
+    /// Topic prefixes, once per subscription as subscriptions are counted.
+    subscriptions: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Subscriber {
+    fn wants(&self, topic: &[u8]) -> bool {
+        let subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.iter().any(|prefix| topic.starts_with(prefix))
+    }
+}
+
+pub struct ZmqPublisher {
+    topic: String,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl ZmqPublisher {
+    pub fn bind(host: &str, port: u16, topic: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind((host, port))?;
+        info!(
+            "Publishing on tcp://{}:{} with topic '{}' (ZeroMQ)",
+            host, port, topic
+        );
+
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let accepted = Arc::clone(&subscribers);
+        thread::spawn(move || accept_subscribers(listener, accepted));
+
+        Ok(Self {
+            topic: topic.to_string(),
+            subscribers,
+        })
+    }
+}
+
+impl Sink for ZmqPublisher {
+    fn name(&self) -> &str {
+        "ZeroMQ"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let topic = self.topic.as_bytes();
+        let mut data = frame(MORE, topic);
+        data.extend(frame(0, &serde_json::to_vec(message)?));
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain_mut(|subscriber| {
+            if !subscriber.wants(topic) {
+                return true;
+            }
+            match subscriber.stream.write_all(&data) {
+                Ok(()) => true,
+                Err(e) => {
+                    info!(sink: "ZeroMQ", "Subscriber disconnected ({})", e);
+                    false
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        match self.subscribers.lock().unwrap().len() {
+            1 => "1 subscriber".to_string(),
+            subscribers => format!("{} subscribers", subscribers),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        for subscriber in self.subscribers.lock().unwrap().drain(..) {
+            let _ = subscriber.stream.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    }
+}
+
+fn accept_subscribers(listener: TcpListener, subscribers: Arc<Mutex<Vec<Subscriber>>>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(sink: "ZeroMQ", "Accepting subscriber failed: {}", e);
+                continue;
+            }
+        };
+
+        let subscribers = Arc::clone(&subscribers);
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_default();
+            if let Err(e) = serve_subscriber(stream, &subscribers) {
+                warn!(sink: "ZeroMQ", "Subscriber {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Performs the handshake, then follows the subscriber's subscriptions
+/// until it disconnects.
+fn serve_subscriber(mut stream: TcpStream, subscribers: &Mutex<Vec<Subscriber>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    stream.write_all(&greeting())?;
+    let mut peer_greeting = [0; GREETING_LEN];
+    stream.read_exact(&mut peer_greeting)?;
+    if peer_greeting[0] != 0xFF || peer_greeting[9] != 0x7F || peer_greeting[10] < 3 {
+        return Err(invalid("not a ZMTP 3 peer"));
+    }
+
+    stream.write_all(&ready_command())?;
+    let (flags, ready) = read_frame(&mut stream)?;
+    if flags & COMMAND == 0 || !ready.starts_with(b"\x05READY") {
+        return Err(invalid("expected READY"));
+    }
+    let socket_type = property(&ready[6..], "Socket-Type").unwrap_or_default();
+    if socket_type != b"SUB" && socket_type != b"XSUB" {
+        return Err(invalid("only SUB sockets can connect to a PUB socket"));
+    }
+
+    let subscriptions = Arc::new(Mutex::new(Vec::new()));
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = stream.try_clone()?;
+    info!(sink: "ZeroMQ", "Subscriber connected from {}", stream.peer_addr()?);
+    subscribers.lock().unwrap().push(Subscriber {
+        stream,
+        subscriptions: Arc::clone(&subscriptions),
+    });
+
+    // Ends with an error once the subscriber disconnects, which the
+    // publishing side notices on its own
+    while let Ok((flags, body)) = read_frame(&mut reader) {
+        let change = if flags & COMMAND != 0 {
+            if let Some(topic) = body.strip_prefix(b"\x09SUBSCRIBE") {
+                Some((SUBSCRIBE, topic))
+            } else {
+                body.strip_prefix(b"\x06CANCEL")
+                    .map(|topic| (UNSUBSCRIBE, topic))
+            }
+        } else {
+            body.split_first().map(|(kind, topic)| (*kind, topic))
+        };
+
+        let mut subscriptions = subscriptions.lock().unwrap();
+        match change {
+            Some((SUBSCRIBE, topic)) => subscriptions.push(topic.to_vec()),
+            Some((UNSUBSCRIBE, topic)) => {
+                if let Some(i) = subscriptions.iter().position(|known| known == topic) {
+                    subscriptions.swap_remove(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// ZMTP 3.0 greeting for the NULL mechanism, as the server.
+fn greeting() -> [u8; GREETING_LEN] {
+    let mut greeting = [0; GREETING_LEN];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+fn ready_command() -> Vec<u8> {
+    let mut body = b"\x05READY".to_vec();
+    body.push(b"Socket-Type".len() as u8);
+    body.extend_from_slice(b"Socket-Type");
+    body.extend_from_slice(&(b"PUB".len() as u32).to_be_bytes());
+    body.extend_from_slice(b"PUB");
+    frame(COMMAND, &body)
+}
+
+/// Looks up a property in the metadata of a READY command.
+fn property<'a>(mut metadata: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    while let Some((&name_len, rest)) = metadata.split_first() {
+        let name_len = usize::from(name_len);
+        let (key, rest) = (rest.get(..name_len)?, rest.get(name_len..)?);
+        let value_len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let value = rest.get(4..4 + value_len)?;
+        if key.eq_ignore_ascii_case(name.as_bytes()) {
+            return Some(value);
+        }
+        metadata = &rest[4 + value_len..];
+    }
+    None
+}
+
+fn frame(flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    match u8::try_from(body.len()) {
+        Ok(len) => frame.extend([flags, len]),
+        Err(_) => {
+            frame.push(flags | LONG);
+            frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(body);
+    frame
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0; 1];
+    stream.read_exact(&mut flags)?;
+    let len = if flags[0] & LONG != 0 {
+        let mut len = [0; 8];
+        stream.read_exact(&mut len)?;
+        u64::from_be_bytes(len)
+    } else {
+        let mut len = [0; 1];
+        stream.read_exact(&mut len)?;
+        u64::from(len[0])
+    };
+
+    let mut body = Vec::new();
+    stream.by_ref().take(len).read_to_end(&mut body)?;
+    if body.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((flags[0], body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    /// A SUB socket's READY command.
+    fn sub_ready() -> Vec<u8> {
+        let mut body = b"\x05READY".to_vec();
+        body.push(b"Socket-Type".len() as u8);
+        body.extend_from_slice(b"Socket-Type");
+        body.extend_from_slice(&3u32.to_be_bytes());
+        body.extend_from_slice(b"SUB");
+        frame(COMMAND, &body)
+    }
+
+    #[test]
+    fn the_greeting_offers_zmtp_3_with_the_null_mechanism() {
+        let greeting = greeting();
+        assert_eq!(greeting[..10], [0xFF, 0, 0, 0, 0, 0, 0, 0, 0, 0x7F]);
+        assert_eq!(greeting[10], 3);
+        assert_eq!(&greeting[12..32], b"NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        // The as-server flag, unused by NULL, and the filler
+        assert_eq!(greeting[32], 0);
+        assert!(greeting[33..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn the_ready_command_names_a_pub_socket() {
+        let (flags, body) = read_frame(&mut &ready_command()[..]).unwrap();
+        assert_eq!(flags, COMMAND);
+        assert_eq!(&body[..6], b"\x05READY");
+        assert_eq!(property(&body[6..], "socket-type"), Some(&b"PUB"[..]));
+        assert_eq!(property(&body[6..], "Identity"), None);
+    }
+
+    #[test]
+    fn a_broken_property_list_has_no_properties() {
+        // A value said to be longer than what's left
+        let metadata = [&[4][..], b"Name", &[0, 0, 0, 9], b"abc"].concat();
+        assert_eq!(property(&metadata, "Name"), None);
+    }
+
+    #[test]
+    fn frames_past_255_bytes_have_a_long_size() {
+        assert_eq!(frame(MORE, b"topic"), [&[MORE, 5][..], b"topic"].concat());
+
+        let body = vec![b'x'; 256];
+        let long = frame(0, &body);
+        assert_eq!(long[..9], [LONG, 0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(read_frame(&mut &long[..]).unwrap(), (LONG, body));
+    }
+
+    #[test]
+    fn a_cut_off_frame_is_an_error() {
+        let frame = frame(0, b"payload");
+        assert!(read_frame(&mut &frame[..frame.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn a_subscriber_gets_only_the_topics_it_asked_for() {
+        let port = TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut publisher = ZmqPublisher::bind("127.0.0.1", port, "microbit").unwrap();
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // The same as the server's, as NULL has no client or server
+        stream.write_all(&greeting()).unwrap();
+        let mut server_greeting = [0; GREETING_LEN];
+        stream.read_exact(&mut server_greeting).unwrap();
+        assert_eq!(server_greeting, greeting());
+        stream.write_all(&sub_ready()).unwrap();
+        let (flags, ready) = read_frame(&mut stream).unwrap();
+        assert_eq!(flags, COMMAND);
+        assert_eq!(property(&ready[6..], "Socket-Type"), Some(&b"PUB"[..]));
+
+        // A ZMTP 3.0 subscription message, for a prefix of the topic
+        stream.write_all(&frame(0, b"\x01micro")).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let subscribers = Arc::clone(&publisher.subscribers);
+        let subscribed = || {
+            let subscribers = subscribers.lock().unwrap();
+            subscribers.first().is_some_and(|s| s.wants(b"microbit"))
+        };
+        while !subscribed() {
+            assert!(Instant::now() < deadline, "subscription never arrived");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let message = json!({"x": 1.5, "y": -2.0, "z": 90.0});
+        publisher.send(&message).unwrap();
+        assert_eq!(
+            read_frame(&mut stream).unwrap(),
+            (MORE, b"microbit".to_vec())
+        );
+        let (flags, payload) = read_frame(&mut stream).unwrap();
+        assert_eq!(flags, 0);
+        assert_eq!(serde_json::from_slice::<Value>(&payload).unwrap(), message);
+
+        // Cancelled with a ZMTP 3.1 command, the topic isn't sent anymore
+        stream
+            .write_all(&frame(COMMAND, b"\x06CANCELmicro"))
+            .unwrap();
+        while subscribed() {
+            assert!(Instant::now() < deadline, "cancellation never arrived");
+            thread::sleep(Duration::from_millis(10));
+        }
+        publisher.send(&message).unwrap();
+        publisher.close().unwrap();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+}
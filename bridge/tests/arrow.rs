@@ -0,0 +1,132 @@
+use microblender_bridge::arrow::ArrowRecorder;
+use microblender_bridge::sink::Sink;
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+
+const MAGIC: &[u8] = b"ARROW1";
+const COLUMNS: [&str; 7] = [
+    "timestamp",
+    "pitch",
+    "roll",
+    "yaw",
+    "event",
+    "marker",
+    "device",
+];
+
+fn u32_at(buffer: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(buffer[at..at + 4].try_into().unwrap()) as usize
+}
+
+/// The position of field `id` of the flatbuffers table at `table`.
+fn field(buffer: &[u8], table: usize, id: usize) -> Option<usize> {
+    let back = i32::from_le_bytes(buffer[table..table + 4].try_into().unwrap());
+    let vtable = (table as i64 - i64::from(back)) as usize;
+    let vtable_length = u16::from_le_bytes([buffer[vtable], buffer[vtable + 1]]) as usize;
+    let slot = 4 + 2 * id;
+    if slot >= vtable_length {
+        return None;
+    }
+    let offset = u16::from_le_bytes([buffer[vtable + slot], buffer[vtable + slot + 1]]);
+    (offset != 0).then_some(table + offset as usize)
+}
+
+/// Follows the offset stored at `at`.
+fn follow(buffer: &[u8], at: usize) -> usize {
+    at + u32_at(buffer, at)
+}
+
+fn string(buffer: &[u8], at: usize) -> &str {
+    let length = u32_at(buffer, at);
+    std::str::from_utf8(&buffer[at + 4..at + 4 + length]).unwrap()
+}
+
+/// The column names of the schema table at `schema`.
+fn column_names(buffer: &[u8], schema: usize) -> Vec<&str> {
+    let fields = follow(buffer, field(buffer, schema, 1).unwrap());
+    (0..u32_at(buffer, fields))
+        .map(|index| {
+            let column = follow(buffer, fields + 4 + 4 * index);
+            string(buffer, follow(buffer, field(buffer, column, 0).unwrap()))
+        })
+        .collect()
+}
+
+/// The encapsulated message at `at`: its metadata as a flatbuffer.
+fn message(file: &[u8], at: usize) -> &[u8] {
+    assert_eq!(&file[at..at + 4], [0xFF; 4]);
+    let length = u32_at(file, at + 4);
+    &file[at + 8..at + 8 + length]
+}
+
+fn record(name: &str, close: bool) -> Vec<u8> {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "microblender-{}-{}.arrow",
+        name,
+        std::process::id()
+    ));
+    let mut recorder = ArrowRecorder::create(&path).unwrap();
+    recorder
+        .send(&json!({"x": 1.0, "y": 2.0, "z": 3.0, "seq": 0}))
+        .unwrap();
+    recorder.send(&json!({"event": "shake"})).unwrap();
+    recorder.mark("start").unwrap();
+    if close {
+        recorder.close().unwrap();
+    }
+    drop(recorder);
+
+    let file = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    file
+}
+
+/// Checks the magic, schema, record batch and footer of a recording of
+/// three rows.
+fn assert_readable(file: &[u8]) {
+    assert_eq!(&file[..6], MAGIC);
+    assert_eq!(&file[file.len() - 6..], MAGIC);
+
+    let schema_message = message(file, 8);
+    let root = follow(schema_message, 0);
+    let header_type = field(schema_message, root, 1).unwrap();
+    assert_eq!(schema_message[header_type], 1);
+    let schema = follow(schema_message, field(schema_message, root, 2).unwrap());
+    assert_eq!(column_names(schema_message, schema), COLUMNS);
+
+    let footer_length = u32_at(file, file.len() - 10);
+    let footer_start = file.len() - 10 - footer_length;
+    assert_eq!(
+        &file[footer_start - 8..footer_start],
+        [0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]
+    );
+    let footer = &file[footer_start..file.len() - 10];
+    let root = follow(footer, 0);
+    let schema = follow(footer, field(footer, root, 1).unwrap());
+    assert_eq!(column_names(footer, schema), COLUMNS);
+
+    let blocks = follow(footer, field(footer, root, 3).unwrap());
+    assert_eq!(u32_at(footer, blocks), 1);
+    let offset = u64::from_le_bytes(footer[blocks + 4..blocks + 12].try_into().unwrap());
+    let batch_message = message(file, offset as usize);
+    let root = follow(batch_message, 0);
+    let header_type = field(batch_message, root, 1).unwrap();
+    assert_eq!(batch_message[header_type], 3);
+    let batch = follow(batch_message, field(batch_message, root, 2).unwrap());
+    let rows = field(batch_message, batch, 0).unwrap();
+    assert_eq!(
+        i64::from_le_bytes(batch_message[rows..rows + 8].try_into().unwrap()),
+        3
+    );
+}
+
+#[test]
+fn a_closed_recording_is_readable() {
+    assert_readable(&record("closed", true));
+}
+
+#[test]
+fn a_recording_dropped_without_closing_gets_its_footer() {
+    assert_readable(&record("dropped", false));
+}
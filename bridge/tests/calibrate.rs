@@ -0,0 +1,37 @@
+use microblender_bridge::calibrate::Hold;
+
+#[test]
+fn a_board_held_still_gives_its_mean_and_noise() {
+    let mut hold = Hold::new(4, 1.0);
+    assert_eq!(hold.push([10.0, -5.0, 90.0]), None);
+    assert_eq!(hold.push([10.2, -5.2, 90.2]), None);
+    assert_eq!(hold.push([9.8, -4.8, 89.8]), None);
+    let reading = hold.push([10.0, -5.0, 90.0]).unwrap();
+
+    for (mean, expected) in reading.mean.iter().zip([10.0, -5.0, 90.0]) {
+        assert!((mean - expected).abs() < 1e-9);
+    }
+    for noise in reading.noise {
+        assert!((noise - 0.2f64 / 2f64.sqrt()).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn moving_the_board_starts_over() {
+    let mut hold = Hold::new(3, 1.0);
+    assert_eq!(hold.push([0.0, 0.0, 0.0]), None);
+    assert_eq!(hold.push([0.5, 0.0, 0.0]), None);
+    // Moved, this is the first sample of the next try
+    assert_eq!(hold.push([20.0, 0.0, 0.0]), None);
+    assert_eq!(hold.push([20.0, 0.0, 0.0]), None);
+    let reading = hold.push([20.0, 0.0, 0.0]).unwrap();
+    assert_eq!(reading.mean, [20.0, 0.0, 0.0]);
+}
+
+#[test]
+fn the_yaw_may_wrap_around_while_still() {
+    let mut hold = Hold::new(2, 1.0);
+    assert_eq!(hold.push([0.0, 0.0, 179.8]), None);
+    let reading = hold.push([0.0, 0.0, -179.8]).unwrap();
+    assert!((reading.mean[2].abs() - 180.0).abs() < 1e-9);
+}
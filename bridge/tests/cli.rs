@@ -0,0 +1,82 @@
+use clap::Parser;
+use microblender_bridge::cli::{self, Args, Command, ServiceAction};
+
+#[test]
+fn options_can_follow_the_command() {
+    let args = Args::parse_from(["bridge", "run", "--blender", "--simulate"]);
+    assert!(matches!(args.command, Some(Command::Run)));
+    assert!(args.blender);
+    assert!(args.simulate.is_some());
+
+    let args = Args::parse_from(["bridge", "--blender", "monitor", "--visualizer"]);
+    assert!(matches!(args.command, Some(Command::Monitor)));
+    assert!(args.blender && args.visualizer);
+}
+
+#[test]
+fn the_options_of_a_service_are_checked_with_the_command() {
+    let args = Args::parse_from(["bridge", "service", "install", "--listen", "7000"]);
+    assert!(matches!(
+        args.command,
+        Some(Command::Service {
+            action: ServiceAction::Install
+        })
+    ));
+    assert_eq!(args.listen, Some(7000));
+    assert!(Args::try_parse_from(["bridge", "service", "install", "--no-such-option"]).is_err());
+}
+
+#[test]
+fn calibrate_has_options_of_its_own_besides_the_shared_ones() {
+    let args = Args::parse_from([
+        "bridge",
+        "calibrate",
+        "--port",
+        "/dev/ttyACM0",
+        "--samples",
+        "50",
+    ]);
+    let Some(Command::Calibrate { options }) = args.command else {
+        panic!("expected calibrate");
+    };
+    assert_eq!(options.samples, 50);
+    assert_eq!(args.port, ["/dev/ttyACM0"]);
+    assert!(args.commands.is_empty());
+}
+
+#[test]
+fn every_command_parses_its_help() {
+    for command in [
+        "run",
+        "monitor",
+        "calibrate",
+        "record",
+        "replay",
+        "latency",
+        "export",
+        "bake",
+        "list-ports",
+        "selftest",
+        "discover",
+        "service",
+    ] {
+        let error = Args::try_parse_from(["bridge", command, "--help"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::DisplayHelp);
+    }
+}
+
+#[test]
+fn bake_keeps_its_own_format() {
+    let args = Args::parse_from(["bridge", "bake", "in.jsonl", "out.py", "--format", "json"]);
+    assert!(matches!(args.command, Some(Command::Bake { .. })));
+    assert!(args.formats.is_empty());
+}
+
+#[test]
+fn the_panel_needs_the_gui_feature() {
+    let args = Args::parse_from(["bridge", "--simulate", "--gui"]);
+    assert_eq!(
+        cli::validate_configuration(&args).is_ok(),
+        cfg!(feature = "gui")
+    );
+}
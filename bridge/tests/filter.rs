@@ -0,0 +1,143 @@
+use microblender_bridge::filter::{FilterPipeline, FilterSpec};
+use serde_json::json;
+
+fn pipeline(specs: &[&str]) -> FilterPipeline {
+    let specs: Vec<FilterSpec> = specs.iter().map(|spec| spec.parse().unwrap()).collect();
+    FilterPipeline::new(&specs)
+}
+
+/// Filters `values` as the `field` of consecutive messages.
+fn filter_field(specs: &[&str], field: &str, values: &[f64]) -> Vec<f64> {
+    let mut pipeline = pipeline(specs);
+    values
+        .iter()
+        .map(|&value| {
+            let mut message = json!({ field: value });
+            pipeline.apply(&mut message);
+            message[field].as_f64().unwrap()
+        })
+        .collect()
+}
+
+fn filter(specs: &[&str], values: &[f64]) -> Vec<f64> {
+    filter_field(specs, "x", values)
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+#[test]
+fn hampel_modes_are_hold_and_clamp() {
+    let spec: FilterSpec = "hampel".parse().unwrap();
+    assert_eq!(spec.to_string(), "hampel:window=7,k=3,min=5,mode=hold");
+    let spec: FilterSpec = "hampel:mode=clamp".parse().unwrap();
+    assert_eq!(spec.to_string(), "hampel:window=7,k=3,min=5,mode=clamp");
+    assert!("hampel:mode=drop".parse::<FilterSpec>().is_err());
+}
+
+#[test]
+fn ema_moves_part_of_the_way_to_each_value() {
+    let output = filter(&["ema:alpha=0.5"], &[0.0, 10.0, 10.0, 10.0]);
+    assert_eq!(output, [0.0, 5.0, 7.5, 8.75]);
+}
+
+#[test]
+fn ema_alphas_can_differ_per_axis() {
+    let mut pipeline = pipeline(&["ema:alpha=0.5,z=1"]);
+    let mut first = json!({"x": 0.0, "z": 0.0});
+    pipeline.apply(&mut first);
+    let mut second = json!({"x": 10.0, "z": 10.0});
+    pipeline.apply(&mut second);
+    assert_eq!(second, json!({"x": 5.0, "z": 10.0}));
+}
+
+#[test]
+fn ema_follows_the_yaw_across_180_degrees() {
+    let output = filter_field(&["ema:alpha=0.25"], "z", &[170.0, -170.0]);
+    assert!(close(output[1], 175.0), "{:?}", output);
+}
+
+#[test]
+fn every_device_is_filtered_on_its_own() {
+    let mut pipeline = pipeline(&["ema:alpha=0.5"]);
+    let mut outputs = Vec::new();
+    for (device, x) in [("a", 0.0), ("b", 100.0), ("a", 10.0), ("b", 100.0)] {
+        let mut message = json!({"device": device, "x": x});
+        pipeline.apply(&mut message);
+        outputs.push(message["x"].as_f64().unwrap());
+    }
+    assert_eq!(outputs, [0.0, 100.0, 5.0, 100.0]);
+}
+
+#[test]
+fn kalman_keeps_a_steady_value() {
+    let output = filter(&["kalman"], &[12.5; 20]);
+    assert!(output.iter().all(|&value| close(value, 12.5)));
+}
+
+#[test]
+fn kalman_approaches_a_step_without_overshooting() {
+    let mut values = vec![0.0];
+    values.extend([10.0; 50]);
+    let output = filter(&["kalman:q=0.5,r=4"], &values);
+
+    // The first value leaves an error of 4.5 * 4 / 8.5, plus q before the
+    // step that's a gain of 89 / 225
+    assert!(close(output[1], 10.0 * 89.0 / 225.0), "{:?}", output);
+    assert!(output.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert!(output.iter().all(|&value| value <= 10.0));
+    assert!((output[50] - 10.0).abs() < 0.01);
+}
+
+#[test]
+fn kalman_trusts_precise_measurements_more() {
+    let precise = filter(&["kalman:r=0.5"], &[0.0, 10.0]);
+    let noisy = filter(&["kalman:r=10"], &[0.0, 10.0]);
+    assert!(precise[1] > noisy[1]);
+}
+
+#[test]
+fn deadzone_holds_while_the_input_stays_within_the_threshold() {
+    let output = filter(&["deadzone:threshold=1"], &[0.0, 0.5, -0.8, 0.9, -1.0]);
+    assert_eq!(output, [0.0; 5]);
+}
+
+#[test]
+fn deadzone_follows_a_movement_until_it_settles() {
+    let values = [0.0, 2.0, 3.0, 3.1, 3.0, 3.2, 3.1, 3.0, 3.8, 3.6];
+    let output = filter(&["deadzone:threshold=1,release=0.5"], &values);
+    // Followed while moving and settling, then held where it settled
+    assert_eq!(output, [0.0, 2.0, 3.0, 3.1, 3.0, 3.2, 3.1, 3.0, 3.0, 3.0]);
+}
+
+#[test]
+fn hampel_holds_the_last_value_in_place_of_a_spike() {
+    let output = filter(
+        &["hampel:window=5"],
+        &[10.0, 10.0, 10.0, 10.0, 10.0, 100.0, 11.0],
+    );
+    assert_eq!(output, [10.0, 10.0, 10.0, 10.0, 10.0, 10.0, 11.0]);
+}
+
+#[test]
+fn hampel_clamp_pulls_a_spike_back_to_the_limit() {
+    let values = [10.0, 10.0, 10.0, 10.0, 10.0, 100.0, -100.0];
+    let output = filter(&["hampel:window=5,min=5,mode=clamp"], &values);
+    assert_eq!(output[5..], [15.0, 5.0]);
+}
+
+#[test]
+fn hampel_passes_values_within_the_spread_of_the_window() {
+    // A median of 4 and a deviation of 2, so a limit of about 8.9
+    let values = [0.0, 4.0, 8.0, 2.0, 6.0, 12.0];
+    let output = filter(&["hampel:window=5,min=0"], &values);
+    assert_eq!(output, values);
+}
+
+#[test]
+fn hampel_accepts_a_real_jump_once_it_fills_half_the_window() {
+    let values = [10.0, 10.0, 10.0, 10.0, 10.0, 50.0, 50.0, 50.0, 50.0];
+    let output = filter(&["hampel:window=5"], &values);
+    assert_eq!(output[5..], [10.0, 10.0, 10.0, 50.0]);
+}
@@ -0,0 +1,190 @@
+use microblender_bridge::crc::crc16;
+use microblender_bridge::framing::{split_lines, Framer, MAX_FRAME_LEN};
+use microblender_bridge::serial::WireFormat;
+
+#[test]
+fn every_line_of_a_read_is_returned() {
+    let mut buffer = Vec::new();
+    let lines = split_lines(b"{\"seq\":0}\n{\"seq\":1}\n{\"seq\":2}\n", &mut buffer);
+    assert_eq!(lines, ["{\"seq\":0}", "{\"seq\":1}", "{\"seq\":2}"]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn an_incomplete_line_waits_for_the_next_read() {
+    let mut buffer = Vec::new();
+    assert_eq!(
+        split_lines(b"{\"seq\":0}\n{\"se", &mut buffer),
+        ["{\"seq\":0}"]
+    );
+    assert_eq!(buffer, b"{\"se");
+    assert_eq!(split_lines(b"q\":1}\n", &mut buffer), ["{\"seq\":1}"]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn a_read_without_a_newline_returns_nothing() {
+    let mut buffer = Vec::new();
+    assert!(split_lines(b"{\"seq\":", &mut buffer).is_empty());
+    assert!(split_lines(b"0}", &mut buffer).is_empty());
+    assert_eq!(split_lines(b"\n", &mut buffer), ["{\"seq\":0}"]);
+}
+
+#[test]
+fn line_endings_and_blank_lines_are_dropped() {
+    let mut buffer = Vec::new();
+    let lines = split_lines(b"{\"seq\":0}\r\n\r\n\n  {\"seq\":1}  \r\n", &mut buffer);
+    assert_eq!(lines, ["{\"seq\":0}", "{\"seq\":1}"]);
+}
+
+#[test]
+fn a_character_split_across_reads_survives() {
+    let mut buffer = Vec::new();
+    let text = "{\"event\":\"h\u{e9}\"}\n".as_bytes();
+    let split = text.iter().position(|&byte| byte == 0xC3).unwrap() + 1;
+    assert!(split_lines(&text[..split], &mut buffer).is_empty());
+    assert_eq!(
+        split_lines(&text[split..], &mut buffer),
+        ["{\"event\":\"h\u{e9}\"}"]
+    );
+}
+
+#[test]
+fn the_framer_drains_every_json_message() {
+    let mut framer = Framer::new(WireFormat::Json);
+    let messages = framer.push(b"{\"seq\":0}\n{\"seq\":1}\n{\"seq\":2");
+    assert_eq!(
+        messages,
+        [Ok("{\"seq\":0}".to_string()), Ok("{\"seq\":1}".to_string())]
+    );
+    let messages = framer.push(b"}\n");
+    assert_eq!(messages, [Ok("{\"seq\":2}".to_string())]);
+}
+
+#[test]
+fn the_framer_forgets_a_partial_message_when_cleared() {
+    let mut framer = Framer::new(WireFormat::Json);
+    assert!(framer.push(b"{\"seq\":0,\"x\":1").is_empty());
+    framer.clear();
+    assert_eq!(
+        framer.push(b"{\"seq\":1}\n"),
+        [Ok("{\"seq\":1}".to_string())]
+    );
+}
+
+#[test]
+fn the_framer_drains_every_cbor_frame() {
+    let mut framer = Framer::new(WireFormat::Cbor);
+    // {"seq":0} and {"seq":1} as CBOR, COBS encoded and delimited by zeros
+    let frames = [
+        0x06, 0xA1, 0x63, b's', b'e', b'q', 0x01, 0x00, //
+        0x07, 0xA1, 0x63, b's', b'e', b'q', 0x01, 0x00,
+    ];
+    let messages = framer.push(&frames);
+    assert_eq!(
+        messages,
+        [Ok("{\"seq\":0}".to_string()), Ok("{\"seq\":1}".to_string())]
+    );
+}
+
+#[test]
+fn an_overlong_line_is_skipped_up_to_the_next_newline() {
+    let mut framer = Framer::new(WireFormat::Json);
+    let garbage = vec![b'#'; MAX_FRAME_LEN + 1];
+    let messages = framer.push(&garbage);
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].is_err());
+    // The rest of the overlong line is dropped as well
+    assert!(framer.push(b"more garbage\n").is_empty());
+    assert_eq!(
+        framer.push(b"{\"seq\":1}\n"),
+        [Ok("{\"seq\":1}".to_string())]
+    );
+    assert_eq!(framer.resyncs(), 1);
+}
+
+#[test]
+fn an_overlong_line_in_one_read_leaves_the_next_line_intact() {
+    let mut framer = Framer::new(WireFormat::Json);
+    let mut data = vec![b'#'; MAX_FRAME_LEN * 3];
+    data.extend_from_slice(b"\n{\"seq\":1}\n");
+    let messages = framer.push(&data);
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].is_err());
+    assert_eq!(messages[1], Ok("{\"seq\":1}".to_string()));
+    assert_eq!(framer.resyncs(), 1);
+}
+
+#[test]
+fn a_line_joined_midway_costs_only_that_line() {
+    let mut framer = Framer::new(WireFormat::Json);
+    let messages = framer.push(b"q\":0}\n{\"seq\":1}\n");
+    assert_eq!(
+        messages,
+        [Ok("q\":0}".to_string()), Ok("{\"seq\":1}".to_string())]
+    );
+    assert_eq!(framer.resyncs(), 0);
+}
+
+#[test]
+fn an_overlong_cbor_frame_is_skipped_up_to_the_next_zero() {
+    let mut framer = Framer::new(WireFormat::Cbor);
+    let mut data = vec![0xFF; MAX_FRAME_LEN + 1];
+    data.extend_from_slice(&[0x00, 0x06, 0xA1, 0x63, b's', b'e', b'q', 0x01, 0x00]);
+    let messages = framer.push(&data);
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].is_err());
+    assert_eq!(messages[1], Ok("{\"seq\":0}".to_string()));
+    assert_eq!(framer.resyncs(), 1);
+}
+
+/// A binary frame of the given type and payload, with sync bytes and CRC.
+fn binary_frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut body = vec![kind];
+    body.extend_from_slice(payload);
+    let mut frame = vec![0xAA, 0x55];
+    frame.extend_from_slice(&body);
+    frame.extend_from_slice(&crc16(&body).to_le_bytes());
+    frame
+}
+
+#[test]
+fn the_framer_decodes_binary_frames_split_across_reads() {
+    let mut framer = Framer::new(WireFormat::Binary);
+    let mut payload = Vec::new();
+    for value in [1234i16, -550, 18000, 16384, 0, -1, 200, -300, 400] {
+        payload.extend_from_slice(&value.to_le_bytes());
+    }
+    payload.extend_from_slice(&7u32.to_le_bytes());
+    let mut data = binary_frame(0x01, &payload);
+    data.extend(binary_frame(0x03, b"\x0e{\"ack\":\"tare\"}"));
+
+    assert!(framer.push(&data[..10]).is_empty());
+    let messages = framer.push(&data[10..]);
+    assert_eq!(
+        messages,
+        [
+            Ok(concat!(
+                "{\"ax\":16384,\"ay\":0,\"az\":-1,\"mx\":200,\"my\":-300,\"mz\":400,",
+                "\"seq\":7,\"x\":12.34,\"y\":-5.5,\"z\":180.0}"
+            )
+            .to_string()),
+            Ok("{\"ack\":\"tare\"}".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn a_corrupted_binary_frame_costs_only_that_frame() {
+    let mut framer = Framer::new(WireFormat::Binary);
+    let mut corrupted = binary_frame(0x03, b"\x09{\"seq\":0}");
+    corrupted[6] ^= 0x01;
+    // Joined midway, after the garbage a corrupted frame and a good one
+    let mut data = vec![0x12, 0x55, 0xAA];
+    data.extend(corrupted);
+    data.extend(binary_frame(0x03, b"\x09{\"seq\":1}"));
+    let messages = framer.push(&data);
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].is_err());
+    assert_eq!(messages[1], Ok("{\"seq\":1}".to_string()));
+}
@@ -0,0 +1,160 @@
+use microblender_bridge::command::BoardLink;
+use microblender_bridge::grpc::GrpcServer;
+use microblender_bridge::http2::{self, HpackDecoder};
+use microblender_bridge::protobuf;
+use microblender_bridge::sink::Sink;
+use serde_json::json;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A gRPC server on a port of its own, and a client connected to it.
+fn connect() -> (GrpcServer, Client) {
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server = GrpcServer::bind("127.0.0.1", port, BoardLink::default()).unwrap();
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    std::io::Write::write_all(&mut stream, http2::PREFACE).unwrap();
+    http2::write_frame(&mut stream, http2::SETTINGS, 0, 0, &[]).unwrap();
+    let client = Client {
+        stream,
+        decoder: HpackDecoder::default(),
+    };
+    (server, client)
+}
+
+struct Client {
+    stream: TcpStream,
+    decoder: HpackDecoder,
+}
+
+/// What the server sent on a stream.
+#[derive(Default)]
+struct Response {
+    headers: Vec<(String, String)>,
+    messages: Vec<Vec<u8>>,
+    ended: bool,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Client {
+    fn call(&mut self, id: u32, path: &str, body: Option<&[u8]>) {
+        let block = http2::encode_headers(&[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", path),
+            ("content-type", "application/grpc"),
+        ]);
+        let flags = match body {
+            Some(_) => http2::END_HEADERS,
+            None => http2::END_HEADERS | http2::END_STREAM,
+        };
+        http2::write_frame(&mut self.stream, http2::HEADERS, flags, id, &block).unwrap();
+        if let Some(body) = body {
+            let mut data = vec![0];
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            data.extend_from_slice(body);
+            http2::write_frame(&mut self.stream, http2::DATA, http2::END_STREAM, id, &data)
+                .unwrap();
+        }
+    }
+
+    /// Reads frames until stream `id` has `messages` messages, or ended.
+    fn receive(&mut self, id: u32, messages: usize) -> Response {
+        let mut response = Response::default();
+        while !response.ended && response.messages.len() < messages {
+            let frame = http2::read_frame(&mut self.stream).unwrap();
+            if frame.stream != id {
+                continue;
+            }
+            match frame.kind {
+                http2::HEADERS => {
+                    let block = frame.content().unwrap();
+                    response.headers.extend(self.decoder.decode(block).unwrap());
+                }
+                http2::DATA => {
+                    let content = frame.content().unwrap();
+                    assert_eq!(content[0], 0, "compressed message");
+                    response.messages.push(content[5..].to_vec());
+                }
+                _ => {}
+            }
+            response.ended = frame.flags & http2::END_STREAM != 0;
+        }
+        response
+    }
+}
+
+#[test]
+fn a_control_call_is_answered_with_a_reply() {
+    let (_server, mut client) = connect();
+    // ControlRequest { rate_hz: 10 }
+    let request = [&[0x11][..], &10.0f64.to_le_bytes()].concat();
+    client.call(1, "/microblender.Bridge/Control", Some(&request));
+
+    let response = client.receive(1, usize::MAX);
+    assert!(response.ended);
+    assert_eq!(response.header(":status"), Some("200"));
+    assert_eq!(response.header("grpc-status"), Some("0"));
+    // ControlReply { ok: true, message: ... }
+    let text = b"Streaming at up to 10 Hz";
+    let reply = [&[0x08, 0x01, 0x12, text.len() as u8][..], text].concat();
+    assert_eq!(response.messages, [reply]);
+}
+
+#[test]
+fn an_unknown_method_is_unimplemented() {
+    let (_server, mut client) = connect();
+    client.call(1, "/microblender.Bridge/Nothing", Some(&[]));
+
+    let response = client.receive(1, usize::MAX);
+    assert!(response.ended);
+    assert_eq!(response.header("grpc-status"), Some("12"));
+    assert!(response.messages.is_empty());
+}
+
+#[test]
+fn a_stream_gets_every_message_until_the_server_closes() {
+    let (mut server, mut client) = connect();
+    client.call(1, "/microblender.Bridge/StreamOrientation", None);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.status() != "1 stream" {
+        assert!(Instant::now() < deadline, "stream never started");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let messages = [
+        json!({"x": 1.5, "y": -2.0, "z": 90.0, "seq": 1}),
+        json!({"x": 2.5, "y": -3.0, "z": 91.0, "seq": 2}),
+    ];
+    for message in &messages {
+        server.send(message).unwrap();
+    }
+    let response = client.receive(1, messages.len());
+    assert_eq!(response.header("content-type"), Some("application/grpc"));
+    let expected: Vec<Vec<u8>> = messages
+        .iter()
+        .map(|message| protobuf::encode_orientation(message).unwrap())
+        .collect();
+    assert_eq!(response.messages, expected);
+
+    server.close().unwrap();
+    let trailers = client.receive(1, usize::MAX);
+    assert!(trailers.ended);
+    assert_eq!(trailers.header("grpc-status"), Some("0"));
+}
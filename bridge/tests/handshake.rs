@@ -0,0 +1,51 @@
+use clap::Parser;
+use microblender_bridge::cli::Args;
+use microblender_bridge::format::Format;
+use microblender_bridge::handshake;
+use serde_json::json;
+
+fn hello(args: &[&str], formats: &[&str]) -> serde_json::Value {
+    let args = Args::parse_from(["bridge"].iter().chain(args));
+    let formats: Vec<Format> = formats.iter().map(|f| f.parse().unwrap()).collect();
+    handshake::hello(&args, "Blender", &formats)["hello"].clone()
+}
+
+#[test]
+fn the_hello_lists_what_the_board_sends() {
+    let hello = hello(&["--port", "/dev/ttyACM0"], &[]);
+    assert_eq!(
+        hello["fields"],
+        json!(["x", "y", "z", "ax", "ay", "az", "mx", "my", "mz", "seq"])
+    );
+    assert_eq!(
+        hello["optional"],
+        json!(["qw", "qx", "qy", "qz", "gx", "gy", "gz"])
+    );
+    assert_eq!(hello["devices"], json!(["/dev/ttyACM0"]));
+}
+
+#[test]
+fn the_fields_are_named_as_the_sinks_format_has_them() {
+    let renamed = hello(
+        &["--timestamp", "wall"],
+        &["pitch=x,roll=y,yaw=z,-seq", "Visualizer:+x"],
+    );
+    assert_eq!(
+        renamed["fields"],
+        json!(["ax", "ay", "az", "mx", "my", "mz", "t_wall", "pitch", "roll", "yaw"])
+    );
+
+    let kept = hello(&[], &["Blender:+x,+y,+z,+gx,gy_rate=gy"]);
+    assert_eq!(kept["fields"], json!(["x", "y", "z"]));
+    assert_eq!(kept["optional"], json!(["gx", "gy_rate"]));
+}
+
+#[test]
+fn several_boards_tag_their_messages() {
+    let hello = hello(&["--port", "/dev/ttyACM0,/dev/ttyACM1"], &[]);
+    assert!(hello["fields"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("device")));
+    assert_eq!(hello["devices"], json!(["/dev/ttyACM0", "/dev/ttyACM1"]));
+}
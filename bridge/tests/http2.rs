@@ -0,0 +1,152 @@
+use microblender_bridge::http2::{self, HpackDecoder};
+
+/// Parses the hex dumps of RFC 7541, Appendix C.
+fn hex(dump: &str) -> Vec<u8> {
+    let digits: Vec<u8> = dump.bytes().filter(u8::is_ascii_hexdigit).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn a_written_frame_reads_back_the_same() {
+    let mut wire = Vec::new();
+    http2::write_frame(&mut wire, http2::DATA, http2::END_STREAM, 3, b"hello").unwrap();
+    assert_eq!(&wire[..9], &[0, 0, 5, 0x0, 0x1, 0, 0, 0, 3]);
+
+    let frame = http2::read_frame(&mut &wire[..]).unwrap();
+    assert_eq!(frame.kind, http2::DATA);
+    assert_eq!(frame.flags, http2::END_STREAM);
+    assert_eq!(frame.stream, 3);
+    assert_eq!(frame.payload, b"hello");
+}
+
+#[test]
+fn the_reserved_bit_of_the_stream_id_is_ignored() {
+    let wire = [0, 0, 0, http2::PING, 0, 0x80, 0, 0, 1];
+    assert_eq!(http2::read_frame(&mut &wire[..]).unwrap().stream, 1);
+}
+
+#[test]
+fn frames_larger_than_the_default_maximum_are_refused() {
+    let mut wire = Vec::new();
+    let payload = vec![0; http2::MAX_FRAME_SIZE + 1];
+    http2::write_frame(&mut wire, http2::DATA, 0, 1, &payload).unwrap();
+    assert!(http2::read_frame(&mut &wire[..]).is_err());
+}
+
+#[test]
+fn the_content_leaves_out_padding_and_priority() {
+    let mut wire = Vec::new();
+    // Pad length, stream dependency and weight, the block, then padding
+    let payload = [&[2][..], &[0, 0, 0, 0, 16], b"block", &[0, 0]].concat();
+    let flags = http2::END_HEADERS | http2::PADDED | http2::PRIORITY;
+    http2::write_frame(&mut wire, http2::HEADERS, flags, 1, &payload).unwrap();
+
+    let frame = http2::read_frame(&mut &wire[..]).unwrap();
+    assert_eq!(frame.content().unwrap(), b"block");
+}
+
+#[test]
+fn padding_longer_than_the_frame_is_malformed() {
+    let mut wire = Vec::new();
+    http2::write_frame(&mut wire, http2::DATA, http2::PADDED, 1, &[9, 1, 2]).unwrap();
+    let frame = http2::read_frame(&mut &wire[..]).unwrap();
+    assert!(frame.content().is_err());
+}
+
+#[test]
+fn encoded_headers_decode_to_the_same() {
+    // A value past 127 bytes takes more than the prefix for its length
+    let long = "x".repeat(300);
+    let sent = [
+        (":status", "200"),
+        ("content-type", "application/grpc"),
+        ("grpc-message", long.as_str()),
+        ("grpc-status", ""),
+    ];
+    let block = http2::encode_headers(&sent);
+    assert_eq!(
+        HpackDecoder::default().decode(&block).unwrap(),
+        headers(&sent)
+    );
+}
+
+#[test]
+fn requests_share_the_dynamic_table() {
+    // RFC 7541, C.3
+    let mut decoder = HpackDecoder::default();
+    let first = hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d");
+    assert_eq!(
+        decoder.decode(&first).unwrap(),
+        headers(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ])
+    );
+
+    let second = hex("8286 84be 5808 6e6f 2d63 6163 6865");
+    assert_eq!(
+        decoder.decode(&second).unwrap(),
+        headers(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ])
+    );
+
+    let third = hex("8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65");
+    assert_eq!(
+        decoder.decode(&third).unwrap(),
+        headers(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ])
+    );
+}
+
+#[test]
+fn huffman_coded_strings_are_decoded() {
+    // RFC 7541, C.4
+    let mut decoder = HpackDecoder::default();
+    let first = hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff");
+    assert_eq!(
+        decoder.decode(&first).unwrap(),
+        headers(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ])
+    );
+
+    let second = hex("8286 84be 5886 a8eb 1064 9cbf");
+    assert_eq!(
+        decoder.decode(&second).unwrap()[4],
+        ("cache-control".to_string(), "no-cache".to_string())
+    );
+}
+
+#[test]
+fn broken_header_blocks_are_refused() {
+    let mut decoder = HpackDecoder::default();
+    // Index 0, an index past both tables, and a string cut short
+    assert!(decoder.decode(&[0x80]).is_err());
+    assert!(decoder.decode(&[0xFF, 0x00]).is_err());
+    assert!(decoder.decode(&[0x00, 0x05, b'a']).is_err());
+}
@@ -0,0 +1,62 @@
+use clap::Parser;
+use microblender_bridge::cli::Args;
+use microblender_bridge::command::BoardLink;
+use microblender_bridge::manager::ConnectionManager;
+use serde_json::{json, Value};
+use std::fs;
+
+#[test]
+fn a_recording_can_be_started_and_stopped_while_running() {
+    let path =
+        std::env::temp_dir().join(format!("microblender-panel-{}.jsonl", std::process::id()));
+    let mut connections =
+        ConnectionManager::new(&Args::parse_from(["bridge"]), &BoardLink::default()).unwrap();
+    connections.forward_data(&json!({"x": 1.0})).unwrap();
+
+    connections.start_recording(&path).unwrap();
+    assert_eq!(connections.recording(), Some(path.as_path()));
+    assert!(connections.start_recording(&path).is_err());
+    connections.forward_data(&json!({"x": 2.0})).unwrap();
+    assert_eq!(connections.stop_recording().unwrap(), Some(path.clone()));
+    assert_eq!(connections.recording(), None);
+    assert_eq!(connections.stop_recording().unwrap(), None);
+    connections.forward_data(&json!({"x": 3.0})).unwrap();
+
+    let recorded: Vec<Value> = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["data"].clone())
+        .collect();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(recorded, [json!({"x": 2.0})]);
+}
+
+#[test]
+fn the_record_command_records_the_messages_before_any_conversion() {
+    let path =
+        std::env::temp_dir().join(format!("microblender-record-{}.jsonl", std::process::id()));
+    let args = Args::parse_from([
+        "bridge",
+        "--units",
+        "rad",
+        "--queue",
+        "size=1,overflow=drop-newest",
+        "record",
+        path.to_str().unwrap(),
+        "--no-forward",
+    ]);
+    let mut connections = ConnectionManager::new(&args, &BoardLink::default()).unwrap();
+    for x in 0..100 {
+        connections.forward_data(&json!({ "x": x })).unwrap();
+    }
+    connections.close();
+
+    let recorded: Vec<Value> = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["data"].clone())
+        .collect();
+    fs::remove_file(&path).unwrap();
+    let expected: Vec<Value> = (0..100).map(|x| json!({ "x": x })).collect();
+    assert_eq!(recorded, expected);
+}
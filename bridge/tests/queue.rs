@@ -0,0 +1,117 @@
+use microblender_bridge::queue::{self, OverflowPolicy, QueueSpec};
+use microblender_bridge::sink::Sink;
+use serde_json::{json, Value};
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Waits for a go before each job, and notes what it was given.
+struct GatedSink {
+    gate: Receiver<()>,
+    seen: Seen,
+}
+
+impl GatedSink {
+    fn note(&mut self, job: String) {
+        self.gate.recv().unwrap();
+        self.seen.lock().unwrap().push(job);
+    }
+}
+
+impl Sink for GatedSink {
+    fn name(&self) -> &str {
+        "Gated"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.note(message["seq"].to_string());
+        Ok(())
+    }
+
+    fn mark(&mut self, label: &str) -> io::Result<()> {
+        self.note(label.to_string());
+        Ok(())
+    }
+}
+
+/// What a `GatedSink` was given, in order.
+type Seen = Arc<Mutex<Vec<String>>>;
+
+fn gated(capacity: usize) -> (Box<dyn Sink>, Sender<()>, Seen) {
+    let (sink, go, seen) = gate();
+    let sink = queue::wrap(sink, capacity, OverflowPolicy::DropOldest, &[]);
+    (sink, go, seen)
+}
+
+fn gate() -> (Box<dyn Sink>, Sender<()>, Seen) {
+    let (go, gate) = mpsc::channel();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = GatedSink {
+        gate,
+        seen: Arc::clone(&seen),
+    };
+    (Box::new(sink), go, seen)
+}
+
+#[test]
+fn dropping_the_oldest_keeps_the_markers() {
+    let (mut sink, go, seen) = gated(3);
+    // Taken by the thread, which waits at the gate with it
+    sink.send(&json!({"seq": 0})).unwrap();
+    while sink.stats().queued > 0 {
+        std::thread::yield_now();
+    }
+
+    sink.mark("start").unwrap();
+    for seq in 1..=4 {
+        sink.send(&json!({ "seq": seq })).unwrap();
+    }
+    sink.mark("end").unwrap();
+    assert_eq!(sink.stats().dropped, 3);
+
+    for _ in 0..4 {
+        go.send(()).unwrap();
+    }
+    sink.close().unwrap();
+    assert_eq!(*seen.lock().unwrap(), ["0", "start", "4", "end"]);
+}
+
+#[test]
+fn a_queue_of_markers_takes_one_more() {
+    let (mut sink, go, seen) = gated(1);
+    sink.mark("taken").unwrap();
+    while sink.stats().queued > 0 {
+        std::thread::yield_now();
+    }
+
+    sink.mark("a").unwrap();
+    sink.mark("b").unwrap();
+    assert_eq!(sink.stats().dropped, 0);
+
+    for _ in 0..3 {
+        go.send(()).unwrap();
+    }
+    sink.close().unwrap();
+    assert_eq!(*seen.lock().unwrap(), ["taken", "a", "b"]);
+}
+
+#[test]
+fn a_file_waits_for_room_whatever_the_other_queues_drop() {
+    let (sink, go, seen) = gate();
+    let specs: Vec<QueueSpec> = vec!["overflow=drop-oldest".parse().unwrap()];
+    let mut sink = queue::wrap_file(sink, 2, &specs);
+    std::thread::spawn(move || {
+        for _ in 0..10 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            go.send(()).unwrap();
+        }
+    });
+
+    for seq in 0..10 {
+        sink.send(&json!({ "seq": seq })).unwrap();
+    }
+    sink.close().unwrap();
+    assert_eq!(sink.stats().dropped, 0);
+    let expected: Vec<String> = (0..10).map(|seq| seq.to_string()).collect();
+    assert_eq!(*seen.lock().unwrap(), expected);
+}
@@ -0,0 +1,78 @@
+use microblender_bridge::rate::{self, RateMode};
+use microblender_bridge::sink::Sink;
+use serde_json::{json, Value};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Notes every message it's given.
+struct CollectingSink(Arc<Mutex<Vec<Value>>>);
+
+impl Sink for CollectingSink {
+    fn name(&self) -> &str {
+        "Collecting"
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.0.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+/// The message the averaging limiter sends for `messages`, all but the
+/// first arriving before it may send again.
+fn averaged(messages: &[Value]) -> Value {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sink = Box::new(CollectingSink(Arc::clone(&sent)));
+    let mut sink = rate::limit(sink, &["20".parse().unwrap()], RateMode::Average);
+    // Sent right away, alone
+    sink.send(&json!({ "x": 0.0 })).unwrap();
+
+    let (last, earlier) = messages.split_last().unwrap();
+    for message in earlier {
+        sink.send(message).unwrap();
+    }
+    thread::sleep(Duration::from_millis(60));
+    sink.send(last).unwrap();
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    sent[1].clone()
+}
+
+#[test]
+fn readings_are_averaged() {
+    let message = averaged(&[
+        json!({ "x": 10.0, "y": -4.0, "gx": 20.5 }),
+        json!({ "x": 20.0, "y": -2.0, "gx": 21.5 }),
+    ]);
+    assert_eq!(message, json!({ "x": 15.0, "y": -3.0, "gx": 21.0 }));
+}
+
+#[test]
+fn counters_and_raw_readings_are_taken_from_the_latest_message() {
+    let message = averaged(&[
+        json!({ "x": 1.0, "seq": 7, "ax": 12, "mz": -300 }),
+        json!({ "x": 3.0, "seq": 8, "ax": 15, "mz": -310 }),
+    ]);
+    assert_eq!(message, json!({ "x": 2.0, "seq": 8, "ax": 15, "mz": -310 }));
+}
+
+#[test]
+fn angles_average_across_the_wrap_around() {
+    let message = averaged(&[
+        json!({ "x": -179.0, "y": 170.0, "z": 179.0 }),
+        json!({ "x": 179.0, "y": -170.0, "z": -179.0 }),
+    ]);
+    for axis in ["x", "y", "z"] {
+        let angle = message[axis].as_f64().unwrap();
+        assert_eq!(angle.abs(), 180.0, "{} averaged to {}", axis, angle);
+    }
+}
+
+#[test]
+fn whole_numbered_angles_are_averaged_too() {
+    let message = averaged(&[json!({ "x": 10, "y": 1.5 }), json!({ "x": 11.0, "y": 2 })]);
+    assert_eq!(message, json!({ "x": 10.5, "y": 1.75 }));
+}
@@ -0,0 +1,72 @@
+use microblender_bridge::rate::RateSpec;
+use microblender_bridge::resample;
+use microblender_bridge::sink::Sink;
+use serde_json::{json, Value};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct PanickingSink;
+
+impl Sink for PanickingSink {
+    fn name(&self) -> &str {
+        "Panicking"
+    }
+
+    fn send(&mut self, _message: &Value) -> io::Result<()> {
+        panic!("the sink broke");
+    }
+}
+
+/// Notes when the resampler let go of it.
+struct DroppedSink(Arc<AtomicBool>);
+
+impl Sink for DroppedSink {
+    fn name(&self) -> &str {
+        "Dropped"
+    }
+
+    fn send(&mut self, _message: &Value) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DroppedSink {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+fn resampled(sink: Box<dyn Sink>) -> Box<dyn Sink> {
+    resample::apply(sink, &["100".parse::<RateSpec>().unwrap()])
+}
+
+#[test]
+fn a_panicking_sink_fails_the_next_call() {
+    let mut sink = resampled(Box::new(PanickingSink));
+    sink.send(&json!({"x": 1.0, "y": 2.0, "z": 3.0})).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let error = loop {
+        match sink.tick() {
+            Err(e) => break e,
+            Ok(()) if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+            Ok(()) => panic!("the panic was never reported"),
+        }
+    };
+    assert!(error.to_string().contains("panicked"));
+    assert!(sink.send(&json!({"x": 1.0})).is_err());
+    assert!(sink.close().is_err());
+}
+
+#[test]
+fn dropping_an_unclosed_sink_stops_its_clock() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let mut sink = resampled(Box::new(DroppedSink(Arc::clone(&dropped))));
+    sink.send(&json!({"x": 1.0, "y": 2.0, "z": 3.0})).unwrap();
+
+    drop(sink);
+    assert!(dropped.load(Ordering::Relaxed));
+}
@@ -0,0 +1,116 @@
+use clap::Parser;
+use microblender_bridge::bridge;
+use microblender_bridge::cli::Args;
+use microblender_bridge::command::BoardLink;
+use microblender_bridge::crc::crc16;
+use microblender_bridge::source::{DataSource, MockSource};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+/// Runs the bridge on `sources` with the options `extra`, and returns the
+/// messages a TCP sink received.
+fn capture(extra: &[&str], sources: Vec<Box<dyn DataSource>>) -> Vec<Value> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let output = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        output
+    });
+
+    let sink = format!("Capture=tcp://127.0.0.1:{}", port);
+    let mut args = vec!["test", "--sink", &sink, "--no-hello", "--heartbeat", "0"];
+    args.extend(extra);
+    bridge::run_sources(&Args::parse_from(args), sources, BoardLink::default()).unwrap();
+
+    output
+        .join()
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn mock(name: &str, lines: &[&str]) -> Box<dyn DataSource> {
+    Box::new(MockSource::new(name, lines.iter().copied()))
+}
+
+#[test]
+fn a_mock_source_gives_out_its_lines_then_ends() {
+    let mut source = MockSource::new("mock", ["a", "b"]);
+    assert_eq!(source.name(), "mock");
+    assert_eq!(source.next_line().as_deref(), Some("a"));
+    assert_eq!(source.next_line().as_deref(), Some("b"));
+    assert_eq!(source.next_line(), None);
+}
+
+#[test]
+fn every_message_reaches_the_sink_in_order() {
+    let lines = [
+        r#"{"x":1.0,"y":2.0,"z":3.0,"seq":0}"#,
+        r#"{"x":4.0,"y":5.0,"z":6.0,"seq":1}"#,
+        r#"{"x":7.0,"y":8.0,"z":9.0,"seq":2}"#,
+    ];
+    let messages = capture(&[], vec![mock("board", &lines)]);
+    assert_eq!(
+        messages,
+        [
+            json!({"x": 1.0, "y": 2.0, "z": 3.0, "seq": 0}),
+            json!({"x": 4.0, "y": 5.0, "z": 6.0, "seq": 1}),
+            json!({"x": 7.0, "y": 8.0, "z": 9.0, "seq": 2}),
+        ]
+    );
+}
+
+#[test]
+fn broken_and_unchecked_lines_are_dropped() {
+    let payload = r#"{"x":1.0,"y":2.0,"z":3.0,"seq":0}"#;
+    let good = format!("{}*{:04X}", payload, crc16(payload.as_bytes()));
+    let corrupted = format!("{}*0000", payload);
+    let lines = [good.as_str(), corrupted.as_str(), payload, "not json"];
+
+    let messages = capture(&["--require-crc"], vec![mock("board", &lines)]);
+    assert_eq!(messages, [json!({"x": 1.0, "y": 2.0, "z": 3.0, "seq": 0})]);
+}
+
+#[test]
+fn the_messages_of_several_sources_are_tagged_with_their_device() {
+    let messages = capture(
+        &[],
+        vec![
+            mock("left", &[r#"{"x":1.0,"y":0.0,"z":0.0,"seq":0}"#]),
+            mock("right", &[r#"{"x":2.0,"y":0.0,"z":0.0,"seq":0}"#]),
+        ],
+    );
+    let mut devices: Vec<(&str, f64)> = messages
+        .iter()
+        .map(|message| {
+            (
+                message["device"].as_str().unwrap(),
+                message["x"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    devices.sort_by(|a, b| a.0.cmp(b.0));
+    assert_eq!(devices, [("left", 1.0), ("right", 2.0)]);
+}
+
+#[test]
+fn transforms_apply_to_the_sources_messages() {
+    let messages = capture(
+        &["--units", "rad"],
+        vec![mock("board", &[r#"{"x":180.0,"y":0.0,"z":-90.0,"seq":0}"#])],
+    );
+    assert_eq!(messages.len(), 1);
+    let x = messages[0]["x"].as_f64().unwrap();
+    let z = messages[0]["z"].as_f64().unwrap();
+    assert!((x - std::f64::consts::PI).abs() < 1e-9);
+    assert!((z + std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+}
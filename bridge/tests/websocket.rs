@@ -0,0 +1,58 @@
+use microblender_bridge::sink::Sink;
+use microblender_bridge::websocket::{self, WebSocketServer};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn an_upgraded_client_gets_every_message_as_a_text_frame() {
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut server = WebSocketServer::bind("127.0.0.1", port).unwrap();
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\n\
+         Host: localhost\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    )
+    .unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut response = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.trim_end().is_empty() {
+            break;
+        }
+        response.push(line.trim_end().to_string());
+    }
+    assert_eq!(response[0], "HTTP/1.1 101 Switching Protocols");
+    assert!(response.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.status() != "1 client" {
+        assert!(Instant::now() < deadline, "client never joined");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let message = json!({"x": 1.5, "y": -2.0, "z": 90.0});
+    server.send(&message).unwrap();
+
+    let expected = websocket::frame(websocket::TEXT, message.to_string().as_bytes(), None);
+    let mut frame = vec![0; expected.len()];
+    reader.read_exact(&mut frame).unwrap();
+    assert_eq!(frame, expected);
+}
@@ -24,11 +24,13 @@ show_help() {
     echo "  -b, --blender     Enable Blender visualization (copies script to clipboard)"
     echo "  -v, --visualizer  Enable Python visualizer (runs in background)"
     echo "  -p, --port PATH   Specify Microbit port (default: /dev/ttyACM0)"
+    echo "  -s, --simulate    Use simulated data instead of a Microbit"
     echo "  -h, --help        Show this help message"
     echo -e "\nExamples:"
     echo "  $0 -v             Run Python visualizer only"
     echo "  $0 -b -v          Run both visualizations"
     echo "  $0 -p /dev/ttyACM1 -v   Use specific port"
+    echo "  $0 -s -b          Demo the Blender visualization without hardware"
 }
 
 handle_error() {
@@ -173,6 +175,7 @@ cleanup() {
 # Parse command line arguments
 BLENDER=false
 VISUALIZER=false
+SIMULATE=false
 
 while [[ $# -gt 0 ]]; do
     case $1 in
@@ -192,6 +195,10 @@ while [[ $# -gt 0 ]]; do
             MICROBIT_PORT="$2"
             shift 2
             ;;
+        -s|--simulate)
+            SIMULATE=true
+            shift
+            ;;
         -h|--help)
             show_help
             exit 0
@@ -210,15 +217,19 @@ fi
 # Set up trap for cleanup
 trap cleanup SIGINT SIGTERM EXIT
 
-start_microbit "$MICROBIT_PORT" "$MICROBIT_TIMEOUT" "$FORCE_FLASH"
+if $SIMULATE; then
+    BRIDGE_ARGS="--simulate gestures"
+else
+    start_microbit "$MICROBIT_PORT" "$MICROBIT_TIMEOUT" "$FORCE_FLASH"
+    BRIDGE_ARGS="--port $MICROBIT_PORT"
+fi
 
 # Set up visualizations
-BRIDGE_ARGS="--port $MICROBIT_PORT"
 
 if $BLENDER; then
     echo -e "\n${BLUE}Setting up Blender visualization...${NC}"
     copy_to_clipboard "blender.py"
-    BRIDGE_ARGS="$BRIDGE_ARGS --blender"
+    BRIDGE_ARGS="$BRIDGE_ARGS --blender --relay-commands"
 fi
 
 if $VISUALIZER; then
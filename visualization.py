@@ -10,6 +10,9 @@ import time
 
 matplotlib.use('TkAgg')
 
+# Version of the bridge's message format this script understands
+BRIDGE_PROTOCOL = 1
+
 class AccelerometerVisualizer:
     def __init__(self, port=65433):
         self.fig = plt.figure(figsize=(15, 7))
@@ -72,14 +75,38 @@ class AccelerometerVisualizer:
             try:
                 conn, _ = self.sock.accept()
                 print(f"Connected to data source")
-                while self.running:
+                buffer = b''
+                compatible = True
+                while self.running and compatible:
                     data = conn.recv(1024)
                     if not data:
                         break
-                    try:
-                        self.latest_rotation = json.loads(data.decode())
-                    except json.JSONDecodeError:
-                        print("Invalid JSON received")
+                    *lines, buffer = (buffer + data).split(b'\n')
+                    for line in lines:
+                        try:
+                            message = json.loads(line.decode())
+                        except json.JSONDecodeError:
+                            print("Invalid JSON received")
+                            continue
+                        if isinstance(message, list):
+                            # A batch from --batch-format array, only the latest one matters
+                            if not message:
+                                continue
+                            message = message[-1]
+                        if 'hello' in message:
+                            compatible = self.answer_hello(conn, message['hello'])
+                            if not compatible:
+                                conn.close()
+                                break
+                        elif 'event' in message:
+                            print(f"Event: {message['event']}")
+                        elif 'gesture' in message:
+                            print(f"Gesture: {message['gesture']} ({message.get('confidence', 0):.0%})")
+                        elif 'ping' in message:
+                            # Sent by the bridge's latency command
+                            conn.sendall((json.dumps({'pong': message['ping']}) + '\n').encode())
+                        elif 'heartbeat' not in message and 'temp' not in message:
+                            self.latest_rotation = message
             except socket.timeout:
                 continue
             except ConnectionResetError:
@@ -94,6 +121,18 @@ class AccelerometerVisualizer:
                 print(f"Error: {e}")
                 time.sleep(1)
 
+    def answer_hello(self, conn, hello):
+        """Acknowledges the hello of a compatible bridge, or rejects it and returns False."""
+        protocol = hello.get('protocol')
+        if protocol != BRIDGE_PROTOCOL:
+            reason = f"protocol {protocol} is not supported, expected {BRIDGE_PROTOCOL}"
+            print(f"Rejecting bridge {hello.get('bridge')}: {reason}")
+            conn.sendall((json.dumps({'hello_reject': reason}) + '\n').encode())
+            return False
+        print(f"Bridge {hello.get('bridge')} connected ({hello.get('source')})")
+        conn.sendall((json.dumps({'hello_ack': {'protocol': BRIDGE_PROTOCOL}}) + '\n').encode())
+        return True
+
     def update_plot(self, _):
         pitch = np.radians(self.latest_rotation['x'])
         roll = np.radians(self.latest_rotation['y'])